@@ -1,19 +1,27 @@
 use log::debug;
 use std::sync::{Arc, Mutex};
 use std::io::{self, Error, ErrorKind, Write};
-use serde_json::{self, Value};
+use serde_json::{self, Map, Value};
 
 use std::thread;
 use std::env;
+use std::path::Path;
 use getopts::Options;
+use zeroize::Zeroizing;
 
 use crate::executor::executor::Executor;
-use crate::executor::parameters::SequenceItem;
+use crate::executor::parameters::{FileTransferMode, FileTransferParams, RawSequenceItem, SequenceItem};
+use crate::executor::report::RunRecord;
+use crate::executor::control::SEQUENCE_CONTROL;
+use crate::executor::progress::{FlashPhase, ProgressEvent, FLASH_PROGRESS};
 use crate::transport::config::CONFIG;
 use crate::utils; // Import the parse config module
 use crate::executor::parse_sequence; // Import the parse sequence module
+use crate::executor::vbf;
+use crate::executor::securityaccess;
 use crate::transport::diag;
 use crate::cli::cli;
+use crate::cli::debugger;
 
 /*****************************************************************************************************************
  *  cli::parse function
@@ -35,56 +43,46 @@ pub fn parse(executor_obj: Arc<Mutex<Executor>>, input: &str) -> Result<(), io::
         return Err(Error::new(ErrorKind::InvalidInput, "wrong input format"));
     }
 
-    let name = parts[0].trim();
+    let mut name = parts[0].trim();
     let action = parts[1].trim();
-    let trimmed_action = action.replace(" ", "");
-    let mut action_value: Value = Value::Null;
-
-    match name {
-        "socket" | "send_doip" => {
-            action_value = Value::String(String::from(trimmed_action))
-        }
-        "send_diag" => {
-            action_value = Value::Array(vec![Value::String(String::from(trimmed_action))])
-        }
-        s if s.starts_with("securityaccess_") => {
-            let result: Result<Value, serde_json::Error> = serde_json::from_str(trimmed_action.as_str());
-            match result {
-                Ok(parsed_json) => {
-                    action_value = parsed_json;
-                }
-                Err(e) => {
-                    println!("Error parsing securityaccess action: {}", e);
-                }
+    let action_value: Value = match name {
+        "socket" | "send_doip" | "send_diag" => Value::String(action.replace(" ", "")),
+        "flash_package" | "authentication" if !action.starts_with('{') => Value::String(action.to_string()),
+        // push:<local> <remote> replaces remote file, pull:<remote> <local> reads it
+        "push" | "pull" => {
+            let paths: Vec<&str> = action.split_whitespace().collect();
+            if paths.len() != 2 {
+                eprintln!("use format like this {}", if name == "push" { "push:app.cfg /data/app.cfg" } else { "pull:/data/app.cfg app.cfg" });
+                return Err(Error::new(ErrorKind::InvalidInput, "wrong action format"));
             }
+            let params = if name == "push" {
+                FileTransferParams::new(FileTransferMode::Replace, paths[1].to_string(), Some(paths[0].to_string()))
+            } else {
+                FileTransferParams::new(FileTransferMode::Read, paths[0].to_string(), Some(paths[1].to_string()))
+            };
+            name = "file_transfer";
+            serde_json::to_value(params)?
         }
-        "swdl" => {
-            let result: Result<Value, serde_json::Error> = serde_json::from_str(trimmed_action.as_str());
-            match result {
-                Ok(parsed_json) => {
-                    action_value = parsed_json;
-                }
-                Err(e) => {
-                    println!("Error parsing swdl action: {}", e);
-                }
+        // Parameters are json, object {"path": "..."} or list ["path:...", "format:vbf"]
+        _ => match serde_json::from_str(action) {
+            Ok(parsed_json) => parsed_json,
+            Err(e) => {
+                eprintln!("Error parsing {} action: {}", name, e);
+                return Err(Error::new(ErrorKind::InvalidInput, "wrong action format"));
             }
-        }
-        _ => {
-            action_value = Value::Null;
-            println!("Handling other cases: {}", name);
-        }
-    }
+        },
+    };
 
-    let item = SequenceItem {
+    let item = SequenceItem::from_raw(RawSequenceItem {
         name: String::from(name),
         description: String::from("item_description"),
-        action: action_value,
+        fields: Map::from_iter([(String::from("action"), action_value)]),
         expect: Value::Array(vec![
             Value::String(String::from("*")),
         ]),
         timeout: String::from("10s"),
         fail: String::from(""),
-    };
+    })?;
 
     match Executor::execute_cmd(Arc::clone(&executor_obj), item, &config.ethernet.vendor) {
         Ok(()) => debug!("Command executed successfully!"),
@@ -101,7 +99,20 @@ pub fn run_cli() {
     // Define the available command-line options
     let mut opts = Options::new();
     opts.optopt("c", "config", "set input config json file name", "config.json");
-    opts.optopt("s", "sequence", "set input sequence json file name", "sequence.json");
+    opts.optopt("s", "sequence", "set input sequence file name (json, yaml or toml)", "sequence.json");
+    opts.optmulti("r", "report", "export sequence report, format by extension (.xml JUnit, .json, .html)", "report.xml");
+    opts.optopt("", "convert", "convert sequence file to the format of --output file (json, yaml, toml)", "sequence.json");
+    opts.optopt("o", "output", "output file name of --convert", "sequence.yaml");
+    opts.optflag("", "step", "execute sequence step by step");
+    opts.optmulti("b", "break", "set breakpoint at step index or name in step mode", "3");
+    opts.optopt("k", "keystore", "unlock keystore, keys are referenced as @name in sequences", "keys.store");
+    opts.optflag("", "keystore-create", "create an empty --keystore file");
+    opts.optopt("", "keystore-add", "add or replace a key of --keystore, the hex value is prompted", "NAME");
+    opts.optflag("", "keystore-list", "list key names of --keystore");
+    opts.optopt("", "keystore-remove", "remove a key of --keystore", "NAME");
+    opts.optflag("", "keystore-passwd", "change password of --keystore");
+    opts.optflag("", "crypto-selftest", "check crypto primitives and the volvo security access exchange against known answers");
+    opts.optmulti("", "verify-vbf", "verify hashes and sw_signature of a vbf file with vbf_verification of --config, no ECU is needed", "app.vbf");
     opts.optflag("h", "help", "print this help menu");
     opts.optflag("d", "debug", "enable debug log");
 
@@ -127,6 +138,49 @@ pub fn run_cli() {
         debug!("Debug logging enabled");
     }
 
+    /* convert sequence file, no config is needed */
+    if let Some(input_filename) = matches.opt_str("convert") {
+        match matches.opt_str("output") {
+            Some(output_filename) => {
+                match parse_sequence::convert(&input_filename, &output_filename) {
+                    Ok(()) => println!("Converted {} to {}", input_filename, output_filename),
+                    Err(err) => eprintln!("Failed to convert sequence: {}", err),
+                }
+            }
+            None => eprintln!("Error: --convert option requires --output"),
+        }
+        return;
+    }
+
+    /* crypto known answer tests, no config is needed */
+    if matches.opt_present("crypto-selftest") {
+        match utils::excrypto::self_test().and_then(|mut passed| {
+            passed.extend(securityaccess::self_test()?);
+            Ok(passed)
+        }) {
+            Ok(passed) => passed.iter().for_each(|name| println!("{}: OK", name)),
+            Err(err) => {
+                eprintln!("Error: {}", err);
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+
+    /* manage keystore, no config is needed */
+    if ["keystore-create", "keystore-add", "keystore-list", "keystore-remove", "keystore-passwd"]
+        .iter().any(|name| matches.opt_present(name)) {
+        match matches.opt_str("keystore") {
+            Some(keystore_filename) => {
+                if let Err(err) = manage_keystore(&matches, &keystore_filename) {
+                    eprintln!("Keystore {}: {}", keystore_filename, err);
+                }
+            }
+            None => eprintln!("Error: keystore commands require --keystore"),
+        }
+        return;
+    }
+
     /* handle json config file */
     if let Some(config_filename) = matches.opt_str("config") {
         // Read the JSON file into a string
@@ -145,6 +199,55 @@ pub fn run_cli() {
         return;
     }
 
+    /* verify vbf files offline, public key comes from config */
+    let vbf_filenames = matches.opt_strs("verify-vbf");
+    if !vbf_filenames.is_empty() {
+        let mut failed = false;
+        for vbf_filename in vbf_filenames.iter() {
+            match vbf::load(vbf_filename).and_then(|vbf_file| vbf::verify(&vbf_file)) {
+                Ok(true) => println!("{}: OK, signature verified", vbf_filename),
+                Ok(false) => println!("{}: OK, signature not checked (no vbf_verification in config)", vbf_filename),
+                Err(err) => {
+                    eprintln!("{}: {}", vbf_filename, err);
+                    failed = true;
+                }
+            }
+        }
+        if failed {
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    /* unlock keystore before the sequence references its keys */
+    if let Some(keystore_filename) = matches.opt_str("keystore") {
+        let result = keystore_password("Keystore password: ")
+            .and_then(|password| utils::keystore::unlock(Path::new(&keystore_filename), &password));
+        match result {
+            Ok(count) => debug!("Unlocked keystore {} with {} keys", keystore_filename, count),
+            Err(err) => {
+                eprintln!("Failed to unlock keystore {}: {}", keystore_filename, err);
+                return;
+            }
+        }
+    }
+
+    // Ctrl-C aborts a running sequence (cleanup is executed), otherwise exits the tool
+    if let Err(err) = ctrlc::set_handler(|| {
+        if SEQUENCE_CONTROL.abort() {
+            eprintln!("Abort requested, running cleanup...");
+        } else if SEQUENCE_CONTROL.is_idle() {
+            std::process::exit(130);
+        } else {
+            //aborted or in cleanup, leaving now would skip the cleanup of the ECU
+            eprintln!("Warning: sequence is already aborting, Ctrl-C is ignored until its cleanup is done");
+        }
+    }) {
+        eprintln!("Failed to set Ctrl-C handler: {}", err);
+    }
+
+    FLASH_PROGRESS.subscribe(print_progress);
+
     /* init transport module */
     let diag_obj = Arc::new(Mutex::new(diag::create_diag()));
 
@@ -152,20 +255,37 @@ pub fn run_cli() {
     let executor_obj = Arc::new(Mutex::new(Executor::create_executor(diag_obj)));
     let executor_obj_clone = Arc::clone(&executor_obj);
 
-    /* handle json sequence file */
-    thread::spawn(move || {
-        if let Some(sequence_filename) = matches.opt_str("sequence") {
-            match parse_sequence::parse(sequence_filename, executor_obj) {
-                Ok(()) => {}
-                Err(err) => {
-                    eprintln!("Error reading sequence file {}", err);
-                }
-            };
-        } else {
-            eprintln!("Info: you can select sequence with --sequence option");
-            print_usage(&args[0], &opts);
+    /* handle json sequence file step by step, then continue with CLI */
+    if matches.opt_present("step") {
+        match matches.opt_str("sequence") {
+            Some(sequence_filename) => {
+                debugger::run_step_mode(executor_obj, &sequence_filename, matches.opt_strs("break"));
+            }
+            None => eprintln!("Error: --step option requires --sequence"),
         }
-    });
+    } else {
+        /* handle json sequence file */
+        thread::spawn(move || {
+            if let Some(sequence_filename) = matches.opt_str("sequence") {
+                let mut record = RunRecord::new(&sequence_filename, &CONFIG.read().unwrap());
+                match parse_sequence::parse(sequence_filename, executor_obj, &mut record) {
+                    Ok(()) => {}
+                    Err(err) => {
+                        eprintln!("Error reading sequence file {}", err);
+                    }
+                };
+                for report_filename in matches.opt_strs("report") {
+                    match record.export(&report_filename) {
+                        Ok(()) => println!("Exported report {}", report_filename),
+                        Err(err) => eprintln!("Failed to export report {}: {}", report_filename, err),
+                    }
+                }
+            } else {
+                eprintln!("Info: you can select sequence with --sequence option");
+                print_usage(&args[0], &opts);
+            }
+        });
+    }
 
     //handle CLI
     loop {
@@ -180,6 +300,29 @@ pub fn run_cli() {
             continue;
         }
 
+        // sequence control commands
+        match input {
+            "pause" => {
+                if !SEQUENCE_CONTROL.pause() {
+                    eprintln!("No running sequence to pause");
+                }
+                continue;
+            }
+            "resume" => {
+                if !SEQUENCE_CONTROL.resume() {
+                    eprintln!("No paused sequence to resume");
+                }
+                continue;
+            }
+            "abort" => {
+                if !SEQUENCE_CONTROL.abort() {
+                    eprintln!("No running sequence to abort");
+                }
+                continue;
+            }
+            _ => {}
+        }
+
         //parse cli
         match cli::parse(Arc::clone(&executor_obj_clone), input) {
             Ok(()) => {}
@@ -190,6 +333,102 @@ pub fn run_cli() {
     }
 }
 
+/*****************************************************************************************************************
+ *  cli::manage_keystore function
+ *  brief      Execute keystore commands: create, add, list, remove and change password
+ *  details    Passwords and key values are prompted without echo, or read line by line from a piped stdin.
+ *             The unlock password can be given by environment variable DIAG_TOOL_KEYSTORE_PASSWORD.
+ *  \param[in]  matches: parsed command line
+ *              keystore_filename: --keystore file
+ *  \param[out] -
+ *  \precondition -
+ *  \reentrant:  FALSE
+ *  \return     error code if any
+ ****************************************************************************************************************/
+fn manage_keystore(matches: &getopts::Matches, keystore_filename: &str) -> Result<(), io::Error> {
+    let path = Path::new(keystore_filename);
+    let mut keystore = if matches.opt_present("keystore-create") {
+        let keystore = utils::keystore::Keystore::create(path, &new_keystore_password()?)?;
+        println!("Created keystore {}", keystore_filename);
+        keystore
+    } else {
+        utils::keystore::Keystore::open(path, &keystore_password("Keystore password: ")?)?
+    };
+
+    if let Some(name) = matches.opt_str("keystore-add") {
+        let value = utils::keystore::read_secret(&format!("Hex value of {}: ", name))?;
+        keystore.insert(&name, &value)?;
+        keystore.save()?;
+        println!("Stored key {}, reference it as @{}", name, name);
+    }
+    if let Some(name) = matches.opt_str("keystore-remove") {
+        keystore.remove(&name)?;
+        keystore.save()?;
+        println!("Removed key {}", name);
+    }
+    if matches.opt_present("keystore-passwd") {
+        keystore.change_password(&new_keystore_password()?)?;
+        println!("Changed password of keystore {}", keystore_filename);
+    }
+    if matches.opt_present("keystore-list") {
+        for name in keystore.names() {
+            println!("@{}", name);
+        }
+    }
+    Ok(())
+}
+
+fn keystore_password(prompt: &str) -> Result<Zeroizing<String>, io::Error> {
+    match env::var(utils::keystore::PASSWORD_ENV) {
+        Ok(password) => Ok(Zeroizing::new(password)),
+        Err(_) => utils::keystore::read_secret(prompt),
+    }
+}
+
+fn new_keystore_password() -> Result<Zeroizing<String>, io::Error> {
+    let password = utils::keystore::read_secret("New keystore password: ")?;
+    if *utils::keystore::read_secret("Repeat new keystore password: ")? != *password {
+        return Err(Error::new(ErrorKind::InvalidInput, "passwords do not match"));
+    }
+    Ok(password)
+}
+
+/*****************************************************************************************************************
+ *  cli::print_progress function
+ *  brief      Draw flashing progress bar on current terminal line
+ *  details    Ex: [##########----------]  50% app.vbf download 0x8000/0x10000 12.5 kB/s ETA 3s
+ *  \param[in]  event: flashing progress event
+ *  \param[out] -
+ *  \precondition -
+ *  \reentrant:  FALSE
+ *  \return -
+ ****************************************************************************************************************/
+fn print_progress(event: &ProgressEvent) {
+    const BAR_WIDTH: u64 = 20;
+    let percent = (event.bytes_sent * 100).checked_div(event.bytes_total).unwrap_or(100).min(100);
+    let filled = (percent * BAR_WIDTH / 100) as usize;
+    let file_name = std::path::Path::new(&event.file).file_name()
+        .map(|name| name.to_string_lossy().to_string()).unwrap_or(event.file.clone());
+    let phase = match event.phase {
+        FlashPhase::Erase => format!("erase {}/{}", event.block_index + 1, event.block_count),
+        FlashPhase::Download => format!("download block {}/{}", event.block_index + 1, event.block_count),
+        FlashPhase::Check => String::from("check"),
+        FlashPhase::Upload => String::from("upload"),
+        FlashPhase::Done => String::from("done"),
+    };
+    let eta = match event.eta_ms {
+        Some(eta_ms) => format!(" ETA {}s", eta_ms.div_ceil(1000)),
+        None => String::new(),
+    };
+    print!("\r[{}{}] {:3}% {} {} 0x{:X}/0x{:X} {:.1} kB/s{}\x1b[K",
+           "#".repeat(filled), "-".repeat(BAR_WIDTH as usize - filled), percent, file_name, phase,
+           event.bytes_sent, event.bytes_total, event.throughput as f64 / 1000.0, eta);
+    if event.phase == FlashPhase::Done {
+        println!();
+    }
+    let _ = io::stdout().flush();
+}
+
 fn print_usage(program: &str, opts: &Options) {
     let brief = format!("Usage: {} [options]", program);
     print!("{}", opts.usage(&brief));
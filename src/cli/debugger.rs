@@ -0,0 +1,129 @@
+use std::sync::{Arc, Mutex};
+use std::io::{self, Write};
+
+use crate::executor::executor::Executor;
+use crate::executor::parse_sequence;
+use crate::executor::stepper::{Breakpoint, StepSession};
+use crate::transport::config::CONFIG;
+
+const STEP_HELP: &str = "\
+n | next           execute current step
+c | continue       run until next breakpoint
+s | skip           skip current step
+j | jump <N>       go to step N (backward or forward)
+b | break <N|name> toggle breakpoint at step index or name
+action <json>      replace action of current step. Ex: action [\"1003\"]
+expect <json>      replace expect of current step. Ex: expect [\"5003*\"]
+p | print          show current step
+l | list           list steps and breakpoints
+r | response       show last request and response
+q | quit           leave step mode, tester-present is stopped";
+
+/*****************************************************************************************************************
+ *  cli::debugger::run_step_mode function
+ *  brief      Execute a sequence one step at a time from user input
+ *  details    Tester-present of config runs while step mode is active and is stopped when leaving it
+ *  \param[in]  executor_obj: executor object
+ *              sequence_filename: path to sequence file
+ *              breakpoints: initial breakpoints, step index or name
+ *  \param[out] -
+ *  \precondition config is parsed
+ *  \reentrant:  FALSE
+ *  \return -
+ ****************************************************************************************************************/
+pub fn run_step_mode(executor_obj: Arc<Mutex<Executor>>, sequence_filename: &str, breakpoints: Vec<String>) {
+    let sequence = match parse_sequence::load(sequence_filename) {
+        Ok(sequence) => sequence,
+        Err(err) => {
+            eprintln!("Error reading sequence file {}", err);
+            return;
+        }
+    };
+    // Keep sessions alive while waiting at a step, like the sequence runner does
+    let is_tester_present_started = match parse_sequence::start_tester_present(&executor_obj) {
+        Ok(is_started) => is_started,
+        Err(_) => return,
+    };
+    let vendor = CONFIG.read().unwrap().ethernet.vendor.clone();
+    let mut session = StepSession::new(sequence);
+    for breakpoint in breakpoints.iter() {
+        session.toggle_breakpoint(Breakpoint::parse(breakpoint));
+    }
+    println!("Step mode, type h for help");
+    print!("{}", session.describe_list());
+
+    loop {
+        let mut input = String::new();
+        print!("STEP {:02}>>> ", session.current_index());
+        io::stdout().flush().expect("Failed to flush stdout");
+        match io::stdin().read_line(&mut input) {
+            Ok(0) | Err(_) => break, // stdin closed
+            Ok(_) => {}
+        }
+        let input = input.trim();
+        let (command, argument) = match input.split_once(' ') {
+            Some((command, argument)) => (command, argument.trim()),
+            None => (input, ""),
+        };
+
+        match command {
+            "" => continue,
+            "n" | "next" => {
+                match session.step(&executor_obj, &vendor) {
+                    Ok(()) => println!("OK"),
+                    Err(err) => eprintln!("FAILED: {}", err),
+                }
+                print!("{}", session.describe_last());
+            }
+            "c" | "continue" => {
+                match session.run_to_breakpoint(&executor_obj, &vendor) {
+                    Ok(()) => println!("OK"),
+                    Err(err) => eprintln!("FAILED: {}", err),
+                }
+                print!("{}", session.describe_last());
+                println!("{}", session.describe_current());
+            }
+            "s" | "skip" => {
+                session.skip();
+                println!("{}", session.describe_current());
+            }
+            "j" | "jump" => {
+                match argument.parse::<usize>() {
+                    Ok(index) => match session.jump(index) {
+                        Ok(()) => println!("{}", session.describe_current()),
+                        Err(err) => eprintln!("{}", err),
+                    },
+                    Err(_) => eprintln!("use format like this: jump 3"),
+                }
+            }
+            "b" | "break" => {
+                if argument.is_empty() {
+                    eprintln!("use format like this: break 3 or break swdl");
+                } else if session.toggle_breakpoint(Breakpoint::parse(argument)) {
+                    println!("breakpoint set at {}", argument);
+                } else {
+                    println!("breakpoint removed at {}", argument);
+                }
+            }
+            "action" | "expect" => {
+                match session.edit_current(command, argument) {
+                    Ok(()) => println!("{}", session.describe_current()),
+                    Err(err) => eprintln!("{}", err),
+                }
+            }
+            "p" | "print" => println!("{}", session.describe_current()),
+            "l" | "list" => print!("{}", session.describe_list()),
+            "r" | "response" => print!("{}", session.describe_last()),
+            "h" | "help" => println!("{}", STEP_HELP),
+            "q" | "quit" => break,
+            _ => eprintln!("unknown command {}, type h for help", command),
+        }
+
+        if session.is_finished() {
+            println!("End of sequence");
+        }
+    }
+    if is_tester_present_started {
+        executor_obj.lock().unwrap().stop_tester_present();
+    }
+}
@@ -0,0 +1,357 @@
+use log::debug;
+use rand::Rng;
+use std::fs;
+use std::io::{self, Error, ErrorKind};
+use std::time::{SystemTime, UNIX_EPOCH};
+use x509_cert::Certificate;
+use x509_cert::der::{Decode, Encode};
+
+use crate::utils;
+use crate::utils::excrypto::{self, HashAlgorithm, PublicKey, SignatureScheme};
+use crate::transport;
+use crate::executor::parameters::{AuthenticationMode, AuthenticationParams};
+
+/*****************************************************************************************************************
+ *  Define all gloval macro & variable here
+ ****************************************************************************************************************/
+const DEAUTHENTICATE: u8 = 0x00;
+const VERIFY_CERTIFICATE_UNIDIRECTIONAL: u8 = 0x01;
+const VERIFY_CERTIFICATE_BIDIRECTIONAL: u8 = 0x02;
+const PROOF_OF_OWNERSHIP: u8 = 0x03;
+const TRANSMIT_CERTIFICATE: u8 = 0x04;
+
+const DEAUTHENTICATION_SUCCESSFUL: u8 = 0x10;
+const OWNERSHIP_VERIFICATION_NECESSARY: u8 = 0x11;
+const OWNERSHIP_VERIFIED: u8 = 0x12;
+const CERTIFICATE_VERIFIED: u8 = 0x13;
+
+const CHALLENGE_LENGTH: usize = 32;
+const DEFAULT_COMMUNICATION_CONFIGURATION: u8 = 0x00;
+const OID_ECDSA_WITH_SHA256: &str = "1.2.840.10045.4.3.2";
+const OID_ECDSA_WITH_SHA512: &str = "1.2.840.10045.4.3.4";
+const OID_SHA256_WITH_RSA: &str = "1.2.840.113549.1.1.11";
+const OID_SHA512_WITH_RSA: &str = "1.2.840.113549.1.1.13";
+
+/* Positive response of verifyCertificateUnidirectional/Bidirectional, server fields are empty for unidirectional */
+#[derive(Debug, Default)]
+pub struct CertificateVerification {
+    pub challenge_server: Vec<u8>,
+    pub certificate_server: Vec<u8>,
+    pub proof_of_ownership_server: Vec<u8>,
+    pub ephemeral_public_key_server: Vec<u8>,
+}
+
+/*****************************************************************************************************************
+ *  executor::authentication::authenticate function
+ *  brief      Execute authentication step with PKI certificate exchange (APCE)
+ *  details    unidirectional: tester certificate is verified by ECU, tester proves ownership by signing the ECU
+ *             challenge. bidirectional: additionally the ECU certificate is verified against trust_anchor and its
+ *             proof of ownership over the tester challenge is checked before the tester proof is sent.
+ *             Ephemeral keys are not exchanged, no session key is established.
+ *  \param[in]  stream: point to Diag object
+ *              params: authentication step parameters
+ *              timeout: timeout per request in milliseconds
+ *  \param[out] -
+ *  \precondition -
+ *  \reentrant:  FALSE
+ *  \return     error code if any
+ ****************************************************************************************************************/
+pub fn authenticate(stream: &mut transport::diag::Diag, params: &AuthenticationParams, timeout: u64) -> Result<(), io::Error> {
+    let certificate_path = params.certificate.as_deref().unwrap_or_default();
+    match params.mode {
+        AuthenticationMode::Deauthenticate => return deauthenticate(stream, timeout),
+        AuthenticationMode::TransmitCertificate => {
+            let evaluation_id = params.certificate_evaluation_id.map(|id| id.0 as u16).unwrap_or_default();
+            let (_, certificate) = load_certificate(certificate_path)?;
+            return transmit_certificate(stream, evaluation_id, &certificate, timeout);
+        }
+        AuthenticationMode::Unidirectional | AuthenticationMode::Bidirectional => {}
+    }
+
+    let (_, certificate) = load_certificate(certificate_path)?;
+    let private_key = excrypto::load_private_key(params.private_key.as_deref().unwrap_or_default())?;
+    let communication_configuration = params.communication_configuration
+        .map(|configuration| configuration.0 as u8).unwrap_or(DEFAULT_COMMUNICATION_CONFIGURATION);
+    let is_bidirectional = params.mode == AuthenticationMode::Bidirectional;
+    let trust_anchors = match (is_bidirectional, &params.trust_anchor) {
+        (true, Some(path)) => load_trust_anchors(path)?,
+        _ => Vec::new(),
+    };
+    let challenge_client = match is_bidirectional {
+        true => rand::thread_rng().gen::<[u8; CHALLENGE_LENGTH]>().to_vec(),
+        false => Vec::new(),
+    };
+
+    let verification = verify_certificate(stream, is_bidirectional, communication_configuration, &certificate,
+                                          &challenge_client, timeout)?;
+    if is_bidirectional {
+        let certificate_server = Certificate::from_der(&verification.certificate_server)
+            .map_err(|err| Error::new(ErrorKind::InvalidData, format!("ECU certificate is malformed: {}", err)))?;
+        let public_key_server = PublicKey::from_der(&verify_chain(&certificate_server, &trust_anchors)?)?;
+        let scheme = public_key_server.default_scheme(HashAlgorithm::Sha256);
+        excrypto::verify_signature(&public_key_server, scheme, &challenge_client, &verification.proof_of_ownership_server)
+            .map_err(|err| Error::new(ErrorKind::PermissionDenied, format!("ECU proof of ownership: {}", err)))?;
+        debug!("ECU certificate {} verified", certificate_server.tbs_certificate.subject);
+    }
+    if verification.challenge_server.is_empty() {
+        return Err(Error::new(ErrorKind::InvalidData, "ECU sent no challenge for proof of ownership"));
+    }
+
+    let proof = excrypto::sign_sha256(&private_key, &verification.challenge_server)?;
+    let session_key_info = proof_of_ownership(stream, &proof, &[], timeout)?;
+    debug!("Authenticated {:?}, session key info {:02X?}", params.mode, session_key_info);
+    Ok(())
+}
+
+
+/*****************************************************************************************************************
+ *  executor::authentication::deauthenticate function
+ *  brief      Send deAuthenticate 29 00, ECU returns to the unauthenticated state
+ *  details    -
+ *  \param[in]  stream: point to Diag object
+ *              timeout: timeout in milliseconds
+ *  \param[out] -
+ *  \precondition -
+ *  \reentrant:  FALSE
+ *  \return     error code if any
+ ****************************************************************************************************************/
+pub fn deauthenticate(stream: &mut transport::diag::Diag, timeout: u64) -> Result<(), io::Error> {
+    let response = request(stream, vec![0x29, DEAUTHENTICATE], timeout)?;
+    let mut fields = utils::common::ByteReader::new(&response[2..]);
+    check_return_value(fields.take(1), DEAUTHENTICATION_SUCCESSFUL, "deAuthenticate")
+}
+
+
+/*****************************************************************************************************************
+ *  executor::authentication::verify_certificate function
+ *  brief      Send verifyCertificateUnidirectional 29 01 or verifyCertificateBidirectional 29 02
+ *  details    Request carries communicationConfiguration, client certificate and client challenge with 2 bytes
+ *             lengths. ECU certificate and proof of ownership are returned by bidirectional only, they are not
+ *             verified here.
+ *  \param[in]  stream: point to Diag object
+ *              is_bidirectional: send 29 02 instead of 29 01
+ *              communication_configuration: communicationConfiguration byte
+ *              certificate: DER tester certificate
+ *              challenge: tester challenge, may be empty
+ *              timeout: timeout in milliseconds
+ *  \param[out] -
+ *  \precondition -
+ *  \reentrant:  FALSE
+ *  \return     parsed response, error if ECU did not accept the certificate
+ ****************************************************************************************************************/
+pub fn verify_certificate(stream: &mut transport::diag::Diag, is_bidirectional: bool, communication_configuration: u8,
+                          certificate: &[u8], challenge: &[u8], timeout: u64) -> Result<CertificateVerification, io::Error> {
+    let sub_function = if is_bidirectional { VERIFY_CERTIFICATE_BIDIRECTIONAL } else { VERIFY_CERTIFICATE_UNIDIRECTIONAL };
+    let mut byte_vector = vec![0x29, sub_function, communication_configuration];
+    push_length_prefixed(&mut byte_vector, certificate, "certificate")?;
+    push_length_prefixed(&mut byte_vector, challenge, "challenge")?;
+    let response = request(stream, byte_vector, timeout)?;
+
+    let mut fields = utils::common::ByteReader::new(&response[2..]);
+    check_return_value(fields.take(1), OWNERSHIP_VERIFICATION_NECESSARY, "verifyCertificate")?;
+    let mut verification = CertificateVerification {
+        challenge_server: fields.length_prefixed(2).ok_or_else(|| malformed(&response))?.to_vec(),
+        ..Default::default()
+    };
+    if is_bidirectional {
+        verification.certificate_server = fields.length_prefixed(2).ok_or_else(|| malformed(&response))?.to_vec();
+        verification.proof_of_ownership_server = fields.length_prefixed(2).ok_or_else(|| malformed(&response))?.to_vec();
+    }
+    verification.ephemeral_public_key_server = fields.length_prefixed(2).ok_or_else(|| malformed(&response))?.to_vec();
+    if !fields.data.is_empty() {
+        return Err(malformed(&response));
+    }
+    Ok(verification)
+}
+
+
+/*****************************************************************************************************************
+ *  executor::authentication::proof_of_ownership function
+ *  brief      Send proofOfOwnership 29 03 with the tester proof and optional ephemeral public key
+ *  details    -
+ *  \param[in]  stream: point to Diag object
+ *              proof: signature over the ECU challenge
+ *              ephemeral_public_key: tester ephemeral public key, may be empty
+ *              timeout: timeout in milliseconds
+ *  \param[out] -
+ *  \precondition -
+ *  \reentrant:  FALSE
+ *  \return     sessionKeyInfo of ECU, error if ownership was not verified
+ ****************************************************************************************************************/
+pub fn proof_of_ownership(stream: &mut transport::diag::Diag, proof: &[u8], ephemeral_public_key: &[u8],
+                          timeout: u64) -> Result<Vec<u8>, io::Error> {
+    let mut byte_vector = vec![0x29, PROOF_OF_OWNERSHIP];
+    push_length_prefixed(&mut byte_vector, proof, "proof of ownership")?;
+    push_length_prefixed(&mut byte_vector, ephemeral_public_key, "ephemeral public key")?;
+    let response = request(stream, byte_vector, timeout)?;
+
+    let mut fields = utils::common::ByteReader::new(&response[2..]);
+    check_return_value(fields.take(1), OWNERSHIP_VERIFIED, "proofOfOwnership")?;
+    let session_key_info = fields.length_prefixed(2).unwrap_or_default().to_vec(); // optional in short responses
+    if !fields.data.is_empty() {
+        return Err(malformed(&response));
+    }
+    Ok(session_key_info)
+}
+
+
+/*****************************************************************************************************************
+ *  executor::authentication::transmit_certificate function
+ *  brief      Send transmitCertificate 29 04 so ECU evaluates a certificate, for example to add rights
+ *  details    -
+ *  \param[in]  stream: point to Diag object
+ *              evaluation_id: certificateEvaluationId
+ *              certificate: DER certificate
+ *              timeout: timeout in milliseconds
+ *  \param[out] -
+ *  \precondition -
+ *  \reentrant:  FALSE
+ *  \return     error code if ECU did not verify the certificate
+ ****************************************************************************************************************/
+pub fn transmit_certificate(stream: &mut transport::diag::Diag, evaluation_id: u16, certificate: &[u8],
+                            timeout: u64) -> Result<(), io::Error> {
+    let mut byte_vector = vec![0x29, TRANSMIT_CERTIFICATE];
+    byte_vector.extend_from_slice(&evaluation_id.to_be_bytes());
+    push_length_prefixed(&mut byte_vector, certificate, "certificate")?;
+    let response = request(stream, byte_vector, timeout)?;
+    let mut fields = utils::common::ByteReader::new(&response[2..]);
+    check_return_value(fields.take(1), CERTIFICATE_VERIFIED, "transmitCertificate")
+}
+
+
+/*****************************************************************************************************************
+ *  executor::authentication::load_certificate function
+ *  brief      Read an X.509 certificate file
+ *  details    PEM or DER, the first certificate of a PEM chain is used
+ *  \param[in]  path: certificate file
+ *  \param[out] -
+ *  \precondition -
+ *  \reentrant:  TRUE
+ *  \return     parsed certificate and its DER encoding
+ ****************************************************************************************************************/
+pub fn load_certificate(path: &str) -> Result<(Certificate, Vec<u8>), io::Error> {
+    let certificate = load_certificates(path)?.into_iter().next()
+        .ok_or_else(|| Error::new(ErrorKind::InvalidData, format!("{} contains no certificate", path)))?;
+    let der = certificate.to_der()
+        .map_err(|err| Error::new(ErrorKind::InvalidData, format!("{}: {}", path, err)))?;
+    Ok((certificate, der))
+}
+
+pub fn load_trust_anchors(path: &str) -> Result<Vec<Certificate>, io::Error> {
+    let anchors = load_certificates(path)?;
+    if anchors.is_empty() {
+        return Err(Error::new(ErrorKind::InvalidData, format!("{} contains no trust anchor", path)));
+    }
+    Ok(anchors)
+}
+
+fn load_certificates(path: &str) -> Result<Vec<Certificate>, io::Error> {
+    let contents = fs::read(path)?;
+    let certificates = if contents.starts_with(b"-----BEGIN") || String::from_utf8_lossy(&contents).contains("-----BEGIN CERTIFICATE") {
+        Certificate::load_pem_chain(&contents).map_err(|err| err.to_string())
+    } else {
+        Certificate::from_der(&contents).map(|certificate| vec![certificate]).map_err(|err| err.to_string())
+    };
+    certificates.map_err(|err| Error::new(ErrorKind::InvalidData, format!("{} is not an X.509 certificate: {}", path, err)))
+}
+
+
+/*****************************************************************************************************************
+ *  executor::authentication::verify_chain function
+ *  brief      Verify a certificate against trust anchors
+ *  details    Certificate must be valid now and be signed by an anchor whose subject is its issuer, or be one of the
+ *             anchors. Signatures ecdsa-with-SHA256/SHA512 and sha256/sha512WithRSAEncryption are supported.
+ *  \param[in]  certificate: certificate to check
+ *              anchors: trusted certificates
+ *  \param[out] -
+ *  \precondition -
+ *  \reentrant:  TRUE
+ *  \return     DER SubjectPublicKeyInfo of certificate, PermissionDenied error if not trusted
+ ****************************************************************************************************************/
+pub fn verify_chain(certificate: &Certificate, anchors: &[Certificate]) -> Result<Vec<u8>, io::Error> {
+    let tbs = &certificate.tbs_certificate;
+    let encode_error = |err: x509_cert::der::Error| Error::new(ErrorKind::InvalidData, format!("certificate encoding: {}", err));
+    let public_key = tbs.subject_public_key_info.to_der().map_err(encode_error)?;
+    check_validity(certificate)?;
+    if anchors.contains(certificate) {
+        return Ok(public_key);
+    }
+
+    let algorithm = certificate.signature_algorithm.oid.to_string();
+    let scheme = match algorithm.as_str() {
+        OID_ECDSA_WITH_SHA256 => SignatureScheme::EcdsaSha256,
+        OID_ECDSA_WITH_SHA512 => SignatureScheme::EcdsaSha512,
+        OID_SHA256_WITH_RSA => SignatureScheme::RsaPkcs1Sha256,
+        OID_SHA512_WITH_RSA => SignatureScheme::RsaPkcs1Sha512,
+        _ => return Err(Error::new(ErrorKind::InvalidData, format!("certificate signature algorithm {} is not supported", algorithm))),
+    };
+    let signed_data = tbs.to_der().map_err(encode_error)?;
+    let signature = certificate.signature.raw_bytes();
+    for anchor in anchors.iter().filter(|anchor| anchor.tbs_certificate.subject == tbs.issuer) {
+        let anchor_key = PublicKey::from_der(&anchor.tbs_certificate.subject_public_key_info.to_der().map_err(encode_error)?);
+        if anchor_key.is_ok_and(|anchor_key| excrypto::verify_signature(&anchor_key, scheme, &signed_data, signature).is_ok()) {
+            check_validity(anchor)?;
+            return Ok(public_key);
+        }
+    }
+    Err(Error::new(ErrorKind::PermissionDenied, format!("certificate {} is not issued by a trust anchor", tbs.subject)))
+}
+
+fn check_validity(certificate: &Certificate) -> Result<(), io::Error> {
+    let validity = &certificate.tbs_certificate.validity;
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+    if now < validity.not_before.to_unix_duration() || now > validity.not_after.to_unix_duration() {
+        return Err(Error::new(ErrorKind::PermissionDenied, format!("certificate {} is not valid now ({} - {})",
+            certificate.tbs_certificate.subject, validity.not_before, validity.not_after)));
+    }
+    Ok(())
+}
+
+
+fn request(stream: &mut transport::diag::Diag, byte_vector: Vec<u8>, timeout: u64) -> Result<Vec<u8>, io::Error> {
+    let sub_function = byte_vector[1];
+    stream.send_diag(byte_vector)?;
+    let response = stream.receive_diag(timeout)?;
+    debug!("Sent Authentication {:02X}, Receive {:02X?}", sub_function, response);
+    if response.len() < 2 || response[0] != 0x69 || response[1] != sub_function {
+        return Err(Error::new(ErrorKind::PermissionDenied,
+            format!("authentication 0x{:02X} failed: {}", sub_function, utils::common::describe_response(&response))));
+    }
+    Ok(response)
+}
+
+fn push_length_prefixed(byte_vector: &mut Vec<u8>, field: &[u8], name: &str) -> Result<(), io::Error> {
+    let length = u16::try_from(field.len())
+        .map_err(|_| Error::new(ErrorKind::InvalidInput, format!("{} is longer than 65535 bytes", name)))?;
+    byte_vector.extend_from_slice(&length.to_be_bytes());
+    byte_vector.extend_from_slice(field);
+    Ok(())
+}
+
+fn check_return_value(return_value: Option<&[u8]>, expected: u8, name: &str) -> Result<(), io::Error> {
+    match return_value {
+        Some([value]) if *value == expected => Ok(()),
+        Some([value]) => Err(Error::new(ErrorKind::PermissionDenied,
+            format!("{} returned {} (0x{:02X}), expected {}", name, return_value_name(*value), value, return_value_name(expected)))),
+        _ => Err(Error::new(ErrorKind::InvalidData, format!("{} response has no authenticationReturnParameter", name))),
+    }
+}
+
+fn malformed(response: &[u8]) -> Error {
+    Error::new(ErrorKind::InvalidData, format!("authentication response {:02X?} is not expected", response))
+}
+
+pub fn return_value_name(value: u8) -> &'static str {
+    match value {
+        0x00 => "RequestAccepted",
+        0x01 => "GeneralReject",
+        0x02 => "AuthenticationConfiguration APCE",
+        0x03 => "AuthenticationConfiguration ACR with asymmetric cryptography",
+        0x04 => "AuthenticationConfiguration ACR with symmetric cryptography",
+        0x10 => "DeAuthentication successful",
+        0x11 => "CertificateVerified, OwnershipVerificationNecessary",
+        0x12 => "OwnershipVerified, AuthenticationComplete",
+        0x13 => "CertificateVerified",
+        _ => "vehicle manufacturer specific",
+    }
+}
@@ -0,0 +1,138 @@
+use std::sync::{Arc, RwLock};
+
+/*****************************************************************************************************************
+ *  Define all gloval macro & variable here
+ ****************************************************************************************************************/
+lazy_static::lazy_static! {
+    static ref COMPRESSORS: RwLock<Vec<Arc<dyn Compressor>>> = RwLock::new(Vec::new()); // filled by register, lzss in main
+}
+
+const LZSS_WINDOW_SIZE: usize = 4096; // ring buffer size, 12 bits position
+const LZSS_MAX_MATCH: usize = 18;     // 4 bits length
+const LZSS_THRESHOLD: usize = 2;      // matches up to this length are sent as literals
+const LZSS_HASH_SIZE: usize = 1 << 14;
+const LZSS_MAX_CHAIN: usize = 64;     // candidates checked per position, trades ratio for speed
+
+/*****************************************************************************************************************
+ *  Compression of raw images before TransferData. method is the compressionMethod, high nibble of
+ *  dataFormatIdentifier, the ECU has to support the same method.
+ ****************************************************************************************************************/
+pub trait Compressor: Send + Sync {
+    fn name(&self) -> &str;
+    fn method(&self) -> u8;
+    fn compress(&self, data: &[u8]) -> Vec<u8>;
+}
+
+/*****************************************************************************************************************
+ *  executor::compression::register function
+ *  brief      Register a compressor usable by "compression" parameter of swdl steps
+ *  details    A compressor with the same name replaces the registered one
+ *  \param[in]  compressor: compressor implementation
+ *  \param[out] -
+ *  \precondition -
+ *  \reentrant:  TRUE
+ *  \return -
+ ****************************************************************************************************************/
+pub fn register(compressor: Arc<dyn Compressor>) {
+    let mut compressors = COMPRESSORS.write().unwrap();
+    compressors.retain(|registered| !registered.name().eq_ignore_ascii_case(compressor.name()));
+    compressors.push(compressor);
+}
+
+/* Compressor usable by "compression" parameter of swdl steps, name is not case sensitive */
+pub fn find(name: &str) -> Option<Arc<dyn Compressor>> {
+    COMPRESSORS.read().unwrap().iter().find(|compressor| compressor.name().eq_ignore_ascii_case(name)).cloned()
+}
+
+
+/*****************************************************************************************************************
+ *  LZSS compressor, compressionMethod 1. Output is the classic Okumura format: a flag byte announces 8 items,
+ *  bit set is a literal byte, bit cleared is a 2 bytes reference to a ring buffer of 4096 bytes which is
+ *  initialized with spaces and written from position 4096-18.
+ ****************************************************************************************************************/
+pub struct Lzss;
+
+impl Lzss {
+fn hash(data: &[u8]) -> usize {
+    (((data[0] as usize) << 6) ^ ((data[1] as usize) << 3) ^ data[2] as usize) & (LZSS_HASH_SIZE - 1)
+}
+}
+
+impl Compressor for Lzss {
+fn name(&self) -> &str {
+    "lzss"
+}
+
+fn method(&self) -> u8 {
+    0x1
+}
+
+/*****************************************************************************************************************
+ *  executor::compression::Lzss::compress function
+ *  brief      Compress data with LZSS
+ *  details    Longest match is searched with hash chains over the last 4078 bytes of input, older bytes of
+ *             ring buffer may be overwritten while the decoder copies a match
+ *  \param[in]  data: uncompressed data
+ *  \param[out] -
+ *  \precondition -
+ *  \reentrant:  TRUE
+ *  \return     compressed data
+ ****************************************************************************************************************/
+fn compress(&self, data: &[u8]) -> Vec<u8> {
+    let mut output: Vec<u8> = Vec::with_capacity(data.len() / 2 + 16);
+    let mut head: Vec<usize> = vec![usize::MAX; LZSS_HASH_SIZE];
+    let mut previous: Vec<usize> = vec![usize::MAX; data.len()];
+    let mut flag_index = 0;
+    let mut flag_bit = 8;
+    let mut position = 0;
+
+    while position < data.len() {
+        if flag_bit == 8 {
+            flag_index = output.len();
+            output.push(0);
+            flag_bit = 0;
+        }
+        let max_length = std::cmp::min(LZSS_MAX_MATCH, data.len() - position);
+        let (mut match_length, mut match_position) = (0, 0);
+        if max_length > LZSS_THRESHOLD {
+            let mut candidate = head[Lzss::hash(&data[position..])];
+            let mut chain = 0;
+            while candidate != usize::MAX && position - candidate <= LZSS_WINDOW_SIZE - LZSS_MAX_MATCH && chain < LZSS_MAX_CHAIN {
+                let length = data[candidate..].iter().zip(data[position..position + max_length].iter())
+                    .take_while(|(a, b)| a == b).count();
+                if length > match_length {
+                    match_length = length;
+                    match_position = candidate;
+                    if length == max_length {
+                        break;
+                    }
+                }
+                candidate = previous[candidate];
+                chain += 1;
+            }
+        }
+
+        let step = if match_length > LZSS_THRESHOLD {
+            let ring_position = (match_position + LZSS_WINDOW_SIZE - LZSS_MAX_MATCH) % LZSS_WINDOW_SIZE;
+            output.push(ring_position as u8);
+            output.push((((ring_position >> 4) & 0xF0) | (match_length - LZSS_THRESHOLD - 1)) as u8);
+            match_length
+        } else {
+            output[flag_index] |= 1 << flag_bit;
+            output.push(data[position]);
+            1
+        };
+        flag_bit += 1;
+
+        for index in position..position + step {
+            if index + LZSS_THRESHOLD < data.len() {
+                let hash = Lzss::hash(&data[index..]);
+                previous[index] = head[hash];
+                head[hash] = index;
+            }
+        }
+        position += step;
+    }
+    output
+}
+}
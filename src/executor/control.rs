@@ -0,0 +1,193 @@
+use log::debug;
+use std::io::{self, Error, ErrorKind};
+use std::sync::{Condvar, Mutex};
+use std::thread;
+use std::time::Duration;
+
+/*****************************************************************************************************************
+ *  Define all gloval macro & variable here
+ ****************************************************************************************************************/
+lazy_static::lazy_static! {
+    pub static ref SEQUENCE_CONTROL: SequenceControl = SequenceControl::new();
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RunState {
+    Idle,
+    Running,
+    Paused,
+    Aborted,
+    Cleanup, // cleanup items of an aborted sequence are running, they are not interrupted
+}
+
+pub struct SequenceControl {
+    state: Mutex<RunState>,
+    cvar: Condvar,
+}
+
+impl SequenceControl {
+
+pub fn new() -> Self {
+    SequenceControl { state: Mutex::new(RunState::Idle), cvar: Condvar::new() }
+}
+
+/*****************************************************************************************************************
+ *  executor::control::start function
+ *  brief      Mark a sequence as running, clear previous pause/abort request
+ *  details    -
+ *  \param[in]  -
+ *  \param[out] -
+ *  \precondition -
+ *  \reentrant:  TRUE
+ *  \return -
+ ****************************************************************************************************************/
+pub fn start(&self) {
+    *self.state.lock().unwrap() = RunState::Running;
+    self.cvar.notify_all();
+}
+
+/*****************************************************************************************************************
+ *  executor::control::finish function
+ *  brief      Mark that no sequence is running anymore
+ *  details    -
+ *  \param[in]  -
+ *  \param[out] -
+ *  \precondition -
+ *  \reentrant:  TRUE
+ *  \return -
+ ****************************************************************************************************************/
+pub fn finish(&self) {
+    *self.state.lock().unwrap() = RunState::Idle;
+    self.cvar.notify_all();
+}
+
+/*****************************************************************************************************************
+ *  executor::control::start_cleanup function
+ *  brief      Mark that cleanup items of an aborted sequence are running
+ *  details    Cleanup is not aborted itself: delays are waited for and downloads are not stopped. A new abort
+ *             request is refused until finish
+ *  \param[in]  -
+ *  \param[out] -
+ *  \precondition -
+ *  \reentrant:  TRUE
+ *  \return -
+ ****************************************************************************************************************/
+pub fn start_cleanup(&self) {
+    *self.state.lock().unwrap() = RunState::Cleanup;
+    self.cvar.notify_all();
+}
+
+/*****************************************************************************************************************
+ *  executor::control::pause function
+ *  brief      Request running sequence to pause before its next item
+ *  details    -
+ *  \param[in]  -
+ *  \param[out] -
+ *  \precondition -
+ *  \reentrant:  TRUE
+ *  \return     true if a running sequence will pause
+ ****************************************************************************************************************/
+pub fn pause(&self) -> bool {
+    let mut state = self.state.lock().unwrap();
+    if *state == RunState::Running {
+        *state = RunState::Paused;
+        debug!("Sequence pause requested");
+        return true;
+    }
+    false
+}
+
+/*****************************************************************************************************************
+ *  executor::control::resume function
+ *  brief      Resume a paused sequence
+ *  details    -
+ *  \param[in]  -
+ *  \param[out] -
+ *  \precondition -
+ *  \reentrant:  TRUE
+ *  \return     true if a paused sequence was resumed
+ ****************************************************************************************************************/
+pub fn resume(&self) -> bool {
+    let mut state = self.state.lock().unwrap();
+    if *state == RunState::Paused {
+        *state = RunState::Running;
+        self.cvar.notify_all();
+        debug!("Sequence resumed");
+        return true;
+    }
+    false
+}
+
+/*****************************************************************************************************************
+ *  executor::control::abort function
+ *  brief      Request running or paused sequence to abort
+ *  details    Sequence stops at its next check point (between items, between transfer blocks) and runs cleanup
+ *  \param[in]  -
+ *  \param[out] -
+ *  \precondition -
+ *  \reentrant:  TRUE
+ *  \return     true if a sequence was running
+ ****************************************************************************************************************/
+pub fn abort(&self) -> bool {
+    let mut state = self.state.lock().unwrap();
+    match *state {
+        RunState::Running | RunState::Paused => {
+            *state = RunState::Aborted;
+            self.cvar.notify_all();
+            debug!("Sequence abort requested");
+            true
+        }
+        _ => false,
+    }
+}
+
+pub fn is_aborted(&self) -> bool {
+    *self.state.lock().unwrap() == RunState::Aborted
+}
+
+pub fn is_idle(&self) -> bool {
+    *self.state.lock().unwrap() == RunState::Idle
+}
+
+/*****************************************************************************************************************
+ *  executor::control::delay function
+ *  brief      Sleep for delay steps
+ *  details    Sleeps in slices so an aborted sequence does not wait for the whole delay
+ *  \param[in]  milliseconds  delay time
+ *  \param[out] -
+ *  \precondition -
+ *  \reentrant:  TRUE
+ *  \return     error kind Interrupted if sequence is aborted
+ ****************************************************************************************************************/
+pub fn delay(&self, milliseconds: u64) -> Result<(), io::Error> {
+    let mut remaining = milliseconds;
+    while remaining > 0 {
+        if self.is_aborted() {
+            return Err(Error::new(ErrorKind::Interrupted, "sequence aborted"));
+        }
+        let slice = std::cmp::min(remaining, 100);
+        thread::sleep(Duration::from_millis(slice));
+        remaining -= slice;
+    }
+    Ok(())
+}
+
+/*****************************************************************************************************************
+ *  executor::control::wait_if_paused function
+ *  brief      Block caller while sequence is paused
+ *  details    -
+ *  \param[in]  -
+ *  \param[out] -
+ *  \precondition -
+ *  \reentrant:  TRUE
+ *  \return     state after waiting, Running or Aborted
+ ****************************************************************************************************************/
+pub fn wait_if_paused(&self) -> RunState {
+    let mut state = self.state.lock().unwrap();
+    while *state == RunState::Paused {
+        state = self.cvar.wait(state).unwrap();
+    }
+    *state
+}
+
+}
@@ -4,17 +4,26 @@ use std::sync::{Arc, Mutex};
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::time::Duration;
 use std::thread;
-use serde_json::Value;
 
 use crate::utils;
 use crate::transport;
-use crate::executor::parameters::SequenceItem;
+use crate::executor::parameters::{DoipAction, SequenceItem, SocketAction, Step};
 use crate::executor::securityaccess;
+use crate::executor::secured;
 use crate::executor::swdl;
+use crate::executor::upload;
+use crate::executor::filetransfer;
+use crate::executor::package;
+use crate::executor::authentication;
+use crate::executor::link::LinkState;
+use crate::executor::vendor;
+use crate::executor::control::SEQUENCE_CONTROL;
 
 pub struct Executor {
     s_diag_obj: Arc<Mutex<transport::diag::Diag>>,
     is_enable_tester_present: AtomicBool,
+    s_last_trace: Vec<transport::diag::DiagExchange>,
+    s_link_state: LinkState,
 }
 
 
@@ -40,289 +49,174 @@ pub fn execute_cmd(this: Arc<Mutex<Executor>>, item: SequenceItem, vendor: &str)
     }
 
     //let clone_self_obj = this.clone();
-    let self_obj_lock = this.lock().unwrap();
-    let mut stream = self_obj_lock.s_diag_obj.lock().unwrap();
-    match item.name.as_str() {
-        "socket" => {
-            match &item.action {
-                Value::String(single_action_str) => {
-                    match single_action_str.as_str() {
-                        "connect" => {
-                            match stream.connect() {
-                                Ok(()) => debug!("Connected successfully!"),
-                                Err(err) => {
-                                    eprintln!("Failed to connect: {}", err);
-                                    return Err(err);
-                                }
-                            }
-                        }
-                        "disconnect" => {
-                            match stream.disconnect() {
-                                Ok(()) => debug!("Disconnected successfully!"),
-                                Err(err) => {
-                                    eprintln!("Failed to disconnect: {}", err);
-                                    return Err(err);
-                                }
-                            }
-                        }
-                        _ => eprintln!("Invalid single action format: {}", single_action_str),
-                    }
-                }
-                Value::Array(multiple_actions) => {
-                    debug!("Not allow Multiple Actions in socket object:");
-                    for action in multiple_actions {
-                        if let Value::String(action_string) = action {
-                            println!("{}", action_string);
-                        }
-                    }
+    let mut self_obj_lock = this.lock().unwrap();
+    if let Step::Delay { .. } = item.step {
+        //unlock objects
+        drop(self_obj_lock);
+        return SEQUENCE_CONTROL.delay(timeout);
+    }
+
+    // Record diag exchanges of this command, tester-present cannot interleave while executor is locked
+    let diag_obj = Arc::clone(&self_obj_lock.s_diag_obj);
+    let mut stream = diag_obj.lock().unwrap();
+    stream.start_trace();
+    let result = Executor::execute_item(&mut stream, &item, &self_obj_lock.s_link_state, vendor, timeout);
+    self_obj_lock.s_last_trace = stream.stop_trace();
+    if result.is_ok() {
+        self_obj_lock.s_link_state.record(&item.step, vendor);
+    }
+    result
+}
+
+
+/*****************************************************************************************************************
+ *  executor::executor::execute_item function
+ *  brief      Function to execute one sequence item on locked diag object
+ *  details    -
+ *  \param[in]  stream: point to Diag object
+ *              item: refer to SequenceItem
+ *              link: diagnostic state reached by previous steps, restored by swdl after communication loss
+ *              vendor: vendor name in config
+ *              timeout: timeout per request in milliseconds
+ *  \param[out] -
+ *  \precondition -
+ *  \reentrant:  FALSE
+ *  \return     error code if any
+ ****************************************************************************************************************/
+pub fn execute_item(stream: &mut transport::diag::Diag, item: &SequenceItem, link: &LinkState, vendor: &str,
+                timeout: u64) -> Result<(), io::Error> {
+    match &item.step {
+        Step::Socket { action: SocketAction::Connect } => {
+            match stream.connect() {
+                Ok(()) => debug!("Connected successfully!"),
+                Err(err) => {
+                    eprintln!("Failed to connect: {}", err);
+                    return Err(err);
                 }
-                _ => {
-                    debug!("socket Invalid action format");
+            }
+        }
+        Step::Socket { action: SocketAction::Disconnect } => {
+            match stream.disconnect() {
+                Ok(()) => debug!("Disconnected successfully!"),
+                Err(err) => {
+                    eprintln!("Failed to disconnect: {}", err);
+                    return Err(err);
                 }
             }
         }
-        "send_doip" => {
-            match &item.action {
-                Value::String(single_action_str) => {
-                    match single_action_str.as_str() {
-                        "activation" => {
-                            match stream.send_doip_routing_activation() {
-                                Ok(()) => debug!("Send doip successfully!"),
-                                Err(err) => {
-                                    eprintln!("Failed to send doip activation: {}", err);
-                                    return Err(err);
-                                }
-                            }
-                            match stream.receive_doip(timeout) {
-                                Ok(Some(data)) => debug!("Receive doip data {:02X?} successfully!", data),
-                                Ok(None) => {
-                                    debug!("Doip activation successfully!");
-                                }
-                                Err(err) => {
-                                    eprintln!("Failed to Receive doip activation: {}", err);
-                                    return Err(err);
-                                }
-                            }
-                        }
-                        _ => eprintln!("Invalid single action format: {}", single_action_str),
-                    }
+        Step::SendDoip { action: DoipAction::Activation, .. } => {
+            match stream.send_doip_routing_activation() {
+                Ok(()) => debug!("Send doip successfully!"),
+                Err(err) => {
+                    eprintln!("Failed to send doip activation: {}", err);
+                    return Err(err);
                 }
-                Value::Array(multiple_actions) => {
-                    // Handle Value::Array case
-                    let mut action_vecs: Vec<Vec<u8>> = Vec::new();
-                    for action_str in multiple_actions.iter() {
-                        if let Some(action) = action_str.as_str() {
-                            let trimmed_value = action.replace(" ", "");
-                            let parsed_action: Vec<u8> = trimmed_value
-                                .chars()
-                                .collect::<Vec<char>>()
-                                .chunks(2)
-                                .map(|chunk| {
-                                    let hex_str: String = chunk.iter().collect();
-                                    u8::from_str_radix(&hex_str, 16).unwrap_or(0)
-                                })
-                                .collect();
-                            action_vecs.push(parsed_action);
-                        }
-                    }
-                    // Now 'action_vecs' contains the parsed Vec<u8> for multiple actions
-                    for (i, action) in action_vecs.iter().enumerate() {
-                        let hex_action: Vec<String> = action.iter().map(|&x| format!("0x{:02X}", x)).collect();
-                        let u8_action = utils::common::vec_hex_strings_to_u8(&hex_action);
-                        let clone_u8_action = u8_action.clone();
-                        match stream.send_doip_raw(u8_action) {
-                            Ok(()) => {}
-                            Err(err) => {
-                                eprintln!("Failed to send diag data: {}", err);
-                                return Err(err);
-                            }
-                        }
-                        match stream.receive_doip(timeout) {
-                            //Ok(Some(data)) => debug!("Receive doip data {:02X?} successfully!", data),
-                            Ok(None) => {debug!("Doip received none packet!");}
-                            Ok(Some(data)) => {
-                                // Access the "expect" array
-                                if let Some(expect_array) = item.expect.as_array() {
-                                    if i < expect_array.len() {
-                                        // Access the "expect" value at the specified index
-                                        let expect_value = &expect_array[i];
-                                        // Check if the value is a string
-                                        if let Some(expect_str) = expect_value.as_str() {
-                                            debug!("{:?} ",  format!("Sent {:02X?}, Expect at index {}: {}, Received {:02X?}", clone_u8_action, i, expect_str, data));
-                                            if utils::common::compare_expect_value(expect_str, data) == false {
-                                                return Err(Error::new(ErrorKind::InvalidData, "Diag data received is not expected"));
-                                            }
-                                        } else {
-                                            eprintln!("Value at index {} is not a string.", i);
-                                            return Err(Error::new(ErrorKind::InvalidData, "wrong sequence json format"));
-                                        }
-                                    }
-                                }
-                            }
-                            Err(err) => {
-                                return Err(err);
-                            }
-                        }
-                    }
+            }
+            match stream.receive_doip(timeout) {
+                Ok(Some(data)) => debug!("Receive doip data {:02X?} successfully!", data),
+                Ok(None) => {
+                    debug!("Doip activation successfully!");
+                }
+                Err(err) => {
+                    eprintln!("Failed to Receive doip activation: {}", err);
+                    return Err(err);
                 }
-                _ => debug!("send_doip Invalid action format"),
             }
         }
-        "send_diag" => {
-            match &item.action {
-                Value::String(single_action_str) => {
-                    match single_action_str.as_str() {
-                        _ => debug!("Please use action&expect of send_diag format like this: [\"1001\"]")
+        Step::SendDoip { action: DoipAction::Raw(requests), expect } => {
+            for (i, action) in requests.iter().enumerate() {
+                match stream.send_doip_raw(action.0.clone()) {
+                    Ok(()) => {}
+                    Err(err) => {
+                        eprintln!("Failed to send diag data: {}", err);
+                        return Err(err);
                     }
                 }
-                Value::Array(multiple_actions) => {
-                    // Handle Value::Array case
-                    let mut action_vecs: Vec<Vec<u8>> = Vec::new();
-                    for action_str in multiple_actions.iter() {
-                        if let Some(action) = action_str.as_str() {
-                            // let trimmed_action = action.trim_matches(|c: char| {
-                            //     c == '{' || c == '}' || c == ' ' || c == '\n' || c == '\t' || c == '\r' || c == '\x0c' || c == ';'
-                            // });
-                            let trimmed_value = action.replace(" ", "");
-                            let parsed_action: Vec<u8> = trimmed_value
-                                .chars()
-                                .collect::<Vec<char>>()
-                                .chunks(2)
-                                .map(|chunk| {
-                                    let hex_str: String = chunk.iter().collect();
-                                    u8::from_str_radix(&hex_str, 16).unwrap_or(0)
-                                })
-                                .collect();
-                            action_vecs.push(parsed_action);
-                        }
-                    }
-                    // Now 'action_vecs' contains the parsed Vec<u8> for multiple actions
-                    for (i, action) in action_vecs.iter().enumerate() {
-                        let hex_action: Vec<String> = action.iter().map(|&x| format!("0x{:02X}", x)).collect();
-                        let u8_action = utils::common::vec_hex_strings_to_u8(&hex_action);
-                        let clone_u8_action = u8_action.clone();
-                        let mut sub_service_byte: u8 = 0;
-                        if u8_action.len() > 1 {
-                            sub_service_byte = u8_action[1];
-                        }
-                        let diag_len = u8_action.len();
-                        match stream.send_diag(u8_action) {
-                            Ok(()) => {}
-                            Err(err) => {
-                                eprintln!("Failed to send diag data: {}", err);
-                                return Err(err);
-                            }
-                        }
-                        //Check suppress reply bit
-                        if diag_len == 2 && (sub_service_byte & 0x80) == 0x80 {
-                            debug!("found suppress bit, ignore checking respond diag");
-                            //Ignore DoIP ACK
-                            match stream.receive_doip(timeout) {
-                                Ok(Some(_data)) => {}
-                                Ok(None) => {}
-                                Err(err) => eprintln!("Failed to Receive doip Ack: {}", err),
-                            }
-                            continue;
-                        }
-                        match stream.receive_diag(timeout) {
-                            Ok(data) => {
-                                // Access the "expect" array
-                                if let Some(expect_array) = item.expect.as_array() {
-                                    if i < expect_array.len() {
-                                        // Access the "expect" value at the specified index
-                                        let expect_value = &expect_array[i];
-                                        // Check if the value is a string
-                                        if let Some(expect_str) = expect_value.as_str() {
-                                            debug!("{:?} ",  format!("Sent {:02X?}, Expect at index {}: {}, Received {:02X?}", clone_u8_action, i, expect_str, data));
-                                            if utils::common::compare_expect_value(expect_str, data) == false {
-                                                return Err(Error::new(ErrorKind::InvalidData, "Diag data received is not expected"));
-                                            }
-                                        } else {
-                                            eprintln!("Value at index {} is not a string.", i);
-                                            return Err(Error::new(ErrorKind::InvalidData, "wrong sequence json format"));
-                                        }
-                                    }
-                                }
-                            }
-                            Err(err) => {
-                                return Err(err);
+                match stream.receive_doip(timeout) {
+                    Ok(None) => {debug!("Doip received none packet!");}
+                    Ok(Some(data)) => {
+                        if let Some(expect_pattern) = expect.get(i) {
+                            debug!("{:?} ",  format!("Sent {:02X?}, Expect at index {}: {}, Received {:02X?}", action.0, i, expect_pattern.as_str(), data));
+                            if !utils::common::compare_expect_value(expect_pattern.as_str(), data) {
+                                return Err(Error::new(ErrorKind::InvalidData, "Diag data received is not expected"));
                             }
                         }
                     }
-                }
-                _ => {
-                    eprintln!("send_diag Invalid action format");
-                    return Err(Error::new(ErrorKind::InvalidData, "wrong sequence json format"));
+                    Err(err) => {
+                        return Err(err);
+                    }
                 }
             }
         }
-        s if s.starts_with("securityaccess_") => {
-            // Extract the number after "securityaccess_"
-            if let Ok(level) = u8::from_str_radix(&s[15..], 16) {
-                if vendor == "volvo" {
-                    match securityaccess::security_access_volvo(stream, item, level, timeout) {
-                        Ok(()) => {debug!("Security Access level {} successful", level);}
-                        Err(err) => {
-                            eprintln!("Failed to send diag Secure access: {}", err);
-                            return Err(err);
+        Step::SendDiag { requests, expect, secured } => {
+            for (i, action) in requests.iter().enumerate() {
+                if secured.is_secured(i) {
+                    let data = secured::exchange(stream, &action.0, timeout).map_err(|err| {
+                        eprintln!("Failed to send secured diag data: {}", err);
+                        err
+                    })?;
+                    if let Some(expect_pattern) = expect.get(i) {
+                        debug!("Sent secured {:02X?}, Expect at index {}: {}, Received {:02X?}", action.0, i, expect_pattern.as_str(), data);
+                        if !utils::common::compare_expect_value(expect_pattern.as_str(), data) {
+                            return Err(Error::new(ErrorKind::InvalidData, "Diag data received is not expected"));
                         }
                     }
+                    continue;
                 }
-                else {
-                    return Err(Error::new(ErrorKind::InvalidInput, "Not support this vendor"));
+                let u8_action = action.0.clone();
+                let mut sub_service_byte: u8 = 0;
+                if u8_action.len() > 1 {
+                    sub_service_byte = u8_action[1];
                 }
-            } else {
-                eprintln!("Invalid security name format: {}", s);
-                return Err(Error::new(ErrorKind::InvalidInput, "Invalid security name format"));
-            }
-        }
-        "swdl" => {
-            let mut sw_file_path = "";
-            let mut format = "";
-            match &item.action {
-                Value::Array(multiple_actions) => {
-                    //get swdl parameters in action item
-                    for action_str in multiple_actions.iter() {
-                        if let Some(action) = action_str.as_str() {
-                            let parts: Vec<&str> = action.splitn(2, ':').collect();
-                            if parts.len() == 2 {
-                                match parts[0] {
-                                    "path" => sw_file_path = parts[1],
-                                    "format" => format = parts[1],
-                                    _ => (),
-                                }
-                            }
-                            else {
-                                eprintln!("SWDL: parameter number is not match 2");
-                                return Err(Error::new(ErrorKind::InvalidData, "wrong sequence json format"));
+                let diag_len = u8_action.len();
+                match stream.send_diag(u8_action) {
+                    Ok(()) => {}
+                    Err(err) => {
+                        eprintln!("Failed to send diag data: {}", err);
+                        return Err(err);
+                    }
+                }
+                //Check suppress reply bit
+                if diag_len == 2 && (sub_service_byte & 0x80) == 0x80 {
+                    debug!("found suppress bit, ignore checking respond diag");
+                    //Ignore DoIP ACK
+                    match stream.receive_doip(timeout) {
+                        Ok(Some(_data)) => {}
+                        Ok(None) => {}
+                        Err(err) => eprintln!("Failed to Receive doip Ack: {}", err),
+                    }
+                    continue;
+                }
+                match stream.receive_diag(timeout) {
+                    Ok(data) => {
+                        if let Some(expect_pattern) = expect.get(i) {
+                            debug!("{:?} ",  format!("Sent {:02X?}, Expect at index {}: {}, Received {:02X?}", action.0, i, expect_pattern.as_str(), data));
+                            if !utils::common::compare_expect_value(expect_pattern.as_str(), data) {
+                                return Err(Error::new(ErrorKind::InvalidData, "Diag data received is not expected"));
                             }
                         }
                     }
-                }
-                _ => {
-                    eprintln!("SWDL: Not enough parameters");
-                    return Err(Error::new(ErrorKind::InvalidData, "wrong sequence json format"));
+                    Err(err) => {
+                        return Err(err);
+                    }
                 }
             }
-            if format == "vbf" {
-                match swdl::parse_vbf(stream, sw_file_path.to_string(), 4093, timeout) {
-                    Ok(()) => {}
-                    Err(err) => return Err(err)
+        }
+        Step::SecurityAccess { level, params, expect } => {
+            match securityaccess::security_access(stream, params, expect, *level, vendor, timeout) {
+                Ok(()) => {debug!("Security Access level {} successful", level);}
+                Err(err) => {
+                    eprintln!("Failed to send diag Secure access: {}", err);
+                    return Err(err);
                 }
             }
-            else {
-                eprintln!("SWDL: file format not support");
-                return Err(Error::new(ErrorKind::InvalidData, "software file format not support"));
-            }
-        }
-        "delay" => {
-            //unlock objects
-            drop(stream);
-            drop(self_obj_lock);
-            let duration = Duration::from_millis(timeout);
-            thread::sleep(duration);
         }
-        _ => println!("This action name is not supported"),
+        Step::Swdl { params } => swdl::download_image(stream, params, link, vendor::find(vendor)?.as_ref(), timeout)?,
+        Step::Upload { params } => upload::upload_memory(stream, params, timeout)?,
+        Step::FileTransfer { params } => filetransfer::transfer_file(stream, params, timeout)?,
+        Step::FlashPackage { params } => package::flash_package(stream, params, link, vendor, timeout)?,
+        Step::Authentication { params } => authentication::authenticate(stream, params, timeout)?,
+        Step::Delay { .. } => {} // handled by execute_cmd without locking diag object
     }
     Ok(())
 }
@@ -332,18 +226,15 @@ pub fn start_tester_present(this: Arc<Mutex<Executor>>, interval_str: String) ->
     let clone_self_obj = this.clone();
     let mu_self_obj = this.lock().unwrap();
     let is_enable_tester_present = mu_self_obj.is_enable_tester_present.load(Ordering::Relaxed);
-    if is_enable_tester_present == false {
-        let interval = match utils::common::parse_duration_to_milliseconds(interval_str.as_str()) {
-            Some(temp) => temp,
-            None => 2000, //2000ms
-        };
+    if !is_enable_tester_present {
+        let interval = utils::common::parse_duration_to_milliseconds(interval_str.as_str()).unwrap_or(2000); //2000ms
         mu_self_obj.is_enable_tester_present.store(true, Ordering::Relaxed);
         thread::spawn(move || {
             // start tester-present
             loop {
                 {
                     let mu_clone_self_obj = clone_self_obj.lock().unwrap();
-                    if mu_clone_self_obj.is_enable_tester_present.load(Ordering::Relaxed) == false {
+                    if !mu_clone_self_obj.is_enable_tester_present.load(Ordering::Relaxed) {
                         //stop tester-present
                         break;
                     }
@@ -381,9 +272,24 @@ pub fn stop_tester_present(&mut self) {
     self.is_enable_tester_present.store(false, Ordering::Relaxed);
 }
 
+/*****************************************************************************************************************
+ *  executor::executor::take_last_trace function
+ *  brief      Function to get diag exchanges recorded by the last executed command
+ *  details    -
+ *  \param[in]  -
+ *  \param[out] -
+ *  \precondition -
+ *  \reentrant:  FALSE
+ *  \return     recorded request/response pairs
+ ****************************************************************************************************************/
+pub fn take_last_trace(&mut self) -> Vec<transport::diag::DiagExchange> {
+    std::mem::take(&mut self.s_last_trace)
+}
+
 // Public function that returns a new Executor object
 pub fn create_executor(s_diag_obj: Arc<Mutex<transport::diag::Diag>>) -> Self {
-    Executor { s_diag_obj, is_enable_tester_present: AtomicBool::new(false) }
+    Executor { s_diag_obj, is_enable_tester_present: AtomicBool::new(false), s_last_trace: Vec::new(),
+               s_link_state: LinkState::default() }
 }
 
 }
@@ -0,0 +1,158 @@
+use std::io::{self, Error, ErrorKind};
+use crate::transport;
+use log::debug;
+use std::fs;
+
+use crate::utils;
+use crate::executor::swdl;
+use crate::executor::progress::{FileProgress, FlashPhase};
+use crate::executor::parameters::{FileTransferMode, FileTransferParams};
+
+const DEFAULT_DATA_FORMAT: u8 = 0x00;
+const FILE_SIZE_PARAMETER_LENGTH: u8 = 4; // bytes of fileSizeUnCompressed/fileSizeCompressed in requests
+
+/* Positive response of RequestFileTransfer, fields depend on modeOfOperation */
+#[derive(Debug, Default)]
+struct FileTransferResponse {
+    max_data_length: usize,
+    data_format_identifier: u8,
+    size: u64,          // fileSizeCompressed of read, dirInfoLength of read_dir
+    file_position: u64, // resume
+}
+
+/*****************************************************************************************************************
+ *  filetransfer::transfer_file function
+ *  brief      Execute RequestFileTransfer with one modeOfOperation and its TransferData/RequestTransferExit
+ *  details    add, replace and resume send local file, read writes remote file to local file, read_dir writes or
+ *             prints directory info, delete only sends the request. Data is transferred as it is, dataFormatIdentifier
+ *             tells ECU if local file is compressed/encrypted.
+ *  \param[in]  params  file_transfer step parameters
+ *  \param[out] -
+ *  \precondition -
+ *  \reentrant:  FALSE
+ *  \return     error code if any
+ ****************************************************************************************************************/
+pub fn transfer_file(stream: &mut transport::diag::Diag, params: &FileTransferParams, timeout: u64) -> Result<(), io::Error> {
+    let data_format = params.data_format_identifier.map(|dfi| dfi.0 as u8).unwrap_or(DEFAULT_DATA_FORMAT);
+    let mode = params.mode;
+    let local_path = params.local_path.clone().unwrap_or_default();
+
+    let mut byte_vector: Vec<u8> = vec![0x38, mode.mode_of_operation()];
+    byte_vector.extend_from_slice(&(params.remote_path.len() as u16).to_be_bytes());
+    byte_vector.extend_from_slice(params.remote_path.as_bytes());
+    let local_data = match mode {
+        FileTransferMode::Add | FileTransferMode::Replace | FileTransferMode::Resume => {
+            let local_data = fs::read(&local_path)?;
+            if local_data.len() as u64 > u32::MAX as u64 {
+                return Err(Error::new(ErrorKind::InvalidInput, format!("{} is larger than 4 GiB", local_path)));
+            }
+            byte_vector.extend_from_slice(&[data_format, FILE_SIZE_PARAMETER_LENGTH]);
+            byte_vector.extend_from_slice(&(local_data.len() as u32).to_be_bytes()); // fileSizeUnCompressed
+            byte_vector.extend_from_slice(&(local_data.len() as u32).to_be_bytes()); // fileSizeCompressed
+            local_data
+        }
+        FileTransferMode::Read => {
+            byte_vector.push(data_format);
+            Vec::new()
+        }
+        FileTransferMode::Delete | FileTransferMode::ReadDir => Vec::new(),
+    };
+
+    stream.send_diag(byte_vector)?;
+    let response = stream.receive_diag(timeout)?;
+    debug!("Sent Request File Transfer {:?} {}, Receive {:02X?}", mode, params.remote_path, response);
+    let response = parse_response(&response, mode)?;
+    if mode == FileTransferMode::Delete {
+        debug!("Deleted {}", params.remote_path);
+        return Ok(());
+    }
+
+    match mode {
+        FileTransferMode::Add | FileTransferMode::Replace | FileTransferMode::Resume => {
+            let mut acked = response.file_position as usize;
+            if acked > local_data.len() {
+                return Err(Error::new(ErrorKind::InvalidData,
+                    format!("ECU resumes at 0x{:X}, beyond size 0x{:X} of {}", acked, local_data.len(), local_path)));
+            }
+            let mut progress = FileProgress::new(&local_path, local_data.len() as u64);
+            progress.start_phase(FlashPhase::Download, 1);
+            progress.add_bytes(acked);
+            swdl::transfer_data(stream, &local_data, response.max_data_length, &mut progress, &mut acked, timeout)?;
+            swdl::transfer_exit(stream, timeout)?;
+            progress.finish();
+        }
+        _ => {
+            let mut progress = FileProgress::new(params.local_path.as_deref().unwrap_or(&params.remote_path), response.size);
+            progress.start_phase(FlashPhase::Upload, 1);
+            let data = swdl::receive_transfer_data(stream, response.size as usize, &mut progress, timeout)?;
+            swdl::transfer_exit(stream, timeout)?;
+            progress.finish();
+            match &params.local_path {
+                Some(local_path) => fs::write(local_path, &data)?,
+                None => println!("{}:\n{}", params.remote_path, String::from_utf8_lossy(&data)),
+            }
+            if response.data_format_identifier != DEFAULT_DATA_FORMAT {
+                debug!("{} is written as received, dataFormatIdentifier 0x{:02X}", params.remote_path, response.data_format_identifier);
+            }
+        }
+    }
+    debug!("File transfer {:?} {} done", mode, params.remote_path);
+    Ok(())
+}
+
+
+/*****************************************************************************************************************
+ *  filetransfer::parse_response function
+ *  brief      Parse RequestFileTransfer positive response
+ *  details    78 modeOfOperation lengthFormatIdentifier maxNumberOfBlockLength dataFormatIdentifier, followed by
+ *             fileSizeParameterLength and file sizes for read, dirInfoLength for read_dir or filePosition for resume.
+ *             Unlike RequestDownload, lengthFormatIdentifier is the whole byte count of maxNumberOfBlockLength.
+ *  \param[in]  response  RequestFileTransfer response
+ *              mode  requested modeOfOperation
+ *  \param[out] -
+ *  \precondition -
+ *  \reentrant:  TRUE
+ *  \return     parsed response or error if negative/malformed
+ ****************************************************************************************************************/
+fn parse_response(response: &[u8], mode: FileTransferMode) -> Result<FileTransferResponse, io::Error> {
+    let malformed = || Error::new(ErrorKind::InvalidData,
+        format!("request-file-transfer response {:02X?} is not expected", response));
+    if response.len() < 2 || response[0] != 0x78 || response[1] != mode.mode_of_operation() {
+        return Err(Error::new(ErrorKind::InvalidData,
+            format!("request-file-transfer failed: {}", utils::common::describe_response(response))));
+    }
+    let mut parsed = FileTransferResponse::default();
+    if mode == FileTransferMode::Delete {
+        return Ok(parsed);
+    }
+
+    let mut fields = utils::common::ByteReader::new(&response[2..]);
+    let length_size = fields.take(1).ok_or_else(malformed)?[0] as usize;
+    if !(1..=4).contains(&length_size) {
+        return Err(malformed());
+    }
+    parsed.max_data_length = swdl::max_data_length(fields.number(length_size).ok_or_else(malformed)? as usize)?;
+    parsed.data_format_identifier = fields.take(1).ok_or_else(malformed)?[0];
+    match mode {
+        FileTransferMode::Read | FileTransferMode::ReadDir => {
+            let size_length = fields.number(2).ok_or_else(malformed)? as usize;
+            if !(1..=8).contains(&size_length) {
+                return Err(malformed());
+            }
+            let uncompressed_size = fields.number(size_length).ok_or_else(malformed)?;
+            // read_dir has only dirInfoLength, read has fileSizeUncompressed and fileSizeCompressed
+            parsed.size = if mode == FileTransferMode::Read {
+                fields.number(size_length).ok_or_else(malformed)?
+            } else {
+                uncompressed_size
+            };
+        }
+        FileTransferMode::Resume => parsed.file_position = fields.number(8).ok_or_else(malformed)?,
+        _ => {}
+    }
+    if !fields.data.is_empty() {
+        return Err(malformed());
+    }
+    debug!("request-file-transfer response {:?}", parsed);
+    Ok(parsed)
+}
@@ -0,0 +1,338 @@
+use log::debug;
+use std::io::{self, Error, ErrorKind};
+
+/*****************************************************************************************************************
+ *  Memory image built from software files, one segment per contiguous memory area
+ ****************************************************************************************************************/
+#[derive(Debug, Clone, PartialEq)]
+pub struct MemorySegment {
+    pub address: u32,
+    pub data: Vec<u8>,
+}
+
+impl MemorySegment {
+    pub fn end_address(&self) -> u64 {
+        self.address as u64 + self.data.len() as u64
+    }
+}
+
+fn invalid_line(line_number: usize, reason: &str) -> io::Error {
+    Error::new(ErrorKind::InvalidData, format!("line {}: {}", line_number, reason))
+}
+
+fn decode_record(line: &str, line_number: usize) -> Result<Vec<u8>, io::Error> {
+    match hex::decode(line) {
+        Ok(bytes) => Ok(bytes),
+        Err(err) => Err(invalid_line(line_number, &format!("invalid hex record: {}", err))),
+    }
+}
+
+
+/*****************************************************************************************************************
+ *  executor::image::merge_records function
+ *  brief      Merge data records into contiguous memory segments
+ *  details    Records are sorted by address, records which touch each other are joined.
+ *             Overlapping records are rejected, a file must describe every byte only once.
+ *  \param[in]  records: (address, data) records in file order
+ *  \param[out] -
+ *  \precondition -
+ *  \reentrant:  TRUE
+ *  \return     memory segments sorted by address
+ ****************************************************************************************************************/
+pub fn merge_records(mut records: Vec<(u64, Vec<u8>)>) -> Result<Vec<MemorySegment>, io::Error> {
+    records.retain(|(_, data)| !data.is_empty());
+    records.sort_by_key(|(address, _)| *address);
+    let mut segments: Vec<MemorySegment> = Vec::new();
+    for (address, data) in records {
+        if address + data.len() as u64 > u32::MAX as u64 + 1 {
+            return Err(Error::new(ErrorKind::InvalidData, format!("data at 0x{:X} exceeds 32-bit address space", address)));
+        }
+        if let Some(last) = segments.last_mut() {
+            if address < last.end_address() {
+                return Err(Error::new(ErrorKind::InvalidData,
+                    format!("overlapping data at 0x{:08X}, segment 0x{:08X}-0x{:08X}", address, last.address, last.end_address() - 1)));
+            }
+            if address == last.end_address() {
+                last.data.extend_from_slice(&data);
+                continue;
+            }
+        }
+        segments.push(MemorySegment { address: address as u32, data });
+    }
+    for segment in segments.iter() {
+        debug!("segment 0x{:08X} length 0x{:X}", segment.address, segment.data.len());
+    }
+    Ok(segments)
+}
+
+
+/*****************************************************************************************************************
+ *  executor::image::parse_ihex function
+ *  brief      Parse Intel HEX file content to memory segments
+ *  details    Supports data (00), end of file (01), extended segment address (02) and
+ *             extended linear address (04) records. Start address records (03, 05) are ignored.
+ *  \param[in]  contents: content of .hex file
+ *  \param[out] -
+ *  \precondition -
+ *  \reentrant:  TRUE
+ *  \return     memory segments sorted by address
+ ****************************************************************************************************************/
+pub fn parse_ihex(contents: &str) -> Result<Vec<MemorySegment>, io::Error> {
+    let mut records: Vec<(u64, Vec<u8>)> = Vec::new();
+    let mut base_address: u64 = 0;
+    let mut is_eof = false;
+
+    for (index, line) in contents.lines().enumerate() {
+        let line_number = index + 1;
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        if is_eof {
+            return Err(invalid_line(line_number, "record after end of file record"));
+        }
+        let record = match line.strip_prefix(':') {
+            Some(record) => decode_record(record, line_number)?,
+            None => return Err(invalid_line(line_number, "record does not start with ':'")),
+        };
+        if record.len() < 5 || record.len() != record[0] as usize + 5 {
+            return Err(invalid_line(line_number, "record length does not match byte count"));
+        }
+        if record.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte)) != 0 {
+            return Err(invalid_line(line_number, "wrong checksum"));
+        }
+        let offset = u16::from_be_bytes([record[1], record[2]]) as u64;
+        let data = &record[4..record.len() - 1];
+        match record[3] {
+            0x00 => records.push((base_address + offset, data.to_vec())),
+            0x01 => is_eof = true,
+            0x02 | 0x04 if data.len() != 2 => {
+                return Err(invalid_line(line_number, &format!("record type {:02X} has {} data bytes, expected 2", record[3], data.len())));
+            }
+            0x03 | 0x05 if data.len() != 4 => {
+                return Err(invalid_line(line_number, &format!("record type {:02X} has {} data bytes, expected 4", record[3], data.len())));
+            }
+            0x02 => base_address = (u16::from_be_bytes([data[0], data[1]]) as u64) << 4,
+            0x04 => base_address = (u16::from_be_bytes([data[0], data[1]]) as u64) << 16,
+            0x03 | 0x05 => {} // start address, not used for download
+            record_type => return Err(invalid_line(line_number, &format!("unsupported record type {:02X}", record_type))),
+        }
+    }
+    if !is_eof {
+        return Err(Error::new(ErrorKind::InvalidData, "missing end of file record"));
+    }
+    merge_records(records)
+}
+
+
+/*****************************************************************************************************************
+ *  executor::image::parse_srec function
+ *  brief      Parse Motorola S-record file content to memory segments
+ *  details    Supports S19 (S1), S28 (S2) and S37 (S3) data records, header/count/termination records are checked
+ *             for checksum and skipped.
+ *  \param[in]  contents: content of .s19/.s28/.s37 file
+ *  \param[out] -
+ *  \precondition -
+ *  \reentrant:  TRUE
+ *  \return     memory segments sorted by address
+ ****************************************************************************************************************/
+pub fn parse_srec(contents: &str) -> Result<Vec<MemorySegment>, io::Error> {
+    let mut records: Vec<(u64, Vec<u8>)> = Vec::new();
+
+    for (index, line) in contents.lines().enumerate() {
+        let line_number = index + 1;
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        let (record_type, record) = match (line.strip_prefix('S'), line.get(1..2), line.get(2..)) {
+            (Some(_), Some(record_type), Some(record)) => (record_type, decode_record(record, line_number)?),
+            _ => return Err(invalid_line(line_number, "record does not start with 'S'")),
+        };
+        if record.is_empty() || record.len() != record[0] as usize + 1 {
+            return Err(invalid_line(line_number, "record length does not match byte count"));
+        }
+        let sum = record.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte));
+        if sum != 0xFF {
+            return Err(invalid_line(line_number, "wrong checksum"));
+        }
+        let address_len = match record_type {
+            "0" | "1" | "5" | "9" => 2,
+            "2" | "6" | "8" => 3,
+            "3" | "7" => 4,
+            _ => return Err(invalid_line(line_number, &format!("unsupported record type S{}", record_type))),
+        };
+        if record.len() < address_len + 2 {
+            return Err(invalid_line(line_number, "record too short for its address"));
+        }
+        if let "1" | "2" | "3" = record_type {
+            let address = record[1..1 + address_len].iter().fold(0u64, |address, byte| (address << 8) | *byte as u64);
+            records.push((address, record[1 + address_len..record.len() - 1].to_vec()));
+        }
+    }
+    if records.is_empty() {
+        return Err(Error::new(ErrorKind::InvalidData, "no data record in S-record file"));
+    }
+    merge_records(records)
+}
+
+
+/*****************************************************************************************************************
+ *  executor::image::from_bin function
+ *  brief      Build memory segment from raw binary image
+ *  details    -
+ *  \param[in]  contents: content of .bin file
+ *              address: start address of image in ECU memory
+ *              offset: first byte of file to download
+ *              length: number of bytes to download, rest of file from offset if not set
+ *  \param[out] -
+ *  \precondition -
+ *  \reentrant:  TRUE
+ *  \return     memory segment
+ ****************************************************************************************************************/
+pub fn from_bin(mut contents: Vec<u8>, address: u32, offset: u32, length: Option<u32>) -> Result<Vec<MemorySegment>, io::Error> {
+    let offset = offset as usize;
+    if offset > contents.len() {
+        return Err(Error::new(ErrorKind::InvalidData, format!("offset 0x{:X} is beyond file size 0x{:X}", offset, contents.len())));
+    }
+    let length = length.map(|length| length as usize).unwrap_or(contents.len() - offset);
+    if offset + length > contents.len() {
+        return Err(Error::new(ErrorKind::InvalidData,
+            format!("offset 0x{:X} + length 0x{:X} is beyond file size 0x{:X}", offset, length, contents.len())));
+    }
+    contents.truncate(offset + length);
+    merge_records(vec![(address as u64, contents.split_off(offset))])
+}
+
+
+/*****************************************************************************************************************
+ *  executor::image::to_ihex function
+ *  brief      Write memory segments as Intel HEX file content
+ *  details    16 data bytes per record, extended linear address records (04) before every 64 KiB page
+ *  \param[in]  segments: memory segments
+ *  \param[out] -
+ *  \precondition -
+ *  \reentrant:  TRUE
+ *  \return     content of .hex file
+ ****************************************************************************************************************/
+pub fn to_ihex(segments: &[MemorySegment]) -> String {
+    fn record(record_type: u8, offset: u16, data: &[u8]) -> String {
+        let mut bytes = vec![data.len() as u8, (offset >> 8) as u8, offset as u8, record_type];
+        bytes.extend_from_slice(data);
+        let checksum = bytes.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte)).wrapping_neg();
+        format!(":{}{:02X}\n", hex::encode_upper(&bytes), checksum)
+    }
+
+    let mut contents = String::new();
+    let mut page: Option<u32> = None;
+    for segment in segments.iter() {
+        let mut address = segment.address;
+        let mut remaining = segment.data.as_slice();
+        while !remaining.is_empty() {
+            if page != Some(address >> 16) {
+                page = Some(address >> 16);
+                contents.push_str(&record(0x04, 0, &((address >> 16) as u16).to_be_bytes()));
+            }
+            // records do not cross a 64 KiB page
+            let length = std::cmp::min(std::cmp::min(16, remaining.len()), 0x10000 - (address & 0xFFFF) as usize);
+            contents.push_str(&record(0x00, address as u16, &remaining[..length]));
+            address = address.wrapping_add(length as u32);
+            remaining = &remaining[length..];
+        }
+    }
+    contents.push_str(&record(0x01, 0, &[]));
+    contents
+}
+
+
+/*****************************************************************************************************************
+ *  executor::image::to_srec function
+ *  brief      Write memory segments as Motorola S-record file content
+ *  details    S0 header, S3 data records with 16 bytes and S7 termination
+ *  \param[in]  segments: memory segments
+ *              header: text of S0 record, cut to the 252 bytes a record can hold
+ *  \param[out] -
+ *  \precondition -
+ *  \reentrant:  TRUE
+ *  \return     content of .s37 file
+ ****************************************************************************************************************/
+pub fn to_srec(segments: &[MemorySegment], header: &str) -> String {
+    fn record(record_type: char, address: &[u8], data: &[u8]) -> String {
+        let mut bytes = vec![(address.len() + data.len() + 1) as u8];
+        bytes.extend_from_slice(address);
+        bytes.extend_from_slice(data);
+        let checksum = !bytes.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte));
+        format!("S{}{}{:02X}\n", record_type, hex::encode_upper(&bytes), checksum)
+    }
+
+    let header = header.as_bytes();
+    let mut contents = record('0', &[0, 0], &header[..std::cmp::min(header.len(), 252)]);
+    for segment in segments.iter() {
+        for (index, data) in segment.data.chunks(16).enumerate() {
+            let address = segment.address.wrapping_add((index * 16) as u32);
+            contents.push_str(&record('3', &address.to_be_bytes(), data));
+        }
+    }
+    contents.push_str(&record('7', &[0, 0, 0, 0], &[]));
+    contents
+}
+
+
+/*****************************************************************************************************************
+ *  executor::image::compare function
+ *  brief      Compare a memory dump with reference segments
+ *  details    Only bytes described by reference are compared, memory of dump without reference data is ignored
+ *  \param[in]  dump: memory read from ECU
+ *              reference: memory segments of reference image
+ *  \param[out] -
+ *  \precondition -
+ *  \reentrant:  TRUE
+ *  \return     (start, length) of every differing range, sorted by address
+ ****************************************************************************************************************/
+pub fn compare(dump: &MemorySegment, reference: &[MemorySegment]) -> Vec<(u32, u32)> {
+    let mut differences: Vec<(u32, u32)> = Vec::new();
+    for segment in reference.iter() {
+        let start = std::cmp::max(dump.address as u64, segment.address as u64);
+        let end = std::cmp::min(dump.end_address(), segment.end_address());
+        for address in start..end {
+            let dump_byte = dump.data[(address - dump.address as u64) as usize];
+            let reference_byte = segment.data[(address - segment.address as u64) as usize];
+            if dump_byte == reference_byte {
+                continue;
+            }
+            match differences.last_mut() {
+                Some((last_start, last_length)) if *last_start as u64 + *last_length as u64 == address => *last_length += 1,
+                _ => differences.push((address as u32, 1)),
+            }
+        }
+    }
+    differences
+}
+
+
+/*****************************************************************************************************************
+ *  executor::image::uncovered function
+ *  brief      Find reference memory which is not part of a memory dump
+ *  details    compare only checks the overlap, these ranges of reference could not be checked at all
+ *  \param[in]  dump: memory read from ECU
+ *              reference: memory segments of reference image
+ *  \param[out] -
+ *  \precondition -
+ *  \reentrant:  TRUE
+ *  \return     (start, length) of every range of reference outside dump, sorted by address
+ ****************************************************************************************************************/
+pub fn uncovered(dump: &MemorySegment, reference: &[MemorySegment]) -> Vec<(u32, u32)> {
+    let mut ranges: Vec<(u32, u32)> = Vec::new();
+    for segment in reference.iter() {
+        let (start, end) = (segment.address as u64, segment.end_address());
+        let before_end = std::cmp::min(end, dump.address as u64);
+        if start < before_end {
+            ranges.push((start as u32, (before_end - start) as u32));
+        }
+        let after_start = std::cmp::max(start, dump.end_address());
+        if after_start < end {
+            ranges.push((after_start as u32, (end - after_start) as u32));
+        }
+    }
+    ranges
+}
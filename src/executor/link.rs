@@ -0,0 +1,159 @@
+use log::debug;
+use std::io::{self, Error, ErrorKind};
+
+use crate::transport;
+use crate::executor::parameters::{AuthenticationMode, AuthenticationParams, DoipAction, ExpectPattern, SecurityAccessParams,
+                                  SocketAction, Step};
+use crate::executor::securityaccess;
+use crate::executor::authentication;
+use crate::executor::vendor;
+
+/*****************************************************************************************************************
+ *  Diagnostic state reached by executed steps: connection, routing activation, session, authentication and
+ *  security access.
+ *  It is replayed after a communication loss so an interrupted download can continue.
+ ****************************************************************************************************************/
+#[derive(Debug, Clone, Default)]
+pub struct LinkState {
+    vendor: String,
+    is_connected: bool,
+    is_routing_activated: bool,
+    session: Option<u8>,
+    authentication: Option<AuthenticationParams>,
+    security_access: Option<SecurityAccessState>,
+}
+
+#[derive(Debug, Clone)]
+struct SecurityAccessState {
+    level: u8,
+    params: SecurityAccessParams,
+    expect: Vec<ExpectPattern>,
+}
+
+/*****************************************************************************************************************
+ *  executor::link::is_link_lost function
+ *  brief      Check if an error means the connection to ECU is lost
+ *  details    Only socket errors count, a missing response (TimedOut) fails the step like any other wrong response
+ *  \param[in]  err: error of transport layer
+ *  \param[out] -
+ *  \precondition -
+ *  \reentrant:  TRUE
+ *  \return     true if reconnecting may help
+ ****************************************************************************************************************/
+pub fn is_link_lost(err: &io::Error) -> bool {
+    matches!(err.kind(), ErrorKind::ConnectionAborted | ErrorKind::ConnectionReset | ErrorKind::NotConnected
+                         | ErrorKind::BrokenPipe | ErrorKind::UnexpectedEof)
+}
+
+impl LinkState {
+
+/*****************************************************************************************************************
+ *  executor::link::record function
+ *  brief      Update diagnostic state after a step was executed successfully
+ *  details    DiagnosticSessionControl and ECUReset drop authentication and security access, connect/disconnect
+ *             start from scratch. The default session of the vendor profile is not replayed, ECU starts in it
+ *  \param[in]  step: executed step
+ *              vendor: vendor name in config
+ *  \param[out] -
+ *  \precondition -
+ *  \reentrant:  FALSE
+ *  \return -
+ ****************************************************************************************************************/
+pub fn record(&mut self, step: &Step, vendor: &str) {
+    match step {
+        Step::Socket { action } => {
+            *self = LinkState::default();
+            self.is_connected = *action == SocketAction::Connect;
+        }
+        Step::SendDoip { action: DoipAction::Activation, .. } => self.is_routing_activated = true,
+        Step::SendDiag { requests, .. } => {
+            for request in requests.iter() {
+                match request.0.as_slice() {
+                    [0x10, session] => {
+                        let default_session = vendor::find(vendor).ok().map(|profile| profile.data().sessions.default.0 as u8);
+                        self.session = Some(session & 0x7F).filter(|session| Some(*session) != default_session);
+                        self.authentication = None;
+                        self.security_access = None;
+                    }
+                    [0x11, ..] => {
+                        self.session = None;
+                        self.authentication = None;
+                        self.security_access = None;
+                    }
+                    _ => {}
+                }
+            }
+        }
+        Step::SecurityAccess { level, params, expect } => {
+            self.vendor = vendor.to_string();
+            self.security_access = Some(SecurityAccessState { level: *level, params: params.clone(), expect: expect.clone() });
+        }
+        Step::Authentication { params } => match params.mode {
+            AuthenticationMode::Unidirectional | AuthenticationMode::Bidirectional => self.authentication = Some(params.clone()),
+            AuthenticationMode::Deauthenticate => self.authentication = None,
+            AuthenticationMode::TransmitCertificate => {}
+        },
+        _ => {}
+    }
+}
+
+
+/*****************************************************************************************************************
+ *  executor::link::restore function
+ *  brief      Reconnect to ECU and replay routing activation, session, authentication and security access
+ *  details    Old connection is dropped first, errors of its disconnect are ignored. Session and security access
+ *             are not replayed if the gateway refuses routing activation
+ *  \param[in]  stream: point to Diag object
+ *              timeout: timeout per request in milliseconds
+ *  \param[out] -
+ *  \precondition -
+ *  \reentrant:  FALSE
+ *  \return     error code if any, PermissionDenied if routing activation is refused
+ ****************************************************************************************************************/
+pub fn restore(&self, stream: &mut transport::diag::Diag, timeout: u64) -> Result<(), io::Error> {
+    if !self.is_connected {
+        return Err(Error::new(ErrorKind::NotConnected, "no connection to restore"));
+    }
+    let _ = stream.disconnect();
+    stream.connect()?;
+    if self.is_routing_activated {
+        stream.send_doip_routing_activation()?;
+        match stream.receive_doip(timeout) {
+            Ok(_) => {}
+            Err(err) if err.kind() == ErrorKind::ConnectionRefused => {
+                return Err(Error::new(ErrorKind::PermissionDenied, format!("routing activation refused after reconnect: {}", err)));
+            }
+            Err(err) => return Err(err),
+        }
+        if !stream.is_routing_activated() {
+            return Err(Error::new(ErrorKind::PermissionDenied, "routing activation was not confirmed after reconnect"));
+        }
+    }
+    if let Some(session) = self.session {
+        stream.send_diag(vec![0x10, session])?;
+        let response = stream.receive_diag(timeout)?;
+        if response.first() != Some(&0x50) {
+            return Err(Error::new(ErrorKind::InvalidData, format!("session 0x{:02X} refused: {:02X?}", session, response)));
+        }
+    }
+    if let Some(params) = &self.authentication {
+        authentication::authenticate(stream, params, timeout)?;
+    }
+    if let Some(security_access) = &self.security_access {
+        securityaccess::security_access(stream, &security_access.params, &security_access.expect,
+                                        security_access.level, &self.vendor, timeout)?;
+    }
+    debug!("Restored link: routing {}, session {:?}, authentication {:?}, security access {:?}", self.is_routing_activated,
+           self.session, self.authentication.as_ref().map(|params| params.mode), self.security_access.as_ref().map(|security_access| security_access.level));
+    Ok(())
+}
+
+pub fn session(&self) -> Option<u8> {
+    self.session
+}
+
+pub fn security_level(&self) -> Option<u8> {
+    self.security_access.as_ref().map(|security_access| security_access.level)
+}
+
+}
@@ -0,0 +1,404 @@
+use log::debug;
+use serde::Deserialize;
+use std::env;
+use std::fs::{self, File};
+use std::io::{self, Error, ErrorKind};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::transport;
+use crate::transport::config::CONFIG;
+use crate::utils;
+use crate::executor::control::SEQUENCE_CONTROL;
+use crate::executor::executor::Executor;
+use crate::executor::link::LinkState;
+use crate::executor::parse_sequence::SequenceFormat;
+use crate::executor::parameters::{FlashPackageParams, HexValue, SequenceItem, Step, SwdlFormat, SwdlParams};
+use crate::executor::swdl;
+use crate::executor::vbf;
+use crate::executor::vendor;
+
+const MANIFEST_NAMES: [&str; 4] = ["manifest.json", "manifest.yaml", "manifest.yml", "manifest.toml"];
+const DEFAULT_STEP_TIMEOUT: u64 = 1000; //1000ms
+
+/*****************************************************************************************************************
+ *  Flash package manifest. Files are flashed in listed order, the secondary bootloader first if there is one:
+ *  programming preconditions routine -> preconditions -> sbl -> activation routine -> files ->
+ *  check dependencies routine -> finalize -> part numbers read back. Routines come from the vendor profile.
+ *  Paths of files are relative to the package root.
+ ****************************************************************************************************************/
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PackageManifest {
+    #[serde(default)]
+    pub name: String,
+    pub ecu_address: HexValue, // must match doip ecu_addr of config
+    #[serde(default)]
+    pub preconditions: Vec<SequenceItem>, // checked before anything is erased. Ex: session, security access, DIDs
+    #[serde(default)]
+    pub sbl: Option<SblEntry>,
+    pub files: Vec<SwdlParams>,
+    #[serde(default)]
+    pub finalize: Vec<SequenceItem>, // executed after the last file. Ex: ECU reset and reconnect
+    #[serde(default)]
+    pub part_numbers: Vec<PartNumberCheck>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SblEntry {
+    pub file: SwdlParams,
+    #[serde(default)]
+    pub activation_routine: Option<HexValue>, // activate_sbl routine of vendor profile if not set
+    #[serde(default)]
+    pub call_address: Option<HexValue>, // call field of vbf header if not set
+}
+
+/* DID read back after flashing. expect is compared with ASCII data, or with raw bytes if it starts with 0x */
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PartNumberCheck {
+    pub did: HexValue,
+    pub expect: String,
+}
+
+pub struct FlashPackage {
+    pub manifest: PackageManifest,
+    sbl_call_address: Option<u32>,
+    _extracted: Option<ExtractedDir>, // zip content, removed when package is dropped
+}
+
+struct ExtractedDir(PathBuf);
+
+impl Drop for ExtractedDir {
+    fn drop(&mut self) {
+        if let Err(err) = fs::remove_dir_all(&self.0) {
+            debug!("Failed to remove {}: {}", self.0.display(), err);
+        }
+    }
+}
+
+/*****************************************************************************************************************
+ *  package::is_package function
+ *  brief      Check if a path looks like a flash package: zip file, manifest file or directory with manifest
+ *  details    Used by GUI to tell packages from single software files
+ *  \param[in]  path  selected path
+ *  \param[out] -
+ *  \precondition -
+ *  \reentrant:  TRUE
+ *  \return     true if path is a package
+ ****************************************************************************************************************/
+#[cfg(feature = "gui")]
+pub fn is_package(path: &Path) -> bool {
+    if path.is_dir() {
+        return find_manifest(path).is_some();
+    }
+    let file_name = path.file_name().and_then(|name| name.to_str()).unwrap_or("").to_lowercase();
+    file_name.ends_with(".zip") || MANIFEST_NAMES.contains(&file_name.as_str())
+}
+
+
+/*****************************************************************************************************************
+ *  package::load function
+ *  brief      Open a flash package and validate it completely without talking to ECU
+ *  details    Zip files are extracted to a temporary directory. Every software file is parsed, so a broken or
+ *             missing file is reported before anything is erased. vbf files must target the ECU of the manifest
+ *             and pass vbf::verify.
+ *  \param[in]  package_path  package directory, zip file or manifest file
+ *  \param[out] -
+ *  \precondition -
+ *  \reentrant:  TRUE
+ *  \return     loaded package with resolved file paths
+ ****************************************************************************************************************/
+pub fn load(package_path: &str) -> Result<FlashPackage, io::Error> {
+    let path = Path::new(package_path);
+    let mut extracted = None;
+    let manifest_path = if path.is_dir() {
+        find_manifest(path)
+    } else if path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("zip")) {
+        let dir = extract_zip(path)?;
+        let manifest_path = find_manifest(&dir.0);
+        extracted = Some(dir);
+        manifest_path
+    } else if path.is_file() {
+        Some(path.to_path_buf())
+    } else {
+        return Err(Error::new(ErrorKind::NotFound, format!("flash package {} does not exist", package_path)));
+    };
+    let manifest_path = manifest_path.ok_or_else(|| Error::new(ErrorKind::NotFound,
+        format!("flash package {} has no {}", package_path, MANIFEST_NAMES.join("/"))))?;
+    let root = manifest_path.parent().map(Path::to_path_buf).unwrap_or_default();
+
+    let manifest_name = manifest_path.display().to_string();
+    let contents = fs::read_to_string(&manifest_path)?;
+    let parse_result: Result<PackageManifest, String> = match SequenceFormat::from_path(&manifest_name) {
+        SequenceFormat::Json => serde_json::from_str(&contents).map_err(|err| err.to_string()),
+        SequenceFormat::Yaml => serde_yaml::from_str(&contents).map_err(|err| err.to_string()),
+        SequenceFormat::Toml => toml::from_str(&contents).map_err(|err| err.to_string()),
+    };
+    let mut manifest = parse_result.map_err(|err| Error::new(ErrorKind::InvalidData,
+        format!("wrong manifest {}: {}", manifest_name, err)))?;
+
+    let sbl_call_address = validate(&root, &mut manifest)?;
+    let package = FlashPackage { manifest, sbl_call_address, _extracted: extracted };
+    debug!("Loaded flash package {} from {}", package.manifest.name, manifest_name);
+    Ok(package)
+}
+
+
+/*****************************************************************************************************************
+ *  package::flash_package function
+ *  brief      Execute a flash package end to end and verify part numbers afterwards
+ *  details    Steps of preconditions and finalize run like sequence items, their diagnostic state is used to
+ *             reconnect if communication is lost during download. Programming preconditions and programming
+ *             dependencies are checked by the routines of the vendor profile, if it has them. Every part number is
+ *             read and compared before the step fails, so all mismatches are reported.
+ *  \param[in]  params  flash_package step parameters
+ *              link  diagnostic state reached by previous steps
+ *              vendor  vendor name in config
+ *              timeout  timeout per request of downloads and part number reads
+ *  \param[out] -
+ *  \precondition -
+ *  \reentrant:  FALSE
+ *  \return     error code if any
+ ****************************************************************************************************************/
+pub fn flash_package(stream: &mut transport::diag::Diag, params: &FlashPackageParams, link: &LinkState, vendor: &str,
+                     timeout: u64) -> Result<(), io::Error> {
+    let package = load(&params.path)?;
+    let manifest = &package.manifest;
+    println!("Package: {} for ECU 0x{:04X}, {} files", if manifest.name.is_empty() { &params.path } else { &manifest.name },
+             manifest.ecu_address.0, manifest.files.len() + manifest.sbl.iter().count());
+    let mut link = link.clone();
+    let profile = vendor::find(vendor)?;
+    let routines = &profile.data().routines;
+
+    if let Some(routine) = &routines.programming_preconditions {
+        swdl::start_routine(stream, routine.id(), &[], &routine.expect(), timeout)
+            .map_err(|err| Error::new(err.kind(), format!("{} programming preconditions are not fulfilled: {}", profile.name(), err)))?;
+    }
+
+    for (index, item) in manifest.preconditions.iter().enumerate() {
+        run_item(stream, item, &mut link, vendor)
+            .map_err(|err| Error::new(err.kind(), format!("precondition {} ({}) failed: {}", index + 1, item.name(), err)))?;
+    }
+
+    if let Some(sbl) = &manifest.sbl {
+        println!("Package: flash secondary bootloader {}", sbl.file.path);
+        swdl::download_image(stream, &sbl.file, &link, profile.as_ref(), timeout)?;
+        let routine = sbl.activation_routine.map(|routine| routine.0 as u16).unwrap_or(routines.activate_sbl.id());
+        let call_address = package.sbl_call_address.unwrap_or_default();
+        swdl::start_routine(stream, routine, &call_address.to_be_bytes(), &routines.activate_sbl.expect_for(routine), timeout)
+            .map_err(|err| Error::new(err.kind(), format!("secondary bootloader activation at 0x{:08X} failed: {}", call_address, err)))?;
+        debug!("Activated secondary bootloader at 0x{:08X}", call_address);
+    }
+
+    for (index, file) in manifest.files.iter().enumerate() {
+        if SEQUENCE_CONTROL.is_aborted() {
+            return Err(Error::new(ErrorKind::Interrupted, "flash package aborted"));
+        }
+        println!("Package: flash file {}/{} {}", index + 1, manifest.files.len(), file.path);
+        swdl::download_image(stream, file, &link, profile.as_ref(), timeout)?;
+    }
+
+    if let Some(routine) = &routines.check_dependencies {
+        swdl::start_routine(stream, routine.id(), &[], &routine.expect(), timeout)
+            .map_err(|err| Error::new(err.kind(), format!("{} programming dependencies check failed: {}", profile.name(), err)))?;
+    }
+
+    for (index, item) in manifest.finalize.iter().enumerate() {
+        run_item(stream, item, &mut link, vendor)
+            .map_err(|err| Error::new(err.kind(), format!("finalize step {} ({}) failed: {}", index + 1, item.name(), err)))?;
+    }
+
+    verify_part_numbers(stream, &manifest.part_numbers, timeout)?;
+    println!("Package: flashed and verified successfully");
+    Ok(())
+}
+
+
+/*****************************************************************************************************************
+ *  package::validate function
+ *  brief      Check manifest against config and software files, resolve file paths relative to package root
+ *  details    -
+ *  \param[in]  root  directory of manifest
+ *              manifest  parsed manifest, its paths are resolved in place
+ *  \param[out] -
+ *  \precondition -
+ *  \reentrant:  TRUE
+ *  \return     call address of secondary bootloader if there is one
+ ****************************************************************************************************************/
+fn validate(root: &Path, manifest: &mut PackageManifest) -> Result<Option<u32>, io::Error> {
+    let invalid = |message: String| Error::new(ErrorKind::InvalidInput, message);
+    let config_ecu_address = CONFIG.read().unwrap().doip.ecu_addr;
+    if manifest.ecu_address.0 != config_ecu_address as u32 {
+        return Err(invalid(format!("package targets ECU 0x{:04X}, config addresses ECU 0x{:04X}",
+                                   manifest.ecu_address.0, config_ecu_address)));
+    }
+    if manifest.files.is_empty() {
+        return Err(invalid(String::from("package has no files")));
+    }
+    for item in manifest.preconditions.iter().chain(manifest.finalize.iter()) {
+        if let Step::FlashPackage { .. } = item.step {
+            return Err(invalid(String::from("flash_package step cannot be nested in a package")));
+        }
+    }
+    for check in manifest.part_numbers.iter() {
+        if check.did.0 > 0xFFFF || check.expect.is_empty() {
+            return Err(invalid(format!("part number of DID 0x{:X} needs a 2 bytes DID and an expected value", check.did.0)));
+        }
+        expected_bytes(&check.expect).map_err(invalid)?;
+    }
+
+    let mut sbl_call_address = None;
+    if let Some(sbl) = manifest.sbl.as_mut() {
+        let header = check_file(root, &mut sbl.file, manifest.ecu_address.0)?;
+        if sbl.activation_routine.is_some_and(|routine| routine.0 > 0xFFFF) {
+            return Err(invalid(String::from("sbl activation_routine is longer than 2 bytes")));
+        }
+        let call_address = sbl.call_address.map(|address| address.0).or_else(|| header.and_then(|header| header.call));
+        if call_address.is_none() {
+            return Err(invalid(format!("sbl {} needs call_address, its file has no call field", sbl.file.path)));
+        }
+        sbl_call_address = call_address;
+    }
+    for file in manifest.files.iter_mut() {
+        check_file(root, file, manifest.ecu_address.0)?;
+    }
+    Ok(sbl_call_address)
+}
+
+
+/*****************************************************************************************************************
+ *  package::check_file function
+ *  brief      Resolve path of a package file, validate its swdl parameters and parse it
+ *  details    -
+ *  \param[in]  root  directory of manifest
+ *              file  swdl parameters of file, path is resolved in place
+ *              ecu_address  target ECU of package
+ *  \param[out] -
+ *  \precondition -
+ *  \reentrant:  TRUE
+ *  \return     vbf header if file is a vbf file
+ ****************************************************************************************************************/
+fn check_file(root: &Path, file: &mut SwdlParams, ecu_address: u32) -> Result<Option<vbf::VbfHeader>, io::Error> {
+    file.path = root.join(&file.path).display().to_string();
+    file.validate().map_err(|err| Error::new(ErrorKind::InvalidInput, format!("{}: {}", file.path, err)))?;
+    if file.format != SwdlFormat::Vbf {
+        swdl::load_image(file).map_err(|err| Error::new(err.kind(), format!("{}: {}", file.path, err)))?;
+        return Ok(None);
+    }
+    let vbf_file = vbf::load(&file.path).map_err(|err| Error::new(err.kind(), format!("{}: {}", file.path, err)))?;
+    vbf::verify(&vbf_file).map_err(|err| Error::new(err.kind(), format!("{}: {}", file.path, err)))?;
+    if vbf_file.header.ecu_address != ecu_address {
+        return Err(Error::new(ErrorKind::InvalidInput, format!("{} is built for ECU 0x{:04X}, package targets ECU 0x{:04X}",
+                                                              file.path, vbf_file.header.ecu_address, ecu_address)));
+    }
+    Ok(Some(vbf_file.header))
+}
+
+
+/*****************************************************************************************************************
+ *  package::run_item function
+ *  brief      Execute a precondition or finalize step of a package and record its diagnostic state
+ *  details    -
+ *  \param[in]  item  sequence item of manifest
+ *              link  diagnostic state, updated after success
+ *              vendor  vendor name in config
+ *  \param[out] -
+ *  \precondition -
+ *  \reentrant:  FALSE
+ *  \return     error code if any
+ ****************************************************************************************************************/
+fn run_item(stream: &mut transport::diag::Diag, item: &SequenceItem, link: &mut LinkState,
+            vendor: &str) -> Result<(), io::Error> {
+    if SEQUENCE_CONTROL.is_aborted() {
+        return Err(Error::new(ErrorKind::Interrupted, "flash package aborted"));
+    }
+    let timeout = utils::common::parse_duration_to_milliseconds(&item.timeout).unwrap_or(DEFAULT_STEP_TIMEOUT);
+    if let Step::Delay { .. } = item.step {
+        return SEQUENCE_CONTROL.delay(timeout);
+    }
+    Executor::execute_item(stream, item, link, vendor, timeout)?;
+    link.record(&item.step, vendor);
+    Ok(())
+}
+
+
+/*****************************************************************************************************************
+ *  package::verify_part_numbers function
+ *  brief      Read part number DIDs and compare them with expected values of manifest
+ *  details    ASCII data is compared without trailing spaces, NUL and 0xFF padding
+ *  \param[in]  checks  part numbers of manifest
+ *  \param[out] -
+ *  \precondition -
+ *  \reentrant:  FALSE
+ *  \return     error kind InvalidData if any part number differs
+ ****************************************************************************************************************/
+fn verify_part_numbers(stream: &mut transport::diag::Diag, checks: &[PartNumberCheck],
+                       timeout: u64) -> Result<(), io::Error> {
+    let mut mismatches = 0;
+    for check in checks.iter() {
+        let did = check.did.0 as u16;
+        let mut byte_vector: Vec<u8> = vec![0x22];
+        byte_vector.extend_from_slice(&did.to_be_bytes());
+        stream.send_diag(byte_vector)?;
+        let response = stream.receive_diag(timeout)?;
+        debug!("Read DID {:04X}, Receive {:02X?}", did, response);
+        if response.len() < 3 || response[0] != 0x62 || response[1..3] != did.to_be_bytes() {
+            return Err(Error::new(ErrorKind::InvalidData,
+                format!("read DID {:04X} failed: {}", did, utils::common::describe_response(&response))));
+        }
+        let data = &response[3..];
+        let (is_match, actual) = match expected_bytes(&check.expect) {
+            Ok(Some(expected)) => (data == expected.as_slice(), format!("0x{}", hex::encode_upper(data))),
+            _ => {
+                let end = data.iter().rposition(|byte| !matches!(byte, 0x00 | 0x20 | 0xFF)).map_or(0, |index| index + 1);
+                let actual = String::from_utf8_lossy(&data[..end]).to_string();
+                (actual == check.expect.trim_end(), actual)
+            }
+        };
+        if is_match {
+            println!("Package: DID {:04X} = {} as expected", did, actual);
+        } else {
+            println!("Package: DID {:04X} = {}, expected {}", did, actual, check.expect);
+            mismatches += 1;
+        }
+    }
+    if mismatches > 0 {
+        return Err(Error::new(ErrorKind::InvalidData,
+            format!("{} of {} part numbers differ after flashing", mismatches, checks.len())));
+    }
+    Ok(())
+}
+
+// Raw bytes of a 0x-prefixed expected value, None for text values
+fn expected_bytes(expect: &str) -> Result<Option<Vec<u8>>, String> {
+    match expect.strip_prefix("0x").or_else(|| expect.strip_prefix("0X")) {
+        Some(hex_str) => hex::decode(hex_str).map(Some).map_err(|err| format!("part number \"{}\" is not hex: {}", expect, err)),
+        None => Ok(None),
+    }
+}
+
+fn find_manifest(dir: &Path) -> Option<PathBuf> {
+    if let Some(path) = MANIFEST_NAMES.iter().map(|name| dir.join(name)).find(|path| path.is_file()) {
+        return Some(path);
+    }
+    // zip tools often pack the package folder itself
+    let entries: Vec<PathBuf> = fs::read_dir(dir).ok()?.filter_map(|entry| entry.ok().map(|entry| entry.path())).collect();
+    match entries.as_slice() {
+        [single] if single.is_dir() => MANIFEST_NAMES.iter().map(|name| single.join(name)).find(|path| path.is_file()),
+        _ => None,
+    }
+}
+
+fn extract_zip(path: &Path) -> Result<ExtractedDir, io::Error> {
+    let nanos = SystemTime::now().duration_since(UNIX_EPOCH).map(|time| time.subsec_nanos()).unwrap_or(0);
+    let dir = ExtractedDir(env::temp_dir().join(format!("diag_tool_package_{}_{}", process::id(), nanos)));
+    fs::create_dir_all(&dir.0)?;
+    let mut archive = zip::ZipArchive::new(File::open(path)?)
+        .map_err(|err| Error::new(ErrorKind::InvalidData, format!("{}: {}", path.display(), err)))?;
+    archive.extract(&dir.0).map_err(|err| Error::new(ErrorKind::InvalidData, format!("{}: {}", path.display(), err)))?;
+    debug!("Extracted {} to {}", path.display(), dir.0.display());
+    Ok(dir)
+}
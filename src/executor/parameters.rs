@@ -1,31 +1,944 @@
-use serde::{Deserialize, Serialize};
+use serde::de::{self, DeserializeOwned, MapAccess, SeqAccess, Unexpected, Visitor};
+use serde::{Deserialize, Deserializer, Serialize};
+use serde_json::{Map, Value};
+use std::convert::TryFrom;
+use std::fmt;
+use std::marker::PhantomData;
+use std::io::{self, Error, ErrorKind};
 
-#[derive(Debug, Deserialize, Serialize)]
+use crate::utils;
+use crate::executor::compression;
+use crate::executor::securityaccess;
+use crate::executor::seedkey;
+
+/*****************************************************************************************************************
+ *  Typed values of sequence items, validated when the sequence is loaded
+ ****************************************************************************************************************/
+/* Diag/doip payload written as hex string. Ex: "22f186", "22 F1 86", "0x22f186" */
+#[derive(Debug, Clone, PartialEq)]
+pub struct HexBytes(pub Vec<u8>);
+
+impl TryFrom<&str> for HexBytes {
+    type Error = String;
+
+    fn try_from(hex_str: &str) -> Result<Self, Self::Error> {
+        let trimmed_value = hex_str.replace(' ', "");
+        let trimmed_value = trimmed_value.strip_prefix("0x").or_else(|| trimmed_value.strip_prefix("0X")).unwrap_or(&trimmed_value);
+        match hex::decode(trimmed_value) {
+            Ok(bytes) if bytes.is_empty() => Err(format!("empty hex string \"{}\"", hex_str)),
+            Ok(bytes) => Ok(HexBytes(bytes)),
+            Err(err) => Err(format!("invalid hex string \"{}\": {}", hex_str, err)),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for HexBytes {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let hex_str = String::deserialize(deserializer)?;
+        HexBytes::try_from(hex_str.as_str()).map_err(serde::de::Error::custom)
+    }
+}
+
+impl Serialize for HexBytes {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&hex::encode(&self.0))
+    }
+}
+
+/* Expected response, hex digits where '*' matches the rest. Ex: "62f186*" */
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExpectPattern(pub String);
+
+impl TryFrom<&str> for ExpectPattern {
+    type Error = String;
+
+    fn try_from(pattern: &str) -> Result<Self, Self::Error> {
+        let trimmed_value = pattern.replace(' ', "");
+        if trimmed_value.is_empty() {
+            return Err(String::from("empty expect pattern, use \"*\" to accept any response"));
+        }
+        if trimmed_value.chars().all(|c| c.is_ascii_hexdigit() || c == '*') {
+            Ok(ExpectPattern(trimmed_value))
+        } else {
+            Err(format!("invalid expect pattern \"{}\", use hex digits and '*'", pattern))
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for ExpectPattern {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let pattern = String::deserialize(deserializer)?;
+        ExpectPattern::try_from(pattern.as_str()).map_err(serde::de::Error::custom)
+    }
+}
+
+impl Serialize for ExpectPattern {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.0)
+    }
+}
+
+impl ExpectPattern {
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SocketAction {
+    Connect,
+    Disconnect,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum DoipAction {
+    Activation,
+    Raw(Vec<HexBytes>),
+}
+
+/* "activation" or raw doip payloads as hex string or list of hex strings */
+impl<'de> Deserialize<'de> for DoipAction {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let actions: Vec<String> = deserialize_one_or_many(deserializer)?;
+        match actions.as_slice() {
+            [action] if action == "activation" => Ok(DoipAction::Activation),
+            _ => actions.iter()
+                .map(|hex_str| HexBytes::try_from(hex_str.as_str()))
+                .collect::<Result<Vec<HexBytes>, String>>()
+                .map(DoipAction::Raw)
+                .map_err(de::Error::custom),
+        }
+    }
+}
+
+impl Serialize for DoipAction {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            DoipAction::Activation => serializer.serialize_str("activation"),
+            DoipAction::Raw(requests) => requests.serialize(serializer),
+        }
+    }
+}
+
+/* secured of send_diag: true/false for every request or a list with one true/false per request */
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(untagged)]
+pub enum SecuredRequests {
+    Every(bool),
+    PerRequest(Vec<bool>),
+}
+
+impl Default for SecuredRequests {
+    fn default() -> Self {
+        SecuredRequests::Every(false)
+    }
+}
+
+impl SecuredRequests {
+    pub fn is_secured(&self, index: usize) -> bool {
+        match self {
+            SecuredRequests::Every(flag) => *flag,
+            SecuredRequests::PerRequest(flags) => flags.get(index) == Some(&true),
+        }
+    }
+
+    fn is_off(&self) -> bool {
+        *self == SecuredRequests::Every(false)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct SecurityAccessParams {
+    pub algorithm: String, // "AES128"/"AES256" for volvo authentication, or a seed/key algorithm. Ex: "xor", "crc32"
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub iv: String,        // volvo authentication: "random" (default) or 16 hex bytes
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub encryption_authentication_key: String,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub proof_of_ownership_key: String,
+    // Seed/key algorithms: hex secret of built-in algorithms, shared library with GenerateKeyEx and its variant
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub secret: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub library: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub variant: Option<String>,
+    // Wrong keys sent to the level in one run before further attempts are refused, 3 if not set
+    #[serde(default, deserialize_with = "deserialize_count", skip_serializing_if = "Option::is_none")]
+    pub max_attempts: Option<u32>,
+    // Longest wait while the ECU answers requiredTimeDelayNotExpired, requestSeed is repeated meanwhile. Ex: "30s"
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub wait_delay: Option<String>,
+}
+
+/* Number written as hex string "0xFF00"/"FF00" or as integer */
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct HexValue(pub u32);
+
+impl TryFrom<&str> for HexValue {
+    type Error = String;
+
+    fn try_from(hex_str: &str) -> Result<Self, Self::Error> {
+        let trimmed_value = hex_str.trim();
+        let trimmed_value = trimmed_value.strip_prefix("0x").or_else(|| trimmed_value.strip_prefix("0X")).unwrap_or(trimmed_value);
+        match u32::from_str_radix(trimmed_value, 16) {
+            Ok(value) => Ok(HexValue(value)),
+            Err(err) => Err(format!("invalid hex value \"{}\": {}", hex_str, err)),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for HexValue {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Repr {
+            Number(u32),
+            Text(String),
+        }
+        match Repr::deserialize(deserializer)? {
+            Repr::Number(value) => Ok(HexValue(value)),
+            Repr::Text(hex_str) => HexValue::try_from(hex_str.as_str()).map_err(serde::de::Error::custom),
+        }
+    }
+}
+
+impl Serialize for HexValue {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&format!("0x{:X}", self.0))
+    }
+}
+
+/* Memory area written as "start,length". Ex: "0x00080000,0x00010000" */
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MemoryRange {
+    pub start: u32,
+    pub length: u32,
+}
+
+impl TryFrom<&str> for MemoryRange {
+    type Error = String;
+
+    fn try_from(range_str: &str) -> Result<Self, Self::Error> {
+        match range_str.split_once(',') {
+            Some((start, length)) => Ok(MemoryRange {
+                start: HexValue::try_from(start)?.0,
+                length: HexValue::try_from(length)?.0,
+            }),
+            None => Err(format!("memory range \"{}\" is not in start,length format", range_str)),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for MemoryRange {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let range_str = String::deserialize(deserializer)?;
+        MemoryRange::try_from(range_str.as_str()).map_err(serde::de::Error::custom)
+    }
+}
+
+impl Serialize for MemoryRange {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&format!("0x{:08X},0x{:08X}", self.start, self.length))
+    }
+}
+
+// Ranges are a list ["start,length", ...] or, in "key:value" form, one string "start,length;start,length"
+fn deserialize_ranges<'de, D: serde::Deserializer<'de>>(deserializer: D) -> Result<Vec<MemoryRange>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Repr {
+        Text(String),
+        List(Vec<MemoryRange>),
+    }
+    match Repr::deserialize(deserializer)? {
+        Repr::List(ranges) => Ok(ranges),
+        Repr::Text(ranges_str) => ranges_str
+            .split(';')
+            .filter(|range_str| !range_str.trim().is_empty())
+            .map(|range_str| MemoryRange::try_from(range_str).map_err(serde::de::Error::custom))
+            .collect(),
+    }
+}
+
+// A single value or a list of values. Ex: "22f186" or ["22f186", "22f187"], null is an empty list
+struct OneOrManyVisitor<T>(PhantomData<T>);
+
+impl<'de, T: Deserialize<'de>> Visitor<'de> for OneOrManyVisitor<T> {
+    type Value = Vec<T>;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a string or a list of strings")
+    }
+
+    fn visit_str<E: de::Error>(self, value: &str) -> Result<Vec<T>, E> {
+        T::deserialize(de::value::StrDeserializer::<E>::new(value)).map(|item| vec![item])
+    }
+
+    fn visit_unit<E: de::Error>(self) -> Result<Vec<T>, E> {
+        Ok(Vec::new())
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Vec<T>, A::Error> {
+        let mut items = Vec::new();
+        while let Some(item) = seq.next_element()? {
+            items.push(item);
+        }
+        Ok(items)
+    }
+}
+
+fn deserialize_one_or_many<'de, D: Deserializer<'de>, T: Deserialize<'de>>(deserializer: D) -> Result<Vec<T>, D::Error> {
+    deserializer.deserialize_any(OneOrManyVisitor(PhantomData))
+}
+
+// Parameters are written as object {"path": "..."} or as legacy list ["path:...", "format:vbf"].
+// Legacy entries are split at the first ':' only, so values like "C:\sw\app.vbf" are kept.
+// Steps with a short form build their parameters from a single string with from_str
+type ParamsFromStr<T> = fn(&str) -> Result<T, String>;
+
+struct ParamsVisitor<T> {
+    from_str: Option<ParamsFromStr<T>>,
+}
+
+impl<'de, T: DeserializeOwned> Visitor<'de> for ParamsVisitor<T> {
+    type Value = T;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("parameters as an object or a list of \"key:value\"")
+    }
+
+    fn visit_str<E: de::Error>(self, value: &str) -> Result<T, E> {
+        match self.from_str {
+            Some(from_str) => from_str(value).map_err(E::custom),
+            None => Err(E::invalid_type(Unexpected::Str(value), &self)),
+        }
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, map: A) -> Result<T, A::Error> {
+        T::deserialize(de::value::MapAccessDeserializer::new(map))
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<T, A::Error> {
+        let mut map = Map::new();
+        while let Some(entry) = seq.next_element::<String>()? {
+            match entry.split_once(':') {
+                Some((key, value)) => {
+                    if map.insert(key.trim().to_string(), Value::String(value.trim().to_string())).is_some() {
+                        return Err(de::Error::custom(format!("duplicated parameter \"{}\"", key)));
+                    }
+                }
+                None => return Err(de::Error::custom(format!("parameter \"{}\" is not in key:value format", entry))),
+            }
+        }
+        serde_json::from_value(Value::Object(map)).map_err(de::Error::custom)
+    }
+}
+
+fn deserialize_params<'de, D: Deserializer<'de>, T: DeserializeOwned>(deserializer: D) -> Result<T, D::Error> {
+    deserializer.deserialize_any(ParamsVisitor { from_str: None })
+}
+
+// flash_package also takes the package path as string
+fn deserialize_package_params<'de, D: Deserializer<'de>>(deserializer: D) -> Result<FlashPackageParams, D::Error> {
+    deserializer.deserialize_any(ParamsVisitor {
+        from_str: Some(|path| Ok(FlashPackageParams { path: path.to_string() })),
+    })
+}
+
+// authentication also takes the mode as string. Ex: "deauthenticate"
+fn deserialize_authentication_params<'de, D: Deserializer<'de>>(deserializer: D) -> Result<AuthenticationParams, D::Error> {
+    deserializer.deserialize_any(ParamsVisitor {
+        from_str: Some(|mode| Ok(AuthenticationParams {
+            mode: serde_json::from_value(Value::String(mode.to_string()))
+                .map_err(|_| format!("invalid mode \"{}\"", mode))?,
+            certificate: None, private_key: None, trust_anchor: None,
+            communication_configuration: None, certificate_evaluation_id: None,
+        })),
+    })
+}
+
+// Level of securityaccess_XX, hex. Ex: "05"
+fn deserialize_level<'de, D: Deserializer<'de>>(deserializer: D) -> Result<u8, D::Error> {
+    let level = HexValue::deserialize(deserializer)?;
+    u8::try_from(level.0)
+        .map_err(|_| de::Error::custom(format!("security access level 0x{:X} is longer than 1 byte", level.0)))
+}
+
+// delay action is a free note, null if not set
+fn deserialize_note<'de, D: Deserializer<'de>>(deserializer: D) -> Result<String, D::Error> {
+    Ok(Option::<String>::deserialize(deserializer)?.unwrap_or_default())
+}
+
+// Count written as integer or decimal string, "key:value" parameters are always strings
+fn deserialize_count<'de, D: serde::Deserializer<'de>>(deserializer: D) -> Result<Option<u32>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Repr {
+        Number(u32),
+        Text(String),
+    }
+    match Repr::deserialize(deserializer)? {
+        Repr::Number(value) => Ok(Some(value)),
+        Repr::Text(count_str) => count_str.trim().parse::<u32>().map(Some)
+            .map_err(|err| serde::de::Error::custom(format!("invalid count \"{}\": {}", count_str, err))),
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SwdlFormat {
+    Vbf,
+    #[serde(alias = "ihex")]
+    Hex,
+    #[serde(alias = "s19", alias = "s28", alias = "s37")]
+    Srec,
+    Bin,
+}
+
+impl SwdlFormat {
+    // Ex: app.vbf -> Vbf, app.hex -> Hex, app.s37 -> Srec
+    pub fn from_path(path: &str) -> Option<SwdlFormat> {
+        let extension = std::path::Path::new(path).extension()?.to_str()?.to_lowercase();
+        serde_json::from_value(Value::String(extension)).ok()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct SwdlParams {
+    pub path: String,
+    pub format: SwdlFormat,
+    // Erased before download. vbf files use the erase field of their header instead
+    #[serde(default, deserialize_with = "deserialize_ranges", skip_serializing_if = "Vec::is_empty")]
+    pub erase: Vec<MemoryRange>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub erase_routine: Option<HexValue>, // erase_memory routine of vendor profile if not set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub check_routine: Option<HexValue>, // routine started after download. Ex: 0x0202 checkMemory
+    // bin format only: image is downloaded at address, optionally a part of file from offset with length
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub address: Option<HexValue>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub offset: Option<HexValue>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub length: Option<HexValue>,
+    // RequestDownload format, those of vendor profile if not set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub address_and_length_format_identifier: Option<HexValue>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub data_format_identifier: Option<HexValue>,
+    // Compressor name, raw images (hex, srec, bin) are compressed before download. Ex: "lzss"
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub compression: Option<String>,
+    // Reconnections allowed after communication loss, 3 if not set, 0 disables resuming
+    #[serde(default, deserialize_with = "deserialize_count", skip_serializing_if = "Option::is_none")]
+    pub max_reconnects: Option<u32>,
+}
+
+impl SwdlParams {
+    // Parameters with only file path and format, everything else default, used by files opened in gui
+    #[cfg(feature = "gui")]
+    pub fn new(path: String, format: SwdlFormat) -> Self {
+        SwdlParams {
+            path,
+            format,
+            erase: Vec::new(),
+            erase_routine: None,
+            check_routine: None,
+            address: None,
+            offset: None,
+            length: None,
+            address_and_length_format_identifier: None,
+            data_format_identifier: None,
+            compression: None,
+            max_reconnects: None,
+        }
+    }
+
+/*****************************************************************************************************************
+ *  executor::parameters::SwdlParams::validate function
+ *  brief      Check swdl parameters which cannot be expressed by their types
+ *  details    Used by swdl steps and by files of flash packages
+ *  \param[in]  -
+ *  \param[out] -
+ *  \precondition -
+ *  \reentrant:  TRUE
+ *  \return     error message if parameters are not consistent
+ ****************************************************************************************************************/
+pub fn validate(&self) -> Result<(), String> {
+    for routine in [self.erase_routine, self.check_routine].iter().flatten() {
+        if routine.0 > 0xFFFF {
+            return Err(format!("swdl: routine identifier 0x{:X} is longer than 2 bytes", routine.0));
+        }
+    }
+    validate_alfid("swdl", self.address_and_length_format_identifier)?;
+    if self.data_format_identifier.is_some_and(|dfi| dfi.0 > 0xFF) {
+        return Err(String::from("swdl: dataFormatIdentifier is longer than 1 byte"));
+    }
+    if let Some(compression) = &self.compression {
+        let compressor = match compression::find(compression) {
+            Some(compressor) => compressor,
+            None => return Err(format!("swdl: compression \"{}\" is not supported", compression)),
+        };
+        if self.format == SwdlFormat::Vbf {
+            return Err(String::from("swdl: vbf data is downloaded as stored, its header tells if it is compressed"));
+        }
+        if self.data_format_identifier.is_some_and(|dfi| dfi.0 >> 4 != compressor.method() as u32) {
+            return Err(format!("swdl: dataFormatIdentifier compressionMethod does not match {} method {}",
+                               compression, compressor.method()));
+        }
+    }
+    let has_bin_params = self.address.is_some() || self.offset.is_some() || self.length.is_some();
+    if self.format == SwdlFormat::Bin && self.address.is_none() {
+        return Err(String::from("swdl: bin format needs start address, ex: \"address:0x00080000\""));
+    }
+    if self.format != SwdlFormat::Bin && has_bin_params {
+        return Err(String::from("swdl: address, offset and length are only used by bin format"));
+    }
+    Ok(())
+}
+}
+
+/* Service used to read memory by upload step */
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum UploadMethod {
+    Auto,         // RequestUpload, ReadMemoryByAddress if ECU rejects it
+    RequestUpload,
+    ReadMemory,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct UploadParams {
+    pub path: String, // dump file
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub format: Option<SwdlFormat>, // bin, hex or srec, from extension of path if not set
+    pub address: HexValue,
+    pub length: HexValue,
+    // 0x44 (4 bytes address, 4 bytes length) and 0x00 (no compression/encryption) if not set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub address_and_length_format_identifier: Option<HexValue>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub data_format_identifier: Option<HexValue>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub method: Option<UploadMethod>,
+    // bytes per ReadMemoryByAddress request, 0x100 if not set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub block_length: Option<HexValue>,
+    // vbf/hex/srec/bin image compared with the dump, bin is placed at address
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reference: Option<String>,
+}
+
+impl UploadParams {
+    pub fn output_format(&self) -> SwdlFormat {
+        self.format.or_else(|| SwdlFormat::from_path(&self.path)).unwrap_or(SwdlFormat::Bin)
+    }
+}
+
+/* modeOfOperation of RequestFileTransfer */
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FileTransferMode {
+    Add,
+    Delete,
+    Replace,
+    Read,
+    ReadDir,
+    Resume,
+}
+
+impl FileTransferMode {
+    pub fn mode_of_operation(&self) -> u8 {
+        match self {
+            FileTransferMode::Add => 0x01,
+            FileTransferMode::Delete => 0x02,
+            FileTransferMode::Replace => 0x03,
+            FileTransferMode::Read => 0x04,
+            FileTransferMode::ReadDir => 0x05,
+            FileTransferMode::Resume => 0x06,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct FileTransferParams {
+    pub mode: FileTransferMode,
+    pub remote_path: String, // filePathAndName on ECU
+    // file sent by add/replace/resume, written by read. read_dir writes directory info, or prints it if not set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub local_path: Option<String>,
+    // 0x00 (no compression/encryption) if not set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub data_format_identifier: Option<HexValue>,
+}
+
+impl FileTransferParams {
+    pub fn new(mode: FileTransferMode, remote_path: String, local_path: Option<String>) -> Self {
+        FileTransferParams { mode, remote_path, local_path, data_format_identifier: None }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct FlashPackageParams {
+    pub path: String, // package directory, zip file or manifest file
+}
+
+/* Sub-functions of Authentication (0x29) with PKI certificate exchange */
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AuthenticationMode {
+    Deauthenticate,
+    Unidirectional,
+    Bidirectional,
+    TransmitCertificate,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct AuthenticationParams {
+    pub mode: AuthenticationMode,
+    // tester X.509 certificate (PEM/DER) and its ECDSA P-256 or RSA private key
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub certificate: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub private_key: Option<String>,
+    // PEM/DER certificates trusted to issue the ECU certificate, required by bidirectional
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub trust_anchor: Option<String>,
+    // communicationConfiguration byte, 0x00 if not set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub communication_configuration: Option<HexValue>,
+    // certificateEvaluationId of transmit_certificate, 0x0000 if not set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub certificate_evaluation_id: Option<HexValue>,
+}
+
+/* Step of a sequence item, tagged by the item name. "action" holds the requests or parameters of the step */
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(tag = "name", rename_all = "snake_case", deny_unknown_fields)]
+pub enum Step {
+    Socket { action: SocketAction },
+    SendDoip {
+        action: DoipAction,
+        #[serde(default, deserialize_with = "deserialize_one_or_many", skip_serializing_if = "Vec::is_empty")]
+        expect: Vec<ExpectPattern>, // not checked for activation, its response is checked by doip layer
+    },
+    SendDiag {
+        #[serde(rename = "action", deserialize_with = "deserialize_one_or_many")]
+        requests: Vec<HexBytes>,
+        #[serde(default, deserialize_with = "deserialize_one_or_many", skip_serializing_if = "Vec::is_empty")]
+        expect: Vec<ExpectPattern>,
+        #[serde(default, skip_serializing_if = "SecuredRequests::is_off")]
+        secured: SecuredRequests,
+    },
+    #[serde(rename = "securityaccess")] // written as securityaccess_XX, see RawSequenceItem
+    SecurityAccess {
+        #[serde(deserialize_with = "deserialize_level")]
+        level: u8,
+        #[serde(rename = "action", deserialize_with = "deserialize_params")]
+        params: SecurityAccessParams,
+        #[serde(default, deserialize_with = "deserialize_one_or_many", skip_serializing_if = "Vec::is_empty")]
+        expect: Vec<ExpectPattern>,
+    },
+    Swdl {
+        #[serde(rename = "action", deserialize_with = "deserialize_params")]
+        params: SwdlParams,
+    },
+    Upload {
+        #[serde(rename = "action", deserialize_with = "deserialize_params")]
+        params: UploadParams,
+    },
+    FileTransfer {
+        #[serde(rename = "action", deserialize_with = "deserialize_params")]
+        params: FileTransferParams,
+    },
+    FlashPackage {
+        #[serde(rename = "action", deserialize_with = "deserialize_package_params")]
+        params: FlashPackageParams,
+    },
+    Authentication {
+        #[serde(rename = "action", deserialize_with = "deserialize_authentication_params")]
+        params: AuthenticationParams,
+    },
+    Delay {
+        #[serde(rename = "action", default, deserialize_with = "deserialize_note", skip_serializing_if = "String::is_empty")]
+        note: String, // Ex: "wait for activating SBL"
+    },
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(try_from = "RawSequenceItem", into = "RawSequenceItem")]
 pub struct SequenceItem {
+    pub description: String,
+    pub step: Step,
+    pub timeout: String,
+    pub fail: String,
+}
+
+/* On-disk layout of a sequence item. "name" tags the Step read from the other fields (action and, for send_diag,
+   secured). securityaccess_XX names carry the level of the step. expect is ignored by steps without responses */
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct RawSequenceItem {
     pub name: String,
+    #[serde(default)]
     pub description: String,
-    pub action: serde_json::Value, // Use serde_json::Value to handle dynamic action data
-    pub expect: serde_json::Value, // Use serde_json::Value to handle dynamic expect data
+    #[serde(flatten)]
+    pub fields: Map<String, Value>,
+    #[serde(default, skip_serializing_if = "Value::is_null")]
+    pub expect: Value,
+    #[serde(default = "default_timeout")]
     pub timeout: String,
+    #[serde(default)]
     pub fail: String,
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+fn default_timeout() -> String {
+    String::from("1s")
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct FailHandler {
     pub send_diag: SequenceItem,
 }
 
 #[derive(Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
 pub struct Sequence {
     pub sequence: Vec<SequenceItem>,
     pub fail_handler: FailHandler,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub cleanup: Vec<SequenceItem>, // Items executed when sequence is aborted
+}
+
+
+fn validate_alfid(name: &str, alfid: Option<HexValue>) -> Result<(), String> {
+    if let Some(alfid) = alfid {
+        let (length_size, address_size) = (alfid.0 >> 4, alfid.0 & 0x0F);
+        if alfid.0 > 0xFF || !(1..=4).contains(&length_size) || !(1..=4).contains(&address_size) {
+            return Err(format!("{}: addressAndLengthFormatIdentifier 0x{:X} is not supported, use 1 to 4 bytes address and length", name, alfid.0));
+        }
+    }
+    Ok(())
+}
+
+/* Hex key, reference "@name" to a key of the keystore which is resolved when the step is executed, or
+   "pkcs11:<label>" of a key which stays on the token */
+fn validate_hex_key(name: &str, key: &str) -> Result<(), String> {
+    if let Some(reference) = utils::keystore::check_reference(key) {
+        return reference.map_err(|err| format!("{}: {}", name, err));
+    }
+    if let Some(label) = utils::pkcs11::key_label(key) {
+        return if label.is_empty() { Err(format!("{}: pkcs11 key label is empty", name)) } else { Ok(()) };
+    }
+    match hex::decode(key.strip_prefix("0x").or_else(|| key.strip_prefix("0X")).unwrap_or(key)) {
+        Ok(_) => Ok(()),
+        Err(err) => Err(format!("{} is not a hex key: {}", name, err)),
+    }
 }
 
+impl Step {
 
-// lazy_static::lazy_static! {
-//     pub static ref PARAMETERS: RwLock<Parameters> = RwLock::new(Parameters {
-//         vin: String::new(),
-//         tester_present: false,
-//         tester_present_interval: String::new(),
-//     });
-// }
\ No newline at end of file
+/*****************************************************************************************************************
+ *  executor::parameters::Step::validate function
+ *  brief      Check step parameters which cannot be expressed by their types
+ *  details    -
+ *  \param[in]  -
+ *  \param[out] -
+ *  \precondition -
+ *  \reentrant:  TRUE
+ *  \return     error message starting with the item name if parameters are not consistent
+ ****************************************************************************************************************/
+pub fn validate(&self) -> Result<(), String> {
+    match self {
+        Step::SendDiag { requests, secured: SecuredRequests::PerRequest(flags), .. } if flags.len() != requests.len() => {
+            Err(format!("send_diag: secured has {} entries for {} requests", flags.len(), requests.len()))
+        }
+        Step::SecurityAccess { level, params, .. } => {
+            let name = format!("securityaccess_{:02X}", level);
+            if params.max_attempts == Some(0) {
+                return Err(format!("{}: max_attempts should be at least 1", name));
+            }
+            if let Some(wait_delay) = &params.wait_delay {
+                match utils::common::parse_duration_to_milliseconds(wait_delay) {
+                    Some(milliseconds) if milliseconds <= securityaccess::MAX_WAIT_DELAY_MS => {}
+                    Some(_) => return Err(format!("{}: wait_delay \"{}\" is longer than {} minutes", name, wait_delay,
+                                                  securityaccess::MAX_WAIT_DELAY_MS / 60_000)),
+                    None => return Err(format!("{}: invalid wait_delay \"{}\"", name, wait_delay)),
+                }
+            }
+            if params.library.is_some() || seedkey::find(&params.algorithm).is_some() {
+                if level % 2 == 0 {
+                    return Err(format!("{}: requestSeed level should be odd, key is sent with level + 1", name));
+                }
+                if let Some(secret) = &params.secret {
+                    validate_hex_key("secret", secret).map_err(|err| format!("{}: {}", name, err))?;
+                }
+            } else {
+                let algorithm = securityaccess::VolvoAlgorithm::from_name(&params.algorithm)
+                    .ok_or_else(|| format!("{}: {}", name, securityaccess::VolvoSaError::UnknownAlgorithm(params.algorithm.clone())))?;
+                securityaccess::VolvoIv::parse(&params.iv).map_err(|err| format!("{}: {}", name, err))?;
+                for (key_name, key) in [("encryption_authentication_key", &params.encryption_authentication_key),
+                                        ("proof_of_ownership_key", &params.proof_of_ownership_key)] {
+                    if key.is_empty() {
+                        return Err(format!("{}: {} is required by algorithm {}", name, key_name, params.algorithm));
+                    }
+                    validate_hex_key(key_name, key).map_err(|err| format!("{}: {}", name, err))?;
+                    securityaccess::check_key_length(key_name, key, algorithm).map_err(|err| format!("{}: {}", name, err))?;
+                }
+            }
+            Ok(())
+        }
+        Step::Swdl { params } => params.validate(),
+        Step::Upload { params } => {
+            validate_alfid("upload", params.address_and_length_format_identifier)?;
+            if params.data_format_identifier.is_some_and(|dfi| dfi.0 > 0xFF) {
+                return Err(String::from("upload: dataFormatIdentifier is longer than 1 byte"));
+            }
+            if params.output_format() == SwdlFormat::Vbf {
+                return Err(String::from("upload: dump is written as bin, hex or srec"));
+            }
+            if params.length.0 == 0 || params.address.0 as u64 + params.length.0 as u64 > u32::MAX as u64 + 1 {
+                return Err(format!("upload: memory 0x{:X} length 0x{:X} is empty or beyond 32-bit address space",
+                                   params.address.0, params.length.0));
+            }
+            if params.block_length.is_some_and(|block_length| block_length.0 == 0) {
+                return Err(String::from("upload: block_length should not be 0"));
+            }
+            Ok(())
+        }
+        Step::FileTransfer { params } => {
+            if params.remote_path.is_empty() || params.remote_path.len() > 0xFFFF {
+                return Err(String::from("file_transfer: remote_path should have 1 to 65535 bytes"));
+            }
+            match (params.mode, &params.local_path) {
+                (FileTransferMode::Delete, Some(_)) => {
+                    return Err(String::from("file_transfer: delete mode does not use local_path"));
+                }
+                (FileTransferMode::Delete, None) | (FileTransferMode::ReadDir, _) | (_, Some(_)) => {}
+                (mode, None) => return Err(format!("file_transfer: {:?} mode needs local_path", mode)),
+            }
+            if params.data_format_identifier.is_some_and(|dfi| dfi.0 > 0xFF) {
+                return Err(String::from("file_transfer: dataFormatIdentifier is longer than 1 byte"));
+            }
+            Ok(())
+        }
+        Step::FlashPackage { params } if params.path.trim().is_empty() => {
+            Err(String::from("flash_package: path should not be empty"))
+        }
+        Step::Authentication { params } => {
+            let required: &[(&str, &Option<String>)] = match params.mode {
+                AuthenticationMode::Deauthenticate => &[],
+                AuthenticationMode::Unidirectional => &[("certificate", &params.certificate), ("private_key", &params.private_key)],
+                AuthenticationMode::Bidirectional => &[("certificate", &params.certificate), ("private_key", &params.private_key),
+                                                       ("trust_anchor", &params.trust_anchor)],
+                AuthenticationMode::TransmitCertificate => &[("certificate", &params.certificate)],
+            };
+            if let Some((field, _)) = required.iter().find(|(_, value)| value.as_deref().is_none_or(|path| path.trim().is_empty())) {
+                return Err(format!("authentication: {:?} mode needs {}", params.mode, field));
+            }
+            if params.communication_configuration.is_some_and(|configuration| configuration.0 > 0xFF) {
+                return Err(String::from("authentication: communicationConfiguration is longer than 1 byte"));
+            }
+            if params.certificate_evaluation_id.is_some_and(|id| id.0 > 0xFFFF) {
+                return Err(String::from("authentication: certificateEvaluationId is longer than 2 bytes"));
+            }
+            Ok(())
+        }
+        _ => Ok(()),
+    }
+}
+}
+
+impl TryFrom<RawSequenceItem> for SequenceItem {
+    type Error = String;
+
+    fn try_from(raw: RawSequenceItem) -> Result<Self, Self::Error> {
+        if utils::common::parse_duration_to_milliseconds(&raw.timeout).is_none() {
+            return Err(format!("{}: invalid timeout \"{}\"", raw.name, raw.timeout));
+        }
+        let mut fields = raw.fields;
+        let tag = match raw.name.strip_prefix("securityaccess_") {
+            Some(level) => {
+                fields.insert(String::from("level"), Value::String(level.to_string()));
+                "securityaccess"
+            }
+            None => raw.name.as_str(),
+        };
+        fields.insert(String::from("name"), Value::String(tag.to_string()));
+        if has_expect(tag) && !raw.expect.is_null() {
+            fields.insert(String::from("expect"), raw.expect);
+        }
+        let step: Step = serde_json::from_value(Value::Object(fields)).map_err(|err| format!("{}: {}", raw.name, err))?;
+        step.validate()?;
+        Ok(SequenceItem { description: raw.description, step, timeout: raw.timeout, fail: raw.fail })
+    }
+}
+
+impl From<SequenceItem> for RawSequenceItem {
+    fn from(item: SequenceItem) -> Self {
+        let name = item.name();
+        let mut fields = match serde_json::to_value(&item.step) {
+            Ok(Value::Object(fields)) => fields,
+            _ => Map::new(),
+        };
+        // tag and level are written as the item name
+        fields.remove("name");
+        fields.remove("level");
+        let expect = fields.remove("expect").unwrap_or(Value::Null);
+        RawSequenceItem { name, description: item.description, fields, expect, timeout: item.timeout, fail: item.fail }
+    }
+}
+
+/* Steps checking responses with expect patterns, sequence files also set expect of socket, delay or swdl items */
+fn has_expect(tag: &str) -> bool {
+    matches!(tag, "send_doip" | "send_diag" | "securityaccess")
+}
+
+impl SequenceItem {
+
+/*****************************************************************************************************************
+ *  executor::parameters::SequenceItem::from_raw function
+ *  brief      Build and validate a sequence item from name, action and expect values
+ *  details    Used by CLI and GUI to create items with the same rules as sequence files
+ *  \param[in]  raw: item in on-disk layout
+ *  \param[out] -
+ *  \precondition -
+ *  \reentrant:  TRUE
+ *  \return     SequenceItem or InvalidInput error
+ ****************************************************************************************************************/
+pub fn from_raw(raw: RawSequenceItem) -> Result<SequenceItem, io::Error> {
+    SequenceItem::try_from(raw).map_err(|err| Error::new(ErrorKind::InvalidInput, err))
+}
+
+pub fn name(&self) -> String {
+    match &self.step {
+        Step::Socket { .. } => String::from("socket"),
+        Step::SendDoip { .. } => String::from("send_doip"),
+        Step::SendDiag { .. } => String::from("send_diag"),
+        Step::SecurityAccess { level, .. } => format!("securityaccess_{:02X}", level),
+        Step::Swdl { .. } => String::from("swdl"),
+        Step::Upload { .. } => String::from("upload"),
+        Step::FileTransfer { .. } => String::from("file_transfer"),
+        Step::FlashPackage { .. } => String::from("flash_package"),
+        Step::Authentication { .. } => String::from("authentication"),
+        Step::Delay { .. } => String::from("delay"),
+    }
+}
+
+pub fn expect_strings(&self) -> Vec<String> {
+    match &self.step {
+        Step::SendDoip { expect, .. } | Step::SendDiag { expect, .. } | Step::SecurityAccess { expect, .. } => {
+            expect.iter().map(|pattern| pattern.as_str().to_string()).collect()
+        }
+        _ => Vec::new(),
+    }
+}
+
+}
@@ -1,91 +1,296 @@
 use log::debug;
 use std::sync::{Arc, Mutex};
-use std::fs::File;
+use std::fs::{self, File};
 use std::io::{self, Read, Error, ErrorKind};
+use std::path::Path;
+use std::time::Instant;
 
-use crate::executor::parameters::Sequence;
+use crate::executor::parameters::{Sequence, SequenceItem};
 use crate::executor::executor::Executor;
+use crate::executor::report::RunRecord;
+use crate::executor::control::{RunState, SEQUENCE_CONTROL};
 use crate::transport::config::CONFIG;
 
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SequenceFormat {
+    Json,
+    Yaml,
+    Toml,
+}
+
+impl SequenceFormat {
 /*****************************************************************************************************************
- *  executor::parse function
- *  brief      parse_content json file to get sequence parameters
- *  details    -
- *  \param[in]  json_contents  json file content
+ *  executor::SequenceFormat::from_path function
+ *  brief      Select sequence file format by file extension
+ *  details    .yaml/.yml is YAML, .toml is TOML, anything else is JSON
+ *  \param[in]  sequence_filename  path to sequence file
+ *  \param[out] -
+ *  \precondition -
+ *  \reentrant:  TRUE
+ *  \return     SequenceFormat
+ ****************************************************************************************************************/
+pub fn from_path(sequence_filename: &str) -> SequenceFormat {
+    let extension = Path::new(sequence_filename)
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .unwrap_or("")
+        .to_lowercase();
+    match extension.as_str() {
+        "yaml" | "yml" => SequenceFormat::Yaml,
+        "toml" => SequenceFormat::Toml,
+        _ => SequenceFormat::Json,
+    }
+}
+}
+
+
+/*****************************************************************************************************************
+ *  executor::load_content function
+ *  brief      Deserialize sequence content without executing it
+ *  details    All formats are deserialized to the same Sequence model
+ *  \param[in]  contents  sequence file content
+ *              format  format of content
+ *  \param[out] -
+ *  \precondition -
+ *  \reentrant:  TRUE
+ *  \return     Sequence object
+ ****************************************************************************************************************/
+pub fn load_content(contents: &str, format: SequenceFormat) -> Result<Sequence, io::Error> {
+    let result: Result<Sequence, String> = match format {
+        SequenceFormat::Json => serde_json::from_str(contents).map_err(|err| err.to_string()),
+        SequenceFormat::Yaml => serde_yaml::from_str(contents).map_err(|err| err.to_string()),
+        SequenceFormat::Toml => toml::from_str(contents).map_err(|err| err.to_string()),
+    };
+    match result {
+        Ok(obj) => Ok(obj),
+        Err(err) => {
+            eprintln!("Failed to parse {:?} sequence: {}", format, err);
+            Err(Error::new(ErrorKind::InvalidData, format!("wrong sequence {:?} format", format)))
+        }
+    }
+}
+
+
+/*****************************************************************************************************************
+ *  executor::load function
+ *  brief      Read sequence file without executing it
+ *  details    Format is selected by file extension
+ *  \param[in]  sequence_filename  path to sequence file
+ *  \param[out] -
+ *  \precondition -
+ *  \reentrant:  TRUE
+ *  \return     Sequence object
+ ****************************************************************************************************************/
+pub fn load(sequence_filename: &str) -> Result<Sequence, io::Error> {
+    let contents = read_sequence_file(sequence_filename)?;
+    load_content(&contents, SequenceFormat::from_path(sequence_filename))
+}
+
+
+/*****************************************************************************************************************
+ *  executor::save function
+ *  brief      Write sequence object to file
+ *  details    Format is selected by file extension, used to convert sequences between formats
+ *  \param[in]  seq_obj  sequence object
+ *              sequence_filename  path to output sequence file
  *  \param[out] -
  *  \precondition -
  *  \reentrant:  FALSE
- *  \return -
+ *  \return     error code if any
  ****************************************************************************************************************/
-pub fn parse_content(json_contents: String, executor_obj: Arc<Mutex<Executor>>) -> Result<(), io::Error> {
-    let config = CONFIG.read().unwrap();
+pub fn save(seq_obj: &Sequence, sequence_filename: &str) -> Result<(), io::Error> {
+    let format = SequenceFormat::from_path(sequence_filename);
+    let result: Result<String, String> = match format {
+        SequenceFormat::Json => serde_json::to_string_pretty(seq_obj).map_err(|err| err.to_string()),
+        SequenceFormat::Yaml => serde_yaml::to_string(seq_obj).map_err(|err| err.to_string()),
+        SequenceFormat::Toml => toml::to_string_pretty(seq_obj).map_err(|err| err.to_string()),
+    };
+    match result {
+        Ok(contents) => fs::write(sequence_filename, contents),
+        Err(err) => {
+            eprintln!("Failed to serialize {:?} sequence: {}", format, err);
+            Err(Error::new(ErrorKind::InvalidData, err))
+        }
+    }
+}
+
 
-    // Deserialize the JSON content
-    let seq_obj: Sequence = match serde_json::from_str(&json_contents) {
-        Ok(obj) => obj,
+/*****************************************************************************************************************
+ *  executor::convert function
+ *  brief      Convert sequence file to another format
+ *  details    Ex: sequence.json -> sequence.yaml. Input is loaded and validated with the same model as execution
+ *  \param[in]  input_filename  path to input sequence file
+ *              output_filename  path to output sequence file
+ *  \param[out] -
+ *  \precondition -
+ *  \reentrant:  FALSE
+ *  \return     error code if any
+ ****************************************************************************************************************/
+pub fn convert(input_filename: &str, output_filename: &str) -> Result<(), io::Error> {
+    let seq_obj = load(input_filename)?;
+    save(&seq_obj, output_filename)?;
+    // Round-trip check, converted file must load to the same sequence
+    let converted_obj = load(output_filename)?;
+    if serde_json::to_value(&seq_obj).ok() != serde_json::to_value(&converted_obj).ok() {
+        return Err(Error::new(ErrorKind::InvalidData, "converted sequence does not match input"));
+    }
+    debug!("Converted {} to {}", input_filename, output_filename);
+    Ok(())
+}
+
+
+fn read_sequence_file(sequence_filename: &str) -> Result<String, io::Error> {
+    let mut contents = String::new();
+    match File::open(sequence_filename) {
+        Ok(mut file) => {
+            if let Err(err) = file.read_to_string(&mut contents) {
+                eprintln!("Failed to read file: {}", err);
+                return Err(Error::new(ErrorKind::InvalidData, "Cannot read sequence file"));
+            }
+        }
         Err(err) => {
-            eprintln!("Failed to parse JSON: {}", err);
-            return Err(Error::new(ErrorKind::InvalidData, "wrong sequence json format"));
+            eprintln!("Failed to open sequence file: {}", err);
+            return Err(Error::new(ErrorKind::NotFound, "Not found sequence file"));
         }
     };
+    Ok(contents)
+}
+
+
+/*****************************************************************************************************************
+ *  executor::parse function
+ *  brief      parse_content sequence file to get sequence parameters and execute them
+ *  details    -
+ *  \param[in]  contents  sequence file content
+ *              format  format of content
+ *  \param[out] record  result of every executed item, remaining items are marked skipped on failure
+ *  \precondition -
+ *  \reentrant:  FALSE
+ *  \return -
+ ****************************************************************************************************************/
+pub fn parse_content(contents: String, format: SequenceFormat, executor_obj: Arc<Mutex<Executor>>,
+                     record: &mut RunRecord) -> Result<(), io::Error> {
+    // Deserialize the sequence content
+    let seq_obj: Sequence = load_content(&contents, format)?;
 
     // Check tester-present
-    if config.parameter.tester_present == true {
-        match Executor::start_tester_present(Arc::clone(&executor_obj), config.parameter.tester_present_interval.to_string())  {
-            Ok(()) => debug!("start tester present successfully!"),
-            Err(err) => {
-                eprintln!("Error start tester present: {}, STOP", err);
-                return Err(err);
+    start_tester_present(&executor_obj)?;
+
+    let config = CONFIG.read().unwrap();
+    SEQUENCE_CONTROL.start();
+    let mut items = seq_obj.sequence.into_iter();
+    while let Some(item) = items.next() {
+        // Pause/abort is only handled between items
+        if SEQUENCE_CONTROL.wait_if_paused() == RunState::Aborted {
+            record.skip_step(&item);
+            for remaining_item in items.by_ref() {
+                record.skip_step(&remaining_item);
             }
+            run_cleanup(seq_obj.cleanup, &executor_obj, &config.ethernet.vendor, record);
+            SEQUENCE_CONTROL.finish();
+            return Err(Error::new(ErrorKind::Interrupted, "sequence aborted"));
         }
-    }
 
-    for item in seq_obj.sequence {
         // Access fields of the SequenceItem struct for processing
-        debug!("Name: {}", item.name);
+        debug!("Name: {}", item.name());
         debug!("Description: {}", item.description);
-        debug!("Action: {:?}", item.action);
-        debug!("Expect: {:?}", item.expect);
+        debug!("Step: {:?}", item.step);
 
-        match Executor::execute_cmd(Arc::clone(&executor_obj), item, &config.ethernet.vendor) {
+        let start_time = Instant::now();
+        let result = Executor::execute_cmd(Arc::clone(&executor_obj), item.clone(), &config.ethernet.vendor);
+        let exchanges = executor_obj.lock().unwrap().take_last_trace();
+        record.add_step(&item, exchanges, start_time.elapsed().as_millis() as u64, &result);
+        match result {
             Ok(()) => debug!("Command executed successfully!"),
             Err(err) => {
                 eprintln!("Error executing command: {}, STOP", err);
+                for remaining_item in items.by_ref() {
+                    record.skip_step(&remaining_item);
+                }
+                if SEQUENCE_CONTROL.is_aborted() {
+                    run_cleanup(seq_obj.cleanup, &executor_obj, &config.ethernet.vendor, record);
+                }
+                SEQUENCE_CONTROL.finish();
                 return Err(err);
             }
         }
     }
+    SEQUENCE_CONTROL.finish();
 
     Ok(())
 }
 
 
 /*****************************************************************************************************************
- *  executor::parse function
- *  brief      Parse json file to get sequence parameters
- *  details    -
- *  \param[in]  sequence_filename  path to sequence json file
+ *  executor::start_tester_present function
+ *  brief      Start tester-present before a sequence is executed, if it is enabled in config
+ *  details    Used by sequence runner and step mode, so sessions do not time out between items
+ *  \param[in]  executor_obj  executor object
  *  \param[out] -
- *  \precondition -
+ *  \precondition config is parsed
  *  \reentrant:  FALSE
- *  \return -
+ *  \return     true if tester-present was started, error code if it could not be started
  ****************************************************************************************************************/
-pub fn parse(sequence_filename: String, executor_obj: Arc<Mutex<Executor>>) -> Result<(), io::Error> {
-    // Read the JSON file
-    let mut json_contents = String::new();
-    match File::open(&sequence_filename) {
-        Ok(mut file) => {
-            file.read_to_string(&mut json_contents).expect("Failed to read file");
-            if let Err(err) = file.read_to_string(&mut json_contents) {
-                eprintln!("Failed to read file: {}", err);
-                return Err(Error::new(ErrorKind::InvalidData, "Cannot read sequence file"));
-            }
+pub fn start_tester_present(executor_obj: &Arc<Mutex<Executor>>) -> Result<bool, io::Error> {
+    let config = CONFIG.read().unwrap();
+    if !config.parameter.tester_present {
+        return Ok(false);
+    }
+    match Executor::start_tester_present(Arc::clone(executor_obj), config.parameter.tester_present_interval.to_string()) {
+        Ok(()) => {
+            debug!("start tester present successfully!");
+            Ok(true)
         }
         Err(err) => {
-            eprintln!("Failed to open config file: {}", err);
-            return Err(Error::new(ErrorKind::NotFound, "Not found sequence file"));
+            eprintln!("Error start tester present: {}, STOP", err);
+            Err(err)
         }
-    };
+    }
+}
+
+
+/*****************************************************************************************************************
+ *  executor::run_cleanup function
+ *  brief      Execute cleanup items of an aborted sequence
+ *  details    Every cleanup item is executed even if a previous one failed, to leave ECU in a safe state.
+ *             Sequence control leaves the aborted state so delays and downloads of cleanup are executed, caller
+ *             marks the sequence finished afterwards
+ *  \param[in]  cleanup  cleanup items of sequence
+ *  \param[out] record  result of every cleanup item
+ *  \precondition -
+ *  \reentrant:  FALSE
+ *  \return -
+ ****************************************************************************************************************/
+fn run_cleanup(cleanup: Vec<SequenceItem>, executor_obj: &Arc<Mutex<Executor>>, vendor: &str, record: &mut RunRecord) {
+    SEQUENCE_CONTROL.start_cleanup();
+    for item in cleanup {
+        debug!("Cleanup: {}", item.description);
+        let start_time = Instant::now();
+        let result = Executor::execute_cmd(Arc::clone(executor_obj), item.clone(), vendor);
+        let exchanges = executor_obj.lock().unwrap().take_last_trace();
+        record.add_step(&item, exchanges, start_time.elapsed().as_millis() as u64, &result);
+        if let Err(err) = result {
+            eprintln!("Error executing cleanup command: {}, continue", err);
+        }
+    }
+}
+
+
+/*****************************************************************************************************************
+ *  executor::parse function
+ *  brief      Parse sequence file to get sequence parameters
+ *  details    json, yaml and toml sequence files are supported, selected by file extension
+ *  \param[in]  sequence_filename  path to sequence file
+ *  \param[out] record  result of every executed item
+ *  \precondition -
+ *  \reentrant:  FALSE
+ *  \return -
+ ****************************************************************************************************************/
+pub fn parse(sequence_filename: String, executor_obj: Arc<Mutex<Executor>>,
+             record: &mut RunRecord) -> Result<(), io::Error> {
+    // Read the sequence file, format is selected by extension
+    let contents = read_sequence_file(&sequence_filename)?;
+    let format = SequenceFormat::from_path(&sequence_filename);
 
-    return parse_content(json_contents, executor_obj);
+    parse_content(contents, format, executor_obj, record)
 }
\ No newline at end of file
@@ -0,0 +1,185 @@
+use serde::Serialize;
+use std::sync::Mutex;
+use std::time::Instant;
+
+/*****************************************************************************************************************
+ *  Define all gloval macro & variable here
+ ****************************************************************************************************************/
+lazy_static::lazy_static! {
+    pub static ref FLASH_PROGRESS: ProgressPublisher = ProgressPublisher::new();
+}
+const PUBLISH_INTERVAL_MS: u128 = 100; //min time between download progress events of the same percent
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FlashPhase {
+    Erase,
+    Download,
+    Check,
+    Upload,
+    Done,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ProgressEvent {
+    pub file: String,
+    pub phase: FlashPhase,
+    pub block_index: usize, // index of erase range in erase phase, of data block in download phase
+    pub block_count: usize,
+    pub bytes_sent: u64,
+    pub bytes_total: u64,
+    pub throughput: u64, // bytes per second
+    pub eta_ms: Option<u64>,
+}
+
+type ProgressListener = Box<dyn Fn(&ProgressEvent) + Send>;
+
+pub struct ProgressPublisher {
+    listeners: Mutex<Vec<ProgressListener>>,
+}
+
+impl ProgressPublisher {
+
+pub fn new() -> Self {
+    ProgressPublisher { listeners: Mutex::new(Vec::new()) }
+}
+
+/*****************************************************************************************************************
+ *  executor::progress::subscribe function
+ *  brief      Register a listener called for every flashing progress event
+ *  details    Listener is called from the flashing thread, it should return quickly
+ *  \param[in]  listener: callback receiving progress events
+ *  \param[out] -
+ *  \precondition -
+ *  \reentrant:  TRUE
+ *  \return -
+ ****************************************************************************************************************/
+pub fn subscribe<F: Fn(&ProgressEvent) + Send + 'static>(&self, listener: F) {
+    self.listeners.lock().unwrap().push(Box::new(listener));
+}
+
+pub fn publish(&self, event: &ProgressEvent) {
+    for listener in self.listeners.lock().unwrap().iter() {
+        listener(event);
+    }
+}
+
+}
+
+
+/*****************************************************************************************************************
+ *  Progress of one software file, computes throughput and ETA of download phase
+ ****************************************************************************************************************/
+pub struct FileProgress {
+    file: String,
+    phase: FlashPhase,
+    block_index: usize,
+    block_count: usize,
+    bytes_sent: u64,
+    bytes_total: u64,
+    download_start: Option<Instant>,
+    download_end: Option<Instant>,
+    published_at: Option<Instant>,
+    published_percent: u64,
+}
+
+impl FileProgress {
+
+pub fn new(file: &str, bytes_total: u64) -> Self {
+    FileProgress {
+        file: file.to_string(),
+        phase: FlashPhase::Erase,
+        block_index: 0,
+        block_count: 0,
+        bytes_sent: 0,
+        bytes_total,
+        download_start: None,
+        download_end: None,
+        published_at: None,
+        published_percent: 0,
+    }
+}
+
+/*****************************************************************************************************************
+ *  executor::progress::FileProgress::start_phase function
+ *  brief      Enter a new phase of flashing and publish it
+ *  details    -
+ *  \param[in]  phase: new phase
+ *              block_count: number of erase ranges or data blocks of the phase
+ *  \param[out] -
+ *  \precondition -
+ *  \reentrant:  FALSE
+ *  \return -
+ ****************************************************************************************************************/
+pub fn start_phase(&mut self, phase: FlashPhase, block_count: usize) {
+    if (self.phase == FlashPhase::Download || self.phase == FlashPhase::Upload) && phase != self.phase {
+        self.download_end = Some(Instant::now());
+    }
+    self.phase = phase;
+    self.block_index = 0;
+    self.block_count = block_count;
+    if (phase == FlashPhase::Download || phase == FlashPhase::Upload) && self.download_start.is_none() {
+        self.download_start = Some(Instant::now());
+    }
+    self.publish();
+}
+
+pub fn set_block(&mut self, block_index: usize) {
+    let changed = block_index != self.block_index;
+    self.block_index = block_index;
+    // download progress is published by add_bytes, start_phase already published the first block
+    if changed && self.phase != FlashPhase::Download {
+        self.publish();
+    }
+}
+
+// Called for every TransferData block, publish only when the percent changes or some time has passed
+pub fn add_bytes(&mut self, bytes: usize) {
+    self.bytes_sent += bytes as u64;
+    let due = self.published_at.is_none_or(|at| at.elapsed().as_millis() >= PUBLISH_INTERVAL_MS);
+    if due || self.percent() != self.published_percent {
+        self.publish();
+    }
+}
+
+// Download starts again from erase, bytes sent so far do not count anymore
+pub fn restart(&mut self) {
+    self.bytes_sent = 0;
+    self.download_start = None;
+    self.download_end = None;
+}
+
+pub fn finish(&mut self) {
+    self.start_phase(FlashPhase::Done, 0);
+}
+
+fn percent(&self) -> u64 {
+    (self.bytes_sent * 100).checked_div(self.bytes_total).unwrap_or(100).min(100)
+}
+
+fn publish(&mut self) {
+    self.published_at = Some(Instant::now());
+    self.published_percent = self.percent();
+    let elapsed_ms = match (self.download_start, self.download_end) {
+        (Some(start), Some(end)) => end.duration_since(start).as_millis() as u64,
+        (Some(start), None) => start.elapsed().as_millis() as u64,
+        _ => 0,
+    };
+    let throughput = (self.bytes_sent * 1000).checked_div(elapsed_ms).unwrap_or(0);
+    let eta_ms = match self.phase {
+        FlashPhase::Download | FlashPhase::Upload if throughput > 0 => Some(self.bytes_total.saturating_sub(self.bytes_sent) * 1000 / throughput),
+        _ => None,
+    };
+    FLASH_PROGRESS.publish(&ProgressEvent {
+        file: self.file.clone(),
+        phase: self.phase,
+        block_index: self.block_index,
+        block_count: self.block_count,
+        bytes_sent: self.bytes_sent,
+        bytes_total: self.bytes_total,
+        throughput,
+        eta_ms,
+    });
+}
+
+}
@@ -0,0 +1,423 @@
+use log::debug;
+use serde::Serialize;
+use std::fs;
+use std::io::{self, Error, ErrorKind};
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::executor::parameters::SequenceItem;
+use crate::transport::config::Config;
+use crate::transport::diag::DiagExchange;
+
+/*****************************************************************************************************************
+ *  Define all report structs here
+ ****************************************************************************************************************/
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Verdict {
+    Pass,
+    Fail,
+    Skipped,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct ConfigSummary {
+    pub remote_ip: String,
+    pub remote_port: String,
+    pub vendor: String,
+    pub doip_version: String,
+    pub tester_addr: String,
+    pub ecu_addr: String,
+    pub sga_addr: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct EcuIdentification {
+    pub did: String,
+    pub name: String,
+    pub value: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ExchangeRecord {
+    pub request: String,
+    pub response: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct StepRecord {
+    pub index: usize,
+    pub name: String,
+    pub description: String,
+    pub expect: Vec<String>,
+    pub exchanges: Vec<ExchangeRecord>,
+    pub verdict: Verdict,
+    pub duration_ms: u64,
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RunRecord {
+    pub sequence: String,
+    pub started_at: String,
+    pub duration_ms: u64,
+    pub verdict: Verdict,
+    pub config: ConfigSummary,
+    pub ecu_identification: Vec<EcuIdentification>,
+    pub steps: Vec<StepRecord>,
+}
+
+/* Identification DIDs of ISO14229-1 annex C which are collected from read responses */
+const IDENTIFICATION_DIDS: [(u16, &str); 14] = [
+    (0xF180, "bootSoftwareIdentification"),
+    (0xF181, "applicationSoftwareIdentification"),
+    (0xF182, "applicationDataIdentification"),
+    (0xF186, "activeDiagnosticSession"),
+    (0xF187, "sparePartNumber"),
+    (0xF188, "ecuSoftwareNumber"),
+    (0xF189, "ecuSoftwareVersionNumber"),
+    (0xF18A, "systemSupplierIdentifier"),
+    (0xF18C, "ecuSerialNumber"),
+    (0xF190, "VIN"),
+    (0xF191, "ecuHardwareNumber"),
+    (0xF193, "systemSupplierEcuHardwareVersionNumber"),
+    (0xF195, "systemSupplierEcuSoftwareVersionNumber"),
+    (0xF197, "systemNameOrEngineType"),
+];
+
+
+fn bytes_to_hex(bytes: &[u8], total_len: usize) -> String {
+    let mut hex_string: String = bytes.iter().map(|byte| format!("{:02X}", byte)).collect();
+    if total_len > bytes.len() {
+        hex_string.push_str(&format!("… ({} bytes)", total_len));
+    }
+    hex_string
+}
+
+fn epoch_millis() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_millis() as u64).unwrap_or(0)
+}
+
+// Format unix time to ISO8601 UTC string without pulling a date crate
+fn format_timestamp(epoch_ms: u64) -> String {
+    let secs = epoch_ms / 1000;
+    let days = (secs / 86_400) as i64;
+    let (hour, minute, second) = ((secs % 86_400) / 3600, (secs % 3600) / 60, secs % 60);
+    // civil date from days since 1970-01-01
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    format!("{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z", year, month, day, hour, minute, second)
+}
+
+fn escape_xml(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+impl RunRecord {
+
+/*****************************************************************************************************************
+ *  executor::report::new function
+ *  brief      Create an empty run record for a sequence
+ *  details    Configuration summary is copied from current config
+ *  \param[in]  sequence: sequence file name
+ *              config: current config
+ *  \param[out] -
+ *  \precondition -
+ *  \reentrant:  TRUE
+ *  \return     RunRecord object
+ ****************************************************************************************************************/
+pub fn new(sequence: &str, config: &Config) -> Self {
+    RunRecord {
+        sequence: sequence.to_string(),
+        started_at: format_timestamp(epoch_millis()),
+        duration_ms: 0,
+        verdict: Verdict::Pass,
+        config: ConfigSummary {
+            remote_ip: config.ethernet.remote_ip.clone(),
+            remote_port: config.ethernet.remote_port.clone(),
+            vendor: config.ethernet.vendor.clone(),
+            doip_version: format!("0x{:02X}", config.doip.version),
+            tester_addr: format!("0x{:04X}", config.doip.tester_addr),
+            ecu_addr: format!("0x{:04X}", config.doip.ecu_addr),
+            sga_addr: format!("0x{:04X}", config.doip.sga_addr),
+        },
+        ecu_identification: Vec::new(),
+        steps: Vec::new(),
+    }
+}
+
+
+/*****************************************************************************************************************
+ *  executor::report::add_step function
+ *  brief      Append the result of one executed sequence item
+ *  details    Read-identification responses (62 F1xx) found in the exchanges are collected as ECU identification
+ *  \param[in]  item: executed sequence item
+ *              exchanges: diag messages recorded while executing the item
+ *              duration_ms: execution time of the item
+ *              result: result of the item
+ *  \param[out] -
+ *  \precondition -
+ *  \reentrant:  FALSE
+ *  \return     -
+ ****************************************************************************************************************/
+pub fn add_step(&mut self, item: &SequenceItem, exchanges: Vec<DiagExchange>,
+                duration_ms: u64, result: &Result<(), io::Error>) {
+    for exchange in exchanges.iter() {
+        self.collect_identification(exchange);
+    }
+    let verdict = if result.is_ok() { Verdict::Pass } else { Verdict::Fail };
+    if verdict == Verdict::Fail {
+        self.verdict = Verdict::Fail;
+    }
+    self.duration_ms += duration_ms;
+    self.steps.push(StepRecord {
+        index: self.steps.len() + 1,
+        name: item.name(),
+        description: item.description.clone(),
+        expect: item.expect_strings(),
+        exchanges: exchanges
+            .iter()
+            .map(|exchange| ExchangeRecord {
+                request: bytes_to_hex(&exchange.request, exchange.request_len),
+                response: bytes_to_hex(&exchange.response, exchange.response_len),
+            })
+            .collect(),
+        verdict,
+        duration_ms,
+        error: result.as_ref().err().map(|err| err.to_string()),
+    });
+}
+
+
+/*****************************************************************************************************************
+ *  executor::report::skip_step function
+ *  brief      Append an item which was not executed because the sequence stopped before
+ *  details    -
+ *  \param[in]  item: sequence item
+ *  \param[out] -
+ *  \precondition -
+ *  \reentrant:  FALSE
+ *  \return     -
+ ****************************************************************************************************************/
+pub fn skip_step(&mut self, item: &SequenceItem) {
+    self.steps.push(StepRecord {
+        index: self.steps.len() + 1,
+        name: item.name(),
+        description: item.description.clone(),
+        expect: item.expect_strings(),
+        exchanges: Vec::new(),
+        verdict: Verdict::Skipped,
+        duration_ms: 0,
+        error: None,
+    });
+}
+
+fn collect_identification(&mut self, exchange: &DiagExchange) {
+    let response = &exchange.response;
+    if response.len() < 4 || response[0] != 0x62 {
+        return;
+    }
+    let did = u16::from_be_bytes([response[1], response[2]]);
+    if let Some((_, name)) = IDENTIFICATION_DIDS.iter().find(|(id, _)| *id == did) {
+        let data = &response[3..];
+        let value = if data.iter().all(|&c| c.is_ascii_graphic() || c == b' ') {
+            String::from_utf8_lossy(data).trim().to_string()
+        } else {
+            bytes_to_hex(data, exchange.response_len.saturating_sub(3))
+        };
+        self.ecu_identification.retain(|identification| identification.name != *name);
+        self.ecu_identification.push(EcuIdentification {
+            did: format!("{:04X}", did),
+            name: name.to_string(),
+            value,
+        });
+    }
+}
+
+
+/*****************************************************************************************************************
+ *  executor::report::to_junit function
+ *  brief      Render run record as JUnit XML
+ *  details    One testsuite per sequence, one testcase per sequence item
+ *  \param[in]  -
+ *  \param[out] -
+ *  \precondition -
+ *  \reentrant:  TRUE
+ *  \return     XML document
+ ****************************************************************************************************************/
+pub fn to_junit(&self) -> String {
+    let failures = self.steps.iter().filter(|step| step.verdict == Verdict::Fail).count();
+    let skipped = self.steps.iter().filter(|step| step.verdict == Verdict::Skipped).count();
+    let mut xml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    xml.push_str(&format!(
+        "<testsuites tests=\"{}\" failures=\"{}\" skipped=\"{}\" time=\"{:.3}\">\n",
+        self.steps.len(), failures, skipped, self.duration_ms as f64 / 1000.0));
+    xml.push_str(&format!(
+        "  <testsuite name=\"{}\" tests=\"{}\" failures=\"{}\" skipped=\"{}\" time=\"{:.3}\" timestamp=\"{}\">\n",
+        escape_xml(&self.sequence), self.steps.len(), failures, skipped,
+        self.duration_ms as f64 / 1000.0, self.started_at));
+
+    xml.push_str("    <properties>\n");
+    let config = &self.config;
+    let properties = [
+        ("remote_ip", &config.remote_ip), ("remote_port", &config.remote_port),
+        ("vendor", &config.vendor), ("doip_version", &config.doip_version),
+        ("tester_addr", &config.tester_addr), ("ecu_addr", &config.ecu_addr),
+        ("sga_addr", &config.sga_addr),
+    ];
+    for (name, value) in properties.iter() {
+        xml.push_str(&format!("      <property name=\"{}\" value=\"{}\"/>\n", name, escape_xml(value)));
+    }
+    for identification in self.ecu_identification.iter() {
+        xml.push_str(&format!("      <property name=\"ecu.{}\" value=\"{}\"/>\n",
+                              identification.name, escape_xml(&identification.value)));
+    }
+    xml.push_str("    </properties>\n");
+
+    for step in self.steps.iter() {
+        xml.push_str(&format!(
+            "    <testcase classname=\"{}\" name=\"{:02} {}: {}\" time=\"{:.3}\">\n",
+            escape_xml(&self.sequence), step.index, escape_xml(&step.name),
+            escape_xml(&step.description), step.duration_ms as f64 / 1000.0));
+        match step.verdict {
+            Verdict::Fail => {
+                let message = step.error.clone().unwrap_or_default();
+                xml.push_str(&format!("      <failure message=\"{}\">expect: {}</failure>\n",
+                                      escape_xml(&message), escape_xml(&step.expect.join(", "))));
+            }
+            Verdict::Skipped => xml.push_str("      <skipped/>\n"),
+            Verdict::Pass => {}
+        }
+        if !step.exchanges.is_empty() {
+            xml.push_str("      <system-out>");
+            for exchange in step.exchanges.iter() {
+                xml.push_str(&format!("&gt; {}\n&lt; {}\n", exchange.request, exchange.response));
+            }
+            xml.push_str("</system-out>\n");
+        }
+        xml.push_str("    </testcase>\n");
+    }
+    xml.push_str("  </testsuite>\n</testsuites>\n");
+    xml
+}
+
+
+/*****************************************************************************************************************
+ *  executor::report::to_html function
+ *  brief      Render run record as standalone HTML page
+ *  details    Styles are inlined so the file can be opened without any other resource
+ *  \param[in]  -
+ *  \param[out] -
+ *  \precondition -
+ *  \reentrant:  TRUE
+ *  \return     HTML document
+ ****************************************************************************************************************/
+pub fn to_html(&self) -> String {
+    let verdict_label = |verdict: Verdict| match verdict {
+        Verdict::Pass => "PASS",
+        Verdict::Fail => "FAIL",
+        Verdict::Skipped => "SKIPPED",
+    };
+    let mut html = String::from("<!DOCTYPE html>\n<html lang=\"en\">\n<head>\n<meta charset=\"utf-8\">\n");
+    html.push_str(&format!("<title>Sequence report - {}</title>\n", escape_xml(&self.sequence)));
+    html.push_str("<style>\n\
+        body { font-family: sans-serif; margin: 2em; }\n\
+        table { border-collapse: collapse; margin-bottom: 1.5em; }\n\
+        th, td { border: 1px solid #999; padding: 4px 8px; text-align: left; vertical-align: top; }\n\
+        th { background: #eee; }\n\
+        pre { margin: 0; font-size: 0.85em; }\n\
+        .PASS { color: #1a7f1a; font-weight: bold; }\n\
+        .FAIL { color: #c00000; font-weight: bold; }\n\
+        .SKIPPED { color: #888888; }\n\
+        </style>\n</head>\n<body>\n");
+    html.push_str(&format!("<h1>Sequence report: {}</h1>\n", escape_xml(&self.sequence)));
+    html.push_str(&format!("<p>Started {} &mdash; duration {:.3}s &mdash; verdict <span class=\"{2}\">{2}</span></p>\n",
+                           self.started_at, self.duration_ms as f64 / 1000.0, verdict_label(self.verdict)));
+
+    html.push_str("<h2>Configuration</h2>\n<table>\n");
+    let config = &self.config;
+    let properties = [
+        ("Remote IP", &config.remote_ip), ("Remote port", &config.remote_port),
+        ("Vendor", &config.vendor), ("DoIP version", &config.doip_version),
+        ("Tester address", &config.tester_addr), ("ECU address", &config.ecu_addr),
+        ("SGA address", &config.sga_addr),
+    ];
+    for (name, value) in properties.iter() {
+        html.push_str(&format!("<tr><th>{}</th><td>{}</td></tr>\n", name, escape_xml(value)));
+    }
+    html.push_str("</table>\n");
+
+    if !self.ecu_identification.is_empty() {
+        html.push_str("<h2>ECU identification</h2>\n<table>\n<tr><th>DID</th><th>Name</th><th>Value</th></tr>\n");
+        for identification in self.ecu_identification.iter() {
+            html.push_str(&format!("<tr><td>{}</td><td>{}</td><td>{}</td></tr>\n",
+                                   identification.did, identification.name, escape_xml(&identification.value)));
+        }
+        html.push_str("</table>\n");
+    }
+
+    html.push_str("<h2>Steps</h2>\n<table>\n<tr><th>#</th><th>Name</th><th>Description</th><th>Expect</th>\
+                   <th>Request / Response</th><th>Time</th><th>Verdict</th><th>Error</th></tr>\n");
+    for step in self.steps.iter() {
+        let exchanges: String = step.exchanges
+            .iter()
+            .map(|exchange| format!("&gt; {}\n&lt; {}\n", exchange.request, exchange.response))
+            .collect();
+        html.push_str(&format!(
+            "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td><pre>{}</pre></td><td>{:.3}s</td>\
+             <td class=\"{}\">{}</td><td>{}</td></tr>\n",
+            step.index, escape_xml(&step.name), escape_xml(&step.description),
+            escape_xml(&step.expect.join(", ")), exchanges, step.duration_ms as f64 / 1000.0,
+            verdict_label(step.verdict), verdict_label(step.verdict),
+            escape_xml(step.error.as_deref().unwrap_or(""))));
+    }
+    html.push_str("</table>\n</body>\n</html>\n");
+    html
+}
+
+
+/*****************************************************************************************************************
+ *  executor::report::export function
+ *  brief      Write run record to file
+ *  details    Format is selected by file extension: .xml for JUnit, .json, .html/.htm
+ *  \param[in]  report_filename: path to report file
+ *  \param[out] -
+ *  \precondition -
+ *  \reentrant:  FALSE
+ *  \return     error code if any
+ ****************************************************************************************************************/
+pub fn export(&self, report_filename: &str) -> Result<(), io::Error> {
+    let extension = Path::new(report_filename)
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .unwrap_or("")
+        .to_lowercase();
+    let contents = match extension.as_str() {
+        "xml" => self.to_junit(),
+        "json" => match serde_json::to_string_pretty(self) {
+            Ok(json_string) => json_string,
+            Err(err) => return Err(Error::new(ErrorKind::InvalidData, err.to_string())),
+        },
+        "html" | "htm" => self.to_html(),
+        _ => {
+            eprintln!("Report format not support: {}, use .xml, .json or .html", report_filename);
+            return Err(Error::new(ErrorKind::InvalidInput, "report format not support"));
+        }
+    };
+    fs::write(report_filename, contents)?;
+    debug!("Exported report {}", report_filename);
+    Ok(())
+}
+
+}
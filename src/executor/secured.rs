@@ -0,0 +1,277 @@
+use log::debug;
+use rand::Rng;
+use serde::Deserialize;
+use std::io::{self, Error, ErrorKind};
+use std::sync::{Mutex, RwLock};
+
+use crate::utils;
+use crate::utils::excrypto::{self, AesKey, CtrCounter, HashAlgorithm, MacKey};
+use crate::transport;
+use crate::executor::parameters::HexValue;
+
+/*****************************************************************************************************************
+ *  Define all gloval macro & variable here
+ ****************************************************************************************************************/
+lazy_static::lazy_static! {
+    static ref SETTINGS: RwLock<Option<SecuredSettings>> = RwLock::new(None);
+    static ref ANTI_REPLAY_COUNTER: Mutex<u16> = Mutex::new(0);
+}
+
+const SECURED_DATA_TRANSMISSION: u8 = 0x84;
+const SECURED_DATA_TRANSMISSION_RESPONSE: u8 = 0xC4;
+const NEGATIVE_RESPONSE: u8 = 0x7F;
+
+// administrativeParameter bits
+const ADMIN_REQUEST: u16 = 0x0001;
+const ADMIN_PRE_ESTABLISHED_KEY: u16 = 0x0008;
+const ADMIN_ENCRYPTED: u16 = 0x0010;
+const ADMIN_SIGNED: u16 = 0x0020;
+const ADMIN_RESPONSE_SIGNATURE_REQUESTED: u16 = 0x0040;
+
+// SID, administrativeParameter, signatureEncryptionCalculation, signatureLength, antiReplayCounter
+const HEADER_LENGTH: usize = 8;
+const IV_LENGTH: usize = 16;
+
+/* "secured_data_transmission" section of config file */
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SecuredSettings {
+    #[serde(default)]
+    pub signature: SignatureMethod,
+    // hex key, "@name" of keystore or "pkcs11:<label>" (AES-CMAC only)
+    #[serde(default)]
+    pub signature_key: String,
+    // AES-128/192/256 key of AES-CTR, internal messages are sent in clear if empty
+    #[serde(default)]
+    pub encryption_key: String,
+    // signatureEncryptionCalculation byte agreed with the ECU, 0x00 if not set
+    #[serde(default)]
+    pub signature_encryption_calculation: Option<HexValue>,
+    // request a signed response, unsigned responses are rejected
+    #[serde(default)]
+    pub response_signature: bool,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SignatureMethod {
+    #[default]
+    None,
+    AesCmac,
+    HmacSha256,
+}
+
+impl SecuredSettings {
+
+/*****************************************************************************************************************
+ *  executor::secured::SecuredSettings::validate function
+ *  brief      Check the section when config is loaded
+ *  details    Keys are only checked for format, keystore references are resolved when a request is wrapped
+ *  \param[in]  -
+ *  \param[out] -
+ *  \precondition -
+ *  \reentrant:  TRUE
+ *  \return     InvalidInput error naming the wrong field
+ ****************************************************************************************************************/
+pub fn validate(&self) -> Result<(), io::Error> {
+    let invalid = |message: String| Error::new(ErrorKind::InvalidInput, format!("secured_data_transmission: {}", message));
+    if self.signature == SignatureMethod::None && self.encryption_key.is_empty() {
+        return Err(invalid(String::from("neither signature nor encryption_key is set")));
+    }
+    if self.signature == SignatureMethod::None && !self.signature_key.is_empty() {
+        return Err(invalid(String::from("signature_key is set but signature is none")));
+    }
+    if self.signature == SignatureMethod::None && self.response_signature {
+        return Err(invalid(String::from("response_signature needs a signature method")));
+    }
+    if self.signature != SignatureMethod::None && self.signature_key.is_empty() {
+        return Err(invalid(format!("signature {:?} needs signature_key", self.signature)));
+    }
+    let is_aes_signature_key = self.signature == SignatureMethod::AesCmac;
+    for (name, key, is_aes) in [("signature_key", &self.signature_key, is_aes_signature_key),
+                                ("encryption_key", &self.encryption_key, true)] {
+        if key.is_empty() {
+            continue;
+        }
+        if let Some(reference) = utils::keystore::check_reference(key) {
+            reference.map_err(|err| invalid(format!("{}: {}", name, err)))?;
+            continue;
+        }
+        let checked = if is_aes { AesKey::parse(key).map(|_| ()) } else { MacKey::parse(key).map(|_| ()) };
+        checked.map_err(|err| invalid(format!("{}: {}", name, err)))?;
+    }
+    if self.signature_encryption_calculation.is_some_and(|calculation| calculation.0 > 0xFF) {
+        return Err(invalid(String::from("signature_encryption_calculation is longer than 1 byte")));
+    }
+    Ok(())
+}
+
+}
+
+/*****************************************************************************************************************
+ *  executor::secured::configure function
+ *  brief      Set keys and options used for secured requests of send_diag
+ *  details    The anti-replay counter starts again with the next request
+ *  \param[in]  settings: secured_data_transmission section of config, None if config has no such section
+ *  \param[out] -
+ *  \precondition -
+ *  \reentrant:  FALSE
+ *  \return -
+ ****************************************************************************************************************/
+pub fn configure(settings: Option<SecuredSettings>) {
+    *SETTINGS.write().unwrap() = settings;
+    *ANTI_REPLAY_COUNTER.lock().unwrap() = 0;
+}
+
+fn settings() -> Result<SecuredSettings, io::Error> {
+    SETTINGS.read().unwrap().clone()
+        .ok_or_else(|| Error::new(ErrorKind::NotFound, "secured requests need a \"secured_data_transmission\" section in config"))
+}
+
+fn next_counter() -> u16 {
+    let mut counter = ANTI_REPLAY_COUNTER.lock().unwrap();
+    *counter = counter.wrapping_add(1);
+    *counter
+}
+
+
+/*****************************************************************************************************************
+ *  executor::secured::exchange function
+ *  brief      Send a diagnostic request wrapped in SecuredDataTransmission (0x84) and unwrap the response
+ *  details    The internal message (SID and parameters) is encrypted with AES-CTR behind a random iv if an
+ *             encryption_key is configured. The signature covers every byte of the message before it, so it is
+ *             computed over the encrypted message. The response must carry the same anti-replay counter, its
+ *             signature is verified before it is decrypted.
+ *  \param[in]  stream: point to Diag object
+ *              request: internal request. Ex: 22 F1 88
+ *              timeout: timeout in milliseconds
+ *  \param[out] -
+ *  \precondition -
+ *  \reentrant:  FALSE
+ *  \return     internal response. Ex: 62 F1 88 ..., error if ECU refused or response is not authentic
+ ****************************************************************************************************************/
+pub fn exchange(stream: &mut transport::diag::Diag, request: &[u8], timeout: u64) -> Result<Vec<u8>, io::Error> {
+    let settings = settings()?;
+    let counter = next_counter();
+    let message = wrap(&settings, request, counter)?;
+    debug!("SecuredDataTransmission counter 0x{:04X}, internal request {:02X?}", counter, request);
+    stream.send_diag(message)?;
+    let response = stream.receive_diag(timeout)?;
+    let internal = unwrap(&settings, &response, counter)?;
+    debug!("SecuredDataTransmission internal response {:02X?}", internal);
+    Ok(internal)
+}
+
+fn wrap(settings: &SecuredSettings, request: &[u8], counter: u16) -> Result<Vec<u8>, io::Error> {
+    if request.is_empty() {
+        return Err(Error::new(ErrorKind::InvalidInput, "secured request is empty"));
+    }
+    let mut administrative_parameter = ADMIN_REQUEST | ADMIN_PRE_ESTABLISHED_KEY;
+    let internal = if settings.encryption_key.is_empty() {
+        request.to_vec()
+    } else {
+        administrative_parameter |= ADMIN_ENCRYPTED;
+        let iv: [u8; IV_LENGTH] = rand::thread_rng().gen();
+        let key = AesKey::parse(&utils::keystore::resolve(&settings.encryption_key)?)?;
+        let mut encrypted = iv.to_vec();
+        encrypted.extend(excrypto::aes_ctr(&key, &iv, request, CtrCounter::LittleEndian64)?);
+        encrypted
+    };
+    let signature_length: u16 = match settings.signature {
+        SignatureMethod::None => 0,
+        SignatureMethod::AesCmac => 16,
+        SignatureMethod::HmacSha256 => 32,
+    };
+    if settings.signature != SignatureMethod::None {
+        administrative_parameter |= ADMIN_SIGNED;
+    }
+    if settings.response_signature {
+        administrative_parameter |= ADMIN_RESPONSE_SIGNATURE_REQUESTED;
+    }
+
+    let mut message = Vec::with_capacity(HEADER_LENGTH + internal.len() + signature_length as usize);
+    message.push(SECURED_DATA_TRANSMISSION);
+    message.extend_from_slice(&administrative_parameter.to_be_bytes());
+    message.push(settings.signature_encryption_calculation.map_or(0, |calculation| calculation.0 as u8));
+    message.extend_from_slice(&signature_length.to_be_bytes());
+    message.extend_from_slice(&counter.to_be_bytes());
+    message.extend(internal);
+    let signature = sign(settings, &message)?;
+    message.extend(signature);
+    Ok(message)
+}
+
+fn unwrap(settings: &SecuredSettings, response: &[u8], counter: u16) -> Result<Vec<u8>, io::Error> {
+    let invalid = |message: String| Error::new(ErrorKind::InvalidData, format!("SecuredDataTransmission response {}", message));
+    if response.first() == Some(&NEGATIVE_RESPONSE) {
+        return Err(Error::new(ErrorKind::PermissionDenied,
+            format!("SecuredDataTransmission refused: {}", utils::common::describe_response(response))));
+    }
+    if response.len() < HEADER_LENGTH || response[0] != SECURED_DATA_TRANSMISSION_RESPONSE {
+        return Err(invalid(format!("{:02X?} is not expected", response)));
+    }
+    let administrative_parameter = u16::from_be_bytes([response[1], response[2]]);
+    let signature_length = u16::from_be_bytes([response[4], response[5]]) as usize;
+    let response_counter = u16::from_be_bytes([response[6], response[7]]);
+    if administrative_parameter & ADMIN_REQUEST != 0 {
+        return Err(invalid(String::from("is marked as request")));
+    }
+    if response_counter != counter {
+        return Err(invalid(format!("anti-replay counter 0x{:04X} does not match request 0x{:04X}", response_counter, counter)));
+    }
+    if signature_length > response.len() - HEADER_LENGTH {
+        return Err(invalid(format!("signature length {} is longer than the message", signature_length)));
+    }
+    let (signed, signature) = response.split_at(response.len() - signature_length);
+    if administrative_parameter & ADMIN_SIGNED != 0 {
+        if !verify(settings, signed, signature)? {
+            return Err(invalid(String::from("signature is wrong")));
+        }
+    } else if signature_length != 0 {
+        return Err(invalid(String::from("has a signature but is not marked as signed")));
+    } else if settings.response_signature {
+        return Err(invalid(String::from("is not signed, a response signature was requested")));
+    }
+
+    let internal = &signed[HEADER_LENGTH..];
+    let internal = if administrative_parameter & ADMIN_ENCRYPTED != 0 {
+        if settings.encryption_key.is_empty() {
+            return Err(invalid(String::from("is encrypted but no encryption_key is configured")));
+        }
+        if internal.len() <= IV_LENGTH {
+            return Err(invalid(String::from("is encrypted but has no internal message")));
+        }
+        let key = AesKey::parse(&utils::keystore::resolve(&settings.encryption_key)?)?;
+        excrypto::aes_ctr(&key, &internal[..IV_LENGTH], &internal[IV_LENGTH..], CtrCounter::LittleEndian64)?
+    } else {
+        internal.to_vec()
+    };
+    if internal.is_empty() {
+        return Err(invalid(String::from("has no internal message")));
+    }
+    Ok(internal)
+}
+
+fn sign(settings: &SecuredSettings, data: &[u8]) -> Result<Vec<u8>, io::Error> {
+    match settings.signature {
+        SignatureMethod::None => Ok(Vec::new()),
+        SignatureMethod::AesCmac => excrypto::aes_cmac(&AesKey::parse(&utils::keystore::resolve(&settings.signature_key)?)?, data),
+        SignatureMethod::HmacSha256 => {
+            excrypto::hmac(HashAlgorithm::Sha256, &MacKey::parse(&utils::keystore::resolve(&settings.signature_key)?)?, data)
+        }
+    }
+}
+
+fn verify(settings: &SecuredSettings, data: &[u8], signature: &[u8]) -> Result<bool, io::Error> {
+    match settings.signature {
+        SignatureMethod::None => Err(Error::new(ErrorKind::InvalidData,
+            "SecuredDataTransmission response is signed but no signature method is configured")),
+        SignatureMethod::AesCmac => {
+            excrypto::verify_aes_cmac(&AesKey::parse(&utils::keystore::resolve(&settings.signature_key)?)?, data, signature)
+        }
+        SignatureMethod::HmacSha256 => {
+            let key = MacKey::parse(&utils::keystore::resolve(&settings.signature_key)?)?;
+            excrypto::verify_hmac(HashAlgorithm::Sha256, &key, data, signature)
+        }
+    }
+}
@@ -0,0 +1,221 @@
+use log::debug;
+use std::ffi::CString;
+use std::io::{self, Error, ErrorKind};
+use std::os::raw::{c_char, c_int, c_uint};
+use std::sync::{Arc, RwLock};
+
+use crate::utils;
+use crate::executor::parameters::SecurityAccessParams;
+
+/*****************************************************************************************************************
+ *  Define all gloval macro & variable here
+ ****************************************************************************************************************/
+lazy_static::lazy_static! {
+    static ref ALGORITHMS: RwLock<Vec<Arc<dyn SeedKeyAlgorithm>>> = RwLock::new(vec![
+        Arc::new(Xor),
+        Arc::new(Mask),
+        Arc::new(Crc16),
+        Arc::new(Crc32),
+    ]);
+}
+
+const MASK_ROUNDS: u32 = 35;
+const MAX_LIBRARY_KEY_LENGTH: usize = 512; // key buffer handed to GenerateKeyEx
+const GENERATE_KEY_OK: c_int = 0;
+
+/*****************************************************************************************************************
+ *  Key calculation of classic SecurityAccess: requestSeed (odd level) -> key -> sendKey (level + 1).
+ *  params are the parameters of the securityaccess step, algorithms use secret and variant of them.
+ ****************************************************************************************************************/
+pub trait SeedKeyAlgorithm: Send + Sync {
+    fn name(&self) -> &str;
+    fn generate_key(&self, seed: &[u8], level: u8, params: &SecurityAccessParams) -> Result<Vec<u8>, io::Error>;
+}
+
+/*****************************************************************************************************************
+ *  executor::seedkey::register function
+ *  brief      Register a seed/key algorithm usable by "algorithm" parameter of securityaccess steps
+ *  details    An algorithm with the same name replaces the registered one
+ *  \param[in]  algorithm: algorithm implementation
+ *  \param[out] -
+ *  \precondition -
+ *  \reentrant:  TRUE
+ *  \return -
+ ****************************************************************************************************************/
+pub fn register(algorithm: Arc<dyn SeedKeyAlgorithm>) {
+    let mut algorithms = ALGORITHMS.write().unwrap();
+    algorithms.retain(|registered| registered.name() != algorithm.name());
+    algorithms.push(algorithm);
+}
+
+pub fn find(name: &str) -> Option<Arc<dyn SeedKeyAlgorithm>> {
+    ALGORITHMS.read().unwrap().iter().find(|algorithm| algorithm.name().eq_ignore_ascii_case(name)).cloned()
+}
+
+
+/*****************************************************************************************************************
+ *  executor::seedkey::load_library function
+ *  brief      Load a seed/key shared library exporting GenerateKeyEx and register it under its path
+ *  details    A library is loaded once, later calls return the registered algorithm
+ *  \param[in]  path: path to shared library (.so/.dll)
+ *  \param[out] -
+ *  \precondition -
+ *  \reentrant:  TRUE
+ *  \return     algorithm calling GenerateKeyEx of library
+ ****************************************************************************************************************/
+pub fn load_library(path: &str) -> Result<Arc<dyn SeedKeyAlgorithm>, io::Error> {
+    if let Some(algorithm) = find(path) {
+        return Ok(algorithm);
+    }
+    // Safety: loading runs initialisers of the library, it is selected by the user like a software file
+    let library = unsafe { libloading::Library::new(path) }
+        .map_err(|err| Error::new(ErrorKind::NotFound, format!("cannot load seed/key library {}: {}", path, err)))?;
+    let generate_key = unsafe { library.get::<GenerateKeyEx>(b"GenerateKeyEx\0") }
+        .map(|symbol| *symbol)
+        .map_err(|err| Error::new(ErrorKind::InvalidData, format!("{} has no GenerateKeyEx: {}", path, err)))?;
+    let algorithm: Arc<dyn SeedKeyAlgorithm> = Arc::new(LibraryAlgorithm { path: path.to_string(), generate_key, _library: library });
+    register(Arc::clone(&algorithm));
+    debug!("Loaded seed/key library {}", path);
+    Ok(algorithm)
+}
+
+
+/*****************************************************************************************************************
+ *  executor::seedkey::resolve function
+ *  brief      Find seed/key algorithm of securityaccess parameters
+ *  details    library parameter wins over algorithm name
+ *  \param[in]  params: securityaccess step parameters
+ *  \param[out] -
+ *  \precondition -
+ *  \reentrant:  TRUE
+ *  \return     algorithm, None if parameters do not describe a seed/key algorithm
+ ****************************************************************************************************************/
+pub fn resolve(params: &SecurityAccessParams) -> Option<Result<Arc<dyn SeedKeyAlgorithm>, io::Error>> {
+    match &params.library {
+        Some(path) => Some(load_library(path)),
+        None => find(&params.algorithm).map(Ok),
+    }
+}
+
+fn secret_of(params: &SecurityAccessParams, name: &str) -> Result<Vec<u8>, io::Error> {
+    let secret = params.secret.as_deref().unwrap_or("");
+    hex::decode(secret.trim_start_matches("0x"))
+        .map_err(|err| Error::new(ErrorKind::InvalidInput, format!("{}: secret is not hex: {}", name, err)))
+}
+
+
+/* key = seed XOR secret, secret is repeated over the seed */
+pub struct Xor;
+
+impl SeedKeyAlgorithm for Xor {
+fn name(&self) -> &str {
+    "xor"
+}
+
+fn generate_key(&self, seed: &[u8], _level: u8, params: &SecurityAccessParams) -> Result<Vec<u8>, io::Error> {
+    let secret = secret_of(params, self.name())?;
+    if secret.is_empty() {
+        return Err(Error::new(ErrorKind::InvalidInput, "xor: secret is required"));
+    }
+    Ok(seed.iter().zip(secret.iter().cycle()).map(|(seed_byte, secret_byte)| seed_byte ^ secret_byte).collect())
+}
+}
+
+
+/*****************************************************************************************************************
+ *  4 bytes seed shifted left 35 times, XOR with 4 bytes secret mask whenever the shifted-out bit is set.
+ *  This is the shift/mask scheme of many supplier example algorithms.
+ ****************************************************************************************************************/
+pub struct Mask;
+
+impl SeedKeyAlgorithm for Mask {
+fn name(&self) -> &str {
+    "mask"
+}
+
+fn generate_key(&self, seed: &[u8], _level: u8, params: &SecurityAccessParams) -> Result<Vec<u8>, io::Error> {
+    let secret = secret_of(params, self.name())?;
+    let (seed, mask): ([u8; 4], [u8; 4]) = match (seed.try_into(), secret.as_slice().try_into()) {
+        (Ok(seed), Ok(mask)) => (seed, mask),
+        _ => return Err(Error::new(ErrorKind::InvalidInput,
+                 format!("mask: seed and secret should have 4 bytes, got {} and {}", seed.len(), secret.len()))),
+    };
+    let mask = u32::from_be_bytes(mask);
+    let mut key = u32::from_be_bytes(seed);
+    for _ in 0..MASK_ROUNDS {
+        key = if key & 0x8000_0000 != 0 { (key << 1) ^ mask } else { key << 1 };
+    }
+    Ok(key.to_be_bytes().to_vec())
+}
+}
+
+
+/* key = CRC-16/CCITT-FALSE of seed || secret, secret is optional */
+pub struct Crc16;
+
+impl SeedKeyAlgorithm for Crc16 {
+fn name(&self) -> &str {
+    "crc16"
+}
+
+fn generate_key(&self, seed: &[u8], _level: u8, params: &SecurityAccessParams) -> Result<Vec<u8>, io::Error> {
+    let data = [seed, &secret_of(params, self.name())?].concat();
+    Ok(utils::common::crc16_ccitt(&data).to_be_bytes().to_vec())
+}
+}
+
+
+/* key = CRC-32 (IEEE 802.3) of seed || secret, secret is optional */
+pub struct Crc32;
+
+impl SeedKeyAlgorithm for Crc32 {
+fn name(&self) -> &str {
+    "crc32"
+}
+
+fn generate_key(&self, seed: &[u8], _level: u8, params: &SecurityAccessParams) -> Result<Vec<u8>, io::Error> {
+    let data = [seed, &secret_of(params, self.name())?].concat();
+    Ok(utils::common::crc32(&data).to_be_bytes().to_vec())
+}
+}
+
+
+/*****************************************************************************************************************
+ *  Seed/key library with the common C interface:
+ *  int GenerateKeyEx(const unsigned char* seed, unsigned int seedSize, unsigned int securityLevel,
+ *                    const char* variant, unsigned char* key, unsigned int maxKeySize, unsigned int* keySize)
+ *  0 is returned on success. variant parameter of the step is passed as string, empty if not set.
+ ****************************************************************************************************************/
+type GenerateKeyEx = unsafe extern "C" fn(*const u8, c_uint, c_uint, *const c_char, *mut u8, c_uint, *mut c_uint) -> c_int;
+
+struct LibraryAlgorithm {
+    path: String,
+    generate_key: GenerateKeyEx,
+    _library: libloading::Library, // keeps generate_key loaded
+}
+
+impl SeedKeyAlgorithm for LibraryAlgorithm {
+fn name(&self) -> &str {
+    &self.path
+}
+
+fn generate_key(&self, seed: &[u8], level: u8, params: &SecurityAccessParams) -> Result<Vec<u8>, io::Error> {
+    let variant = CString::new(params.variant.clone().unwrap_or_default())
+        .map_err(|_| Error::new(ErrorKind::InvalidInput, "variant should not contain NUL"))?;
+    let mut key = vec![0u8; MAX_LIBRARY_KEY_LENGTH];
+    let mut key_length: c_uint = 0;
+    // Safety: buffers are valid for the sizes passed, the library must not write beyond maxKeySize
+    let result = unsafe {
+        (self.generate_key)(seed.as_ptr(), seed.len() as c_uint, level as c_uint, variant.as_ptr(),
+                            key.as_mut_ptr(), key.len() as c_uint, &mut key_length)
+    };
+    if result != GENERATE_KEY_OK {
+        return Err(Error::new(ErrorKind::InvalidData, format!("{}: GenerateKeyEx failed with {}", self.path, result)));
+    }
+    if key_length as usize > key.len() {
+        return Err(Error::new(ErrorKind::InvalidData, format!("{}: GenerateKeyEx returned key length {}", self.path, key_length)));
+    }
+    key.truncate(key_length as usize);
+    Ok(key)
+}
+}
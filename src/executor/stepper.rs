@@ -0,0 +1,264 @@
+use log::debug;
+use serde_json::Value;
+use std::io::{self, Error, ErrorKind};
+use std::sync::{Arc, Mutex};
+
+use crate::utils;
+use crate::executor::executor::Executor;
+use crate::executor::parameters::{RawSequenceItem, Sequence, SequenceItem};
+use crate::executor::control::SEQUENCE_CONTROL;
+use crate::transport::diag::DiagExchange;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Breakpoint {
+    Index(usize), // 1-based step index as shown to user
+    Name(String), // item name or description
+}
+
+impl Breakpoint {
+/*****************************************************************************************************************
+ *  executor::stepper::Breakpoint::parse function
+ *  brief      Parse breakpoint from user input
+ *  details    A number is a 1-based step index, anything else is matched with item name or description
+ *  \param[in]  input: breakpoint string. Ex: "3", "swdl"
+ *  \param[out] -
+ *  \precondition -
+ *  \reentrant:  TRUE
+ *  \return     Breakpoint
+ ****************************************************************************************************************/
+pub fn parse(input: &str) -> Breakpoint {
+    match input.trim().parse::<usize>() {
+        Ok(index) => Breakpoint::Index(index),
+        Err(_) => Breakpoint::Name(input.trim().to_string()),
+    }
+}
+
+fn matches(&self, index: usize, item: &SequenceItem) -> bool {
+    match self {
+        Breakpoint::Index(bp_index) => *bp_index == index + 1,
+        Breakpoint::Name(name) => item.name() == *name || item.description == *name,
+    }
+}
+}
+
+pub struct StepSession {
+    items: Vec<SequenceItem>,
+    current: usize,
+    breakpoints: Vec<Breakpoint>,
+    last_exchanges: Vec<DiagExchange>,
+}
+
+impl StepSession {
+
+pub fn new(sequence: Sequence) -> Self {
+    StepSession {
+        items: sequence.sequence,
+        current: 0,
+        breakpoints: Vec::new(),
+        last_exchanges: Vec::new(),
+    }
+}
+
+pub fn is_finished(&self) -> bool {
+    self.current >= self.items.len()
+}
+
+pub fn current_index(&self) -> usize {
+    self.current + 1
+}
+
+/*****************************************************************************************************************
+ *  executor::stepper::step function
+ *  brief      Execute the current item and move to the next one
+ *  details    On failure the current item is kept, so it can be edited and executed again
+ *  \param[in]  executor_obj: executor object
+ *              vendor: vendor name in config
+ *  \param[out] -
+ *  \precondition -
+ *  \reentrant:  FALSE
+ *  \return     error code if any
+ ****************************************************************************************************************/
+pub fn step(&mut self, executor_obj: &Arc<Mutex<Executor>>, vendor: &str) -> Result<(), io::Error> {
+    let item = match self.items.get(self.current) {
+        Some(item) => item.clone(),
+        None => return Err(Error::new(ErrorKind::UnexpectedEof, "end of sequence")),
+    };
+    debug!("Step {}: {} {}", self.current + 1, item.name(), item.description);
+    let result = Executor::execute_cmd(Arc::clone(executor_obj), item, vendor);
+    self.last_exchanges = executor_obj.lock().unwrap().take_last_trace();
+    if result.is_ok() {
+        self.current += 1;
+    }
+    result
+}
+
+/*****************************************************************************************************************
+ *  executor::stepper::run_to_breakpoint function
+ *  brief      Execute items until a breakpoint, a failure or the end of sequence
+ *  details    The current item is always executed, even if it has a breakpoint. abort stops the run.
+ *  \param[in]  executor_obj: executor object
+ *              vendor: vendor name in config
+ *  \param[out] -
+ *  \precondition -
+ *  \reentrant:  FALSE
+ *  \return     error code if any
+ ****************************************************************************************************************/
+pub fn run_to_breakpoint(&mut self, executor_obj: &Arc<Mutex<Executor>>, vendor: &str) -> Result<(), io::Error> {
+    SEQUENCE_CONTROL.start();
+    let mut result = self.step(executor_obj, vendor);
+    while result.is_ok() && !self.is_finished() && !self.is_breakpoint(self.current) {
+        if SEQUENCE_CONTROL.is_aborted() {
+            result = Err(Error::new(ErrorKind::Interrupted, "sequence aborted"));
+            break;
+        }
+        result = self.step(executor_obj, vendor);
+    }
+    SEQUENCE_CONTROL.finish();
+    result
+}
+
+pub fn skip(&mut self) {
+    if !self.is_finished() {
+        self.current += 1;
+    }
+}
+
+/*****************************************************************************************************************
+ *  executor::stepper::jump function
+ *  brief      Move to another item, backward or forward, without executing anything
+ *  details    -
+ *  \param[in]  index: 1-based step index
+ *  \param[out] -
+ *  \precondition -
+ *  \reentrant:  FALSE
+ *  \return     error code if index is out of sequence
+ ****************************************************************************************************************/
+pub fn jump(&mut self, index: usize) -> Result<(), io::Error> {
+    if index == 0 || index > self.items.len() {
+        return Err(Error::new(ErrorKind::InvalidInput, "step index out of sequence"));
+    }
+    self.current = index - 1;
+    Ok(())
+}
+
+/*****************************************************************************************************************
+ *  executor::stepper::edit_current function
+ *  brief      Replace action or expect of the current item
+ *  details    -
+ *  \param[in]  field: "action" or "expect"
+ *              json_value: new value in json format. Ex: ["1003"]
+ *  \param[out] -
+ *  \precondition -
+ *  \reentrant:  FALSE
+ *  \return     error code if any
+ ****************************************************************************************************************/
+pub fn edit_current(&mut self, field: &str, json_value: &str) -> Result<(), io::Error> {
+    let value: Value = match serde_json::from_str(json_value) {
+        Ok(value) => value,
+        Err(err) => return Err(Error::new(ErrorKind::InvalidInput, format!("invalid json value: {}", err))),
+    };
+    let item = match self.items.get_mut(self.current) {
+        Some(item) => item,
+        None => return Err(Error::new(ErrorKind::UnexpectedEof, "end of sequence")),
+    };
+    // Edited item is validated with the same rules as sequence files
+    let mut raw_item = RawSequenceItem::from(item.clone());
+    match field {
+        "action" => { raw_item.fields.insert(String::from("action"), value); }
+        "expect" => raw_item.expect = value,
+        _ => return Err(Error::new(ErrorKind::InvalidInput, "only action or expect can be edited")),
+    }
+    *item = SequenceItem::from_raw(raw_item)?;
+    Ok(())
+}
+
+/*****************************************************************************************************************
+ *  executor::stepper::toggle_breakpoint function
+ *  brief      Add a breakpoint or remove it if it already exists
+ *  details    -
+ *  \param[in]  breakpoint: breakpoint to toggle
+ *  \param[out] -
+ *  \precondition -
+ *  \reentrant:  FALSE
+ *  \return     true if breakpoint is set, false if removed
+ ****************************************************************************************************************/
+pub fn toggle_breakpoint(&mut self, breakpoint: Breakpoint) -> bool {
+    if let Some(position) = self.breakpoints.iter().position(|bp| *bp == breakpoint) {
+        self.breakpoints.remove(position);
+        return false;
+    }
+    self.breakpoints.push(breakpoint);
+    true
+}
+
+fn is_breakpoint(&self, index: usize) -> bool {
+    match self.items.get(index) {
+        Some(item) => self.breakpoints.iter().any(|bp| bp.matches(index, item)),
+        None => false,
+    }
+}
+
+/*****************************************************************************************************************
+ *  executor::stepper::describe_list function
+ *  brief      List all items with current position and breakpoints
+ *  details    "=>" marks current item, "*" marks a breakpoint
+ *  \param[in]  -
+ *  \param[out] -
+ *  \precondition -
+ *  \reentrant:  TRUE
+ *  \return     listing string
+ ****************************************************************************************************************/
+pub fn describe_list(&self) -> String {
+    let mut listing = String::new();
+    for (index, item) in self.items.iter().enumerate() {
+        listing.push_str(&format!("{}{} {:02} {}: {}\n",
+                                  if index == self.current { "=>" } else { "  " },
+                                  if self.is_breakpoint(index) { "*" } else { " " },
+                                  index + 1, item.name(), item.description));
+    }
+    if self.is_finished() {
+        listing.push_str("=>  end of sequence\n");
+    }
+    listing
+}
+
+pub fn describe_current(&self) -> String {
+    match self.items.get(self.current) {
+        Some(item) => {
+            let raw_item = RawSequenceItem::from(item.clone());
+            format!("step {:02} {}: {}\n  action: {}\n  expect: {}\n  timeout: {}",
+                    self.current + 1, raw_item.name, raw_item.description,
+                    raw_item.fields.get("action").unwrap_or(&Value::Null),
+                    raw_item.expect, raw_item.timeout)
+        }
+        None => String::from("end of sequence"),
+    }
+}
+
+/*****************************************************************************************************************
+ *  executor::stepper::describe_last function
+ *  brief      Show raw and decoded diag exchanges of the last executed item
+ *  details    -
+ *  \param[in]  -
+ *  \param[out] -
+ *  \precondition -
+ *  \reentrant:  TRUE
+ *  \return     description string
+ ****************************************************************************************************************/
+pub fn describe_last(&self) -> String {
+    if self.last_exchanges.is_empty() {
+        return String::from("no diag message exchanged\n");
+    }
+    let mut description = String::new();
+    for exchange in self.last_exchanges.iter() {
+        description.push_str(&format!("> {:02X?}{}\n< {:02X?}{}\n  {}\n",
+            exchange.request,
+            if exchange.request_len > exchange.request.len() { format!(" … ({} bytes)", exchange.request_len) } else { String::new() },
+            exchange.response,
+            if exchange.response_len > exchange.response.len() { format!(" … ({} bytes)", exchange.response_len) } else { String::new() },
+            utils::common::describe_response(&exchange.response)));
+    }
+    description
+}
+
+}
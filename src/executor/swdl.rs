@@ -1,246 +1,581 @@
-use std::io::{self, Read, Error, ErrorKind};
+use std::io::{self, Error, ErrorKind};
 use crate::transport;
 use log::debug;
-use std::fs::File;
+use std::fs;
+use std::thread;
+use std::time::Duration;
 
 use crate::utils;
+use crate::executor::control::SEQUENCE_CONTROL;
+use crate::executor::image::{self, MemorySegment};
+use crate::executor::compression;
+use crate::executor::vbf;
+use crate::executor::progress::{FileProgress, FlashPhase};
+use crate::executor::link::{is_link_lost, LinkState};
+use crate::executor::parameters::{HexValue, MemoryRange, SwdlFormat, SwdlParams};
+use crate::executor::vendor::{self, VendorProfile};
 
-fn extract_erase_values(erase_content: &str) -> (String, String) {
-    let mut erase_start_addr = String::new();
-    let mut erase_length_addr = String::new();
-    for lines in erase_content.lines()
-    {
-        let start_addr_parts: Vec<&str> = lines
-            .split(',')
-            .map(|s| s.trim())
-            .collect();
-        if start_addr_parts.len() == 2 {
-            erase_start_addr = start_addr_parts[0]
-                .trim_matches(&['{', '}', ' ', '\n', '\t', '\r', '\x0c', ';'][..])
-                //.trim_start_matches("0x")
-                .to_string();
-            erase_length_addr = start_addr_parts[1]
-                .trim_matches(&['{', '}', ' ', '\n', '\t', '\r', '\x0c', ';'][..])
-                //.trim_start_matches("0x")
-                .to_string();
-        }
+const DEFAULT_ADDRESS_AND_LENGTH_FORMAT: u8 = 0x44;
+const DEFAULT_DATA_FORMAT: u8 = 0x00;
+const MAX_TRANSFER_BLOCK_LENGTH: usize = 0x100000; // 1 MiB, larger maxNumberOfBlockLength is treated as broken
+const DEFAULT_MAX_RECONNECTS: u32 = 3;
+const RECONNECT_DELAY_MS: u64 = 1000;
+
+/* dataFormatIdentifier and addressAndLengthFormatIdentifier of RequestDownload/RequestUpload */
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DownloadFormat {
+    pub data_format_identifier: u8,
+    pub address_and_length_format_identifier: u8,
+}
+
+impl DownloadFormat {
+
+// defaults are used for identifiers not set by step parameters, ex: those of vendor profile or vbf header
+pub fn from_params(params: &SwdlParams, defaults: DownloadFormat) -> Self {
+    DownloadFormat {
+        data_format_identifier: params.data_format_identifier.map(|dfi| dfi.0 as u8).unwrap_or(defaults.data_format_identifier),
+        address_and_length_format_identifier: params.address_and_length_format_identifier.map(|alfid| alfid.0 as u8)
+            .unwrap_or(defaults.address_and_length_format_identifier),
     }
-    (erase_start_addr, erase_length_addr)
 }
 
-fn extract_value(contents: &str, field: &str) -> String {
-    if let Some(start_idx) = contents.find(field) {
-        let start_idx = start_idx + field.len();
-        if let Some(end_idx) = contents[start_idx..].find(';') {
-            let mut value = contents[start_idx..start_idx + end_idx].trim();
-            if value.starts_with('=') {
-                // Handling cases like 'field = value'
-                value = &value[1..];
-            }
-            // Trim quotation marks, semicolons, and whitespace
-            value.trim_matches(&['"', ' '][..]).to_string()
-        } else {
-            "".to_string()
-        }
-    } else {
-        "".to_string()
+pub fn from_identifiers(data_format_identifier: Option<HexValue>, address_and_length_format_identifier: Option<HexValue>,
+                        default_data_format: u8) -> Self {
+    DownloadFormat {
+        data_format_identifier: data_format_identifier.map(|dfi| dfi.0 as u8).unwrap_or(default_data_format),
+        address_and_length_format_identifier: address_and_length_format_identifier
+            .map(|alfid| alfid.0 as u8).unwrap_or(DEFAULT_ADDRESS_AND_LENGTH_FORMAT),
+    }
+}
+
+/*****************************************************************************************************************
+ *  swdl::DownloadFormat::encode_address_and_length function
+ *  brief      Encode addressAndLengthFormatIdentifier, memoryAddress and memorySize of a memory area
+ *  details    memoryAddress and memorySize are encoded with the byte numbers of addressAndLengthFormatIdentifier,
+ *             low nibble is address size, high nibble is length size. Used by 0x23/0x34/0x35 requests.
+ *  \param[in]  address  memoryAddress
+ *              length  memorySize
+ *  \param[out] -
+ *  \precondition -
+ *  \reentrant:  TRUE
+ *  \return     encoded bytes or error if address/length does not fit
+ ****************************************************************************************************************/
+pub fn encode_address_and_length(&self, address: u32, length: u32) -> Result<Vec<u8>, io::Error> {
+    let address_size = (self.address_and_length_format_identifier & 0x0F) as usize;
+    let length_size = (self.address_and_length_format_identifier >> 4) as usize;
+    if !(1..=4).contains(&address_size) || !(1..=4).contains(&length_size) {
+        return Err(Error::new(ErrorKind::InvalidInput,
+            format!("addressAndLengthFormatIdentifier 0x{:02X} is not supported", self.address_and_length_format_identifier)));
+    }
+    if (address as u64) >> (8 * address_size) != 0 || (length as u64) >> (8 * length_size) != 0 {
+        return Err(Error::new(ErrorKind::InvalidInput,
+            format!("address 0x{:X} length 0x{:X} do not fit addressAndLengthFormatIdentifier 0x{:02X}",
+                    address, length, self.address_and_length_format_identifier)));
+    }
+    let mut byte_vector: Vec<u8> = vec![self.address_and_length_format_identifier];
+    byte_vector.extend_from_slice(&address.to_be_bytes()[4 - address_size..]);
+    byte_vector.extend_from_slice(&length.to_be_bytes()[4 - length_size..]);
+    Ok(byte_vector)
+}
+
+// RequestDownload (0x34) or RequestUpload (0x35) request for a memory area
+pub fn encode_request(&self, service: u8, address: u32, length: u32) -> Result<Vec<u8>, io::Error> {
+    let mut byte_vector: Vec<u8> = vec![service, self.data_format_identifier];
+    byte_vector.extend(self.encode_address_and_length(address, length)?);
+    Ok(byte_vector)
+}
+
+}
+
+/* Memory segment as sent to ECU. memory_size is memorySize of RequestDownload, the uncompressed size when data
+   is compressed by the tool */
+#[derive(Debug, Clone)]
+pub struct DownloadSegment {
+    pub address: u32,
+    pub memory_size: u32,
+    pub data: Vec<u8>,
+}
+
+impl From<MemorySegment> for DownloadSegment {
+    fn from(segment: MemorySegment) -> Self {
+        DownloadSegment { address: segment.address, memory_size: segment.data.len() as u32, data: segment.data }
     }
 }
 
 /*****************************************************************************************************************
  *  swdl::parse_vbf function
- *  brief      Parse vbf swdl file to get header parameters
- *  details    -
- *  \param[in]  sw_filename  path to swdl file
+ *  brief      Parse vbf swdl file and download its data blocks
+ *  details    File is fully parsed, its checksums, verification block hashes and software signature are verified
+ *             before erasing. Every erase range of header is erased with its own eraseMemory routine.
+ *  \param[in]  params  swdl step parameters, path to swdl file and routine identifiers
+ *              link  diagnostic state restored after communication loss
+ *              profile  vendor profile, routines and RequestDownload format not set by params
  *  \param[out] -
  *  \precondition -
  *  \reentrant:  FALSE
  *  \return -
  ****************************************************************************************************************/
-pub fn parse_vbf(mut stream: std::sync::MutexGuard<transport::diag::Diag>,
-                sw_filename: String, max_buffer_len: u32, timeout: u64) -> Result<(), io::Error> {
-    // Open the file and read its content
-    let sw_filename_clone = sw_filename.clone();
-    let mut file = match File::open(sw_filename) {
-        Ok(file) => file,
-        Err(error) => return Err(error),
+pub fn parse_vbf(stream: &mut transport::diag::Diag, params: &SwdlParams, link: &LinkState,
+                profile: &dyn VendorProfile, timeout: u64) -> Result<(), io::Error> {
+    let routines = &profile.data().routines;
+    let erase_routine = params.erase_routine.map(|routine| routine.0 as u16).unwrap_or(routines.erase_memory.id());
+    let check_routine = params.check_routine.map(|routine| routine.0 as u16).unwrap_or(routines.check_memory.id());
+    let vbf_file = match vbf::load(&params.path) {
+        Ok(vbf_file) => vbf_file,
+        Err(err) => {
+            eprintln!("SWDL: failed to parse {}: {}", params.path, err);
+            return Err(err);
+        }
     };
-    let mut header_content = String::new();
-    let mut brace_count = 0;
-    let mut inside_header = false;
+    let header = &vbf_file.header;
+    debug!("vbf_version: {:?}", header.vbf_version);
+    debug!("description: {:?}", header.description);
+    debug!("sw_part_number: {:?}", header.sw_part_number);
+    debug!("sw_version: {:?}", header.sw_version);
+    debug!("sw_part_type: {:?}", header.sw_part_type);
+    debug!("ecu_address: 0x{:X}", header.ecu_address);
+    debug!("data_format_identifier: 0x{:02X}", header.data_format_identifier);
+    debug!("erase: {:X?}", header.erase);
 
-    loop {
-        let mut byte = [0; 1];
-        if file.read_exact(&mut byte).is_err() {
-            break; // Error or end of file
+    //verify parameters
+    if header.verification_block_start.is_none() || header.verification_block_length.is_none() {
+        return Err(Error::new(ErrorKind::InvalidData, "Parse vbf file error, no verification block"));
+    }
+
+    //verify hashes and software signature before erasing
+    match vbf::verify(&vbf_file) {
+        Ok(signed) => debug!("{} verified, signature {}", params.path, if signed { "checked" } else { "not configured" }),
+        Err(err) => {
+            eprintln!("SWDL: failed to verify {}: {}", params.path, err);
+            return Err(err);
         }
+    }
 
-        let character = byte[0] as char;
-        header_content.push(character);
+    //send diag signature for check_memory if the vendor does
+    let option = profile.check_memory_option(header.sw_signature_dev.as_deref());
+    let check = CheckRoutine { routine: check_routine, option, expect: routines.check_memory.expect_for(check_routine) };
+    let defaults = DownloadFormat { data_format_identifier: header.data_format_identifier, ..profile.data().download_format() };
+    let plan = FlashPlan {
+        sw_filename: &params.path,
+        profile,
+        erase_routine,
+        erase: &header.erase,
+        segments: vbf_file.blocks.into_iter().map(DownloadSegment::from).collect(),
+        format: DownloadFormat::from_params(params, defaults),
+        check: Some(check),
+        max_reconnects: params.max_reconnects.unwrap_or(DEFAULT_MAX_RECONNECTS),
+    };
+    flash_segments(stream, &plan, link, timeout)?;
 
-        if inside_header {
-            if character == '{' {
-                brace_count += 1;
-            } else if character == '}' {
-                brace_count -= 1;
-                if brace_count == 0 {
-                    break; // End of header block
-                }
-            }
-        } else if character == '{' {
-            inside_header = true;
-            brace_count += 1;
-        }
+    debug!("Flashed {} successfully", params.path);
+
+    Ok(())
+}
+
+
+/*****************************************************************************************************************
+ *  swdl::download_image function
+ *  brief      Download software file of swdl step, vbf files are handed over to parse_vbf
+ *  details    Intel HEX, Motorola S-record and raw binary files are parsed to memory segments first, so a broken file is
+ *             rejected before erasing. Erase ranges and routine identifiers come from step parameters, routines not
+ *             set there from the vendor profile. Unmet programming preconditions of the profile are reported.
+ *  \param[in]  params  swdl step parameters
+ *              link  diagnostic state restored after communication loss
+ *              profile  vendor profile of config
+ *  \param[out] -
+ *  \precondition -
+ *  \reentrant:  FALSE
+ *  \return -
+ ****************************************************************************************************************/
+pub fn download_image(stream: &mut transport::diag::Diag, params: &SwdlParams, link: &LinkState,
+                      profile: &dyn VendorProfile, timeout: u64) -> Result<(), io::Error> {
+    for warning in vendor::programming_state_warnings(profile, link) {
+        eprintln!("SWDL: {}", warning);
+    }
+    if params.format == SwdlFormat::Vbf {
+        return parse_vbf(stream, params, link, profile, timeout);
     }
+    let segments = match load_image(params) {
+        Ok(segments) => segments,
+        Err(err) => {
+            eprintln!("SWDL: failed to parse {}: {}", params.path, err);
+            return Err(err);
+        }
+    };
 
-    // Parse the content and extract fields from the header
-    let sw_part_number = extract_value(&header_content, "sw_part_number");
-    let sw_version = extract_value(&header_content, "sw_version");
-    let sw_part_type = extract_value(&header_content, "sw_part_type");
-    let ecu_address = extract_value(&header_content, "ecu_address");
-    let data_format_identifier = extract_value(&header_content, "data_format_identifier");
-    let erase = extract_value(&header_content, "erase");
-    let (erase_start_addr, erase_length_addr) = extract_erase_values(&erase);
-    let verification_block_start = extract_value(&header_content, "verification_block_start");
-    let verification_block_length = extract_value(&header_content, "verification_block_length");
-    let verification_block_root_hash = extract_value(&header_content, "verification_block_root_hash");
-    let sw_signature_dev = extract_value(&header_content, "sw_signature_dev");
-    let file_checksum = extract_value(&header_content, "file_checksum");
-
-    // Print the extracted fields
-    debug!("sw_part_number: {:?}", sw_part_number);
-    debug!("sw_version: {:?}", sw_version);
-    debug!("sw_part_type: {:?}", sw_part_type);
-    debug!("ecu_address: {:?}", ecu_address);
-    debug!("data_format_identifier: {:?}", data_format_identifier);
-    debug!("erase: {:?}", erase);
-    debug!("erase_start_addr: {:?}", erase_start_addr);
-    debug!("erase_length_addr: {:?}", erase_length_addr);
-    debug!("verification_block_start: {:?}", verification_block_start);
-    debug!("verification_block_length: {:?}", verification_block_length);
-    debug!("verification_block_root_hash: {:?}", verification_block_root_hash);
-    debug!("sw_signature_dev: {:?}", sw_signature_dev);
-    debug!("file_checksum: {:?}", file_checksum);
+    let (segments, format) = compress_segments(segments, params, profile.data().download_format())?;
+    let routines = &profile.data().routines;
+    let erase_routine = params.erase_routine.map(|routine| routine.0 as u16).unwrap_or(routines.erase_memory.id());
+    let check = params.check_routine.map(|check_routine| {
+        let routine = check_routine.0 as u16;
+        CheckRoutine { routine, option: Vec::new(), expect: format!("7101{:04X}*", routine) }
+    });
+    let plan = FlashPlan {
+        sw_filename: &params.path,
+        profile,
+        erase_routine,
+        erase: &params.erase,
+        segments,
+        format,
+        check,
+        max_reconnects: params.max_reconnects.unwrap_or(DEFAULT_MAX_RECONNECTS),
+    };
+    flash_segments(stream, &plan, link, timeout)?;
 
-    //verify parameters
-    if ecu_address == "" || verification_block_start == "" || verification_block_length == "" {
-        return Err(Error::new(ErrorKind::InvalidData, "Parse vbf file error"));
+    debug!("Flashed {} successfully", params.path);
+    Ok(())
+}
+
+
+/*****************************************************************************************************************
+ *  swdl::load_image function
+ *  brief      Parse Intel HEX, Motorola S-record or raw binary file of swdl parameters to memory segments
+ *  details    vbf files are loaded by vbf::load, their header is needed too
+ *  \param[in]  params  swdl step parameters
+ *  \param[out] -
+ *  \precondition -
+ *  \reentrant:  TRUE
+ *  \return     memory segments of file
+ ****************************************************************************************************************/
+pub fn load_image(params: &SwdlParams) -> Result<Vec<MemorySegment>, io::Error> {
+    match params.format {
+        SwdlFormat::Hex => image::parse_ihex(&fs::read_to_string(&params.path)?),
+        SwdlFormat::Srec => image::parse_srec(&fs::read_to_string(&params.path)?),
+        SwdlFormat::Bin => image::from_bin(fs::read(&params.path)?,
+                                           params.address.map(|address| address.0).unwrap_or(0),
+                                           params.offset.map(|offset| offset.0).unwrap_or(0),
+                                           params.length.map(|length| length.0)),
+        SwdlFormat::Vbf => Ok(vbf::load(&params.path)?.blocks),
     }
+}
 
-    //send erase memory
-    if !erase_start_addr.is_empty() && !erase_length_addr.is_empty() {
-        let mut byte_vector: Vec<u8> = vec![0x31, 0x01, 0xff, 0x00];
-        match utils::common::hex_string_to_bytes(erase_start_addr.as_str()) {
-            Ok(bytes) => {
-                byte_vector.extend(bytes);
-            }
-            Err(e) => {
-                println!("Error: {}", e);
-            }
-        }
-        match utils::common::hex_string_to_bytes(erase_length_addr.as_str()) {
-            Ok(bytes) => {
-                byte_vector.extend(bytes);
+
+/*****************************************************************************************************************
+ *  swdl::compress_segments function
+ *  brief      Compress memory segments with the compressor of swdl parameters
+ *  details    compressionMethod of compressor is set in high nibble of dataFormatIdentifier, encryptingMethod of
+ *             parameters is kept. Without compressor segments are downloaded as they are.
+ *  \param[in]  segments  memory segments of software file
+ *              params  swdl step parameters
+ *              defaults  RequestDownload format of vendor profile
+ *  \param[out] -
+ *  \precondition -
+ *  \reentrant:  TRUE
+ *  \return     segments to download and RequestDownload format
+ ****************************************************************************************************************/
+fn compress_segments(segments: Vec<MemorySegment>, params: &SwdlParams, defaults: DownloadFormat)
+                     -> Result<(Vec<DownloadSegment>, DownloadFormat), io::Error> {
+    let mut format = DownloadFormat::from_params(params, defaults);
+    let compressor = match &params.compression {
+        Some(name) => match compression::find(name) {
+            Some(compressor) => compressor,
+            None => return Err(Error::new(ErrorKind::InvalidInput, format!("compression \"{}\" is not supported", name))),
+        },
+        None => return Ok((segments.into_iter().map(DownloadSegment::from).collect(), format)),
+    };
+    format.data_format_identifier = (compressor.method() << 4) | (format.data_format_identifier & 0x0F);
+    let download_segments = segments.into_iter().map(|segment| {
+        let data = compressor.compress(&segment.data);
+        debug!("{} segment 0x{:08X}: 0x{:X} -> 0x{:X} bytes", compressor.name(), segment.address, segment.data.len(), data.len());
+        DownloadSegment { address: segment.address, memory_size: segment.data.len() as u32, data }
+    }).collect();
+    Ok((download_segments, format))
+}
+
+
+struct CheckRoutine {
+    routine: u16,
+    option: Vec<u8>,
+    expect: String,
+}
+
+/* Everything needed to flash one software file, built from swdl parameters and file content */
+struct FlashPlan<'a> {
+    sw_filename: &'a str,
+    profile: &'a dyn VendorProfile,
+    erase_routine: u16,
+    erase: &'a [MemoryRange],
+    segments: Vec<DownloadSegment>,
+    format: DownloadFormat,
+    check: Option<CheckRoutine>,
+    max_reconnects: u32,
+}
+
+/* Segment index and offset in segment of the first byte not acknowledged by ECU yet */
+#[derive(Debug, Clone, Copy, Default)]
+struct TransferPosition {
+    segment: usize,
+    offset: usize,
+}
+
+/*****************************************************************************************************************
+ *  swdl::flash_segments function
+ *  brief      Erase memory, download memory segments and start check routine of one software file
+ *  details    Progress of every phase is published to FLASH_PROGRESS listeners.
+ *             When communication is lost during download, link is restored (reconnect, routing activation,
+ *             session, security access) and download continues with a new RequestDownload from the last
+ *             acknowledged TransferData. If ECU refuses to continue, or data is compressed/encrypted so it cannot be
+ *             split, flashing restarts from erase.
+ *  \param[in]  plan  software file to flash
+ *              link  diagnostic state restored after communication loss
+ *  \param[out] -
+ *  \precondition -
+ *  \reentrant:  FALSE
+ *  \return -
+ ****************************************************************************************************************/
+fn flash_segments(stream: &mut transport::diag::Diag, plan: &FlashPlan, link: &LinkState,
+                  timeout: u64) -> Result<(), io::Error> {
+    let bytes_total: u64 = plan.segments.iter().map(|segment| segment.data.len() as u64).sum();
+    let mut progress = FileProgress::new(plan.sw_filename, bytes_total);
+    let mut reconnects: u32 = 0;
+    let mut is_resuming = false;
+    let mut position: Option<TransferPosition> = None; // set once erase is done
+
+    loop {
+        let result = match position.as_mut() {
+            Some(resume_position) if is_resuming => resume_download(stream, plan, &mut progress, resume_position, timeout),
+            _ => {
+                position = None;
+                progress.restart();
+                erase_and_download(stream, plan, &mut progress, &mut position, timeout)
             }
-            Err(e) => {
-                println!("Error: {}", e);
+        };
+        let err = match result {
+            Ok(()) => break,
+            Err(err) => err,
+        };
+        if !is_link_lost(&err) {
+            if is_resuming {
+                eprintln!("SWDL: ECU refused to resume download of {} ({}), restart from erase", plan.sw_filename, err);
+                is_resuming = false;
+                continue;
             }
+            return Err(err);
         }
-        match stream.send_diag(byte_vector) {
-            Ok(()) => {}
-            Err(err) => {
-                eprintln!("Failed to send diag check_memory: {}", err);
+
+        // communication lost, reconnect until link is restored or attempts are used up
+        loop {
+            if reconnects >= plan.max_reconnects || SEQUENCE_CONTROL.is_aborted() {
                 return Err(err);
             }
-        }
-        match stream.receive_diag(timeout) {
-            Ok(data) => {
-                debug!("Sent erase, Expect: {}, Receive {:02X?}", "74*", data);
-                if utils::common::compare_expect_value("7101ff00*", data) == false {
-                    return Err(Error::new(ErrorKind::InvalidData, "erase Diag data received is not expected"));
+            reconnects += 1;
+            eprintln!("SWDL: communication lost ({}), reconnect {}/{}", err, reconnects, plan.max_reconnects);
+            thread::sleep(Duration::from_millis(RECONNECT_DELAY_MS));
+            match link.restore(stream, timeout) {
+                Ok(()) => break,
+                Err(restore_err) if is_link_lost(&restore_err) || restore_err.kind() == ErrorKind::ConnectionRefused => {
+                    debug!("reconnect failed: {}", restore_err);
                 }
-            }
-            Err(err) => {
-                return Err(err);
+                Err(restore_err) => return Err(restore_err),
             }
         }
+        is_resuming = position.is_some() && plan.format.data_format_identifier == DEFAULT_DATA_FORMAT;
     }
 
-    loop {
-        //1. read start and length address of block
-        let mut block_start_address_bytes = [0; 4];
-        if file.read_exact(&mut block_start_address_bytes).is_err() {
-            // UnexpectedEof error indicates end of the file
-            break;
+    if let Some(check) = &plan.check {
+        progress.start_phase(FlashPhase::Check, 1);
+        start_routine(stream, check.routine, &check.option, &check.expect, timeout)?;
+    }
+    progress.finish();
+    Ok(())
+}
+
+
+fn erase_and_download(stream: &mut transport::diag::Diag, plan: &FlashPlan, progress: &mut FileProgress,
+                      position: &mut Option<TransferPosition>, timeout: u64) -> Result<(), io::Error> {
+    progress.start_phase(FlashPhase::Erase, plan.erase.len());
+    for (index, erase_range) in plan.erase.iter().enumerate() {
+        progress.set_block(index);
+        erase_memory(stream, plan.profile, plan.erase_routine, erase_range, plan.format, timeout)?;
+    }
+    progress.start_phase(FlashPhase::Download, plan.segments.len());
+    download_from(stream, plan, progress, position.get_or_insert_with(TransferPosition::default), timeout)
+}
+
+
+/*****************************************************************************************************************
+ *  swdl::resume_download function
+ *  brief      Continue an interrupted download from the last acknowledged TransferData
+ *  details    RequestTransferExit closes the transfer ECU may still have open, its response is ignored because
+ *             an ECU which lost the transfer answers with requestSequenceError.
+ *  \param[in]  plan  software file to flash
+ *  \param[out] position  updated after every acknowledged TransferData
+ *  \precondition link is restored
+ *  \reentrant:  FALSE
+ *  \return -
+ ****************************************************************************************************************/
+fn resume_download(stream: &mut transport::diag::Diag, plan: &FlashPlan, progress: &mut FileProgress,
+                   position: &mut TransferPosition, timeout: u64) -> Result<(), io::Error> {
+    debug!("resume download at segment {} offset 0x{:X}", position.segment, position.offset);
+    stream.send_diag(vec![0x37])?;
+    let response = stream.receive_diag(timeout)?;
+    debug!("Sent transfer-exit before resuming, Receive {:02X?}", response);
+    download_from(stream, plan, progress, position, timeout)
+}
+
+
+fn download_from(stream: &mut transport::diag::Diag, plan: &FlashPlan, progress: &mut FileProgress,
+                 position: &mut TransferPosition, timeout: u64) -> Result<(), io::Error> {
+    while position.segment < plan.segments.len() {
+        let segment = &plan.segments[position.segment];
+        progress.set_block(position.segment);
+        // segment is complete if only its RequestTransferExit was lost, resume_download already sent it again
+        if position.offset < segment.data.len() {
+            download_segment(stream, segment, plan.format, progress, &mut position.offset, timeout)?;
         }
-        let mut block_length_bytes = [0; 4];
-        file.read_exact(&mut block_length_bytes)?;
-        let block_start_address = u32::from_be_bytes(block_start_address_bytes); // or u32::from_le_bytes(a_bytes) for little-endian
-        let block_length = u32::from_be_bytes(block_length_bytes); // or u32::from_le_bytes(b_bytes) for little-endian
-        debug!("block_start_address {:X}", block_start_address);
-        debug!("block_length {:X}", block_length);
-
-        //send Request Data Download
-        let mut byte_vector: Vec<u8> = vec![0x34, 0x00, 0x44];
-        byte_vector.extend_from_slice(&block_start_address.to_be_bytes());
-        byte_vector.extend_from_slice(&block_length.to_be_bytes());
-        match stream.send_diag(byte_vector) {
-            Ok(()) => {}
-            Err(err) => {
-                eprintln!("Failed to send diag Request download: {}", err);
-                return Err(err);
-            }
+        position.segment += 1;
+        position.offset = 0;
+    }
+    Ok(())
+}
+
+
+/*****************************************************************************************************************
+ *  swdl::start_routine function
+ *  brief      Send RoutineControl startRoutine and check its response
+ *  details    -
+ *  \param[in]  routine  routine identifier
+ *              option  routineControlOptionRecord
+ *              expect  expected response. Ex: "7101ff00*"
+ *  \param[out] -
+ *  \precondition -
+ *  \reentrant:  FALSE
+ *  \return -
+ ****************************************************************************************************************/
+pub fn start_routine(stream: &mut transport::diag::Diag, routine: u16, option: &[u8],
+                     expect: &str, timeout: u64) -> Result<(), io::Error> {
+    let mut byte_vector: Vec<u8> = vec![0x31, 0x01];
+    byte_vector.extend_from_slice(&routine.to_be_bytes());
+    byte_vector.extend_from_slice(option);
+    match stream.send_diag(byte_vector) {
+        Ok(()) => {}
+        Err(err) => {
+            eprintln!("Failed to send diag routine {:04X}: {}", routine, err);
+            return Err(err);
         }
-        match stream.receive_diag(timeout) {
-            Ok(data) => {
-                debug!("Sent Request Data Download, Expect: {}, Receive {:02X?}", "74*", data);
-                if utils::common::compare_expect_value("74*", data) == false {
-                    return Err(Error::new(ErrorKind::InvalidData, "request-download Diag data received is not expected"));
-                }
-            }
-            Err(err) => {
-                return Err(err);
+    }
+    match stream.receive_diag(timeout) {
+        Ok(data) => {
+            debug!("Sent routine {:04X}, Expect: {}, Receive {:02X?}", routine, expect, data);
+            if !utils::common::compare_expect_value(expect, data) {
+                return Err(Error::new(ErrorKind::InvalidData, format!("routine {:04X} Diag data received is not expected", routine)));
             }
         }
+        Err(err) => {
+            return Err(err);
+        }
+    }
+    Ok(())
+}
 
-        //2. read data block
-        let mut temp_block_length = block_length;
-        let mut block_seq_num: u8 = 1;
-        //send_diag transfer data
-        while temp_block_length != 0 {
-            let data_file = file.try_clone()?;
-            let data_len: u32 = std::cmp::min(max_buffer_len, temp_block_length);
-            temp_block_length -= data_len;
-            let mut data_blocks = Vec::with_capacity(data_len as usize);
-            data_file.take(data_len as u64).read_to_end(&mut data_blocks)?;
-            let mut byte_vector: Vec<u8> = vec![0x36, block_seq_num];
-            if block_seq_num == 255 {
-                block_seq_num = 0;
-            } else {
-                block_seq_num += 1;
-            }
-            byte_vector.extend_from_slice(&data_blocks);
-            match stream.send_diag(byte_vector) {
-                Ok(()) => {}
-                Err(err) => {
-                    eprintln!("Failed to send diag Request download: {}", err);
-                    return Err(err);
-                }
-            }
-            match stream.receive_diag(timeout) {
-                Ok(data) => {
-                    debug!("Sent diag transfer-block {}, Expect: 76*, Receive {:?}", block_seq_num, data);
-                    if utils::common::compare_expect_value("76*", data) == false {
-                        return Err(Error::new(ErrorKind::InvalidData, "request-download Diag data received is not expected"));
-                    }
-                }
-                Err(err) => {
-                    return Err(err);
-                }
+
+// eraseMemory of one range, option record and expected result as defined by the vendor profile
+pub fn erase_memory(stream: &mut transport::diag::Diag, profile: &dyn VendorProfile, routine: u16,
+                    erase_range: &MemoryRange, format: DownloadFormat, timeout: u64) -> Result<(), io::Error> {
+    let option = profile.erase_option(erase_range, format)?;
+    debug!("erase 0x{:08X} length 0x{:X}", erase_range.start, erase_range.length);
+    start_routine(stream, routine, &option, &profile.data().routines.erase_memory.expect_for(routine), timeout)
+}
+
+
+/*****************************************************************************************************************
+ *  swdl::parse_max_block_length function
+ *  brief      Get max data length of one TransferData request from RequestDownload positive response
+ *  details    Response is 74 lengthFormatIdentifier maxNumberOfBlockLength. maxNumberOfBlockLength counts the
+ *             whole TransferData request, so SID and blockSequenceCounter are subtracted.
+ *  \param[in]  response  RequestDownload positive response
+ *  \param[out] -
+ *  \precondition -
+ *  \reentrant:  TRUE
+ *  \return     max data length or error if the length is undersized or absurd
+ ****************************************************************************************************************/
+pub fn parse_max_block_length(response: &[u8]) -> Result<usize, io::Error> {
+    if response.len() < 2 {
+        return Err(Error::new(ErrorKind::InvalidData, "request-download response has no lengthFormatIdentifier"));
+    }
+    let length_size = (response[1] >> 4) as usize;
+    if !(1..=4).contains(&length_size) || response.len() != 2 + length_size {
+        return Err(Error::new(ErrorKind::InvalidData,
+            format!("request-download response {:02X?} has invalid lengthFormatIdentifier 0x{:02X}", response, response[1])));
+    }
+    let max_block_length = response[2..].iter().fold(0usize, |length, byte| (length << 8) | *byte as usize);
+    max_data_length(max_block_length)
+}
+
+// Data bytes of one TransferData request from maxNumberOfBlockLength, which counts SID and blockSequenceCounter too
+pub fn max_data_length(max_block_length: usize) -> Result<usize, io::Error> {
+    if max_block_length <= 2 || max_block_length > MAX_TRANSFER_BLOCK_LENGTH {
+        return Err(Error::new(ErrorKind::InvalidData,
+            format!("maxNumberOfBlockLength {} is out of range 3..{}", max_block_length, MAX_TRANSFER_BLOCK_LENGTH)));
+    }
+    Ok(max_block_length - 2)
+}
+
+
+/*****************************************************************************************************************
+ *  swdl::download_segment function
+ *  brief      Download one memory segment with RequestDownload, TransferData and RequestTransferExit
+ *  details    Download starts at acked offset of segment, so an interrupted download can continue.
+ *             Only uncompressed data is resumed, there memorySize and data length are the same.
+ *  \param[in]  segment  start address, memorySize and data
+ *              format  dataFormatIdentifier and addressAndLengthFormatIdentifier of RequestDownload
+ *              progress  progress of file, updated after every TransferData
+ *  \param[out] acked  offset of first byte not acknowledged by ECU, updated after every TransferData
+ *  \precondition -
+ *  \reentrant:  FALSE
+ *  \return -
+ ****************************************************************************************************************/
+pub fn download_segment(stream: &mut transport::diag::Diag, segment: &DownloadSegment, format: DownloadFormat,
+                        progress: &mut FileProgress, acked: &mut usize, timeout: u64) -> Result<(), io::Error> {
+    //send Request Data Download
+    let byte_vector = format.encode_request(0x34, segment.address + *acked as u32, segment.memory_size - *acked as u32)?;
+    match stream.send_diag(byte_vector) {
+        Ok(()) => {}
+        Err(err) => {
+            eprintln!("Failed to send diag Request download: {}", err);
+            return Err(err);
+        }
+    }
+    let max_buffer_len = match stream.receive_diag(timeout) {
+        Ok(data) => {
+            debug!("Sent Request Data Download, Expect: {}, Receive {:02X?}", "74*", data);
+            if !utils::common::compare_expect_value("74*", data.clone()) {
+                return Err(Error::new(ErrorKind::InvalidData, "request-download Diag data received is not expected"));
             }
+            parse_max_block_length(&data)?
         }
-        //send_diag transfer exit
-        let byte_vector: Vec<u8> = vec![0x37];
+        Err(err) => {
+            return Err(err);
+        }
+    };
+    debug!("TransferData max data length {}", max_buffer_len);
+
+    transfer_data(stream, &segment.data, max_buffer_len, progress, acked, timeout)?;
+    transfer_exit(stream, timeout)
+}
+
+
+/*****************************************************************************************************************
+ *  swdl::transfer_data function
+ *  brief      Send data with TransferData requests, after RequestDownload or RequestFileTransfer
+ *  details    Sending starts at acked offset of data, blockSequenceCounter starts at 1
+ *  \param[in]  data  whole data of the transfer
+ *              max_data_length  data bytes per TransferData request
+ *              progress  updated after every TransferData
+ *  \param[out] acked  offset of first byte not acknowledged by ECU, updated after every TransferData
+ *  \precondition -
+ *  \reentrant:  FALSE
+ *  \return -
+ ****************************************************************************************************************/
+pub fn transfer_data(stream: &mut transport::diag::Diag, data: &[u8], max_data_length: usize,
+                     progress: &mut FileProgress, acked: &mut usize, timeout: u64) -> Result<(), io::Error> {
+    let mut block_seq_num: u8 = 1;
+    for data_block in data[*acked..].chunks(max_data_length) {
+        if SEQUENCE_CONTROL.is_aborted() {
+            return Err(Error::new(ErrorKind::Interrupted, "software download aborted"));
+        }
+        let mut byte_vector: Vec<u8> = vec![0x36, block_seq_num];
+        byte_vector.extend_from_slice(data_block);
         match stream.send_diag(byte_vector) {
             Ok(()) => {}
             Err(err) => {
@@ -250,8 +585,8 @@ pub fn parse_vbf(mut stream: std::sync::MutexGuard<transport::diag::Diag>,
         }
         match stream.receive_diag(timeout) {
             Ok(data) => {
-                debug!("Sent transfer-exit, Expect: {}, Receive {:02X?}", "77*", data);
-                if utils::common::compare_expect_value("77*", data) == false {
+                debug!("Sent diag transfer-block {}, Expect: 76*, Receive {:?}", block_seq_num, data);
+                if !utils::common::compare_expect_value("76*", data) {
                     return Err(Error::new(ErrorKind::InvalidData, "request-download Diag data received is not expected"));
                 }
             }
@@ -259,44 +594,70 @@ pub fn parse_vbf(mut stream: std::sync::MutexGuard<transport::diag::Diag>,
                 return Err(err);
             }
         }
-
-        //3. read checksum of block
-        let mut checksum_bytes = [0; 2];
-        file.read_exact(&mut checksum_bytes)?;
-        let checksum = u16::from_be_bytes(checksum_bytes);
-        debug!("checksum {:X}", checksum);
+        *acked += data_block.len();
+        progress.add_bytes(data_block.len());
+        block_seq_num = block_seq_num.wrapping_add(1);
     }
+    Ok(())
+}
+
 
-    //send diag signature for check_memory
-    let mut byte_vector: Vec<u8> = vec![0x31, 0x01, 0x02, 0x12];
-    match utils::common::hex_string_to_bytes(sw_signature_dev.as_str()) {
-        Ok(bytes) => {
-            byte_vector.extend(bytes);
+/*****************************************************************************************************************
+ *  swdl::receive_transfer_data function
+ *  brief      Receive data with TransferData requests, after RequestUpload or RequestFileTransfer read modes
+ *  details    Requests carry only blockSequenceCounter, ECU sends data in the responses
+ *  \param[in]  length  number of bytes to receive
+ *              progress  updated after every TransferData
+ *  \param[out] -
+ *  \precondition -
+ *  \reentrant:  FALSE
+ *  \return     received data
+ ****************************************************************************************************************/
+pub fn receive_transfer_data(stream: &mut transport::diag::Diag, length: usize, progress: &mut FileProgress,
+                             timeout: u64) -> Result<Vec<u8>, io::Error> {
+    let mut data: Vec<u8> = Vec::with_capacity(length);
+    let mut block_seq_num: u8 = 1;
+    while data.len() < length {
+        if SEQUENCE_CONTROL.is_aborted() {
+            return Err(Error::new(ErrorKind::Interrupted, "software upload aborted"));
         }
-        Err(e) => {
-            println!("Error: {}", e);
+        stream.send_diag(vec![0x36, block_seq_num])?;
+        let response = stream.receive_diag(timeout)?;
+        if response.len() < 3 || response[0] != 0x76 || response[1] != block_seq_num {
+            return Err(Error::new(ErrorKind::InvalidData,
+                format!("transfer-block {} response is not expected: {}", block_seq_num, utils::common::describe_response(&response))));
         }
+        if data.len() + response.len() - 2 > length {
+            return Err(Error::new(ErrorKind::InvalidData,
+                format!("ECU sent 0x{:X} bytes, 0x{:X} were requested", data.len() + response.len() - 2, length)));
+        }
+        data.extend_from_slice(&response[2..]);
+        progress.add_bytes(response.len() - 2);
+        block_seq_num = block_seq_num.wrapping_add(1);
     }
+    Ok(data)
+}
+
+
+pub fn transfer_exit(stream: &mut transport::diag::Diag, timeout: u64) -> Result<(), io::Error> {
+    let byte_vector: Vec<u8> = vec![0x37];
     match stream.send_diag(byte_vector) {
         Ok(()) => {}
         Err(err) => {
-            eprintln!("Failed to send diag check_memory: {}", err);
+            eprintln!("Failed to send diag Request download: {}", err);
             return Err(err);
         }
     }
     match stream.receive_diag(timeout) {
         Ok(data) => {
-            debug!("Sent check_memory, Expect: {}, Receive {:02X?}", "74*", data);
-            if utils::common::compare_expect_value("710102121000*", data) == false {
-                return Err(Error::new(ErrorKind::InvalidData, "check memory Diag data received is failed"));
+            debug!("Sent transfer-exit, Expect: {}, Receive {:02X?}", "77*", data);
+            if !utils::common::compare_expect_value("77*", data) {
+                return Err(Error::new(ErrorKind::InvalidData, "request-download Diag data received is not expected"));
             }
         }
         Err(err) => {
             return Err(err);
         }
     }
-
-    debug!("Flashed {} successfully", sw_filename_clone);
-
     Ok(())
 }
@@ -0,0 +1,181 @@
+use std::io::{self, Error, ErrorKind};
+use crate::transport;
+use log::debug;
+use std::fs;
+
+use crate::utils;
+use crate::executor::control::SEQUENCE_CONTROL;
+use crate::executor::image::{self, MemorySegment};
+use crate::executor::vbf;
+use crate::executor::swdl::{self, DownloadFormat};
+use crate::executor::progress::{FileProgress, FlashPhase};
+use crate::executor::parameters::{HexValue, SwdlFormat, UploadMethod, UploadParams};
+
+const DEFAULT_DATA_FORMAT: u8 = 0x00;
+const DEFAULT_READ_BLOCK_LENGTH: u32 = 0x100;
+
+/*****************************************************************************************************************
+ *  upload::upload_memory function
+ *  brief      Read memory of ECU and write it to dump file, optionally compare it with a reference image
+ *  details    RequestUpload/TransferData/RequestTransferExit is used first. With method auto, a negative response to
+ *             RequestUpload falls back to ReadMemoryByAddress loops. The dump file is written before comparing, so
+ *             it is kept for analysis when memory differs.
+ *  \param[in]  params  upload step parameters
+ *  \param[out] -
+ *  \precondition -
+ *  \reentrant:  FALSE
+ *  \return     error if memory cannot be read or differs from reference
+ ****************************************************************************************************************/
+pub fn upload_memory(stream: &mut transport::diag::Diag, params: &UploadParams, timeout: u64) -> Result<(), io::Error> {
+    let format = DownloadFormat::from_identifiers(params.data_format_identifier,
+                                                  params.address_and_length_format_identifier, DEFAULT_DATA_FORMAT);
+    let (address, length) = (params.address.0, params.length.0);
+    let mut progress = FileProgress::new(&params.path, length as u64);
+    progress.start_phase(FlashPhase::Upload, 1);
+
+    let data = match params.method.unwrap_or(UploadMethod::Auto) {
+        UploadMethod::RequestUpload => request_upload(stream, format, address, length, &mut progress, timeout)?,
+        UploadMethod::ReadMemory => read_memory(stream, format, address, length, params.block_length, &mut progress, timeout)?,
+        UploadMethod::Auto => match request_upload(stream, format, address, length, &mut progress, timeout) {
+            Ok(data) => data,
+            Err(err) if err.kind() == ErrorKind::Unsupported => {
+                eprintln!("Upload: {}, fall back to ReadMemoryByAddress", err);
+                progress.restart();
+                read_memory(stream, format, address, length, params.block_length, &mut progress, timeout)?
+            }
+            Err(err) => return Err(err),
+        },
+    };
+
+    let dump = MemorySegment { address, data };
+    match params.output_format() {
+        SwdlFormat::Hex => fs::write(&params.path, image::to_ihex(std::slice::from_ref(&dump)))?,
+        SwdlFormat::Srec => fs::write(&params.path, image::to_srec(std::slice::from_ref(&dump), &params.path))?,
+        _ => fs::write(&params.path, &dump.data)?,
+    }
+    debug!("Dumped 0x{:08X} length 0x{:X} to {}", address, length, params.path);
+    progress.finish();
+
+    if let Some(reference_path) = &params.reference {
+        compare_reference(&dump, reference_path)?;
+    }
+    Ok(())
+}
+
+
+/*****************************************************************************************************************
+ *  upload::request_upload function
+ *  brief      Read memory with RequestUpload, TransferData and RequestTransferExit
+ *  details    -
+ *  \param[in]  format  dataFormatIdentifier and addressAndLengthFormatIdentifier of RequestUpload
+ *              address  memoryAddress
+ *              length  memorySize
+ *              progress  updated after every TransferData
+ *  \param[out] -
+ *  \precondition -
+ *  \reentrant:  FALSE
+ *  \return     memory data, error kind Unsupported if ECU rejects RequestUpload
+ ****************************************************************************************************************/
+fn request_upload(stream: &mut transport::diag::Diag, format: DownloadFormat, address: u32, length: u32,
+                  progress: &mut FileProgress, timeout: u64) -> Result<Vec<u8>, io::Error> {
+    stream.send_diag(format.encode_request(0x35, address, length)?)?;
+    let response = stream.receive_diag(timeout)?;
+    debug!("Sent Request Upload, Expect: 75*, Receive {:02X?}", response);
+    if response.first() == Some(&0x7F) {
+        return Err(Error::new(ErrorKind::Unsupported,
+            format!("request-upload rejected, {}", utils::common::describe_response(&response))));
+    }
+    if !utils::common::compare_expect_value("75*", response.clone()) {
+        return Err(Error::new(ErrorKind::InvalidData, "request-upload Diag data received is not expected"));
+    }
+    swdl::parse_max_block_length(&response)?;
+
+    let data = swdl::receive_transfer_data(stream, length as usize, progress, timeout)?;
+    swdl::transfer_exit(stream, timeout)?;
+    Ok(data)
+}
+
+
+/*****************************************************************************************************************
+ *  upload::read_memory function
+ *  brief      Read memory with ReadMemoryByAddress requests of block_length bytes
+ *  details    -
+ *  \param[in]  format  addressAndLengthFormatIdentifier of requests, dataFormatIdentifier is not used
+ *              address  first address
+ *              length  number of bytes
+ *              block_length  bytes per request
+ *              progress  updated after every request
+ *  \param[out] -
+ *  \precondition -
+ *  \reentrant:  FALSE
+ *  \return     memory data
+ ****************************************************************************************************************/
+fn read_memory(stream: &mut transport::diag::Diag, format: DownloadFormat, address: u32, length: u32,
+               block_length: Option<HexValue>, progress: &mut FileProgress,
+               timeout: u64) -> Result<Vec<u8>, io::Error> {
+    let block_length = block_length.map(|block_length| block_length.0).unwrap_or(DEFAULT_READ_BLOCK_LENGTH);
+    let mut data: Vec<u8> = Vec::with_capacity(length as usize);
+    while data.len() < length as usize {
+        if SEQUENCE_CONTROL.is_aborted() {
+            return Err(Error::new(ErrorKind::Interrupted, "memory read aborted"));
+        }
+        let block_address = address + data.len() as u32;
+        let size = std::cmp::min(block_length, length - data.len() as u32);
+        let mut byte_vector: Vec<u8> = vec![0x23];
+        byte_vector.extend(format.encode_address_and_length(block_address, size)?);
+        stream.send_diag(byte_vector)?;
+        let response = stream.receive_diag(timeout)?;
+        if response.first() != Some(&0x63) || response.len() != size as usize + 1 {
+            return Err(Error::new(ErrorKind::InvalidData,
+                format!("read memory 0x{:08X} length 0x{:X} failed: {}", block_address, size, utils::common::describe_response(&response))));
+        }
+        data.extend_from_slice(&response[1..]);
+        progress.add_bytes(size as usize);
+    }
+    Ok(data)
+}
+
+
+/*****************************************************************************************************************
+ *  upload::compare_reference function
+ *  brief      Compare dump with a reference vbf/hex/srec/bin image and print differing ranges
+ *  details    Reference format is selected by extension, bin reference is placed at dump address.
+ *             Reference data outside the dumped memory cannot be confirmed and counts as mismatch.
+ *  \param[in]  dump  memory read from ECU
+ *              reference_path  path to reference image
+ *  \param[out] -
+ *  \precondition -
+ *  \reentrant:  TRUE
+ *  \return     error kind InvalidData if memory differs or reference is not covered by dump
+ ****************************************************************************************************************/
+fn compare_reference(dump: &MemorySegment, reference_path: &str) -> Result<(), io::Error> {
+    let reference = match SwdlFormat::from_path(reference_path) {
+        Some(SwdlFormat::Vbf) => vbf::load(reference_path)?.blocks,
+        Some(SwdlFormat::Hex) => image::parse_ihex(&fs::read_to_string(reference_path)?)?,
+        Some(SwdlFormat::Srec) => image::parse_srec(&fs::read_to_string(reference_path)?)?,
+        _ => image::from_bin(fs::read(reference_path)?, dump.address, 0, None)?,
+    };
+    let differences = image::compare(dump, &reference);
+    let uncovered = image::uncovered(dump, &reference);
+    if differences.is_empty() && uncovered.is_empty() {
+        println!("Upload: memory 0x{:08X} length 0x{:X} matches {}", dump.address, dump.data.len(), reference_path);
+        return Ok(());
+    }
+    for (start, length) in differences.iter() {
+        println!("Upload: differs from {} at 0x{:08X}-0x{:08X} (0x{:X} bytes)", reference_path, start,
+                 *start as u64 + *length as u64 - 1, length);
+    }
+    for (start, length) in uncovered.iter() {
+        println!("Upload: {} at 0x{:08X}-0x{:08X} (0x{:X} bytes) is not in the dump", reference_path, start,
+                 *start as u64 + *length as u64 - 1, length);
+    }
+    let total: u64 = differences.iter().map(|(_, length)| *length as u64).sum();
+    let missing: u64 = uncovered.iter().map(|(_, length)| *length as u64).sum();
+    if uncovered.is_empty() {
+        return Err(Error::new(ErrorKind::InvalidData,
+            format!("memory differs from {} in {} ranges, 0x{:X} bytes", reference_path, differences.len(), total)));
+    }
+    Err(Error::new(ErrorKind::InvalidData,
+        format!("memory differs from {} in {} ranges, 0x{:X} bytes, 0x{:X} bytes of reference are not in the dump",
+                reference_path, differences.len(), total, missing)))
+}
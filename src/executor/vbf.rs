@@ -0,0 +1,598 @@
+use log::debug;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{self, Error, ErrorKind};
+use std::sync::RwLock;
+
+use crate::utils;
+use crate::utils::excrypto::{self, HashAlgorithm, PublicKey, SignatureScheme};
+use crate::executor::image::MemorySegment;
+use crate::executor::parameters::MemoryRange;
+
+/*****************************************************************************************************************
+ *  Define all gloval macro & variable here
+ ****************************************************************************************************************/
+lazy_static::lazy_static! {
+    static ref SIGNATURE_CHECK: RwLock<Option<SignatureCheck>> = RwLock::new(None);
+}
+
+// verification block table: format (2 bytes), number of entries (2 bytes), entries of start address (4 bytes),
+// length (4 bytes) and SHA-256 of the data block (32 bytes)
+const HASH_TABLE_FORMAT: u16 = 0x0000;
+const HASH_TABLE_HEADER_LENGTH: usize = 4;
+const HASH_LENGTH: usize = 32;
+const HASH_TABLE_ENTRY_LENGTH: usize = 8 + HASH_LENGTH;
+
+/* "vbf_verification" section of config file */
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct VerificationSettings {
+    pub public_key: String, // PEM or DER public key or certificate of the software signer
+    #[serde(default)]
+    pub scheme: Option<SignatureScheme>, // ECDSA or RSA PKCS#1 v1.5 with SHA-256 if not set
+    #[serde(default)]
+    pub signature: SignatureField,
+}
+
+/* Header field holding the signature of verification_block_root_hash */
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SignatureField {
+    #[default]
+    SwSignature,
+    SwSignatureDev, // signed with the development key
+}
+
+struct SignatureCheck {
+    public_key: PublicKey,
+    scheme: SignatureScheme,
+    field: SignatureField,
+}
+
+/*****************************************************************************************************************
+ *  Value of a vbf header field, numbers keep their written form so long values (signatures) are not truncated
+ ****************************************************************************************************************/
+#[derive(Debug, Clone, PartialEq)]
+pub enum VbfValue {
+    Number(String),     // Ex: 0x1A01, 123
+    Text(String),       // Ex: "32248791"
+    Identifier(String), // Ex: EXE
+    List(Vec<VbfValue>),
+}
+
+impl VbfValue {
+    pub fn as_u32(&self) -> Option<u32> {
+        match self {
+            VbfValue::Number(number) => match number.strip_prefix("0x").or_else(|| number.strip_prefix("0X")) {
+                Some(hex_number) => u32::from_str_radix(hex_number, 16).ok(),
+                None => number.parse::<u32>().ok(),
+            },
+            _ => None,
+        }
+    }
+
+    // Hex number as bytes, leading zeros are kept. Ex: 0x00AB -> [0x00, 0xAB]
+    pub fn as_bytes(&self) -> Option<Vec<u8>> {
+        match self {
+            VbfValue::Number(number) => {
+                let hex_number = number.strip_prefix("0x").or_else(|| number.strip_prefix("0X"))?;
+                let padded = if hex_number.len() % 2 == 1 { format!("0{}", hex_number) } else { hex_number.to_string() };
+                hex::decode(padded).ok()
+            }
+            _ => None,
+        }
+    }
+
+    pub fn as_text(&self) -> Option<String> {
+        match self {
+            VbfValue::Text(text) | VbfValue::Identifier(text) | VbfValue::Number(text) => Some(text.clone()),
+            VbfValue::List(_) => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct VbfHeader {
+    pub vbf_version: String,
+    pub description: Vec<String>,
+    pub sw_part_number: String,
+    pub sw_version: String,
+    pub sw_part_type: String,
+    pub ecu_address: u32,
+    pub data_format_identifier: u8,
+    pub erase: Vec<MemoryRange>,
+    pub call: Option<u32>,
+    pub verification_block_start: Option<u32>,
+    pub verification_block_length: Option<u32>,
+    pub verification_block_root_hash: Option<Vec<u8>>,
+    pub sw_signature: Option<Vec<u8>>,
+    pub sw_signature_dev: Option<Vec<u8>>,
+    pub file_checksum: u32,
+}
+
+#[derive(Debug, Clone)]
+pub struct VbfFile {
+    pub header: VbfHeader,
+    pub blocks: Vec<MemorySegment>,
+}
+
+
+/*****************************************************************************************************************
+ *  Tokenizer of vbf header, handles strings, // and /* */ comments
+ ****************************************************************************************************************/
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Identifier(String),
+    Number(String),
+    Text(String),
+    Symbol(char),
+}
+
+struct Lexer<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+    line: usize,
+}
+
+impl<'a> Lexer<'a> {
+
+fn new(bytes: &'a [u8]) -> Self {
+    Lexer { bytes, pos: 0, line: 1 }
+}
+
+fn error(&self, reason: &str) -> io::Error {
+    Error::new(ErrorKind::InvalidData, format!("vbf header line {}: {}", self.line, reason))
+}
+
+fn peek_byte(&self, offset: usize) -> Option<u8> {
+    self.bytes.get(self.pos + offset).copied()
+}
+
+fn skip_blank(&mut self) -> Result<(), io::Error> {
+    while let Some(byte) = self.peek_byte(0) {
+        match byte {
+            b'\n' => { self.line += 1; self.pos += 1; }
+            b' ' | b'\t' | b'\r' | 0x0c => self.pos += 1,
+            b'/' if self.peek_byte(1) == Some(b'/') => {
+                while let Some(byte) = self.peek_byte(0) {
+                    if byte == b'\n' {
+                        break;
+                    }
+                    self.pos += 1;
+                }
+            }
+            b'/' if self.peek_byte(1) == Some(b'*') => {
+                self.pos += 2;
+                loop {
+                    match self.peek_byte(0) {
+                        Some(b'*') if self.peek_byte(1) == Some(b'/') => { self.pos += 2; break; }
+                        Some(b'\n') => { self.line += 1; self.pos += 1; }
+                        Some(_) => self.pos += 1,
+                        None => return Err(self.error("unterminated comment")),
+                    }
+                }
+            }
+            _ => break,
+        }
+    }
+    Ok(())
+}
+
+fn next_token(&mut self) -> Result<Token, io::Error> {
+    self.skip_blank()?;
+    let start = self.pos;
+    let byte = match self.peek_byte(0) {
+        Some(byte) => byte,
+        None => return Err(self.error("unexpected end of header")),
+    };
+    match byte {
+        b'{' | b'}' | b'=' | b';' | b',' => {
+            self.pos += 1;
+            Ok(Token::Symbol(byte as char))
+        }
+        b'"' => {
+            let mut text: Vec<u8> = Vec::new();
+            self.pos += 1;
+            loop {
+                match self.peek_byte(0) {
+                    Some(b'"') => { self.pos += 1; break; }
+                    Some(b'\\') if self.peek_byte(1).is_some() => { text.push(self.bytes[self.pos + 1]); self.pos += 2; }
+                    Some(b'\n') | None => return Err(self.error("unterminated string")),
+                    Some(byte) => { text.push(byte); self.pos += 1; }
+                }
+            }
+            Ok(Token::Text(String::from_utf8_lossy(&text).to_string()))
+        }
+        b'0'..=b'9' => {
+            while self.peek_byte(0).is_some_and(|byte| byte.is_ascii_alphanumeric() || byte == b'.') {
+                self.pos += 1;
+            }
+            Ok(Token::Number(String::from_utf8_lossy(&self.bytes[start..self.pos]).to_string()))
+        }
+        byte if byte.is_ascii_alphabetic() || byte == b'_' => {
+            while self.peek_byte(0).is_some_and(|byte| byte.is_ascii_alphanumeric() || byte == b'_') {
+                self.pos += 1;
+            }
+            Ok(Token::Identifier(String::from_utf8_lossy(&self.bytes[start..self.pos]).to_string()))
+        }
+        byte => Err(self.error(&format!("unexpected character '{}'", byte as char))),
+    }
+}
+
+fn expect_symbol(&mut self, symbol: char) -> Result<(), io::Error> {
+    match self.next_token()? {
+        Token::Symbol(found) if found == symbol => Ok(()),
+        token => Err(self.error(&format!("expected '{}', found {:?}", symbol, token))),
+    }
+}
+
+fn parse_value(&mut self) -> Result<VbfValue, io::Error> {
+    match self.next_token()? {
+        Token::Number(number) => Ok(VbfValue::Number(number)),
+        Token::Text(text) => Ok(VbfValue::Text(text)),
+        Token::Identifier(identifier) => Ok(VbfValue::Identifier(identifier)),
+        Token::Symbol('{') => {
+            let mut values: Vec<VbfValue> = Vec::new();
+            let save_pos = (self.pos, self.line);
+            if self.next_token()? == Token::Symbol('}') {
+                return Ok(VbfValue::List(values));
+            }
+            (self.pos, self.line) = save_pos;
+            loop {
+                values.push(self.parse_value()?);
+                match self.next_token()? {
+                    Token::Symbol(',') => continue,
+                    Token::Symbol('}') => break,
+                    token => return Err(self.error(&format!("expected ',' or '}}', found {:?}", token))),
+                }
+            }
+            Ok(VbfValue::List(values))
+        }
+        token => Err(self.error(&format!("expected value, found {:?}", token))),
+    }
+}
+
+}
+
+
+/*****************************************************************************************************************
+ *  executor::vbf::parse_header function
+ *  brief      Parse "vbf_version = x.y; header { ... }" part of a vbf file
+ *  details    Grammar of vbf v2 and v3: field = value; where value is a number, a string, an identifier
+ *             or a list { value, value }. Duplicated fields are rejected.
+ *  \param[in]  bytes: content of vbf file
+ *  \param[out] -
+ *  \precondition -
+ *  \reentrant:  TRUE
+ *  \return     header fields and offset of first byte after header
+ ****************************************************************************************************************/
+pub fn parse_header(bytes: &[u8]) -> Result<(BTreeMap<String, VbfValue>, usize), io::Error> {
+    let mut lexer = Lexer::new(bytes);
+    let mut fields: BTreeMap<String, VbfValue> = BTreeMap::new();
+
+    match lexer.next_token()? {
+        Token::Identifier(name) if name == "vbf_version" => {}
+        token => return Err(lexer.error(&format!("expected vbf_version, found {:?}", token))),
+    }
+    lexer.expect_symbol('=')?;
+    let version = lexer.parse_value()?;
+    lexer.expect_symbol(';')?;
+    fields.insert(String::from("vbf_version"), version);
+
+    match lexer.next_token()? {
+        Token::Identifier(name) if name == "header" => {}
+        token => return Err(lexer.error(&format!("expected header, found {:?}", token))),
+    }
+    lexer.expect_symbol('{')?;
+    loop {
+        let name = match lexer.next_token()? {
+            Token::Symbol('}') => break,
+            Token::Identifier(name) => name,
+            token => return Err(lexer.error(&format!("expected field name, found {:?}", token))),
+        };
+        lexer.expect_symbol('=')?;
+        let value = lexer.parse_value()?;
+        lexer.expect_symbol(';')?;
+        if fields.insert(name.clone(), value).is_some() {
+            return Err(lexer.error(&format!("duplicated field {}", name)));
+        }
+    }
+    Ok((fields, lexer.pos))
+}
+
+
+fn required<'a>(fields: &'a BTreeMap<String, VbfValue>, name: &str) -> Result<&'a VbfValue, io::Error> {
+    fields.get(name).ok_or_else(|| Error::new(ErrorKind::InvalidData, format!("vbf header has no {}", name)))
+}
+
+fn field_u32(fields: &BTreeMap<String, VbfValue>, name: &str) -> Result<Option<u32>, io::Error> {
+    match fields.get(name) {
+        Some(value) => match value.as_u32() {
+            Some(number) => Ok(Some(number)),
+            None => Err(Error::new(ErrorKind::InvalidData, format!("vbf field {} is not a 32-bit number", name))),
+        },
+        None => Ok(None),
+    }
+}
+
+fn field_bytes(fields: &BTreeMap<String, VbfValue>, name: &str) -> Result<Option<Vec<u8>>, io::Error> {
+    match fields.get(name) {
+        Some(value) => match value.as_bytes() {
+            Some(bytes) => Ok(Some(bytes)),
+            None => Err(Error::new(ErrorKind::InvalidData, format!("vbf field {} is not a hex number", name))),
+        },
+        None => Ok(None),
+    }
+}
+
+fn field_text(fields: &BTreeMap<String, VbfValue>, name: &str) -> String {
+    fields.get(name).and_then(|value| value.as_text()).unwrap_or_default()
+}
+
+
+impl VbfHeader {
+
+/*****************************************************************************************************************
+ *  executor::vbf::VbfHeader::from_fields function
+ *  brief      Convert parsed header fields to typed header
+ *  details    Only vbf version 2.x and 3.x are accepted
+ *  \param[in]  fields: parsed header fields
+ *  \param[out] -
+ *  \precondition -
+ *  \reentrant:  TRUE
+ *  \return     typed header
+ ****************************************************************************************************************/
+pub fn from_fields(fields: &BTreeMap<String, VbfValue>) -> Result<VbfHeader, io::Error> {
+    let vbf_version = field_text(fields, "vbf_version");
+    if !(vbf_version.starts_with("2.") || vbf_version.starts_with("3.")) {
+        return Err(Error::new(ErrorKind::InvalidData, format!("vbf version {} is not supported", vbf_version)));
+    }
+
+    let description = match fields.get("description") {
+        Some(VbfValue::List(lines)) => lines.iter().filter_map(|line| line.as_text()).collect(),
+        Some(value) => value.as_text().into_iter().collect(),
+        None => Vec::new(),
+    };
+
+    let mut erase: Vec<MemoryRange> = Vec::new();
+    if let Some(erase_value) = fields.get("erase") {
+        let ranges = match erase_value {
+            VbfValue::List(ranges) => ranges,
+            _ => return Err(Error::new(ErrorKind::InvalidData, "vbf field erase is not a list")),
+        };
+        for range in ranges.iter() {
+            match range {
+                VbfValue::List(pair) if pair.len() == 2 => match (pair[0].as_u32(), pair[1].as_u32()) {
+                    (Some(start), Some(length)) => erase.push(MemoryRange { start, length }),
+                    _ => return Err(Error::new(ErrorKind::InvalidData, "vbf erase range is not a 32-bit number pair")),
+                },
+                _ => return Err(Error::new(ErrorKind::InvalidData, "vbf erase range is not { start, length }")),
+            }
+        }
+    }
+
+    let data_format_identifier = match field_u32(fields, "data_format_identifier")? {
+        Some(dfi) if dfi > 0xFF => return Err(Error::new(ErrorKind::InvalidData, "vbf data_format_identifier is longer than 1 byte")),
+        Some(dfi) => dfi as u8,
+        None => 0x00,
+    };
+    let ecu_address = match required(fields, "ecu_address")?.as_u32() {
+        Some(ecu_address) => ecu_address,
+        None => return Err(Error::new(ErrorKind::InvalidData, "vbf field ecu_address is not a number")),
+    };
+    let file_checksum = match required(fields, "file_checksum")?.as_u32() {
+        Some(file_checksum) => file_checksum,
+        None => return Err(Error::new(ErrorKind::InvalidData, "vbf field file_checksum is not a 32-bit number")),
+    };
+
+    Ok(VbfHeader {
+        vbf_version,
+        description,
+        sw_part_number: field_text(fields, "sw_part_number"),
+        sw_version: field_text(fields, "sw_version"),
+        sw_part_type: field_text(fields, "sw_part_type"),
+        ecu_address,
+        data_format_identifier,
+        erase,
+        call: field_u32(fields, "call")?,
+        verification_block_start: field_u32(fields, "verification_block_start")?,
+        verification_block_length: field_u32(fields, "verification_block_length")?,
+        verification_block_root_hash: field_bytes(fields, "verification_block_root_hash")?,
+        sw_signature: field_bytes(fields, "sw_signature")?,
+        sw_signature_dev: field_bytes(fields, "sw_signature_dev")?,
+        file_checksum,
+    })
+}
+
+}
+
+
+/*****************************************************************************************************************
+ *  executor::vbf::load function
+ *  brief      Read and verify a vbf file
+ *  details    Data blocks are start address (4 bytes), length (4 bytes), data, CRC16-CCITT of data (2 bytes).
+ *             CRC16 of every block and CRC32 of the whole data part (file_checksum) are verified here,
+ *             so a corrupted file is rejected before anything is erased.
+ *  \param[in]  sw_filename: path to vbf file
+ *  \param[out] -
+ *  \precondition -
+ *  \reentrant:  TRUE
+ *  \return     verified vbf file
+ ****************************************************************************************************************/
+pub fn load(sw_filename: &str) -> Result<VbfFile, io::Error> {
+    let contents = fs::read(sw_filename)?;
+    let (fields, header_end) = parse_header(&contents)?;
+    let header = VbfHeader::from_fields(&fields)?;
+    let data_part = &contents[header_end..];
+
+    let file_checksum = utils::common::crc32(data_part);
+    if file_checksum != header.file_checksum {
+        return Err(Error::new(ErrorKind::InvalidData,
+            format!("vbf file_checksum mismatch, header 0x{:08X}, calculated 0x{:08X}", header.file_checksum, file_checksum)));
+    }
+
+    let mut blocks: Vec<MemorySegment> = Vec::new();
+    let mut pos = 0;
+    while pos < data_part.len() {
+        if data_part.len() - pos < 8 {
+            return Err(Error::new(ErrorKind::InvalidData, format!("vbf block {} header is truncated", blocks.len())));
+        }
+        let address = u32::from_be_bytes([data_part[pos], data_part[pos + 1], data_part[pos + 2], data_part[pos + 3]]);
+        let length = u32::from_be_bytes([data_part[pos + 4], data_part[pos + 5], data_part[pos + 6], data_part[pos + 7]]) as usize;
+        pos += 8;
+        if data_part.len() - pos < length + 2 {
+            return Err(Error::new(ErrorKind::InvalidData, format!("vbf block {} at 0x{:08X} is truncated", blocks.len(), address)));
+        }
+        let data = &data_part[pos..pos + length];
+        let checksum = u16::from_be_bytes([data_part[pos + length], data_part[pos + length + 1]]);
+        if utils::common::crc16_ccitt(data) != checksum {
+            return Err(Error::new(ErrorKind::InvalidData,
+                format!("vbf block {} at 0x{:08X} checksum mismatch, block 0x{:04X}, calculated 0x{:04X}",
+                        blocks.len(), address, checksum, utils::common::crc16_ccitt(data))));
+        }
+        debug!("vbf block {} address 0x{:08X} length 0x{:X} checksum 0x{:04X}", blocks.len(), address, length, checksum);
+        blocks.push(MemorySegment { address, data: data.to_vec() });
+        pos += length + 2;
+    }
+
+    debug!("vbf {} {} {} ecu 0x{:X}, {} blocks", header.sw_part_number, header.sw_version, header.sw_part_type,
+           header.ecu_address, blocks.len());
+    Ok(VbfFile { header, blocks })
+}
+
+
+/*****************************************************************************************************************
+ *  executor::vbf::configure function
+ *  brief      Set the public key checking software signatures of vbf files
+ *  details    The key file is read here, so a missing key or a scheme not fitting the key fails when config is loaded
+ *  \param[in]  settings: vbf_verification section of config, None if config has no such section
+ *  \param[out] -
+ *  \precondition -
+ *  \reentrant:  FALSE
+ *  \return     InvalidInput error naming the wrong field
+ ****************************************************************************************************************/
+pub fn configure(settings: Option<VerificationSettings>) -> Result<(), io::Error> {
+    let check = match settings {
+        Some(settings) => {
+            let public_key = PublicKey::load(&settings.public_key)
+                .map_err(|err| Error::new(ErrorKind::InvalidInput, format!("vbf_verification: public_key {}", err)))?;
+            let scheme = settings.scheme.unwrap_or_else(|| public_key.default_scheme(HashAlgorithm::Sha256));
+            let key_fits = matches!((&public_key, scheme),
+                (PublicKey::EcdsaP256(_), SignatureScheme::EcdsaSha256 | SignatureScheme::EcdsaSha512)
+                | (PublicKey::Rsa(_), SignatureScheme::RsaPkcs1Sha256 | SignatureScheme::RsaPkcs1Sha512
+                                      | SignatureScheme::RsaPssSha256 | SignatureScheme::RsaPssSha512));
+            if !key_fits {
+                return Err(Error::new(ErrorKind::InvalidInput,
+                    format!("vbf_verification: scheme {:?} does not fit the key of {}", scheme, settings.public_key)));
+            }
+            Some(SignatureCheck { public_key, scheme, field: settings.signature })
+        }
+        None => None,
+    };
+    *SIGNATURE_CHECK.write().unwrap() = check;
+    Ok(())
+}
+
+
+/*****************************************************************************************************************
+ *  executor::vbf::parse_hash_table function
+ *  brief      Parse the verification block table
+ *  details    -
+ *  \param[in]  data: data of the verification block
+ *  \param[out] -
+ *  \precondition -
+ *  \reentrant:  TRUE
+ *  \return     memory range and SHA-256 of every table entry
+ ****************************************************************************************************************/
+fn parse_hash_table(data: &[u8]) -> Result<Vec<(MemoryRange, &[u8])>, io::Error> {
+    if data.len() < HASH_TABLE_HEADER_LENGTH {
+        return Err(Error::new(ErrorKind::InvalidData, "vbf verification block table is truncated"));
+    }
+    let format = u16::from_be_bytes([data[0], data[1]]);
+    if format != HASH_TABLE_FORMAT {
+        return Err(Error::new(ErrorKind::InvalidData, format!("vbf verification block table format 0x{:04X} is not supported", format)));
+    }
+    let count = u16::from_be_bytes([data[2], data[3]]) as usize;
+    if data.len() != HASH_TABLE_HEADER_LENGTH + count * HASH_TABLE_ENTRY_LENGTH {
+        return Err(Error::new(ErrorKind::InvalidData,
+            format!("vbf verification block table has {} entries but {} bytes", count, data.len())));
+    }
+    Ok(data[HASH_TABLE_HEADER_LENGTH..].chunks(HASH_TABLE_ENTRY_LENGTH).map(|entry| {
+        let start = u32::from_be_bytes([entry[0], entry[1], entry[2], entry[3]]);
+        let length = u32::from_be_bytes([entry[4], entry[5], entry[6], entry[7]]);
+        (MemoryRange { start, length }, &entry[8..])
+    }).collect())
+}
+
+
+/*****************************************************************************************************************
+ *  executor::vbf::verify function
+ *  brief      Verify hashes and software signature of a loaded vbf file without ECU
+ *  details    SHA-256 of every data block must match its entry of the verification block table, every entry must
+ *             have a data block and SHA-256 of the table must be verification_block_root_hash of header. The root
+ *             hash is then checked against the signature field of the key set by configure. Files without
+ *             verification_block_root_hash pass unchanged if no key is configured.
+ *  \param[in]  vbf_file: file loaded by load
+ *  \param[out] -
+ *  \precondition -
+ *  \reentrant:  TRUE
+ *  \return     true if the signature was checked
+ ****************************************************************************************************************/
+pub fn verify(vbf_file: &VbfFile) -> Result<bool, io::Error> {
+    let header = &vbf_file.header;
+    let signature_check = SIGNATURE_CHECK.read().unwrap();
+    let root_hash = match (&header.verification_block_root_hash, signature_check.as_ref()) {
+        (Some(root_hash), _) => root_hash,
+        (None, None) => {
+            debug!("vbf has no verification_block_root_hash, hashes are not checked");
+            return Ok(false);
+        }
+        (None, Some(_)) => return Err(Error::new(ErrorKind::InvalidData, "vbf header has no verification_block_root_hash")),
+    };
+    let (start, length) = match (header.verification_block_start, header.verification_block_length) {
+        (Some(start), Some(length)) => (start, length),
+        _ => return Err(Error::new(ErrorKind::InvalidData, "vbf header has no verification_block_start and verification_block_length")),
+    };
+    let table_block = vbf_file.blocks.iter().find(|block| block.address == start && block.data.len() == length as usize)
+        .ok_or_else(|| Error::new(ErrorKind::InvalidData,
+            format!("vbf has no data block 0x{:08X} length 0x{:X} of verification block", start, length)))?;
+
+    if excrypto::digest(HashAlgorithm::Sha256, &table_block.data) != *root_hash {
+        return Err(Error::new(ErrorKind::InvalidData, "vbf verification block does not match verification_block_root_hash"));
+    }
+    let table = parse_hash_table(&table_block.data)?;
+    for (index, block) in vbf_file.blocks.iter().enumerate().filter(|(_, block)| block.address != start) {
+        let hash = table.iter()
+            .find(|(range, _)| range.start == block.address && range.length as usize == block.data.len())
+            .map(|(_, hash)| *hash)
+            .ok_or_else(|| Error::new(ErrorKind::InvalidData,
+                format!("vbf block {} at 0x{:08X} is not in verification block table", index, block.address)))?;
+        if excrypto::digest(HashAlgorithm::Sha256, &block.data) != hash {
+            return Err(Error::new(ErrorKind::InvalidData,
+                format!("vbf block {} at 0x{:08X} does not match its hash in verification block table", index, block.address)));
+        }
+    }
+    for (range, _) in table.iter() {
+        if !vbf_file.blocks.iter().any(|block| block.address == range.start && block.data.len() == range.length as usize) {
+            return Err(Error::new(ErrorKind::InvalidData,
+                format!("vbf has no data block 0x{:08X} length 0x{:X} of verification block table", range.start, range.length)));
+        }
+    }
+    debug!("vbf verification block table: {} hashes match, root hash matches", table.len());
+
+    let check = match signature_check.as_ref() {
+        Some(check) => check,
+        None => return Ok(false),
+    };
+    let (name, signature) = match check.field {
+        SignatureField::SwSignature => ("sw_signature", &header.sw_signature),
+        SignatureField::SwSignatureDev => ("sw_signature_dev", &header.sw_signature_dev),
+    };
+    let signature = signature.as_ref()
+        .ok_or_else(|| Error::new(ErrorKind::InvalidData, format!("vbf header has no {}", name)))?;
+    excrypto::verify_signature(&check.public_key, check.scheme, root_hash, signature)
+        .map_err(|err| Error::new(err.kind(), format!("vbf {}: {}", name, err)))?;
+    debug!("vbf {} verified with {:?}", name, check.scheme);
+    Ok(true)
+}
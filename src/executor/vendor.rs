@@ -0,0 +1,247 @@
+use serde::Deserialize;
+use std::io::{self, Error, ErrorKind};
+use std::sync::Arc;
+
+use crate::transport;
+use crate::executor::link::LinkState;
+use crate::executor::parameters::{ExpectPattern, HexValue, MemoryRange, SecurityAccessParams};
+use crate::executor::securityaccess;
+use crate::executor::swdl::DownloadFormat;
+
+/*****************************************************************************************************************
+ *  Define all gloval macro & variable here
+ ****************************************************************************************************************/
+lazy_static::lazy_static! {
+    static ref PROFILES: Vec<Arc<dyn VendorProfile>> = vec![
+        Arc::new(Volvo(parse_builtin(VOLVO_PROFILE))),
+        Arc::new(Iso14229(parse_builtin(ISO14229_PROFILE))),
+    ];
+}
+
+const VOLVO_PROFILE: &str = include_str!("../../json/profiles/volvo.json");
+const ISO14229_PROFILE: &str = include_str!("../../json/profiles/iso14229.json");
+
+/*****************************************************************************************************************
+ *  OEM specific part of a diagnostic flow, selected by vendor of config. Identifiers and expected results are
+ *  data of the profile (json/profiles), behaviour which is more than data is implemented by the profile type:
+ *  erase/check option records by default from data, security access exchanges besides seed/key by the OEM.
+ ****************************************************************************************************************/
+pub trait VendorProfile: Send + Sync {
+    fn data(&self) -> &ProfileData;
+
+    fn name(&self) -> &str {
+        &self.data().name
+    }
+
+    // routineControlOptionRecord of eraseMemory for one memory range
+    fn erase_option(&self, range: &MemoryRange, format: DownloadFormat) -> Result<Vec<u8>, io::Error> {
+        match self.data().swdl.erase_record {
+            EraseRecord::StartLength => {
+                let mut option: Vec<u8> = Vec::with_capacity(8);
+                option.extend_from_slice(&range.start.to_be_bytes());
+                option.extend_from_slice(&range.length.to_be_bytes());
+                Ok(option)
+            }
+            EraseRecord::AddressAndLength => format.encode_address_and_length(range.start, range.length),
+        }
+    }
+
+    // routineControlOptionRecord of checkMemory after a vbf file is downloaded
+    fn check_memory_option(&self, sw_signature: Option<&[u8]>) -> Vec<u8> {
+        match self.data().swdl.check_memory_record {
+            CheckMemoryRecord::Signature => sw_signature.unwrap_or_default().to_vec(),
+            CheckMemoryRecord::None => Vec::new(),
+        }
+    }
+
+    // SecurityAccess with an algorithm which is not a seed/key algorithm
+    fn security_access(&self, _stream: &mut transport::diag::Diag, params: &SecurityAccessParams, _expect: &[ExpectPattern],
+                       _level: u8, _timeout: u64) -> Result<(), io::Error> {
+        Err(Error::new(ErrorKind::InvalidInput,
+            format!("vendor profile \"{}\" supports only seed/key algorithms, not {}", self.name(), params.algorithm)))
+    }
+}
+
+/* Content of a profile data file */
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ProfileData {
+    pub name: String,
+    #[serde(default)]
+    pub aliases: Vec<String>, // other vendor names selecting the profile. Ex: "generic"
+    pub sessions: SessionIds,
+    pub security: SecurityLevels,
+    pub routines: Routines,
+    pub download: DownloadDefaults,
+    pub swdl: SwdlFlow,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SessionIds {
+    pub default: HexValue, // not replayed after communication loss, ECU starts in it
+    pub programming: HexValue,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SecurityLevels {
+    pub programming_level: HexValue, // requestSeed level unlocking erase and download
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Routines {
+    pub erase_memory: Routine,
+    pub check_memory: Routine, // default check of vbf files
+    pub activate_sbl: Routine,
+    pub programming_preconditions: Option<Routine>, // started by flash_package before its preconditions
+    pub check_dependencies: Option<Routine>, // started by flash_package after the last file
+}
+
+/* RoutineControl startRoutine, result is the pattern of the response after 71 01 and routine identifier */
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Routine {
+    pub id: HexValue,
+    pub result: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DownloadDefaults {
+    pub data_format_identifier: HexValue,
+    pub address_and_length_format_identifier: HexValue,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SwdlFlow {
+    pub erase_record: EraseRecord,
+    pub check_memory_record: CheckMemoryRecord,
+}
+
+/* Memory range of eraseMemory: 4 bytes start and 4 bytes length, or encoded like RequestDownload */
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EraseRecord {
+    StartLength,
+    AddressAndLength,
+}
+
+/* Option of checkMemory of vbf files: sw_signature_dev of header or nothing */
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CheckMemoryRecord {
+    Signature,
+    None,
+}
+
+impl Routine {
+
+pub fn id(&self) -> u16 {
+    self.id.0 as u16
+}
+
+// Expected response when the routine is started with another identifier, ex: check_routine of a swdl step
+pub fn expect_for(&self, id: u16) -> String {
+    format!("7101{:04X}{}", id, self.result)
+}
+
+pub fn expect(&self) -> String {
+    self.expect_for(self.id())
+}
+
+}
+
+impl ProfileData {
+
+pub fn download_format(&self) -> DownloadFormat {
+    DownloadFormat {
+        data_format_identifier: self.download.data_format_identifier.0 as u8,
+        address_and_length_format_identifier: self.download.address_and_length_format_identifier.0 as u8,
+    }
+}
+
+}
+
+
+/* Volvo: AES authentication exchange for algorithms AES128/AES256 */
+struct Volvo(ProfileData);
+
+impl VendorProfile for Volvo {
+    fn data(&self) -> &ProfileData {
+        &self.0
+    }
+
+    fn security_access(&self, stream: &mut transport::diag::Diag, params: &SecurityAccessParams, expect: &[ExpectPattern],
+                       level: u8, timeout: u64) -> Result<(), io::Error> {
+        securityaccess::security_access_volvo(stream, params, expect, level, timeout)
+    }
+}
+
+/* Generic ISO 14229: everything from data, seed/key security access only */
+struct Iso14229(ProfileData);
+
+impl VendorProfile for Iso14229 {
+    fn data(&self) -> &ProfileData {
+        &self.0
+    }
+}
+
+fn parse_builtin(content: &str) -> ProfileData {
+    serde_json::from_str(content).expect("Failed to parse built-in vendor profile")
+}
+
+
+/*****************************************************************************************************************
+ *  executor::vendor::find function
+ *  brief      Get the profile of a vendor name of config
+ *  details    Names and aliases are compared case insensitive
+ *  \param[in]  vendor: vendor name in config
+ *  \param[out] -
+ *  \precondition -
+ *  \reentrant:  TRUE
+ *  \return     profile or error listing the known vendors
+ ****************************************************************************************************************/
+pub fn find(vendor: &str) -> Result<Arc<dyn VendorProfile>, io::Error> {
+    let profiles = &*PROFILES;
+    let is_match = |profile: &Arc<dyn VendorProfile>| profile.name().eq_ignore_ascii_case(vendor)
+        || profile.data().aliases.iter().any(|alias| alias.eq_ignore_ascii_case(vendor));
+    if let Some(profile) = profiles.iter().find(|profile| is_match(profile)) {
+        return Ok(Arc::clone(profile));
+    }
+    let known: Vec<String> = profiles.iter().map(|profile| {
+        let data = profile.data();
+        if data.aliases.is_empty() { data.name.clone() } else { format!("{} ({})", data.name, data.aliases.join(", ")) }
+    }).collect();
+    Err(Error::new(ErrorKind::InvalidInput, format!("vendor \"{}\" has no profile, known vendors: {}", vendor, known.join(", "))))
+}
+
+
+/*****************************************************************************************************************
+ *  executor::vendor::programming_state_warnings function
+ *  brief      Compare diagnostic state reached by previous steps with the programming preconditions of a profile
+ *  details    Only reported, the ECU decides if it accepts erase and download
+ *  \param[in]  profile: vendor profile
+ *              link: diagnostic state reached by previous steps
+ *  \param[out] -
+ *  \precondition -
+ *  \reentrant:  TRUE
+ *  \return     unmet preconditions, empty if the ECU should accept programming
+ ****************************************************************************************************************/
+pub fn programming_state_warnings(profile: &dyn VendorProfile, link: &LinkState) -> Vec<String> {
+    let data = profile.data();
+    let mut warnings = Vec::new();
+    let programming_session = data.sessions.programming.0 as u8;
+    if link.session() != Some(programming_session) {
+        warnings.push(format!("{} programming needs session 0x{:02X}, current session is {}", profile.name(), programming_session,
+                              link.session().map_or(String::from("default"), |session| format!("0x{:02X}", session))));
+    }
+    let programming_level = data.security.programming_level.0 as u8;
+    if link.security_level() != Some(programming_level) {
+        warnings.push(format!("{} programming needs security access level 0x{:02X}, unlocked level is {}", profile.name(),
+                              programming_level, link.security_level().map_or(String::from("none"), |level| format!("0x{:02X}", level))));
+    }
+    warnings
+}
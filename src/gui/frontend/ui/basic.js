@@ -1,7 +1,6 @@
 const vendorIdToName = {
     common: 'common',
     volvo: 'volvo',
-    vinfast: 'vinfast',
 };
 
 const doipversionIdToName = {
@@ -138,6 +137,22 @@ fileswdlInput.addEventListener('click', () => {
     window.__TAURI__
         .invoke('selectswdlfiles')
 })
+let flashFile = ''
+window.__TAURI__.event.listen('flash-file', (event) => {
+    flashFile = `file ${event.payload.index + 1}/${event.payload.count}`
+})
+window.__TAURI__.event.listen('flash-progress', (event) => {
+    const progress = event.payload
+    const name = progress.file.split(/[\\/]/).pop()
+    const percent = progress.bytes_total > 0 ? Math.floor(progress.bytes_sent * 100 / progress.bytes_total) : 100
+    let phase = progress.phase
+    if (progress.phase === 'erase' || progress.phase === 'download') {
+        phase += ` ${progress.block_index + 1}/${progress.block_count}`
+    }
+    let eta = progress.eta_ms === null ? '' : ` ETA ${Math.ceil(progress.eta_ms / 1000)}s`
+    flashProgress.value = percent
+    flashStatus.textContent = `${flashFile} ${name} ${phase} ${percent}% ${(progress.throughput / 1000).toFixed(1)} kB/s${eta}`
+})
 
 //Handle sequence events
 executeBtn.addEventListener('click', () => {
@@ -150,17 +165,85 @@ filesequenceInput.addEventListener('click', () => {
     window.__TAURI__
         .invoke('selectsequencefile')
 })
+pauseBtn.addEventListener('click', () => {
+    window.__TAURI__
+        .invoke('pausesequence')
+        .then(updateResponse)
+        .catch(updateResponse)
+})
+resumeBtn.addEventListener('click', () => {
+    window.__TAURI__
+        .invoke('resumesequence')
+        .then(updateResponse)
+        .catch(updateResponse)
+})
+abortBtn.addEventListener('click', () => {
+    window.__TAURI__
+        .invoke('abortsequence')
+        .then(updateResponse)
+        .catch(updateResponse)
+})
+
+//Handle step debugger events
+function updateStepBox(response) {
+    stepBox.textContent = typeof response === 'string' ? response : JSON.stringify(response)
+}
+steploadBtn.addEventListener('click', () => {
+    window.__TAURI__
+        .invoke('steploadsequence')
+        .then(updateStepBox)
+        .catch(updateResponse)
+})
+const stepCommands = {
+    '#stepnext-btn': 'next',
+    '#stepcontinue-btn': 'continue',
+    '#stepskip-btn': 'skip',
+    '#stepjump-btn': 'jump',
+    '#stepbreak-btn': 'break',
+    '#stepaction-btn': 'action',
+    '#stepexpect-btn': 'expect',
+};
+for (const [selector, command] of Object.entries(stepCommands)) {
+    document.querySelector(selector).addEventListener('click', () => {
+        window.__TAURI__
+            .invoke('stepcommand', {
+                command: command,
+                argument: stepargumentInput.value,
+            })
+            .then(updateStepBox)
+            .catch(updateResponse)
+    })
+}
+exportreportBtn.addEventListener('click', () => {
+    window.__TAURI__
+        .invoke('exportreport')
+        .then(updateResponse)
+        .catch(updateResponse)
+})
 
 //Handle Security-Access events
 sendSABtn.addEventListener('click', () => {
     window.__TAURI__
         .invoke('sendsecurityaccess', {
             level: SAlevelInput.value,
-            key: SAkeyInput.value,
+            authkey: SAkeyInput.value,
+            proofkey: SApopkeyInput.value,
         })
         .then(updateResponse)
         .catch(updateResponse)
 })
+unlockkeystoreBtn.addEventListener('click', () => {
+    window.__TAURI__
+        .invoke('unlockkeystore', {
+            path: keystoreInput.value,
+            password: keystorepasswordInput.value,
+        })
+        .then((count) => {
+            keystorepasswordInput.value = ''
+            updateResponse('Unlocked keystore with ' + count + ' keys')
+        })
+        .catch(updateResponse)
+})
 
 //Handle Tester-Present events
 testerpresentcheckbox.addEventListener('change', function(event) {
@@ -1,12 +1,18 @@
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")]
 use log::debug;
 use std::sync::{Arc, Mutex};
-use serde_json::{self, Value};
+use serde_json::{self, json, Map};
+use std::convert::TryFrom;
 use std::env;
-// use serde_json::json;
 
 use crate::executor::executor::Executor;
-use crate::executor::parameters::SequenceItem;
+use crate::executor::parameters::{DoipAction, ExpectPattern, FlashPackageParams, HexBytes, RawSequenceItem, SecuredRequests,
+                                  SequenceItem, SocketAction, Step, SwdlFormat, SwdlParams};
+use crate::executor::report::RunRecord;
+use crate::executor::package;
+use crate::executor::control::SEQUENCE_CONTROL;
+use crate::executor::progress::FLASH_PROGRESS;
+use crate::executor::stepper::{Breakpoint, StepSession};
 use crate::utils; // Import the parse config module
 use crate::executor::parse_sequence;
 use crate::transport::diag;
@@ -14,6 +20,7 @@ use crate::transport::config::{Config, Ethernet, Doip, Parameters, CONFIG};
 
 use std::path::PathBuf;
 use tauri::api::dialog::blocking::FileDialogBuilder;
+use tauri::Manager;
 
 
 #[derive(Debug, serde::Serialize)]
@@ -26,6 +33,8 @@ lazy_static::lazy_static! {
     static ref EXECUTOR_OBJ: Arc<Mutex<Executor>> = Arc::new(Mutex::new(Executor::create_executor(Arc::new(Mutex::new(diag::create_diag())))));
     static ref SWDLPATHS: Arc<Mutex<Vec<PathBuf>>> = Arc::new(Mutex::new(Vec::new()));
     static ref SEQUENCEPATH: Arc<Mutex<PathBuf>> = Arc::new(Mutex::new(PathBuf::new()));
+    static ref LASTREPORT: Arc<Mutex<Option<RunRecord>>> = Arc::new(Mutex::new(None));
+    static ref STEPSESSION: Arc<Mutex<Option<StepSession>>> = Arc::new(Mutex::new(None));
 }
 
 #[tauri::command]
@@ -112,12 +121,8 @@ async fn connect (remoteip: String,
     let config: std::sync::RwLockReadGuard<'_, Config> = CONFIG.read().unwrap();
     debug!("get config {:?}", config);
     let item = SequenceItem {
-        name: String::from("socket"),
         description: String::from("connect to SGA"),
-        action: Value::String(String::from("connect")),
-        expect: Value::Array(vec![
-            Value::String(String::from("*")),
-        ]),
+        step: Step::Socket { action: SocketAction::Connect },
         timeout: String::from("2s"),
         fail: String::from(""),
     };
@@ -133,12 +138,8 @@ async fn connect (remoteip: String,
 
     // send doip activation code
     let item = SequenceItem {
-        name: String::from("send_doip"),
         description: String::from("doip activation"),
-        action: Value::String(String::from("activation")),
-        expect: Value::Array(vec![
-            Value::String(String::from("*")),
-        ]),
+        step: Step::SendDoip { action: DoipAction::Activation, expect: Vec::new() },
         timeout: String::from("2s"),
         fail: String::from(""),
     };
@@ -170,12 +171,8 @@ async fn disconnect() -> Result<(), GUIError> {
 
     let config = CONFIG.read().unwrap();
     let item = SequenceItem {
-        name: String::from("socket"),
         description: String::from("disconnect"),
-        action: Value::String(String::from("disconnect")),
-        expect: Value::Array(vec![
-            Value::String(String::from("*")),
-        ]),
+        step: Step::Socket { action: SocketAction::Disconnect },
         timeout: String::from("10s"),
         fail: String::from(""),
     };
@@ -203,14 +200,16 @@ async fn senduds(value: String) -> Result<String, GUIError> {
     };
 
     let config = CONFIG.read().unwrap();
-    let action_value = Value::Array(vec![Value::String(String::from(value))]);
+    let request = match HexBytes::try_from(value.as_str()) {
+        Ok(request) => request,
+        Err(err) => {
+            eprintln!("Error diag message format: {}", err);
+            return Err(GUIError::Error);
+        }
+    };
     let item = SequenceItem {
-        name: String::from("send_diag"),
         description: String::from("Send Diagnostic message"),
-        action: action_value,
-        expect: Value::Array(vec![
-            Value::String(String::from("*")),
-        ]),
+        step: Step::SendDiag { requests: vec![request], expect: vec![ExpectPattern(String::from("*"))], secured: SecuredRequests::default() },
         timeout: String::from("10s"),
         fail: String::from(""),
     };
@@ -239,14 +238,16 @@ async fn senddoip(value: String) -> Result<String, GUIError> {
     };
 
     let config = CONFIG.read().unwrap();
-    let action_value = Value::Array(vec![Value::String(String::from(value))]);
+    let request = match HexBytes::try_from(value.as_str()) {
+        Ok(request) => request,
+        Err(err) => {
+            eprintln!("Error doip message format: {}", err);
+            return Err(GUIError::Error);
+        }
+    };
     let item = SequenceItem {
-        name: String::from("send_doip"),
         description: String::from("Send Doip message"),
-        action: action_value,
-        expect: Value::Array(vec![
-            Value::String(String::from("*")),
-        ]),
+        step: Step::SendDoip { action: DoipAction::Raw(vec![request]), expect: vec![ExpectPattern(String::from("*"))] },
         timeout: String::from("10s"),
         fail: String::from(""),
     };
@@ -263,7 +264,7 @@ async fn senddoip(value: String) -> Result<String, GUIError> {
 }
 
 #[tauri::command]
-async fn flash() -> Result<(), GUIError> {
+async fn flash(window: tauri::Window) -> Result<(), GUIError> {
     lazy_static::lazy_static! {
         static ref LOCK: Arc<Mutex<()>> = Arc::new(Mutex::new(()));
     }
@@ -275,33 +276,36 @@ async fn flash() -> Result<(), GUIError> {
     };
 
     let paths =  SWDLPATHS.lock().unwrap();
-    for path in paths.iter() {
+    for (index, path) in paths.iter().enumerate() {
         debug!("flashing {:?}", path);
+        let _ = window.emit("flash-file", json!({"index": index, "count": paths.len(), "path": path.display().to_string()}));
         let config = CONFIG.read().unwrap();
-        let action_str = format!(r#"["path:{}", "format:vbf"]"#, path.display().to_string().replace("\\", "\\\\"));
-        let result: Result<Value, serde_json::Error> = serde_json::from_str(action_str.as_str());
-        match result {
-            Ok(action_value) => {
-                let item = SequenceItem {
-                    name: String::from("swdl"),
-                    description: String::from("download vbf file"),
-                    action: action_value,
-                    expect: Value::Array(vec![
-                        Value::String(String::from("*")),
-                    ]),
-                    timeout: String::from("10s"),
-                    fail: String::from(""),
-                };
-                match Executor::execute_cmd(EXECUTOR_OBJ.clone(), item, &config.ethernet.vendor) {
-                    Ok(()) => debug!("Command executed successfully!"),
-                    Err(err) => {
-                        eprintln!("Error executing command: {}, STOP", err);
-                        return Err(GUIError::Error);
-                    }
-                }
+        let is_package = package::is_package(path);
+        let path = path.display().to_string();
+        let item = if is_package {
+            SequenceItem {
+                description: String::from("flash package"),
+                step: Step::FlashPackage { params: FlashPackageParams { path } },
+                timeout: String::from("10s"),
+                fail: String::from(""),
+            }
+        } else {
+            let format = SwdlFormat::from_path(&path).unwrap_or(SwdlFormat::Vbf);
+            if format == SwdlFormat::Bin {
+                eprintln!("bin file {} needs a start address, flash it from a sequence file or a package", path);
+                return Err(GUIError::Error);
+            }
+            SequenceItem {
+                description: format!("download {:?} file", format),
+                step: Step::Swdl { params: SwdlParams::new(path, format) },
+                timeout: String::from("10s"),
+                fail: String::from(""),
             }
-            Err(e) => {
-                println!("Error parsing swdl action: {}", e);
+        };
+        match Executor::execute_cmd(EXECUTOR_OBJ.clone(), item, &config.ethernet.vendor) {
+            Ok(()) => debug!("Command executed successfully!"),
+            Err(err) => {
+                eprintln!("Error executing command: {}, STOP", err);
                 return Err(GUIError::Error);
             }
         }
@@ -349,8 +353,11 @@ async fn executesequence() -> Result<(), GUIError> {
     };
 
     let path =  SEQUENCEPATH.lock().unwrap();
+    let mut record = RunRecord::new(&path.display().to_string(), &CONFIG.read().unwrap());
 
-    match parse_sequence::parse(path.display().to_string(), EXECUTOR_OBJ.clone()) {
+    let result = parse_sequence::parse(path.display().to_string(), EXECUTOR_OBJ.clone(), &mut record);
+    *LASTREPORT.lock().unwrap() = Some(record);
+    match result {
         Ok(()) => {}
         Err(err) => {
             eprintln!("Error reading sequence file {}", err);
@@ -361,6 +368,125 @@ async fn executesequence() -> Result<(), GUIError> {
     Ok(())
 }
 
+#[tauri::command]
+async fn pausesequence() -> Result<String, GUIError> {
+    if SEQUENCE_CONTROL.pause() {
+        return Ok("sequence will pause after current step".to_string());
+    }
+    Err(GUIError::Error)
+}
+
+#[tauri::command]
+async fn resumesequence() -> Result<String, GUIError> {
+    if SEQUENCE_CONTROL.resume() {
+        return Ok("sequence resumed".to_string());
+    }
+    Err(GUIError::Error)
+}
+
+#[tauri::command]
+async fn abortsequence() -> Result<String, GUIError> {
+    if SEQUENCE_CONTROL.abort() {
+        return Ok("sequence aborting, running cleanup".to_string());
+    }
+    Err(GUIError::Error)
+}
+
+#[tauri::command]
+async fn steploadsequence() -> Result<String, GUIError> {
+    let path = SEQUENCEPATH.lock().unwrap();
+    match parse_sequence::load(&path.display().to_string()) {
+        Ok(sequence) => {
+            let mut step_session = STEPSESSION.lock().unwrap();
+            // tester-present started for a previous step session is still running
+            if step_session.is_none() && parse_sequence::start_tester_present(&EXECUTOR_OBJ).is_err() {
+                return Err(GUIError::Error);
+            }
+            let session = StepSession::new(sequence);
+            let listing = session.describe_list();
+            *step_session = Some(session);
+            Ok(listing)
+        }
+        Err(err) => {
+            eprintln!("Error reading sequence file {}", err);
+            Err(GUIError::Error)
+        }
+    }
+}
+
+#[tauri::command]
+async fn stepcommand(command: String, argument: String) -> Result<String, GUIError> {
+    let mut step_session = STEPSESSION.lock().unwrap();
+    let session = match step_session.as_mut() {
+        Some(session) => session,
+        None => {
+            eprintln!("Load a sequence in step mode first");
+            return Err(GUIError::Error);
+        }
+    };
+    let vendor = CONFIG.read().unwrap().ethernet.vendor.clone();
+
+    let result = match command.as_str() {
+        "next" => session.step(&EXECUTOR_OBJ, &vendor),
+        "continue" => session.run_to_breakpoint(&EXECUTOR_OBJ, &vendor),
+        "skip" => {
+            session.skip();
+            Ok(())
+        }
+        "jump" => match argument.trim().parse::<usize>() {
+            Ok(index) => session.jump(index),
+            Err(_) => Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "invalid step index")),
+        },
+        "break" => {
+            session.toggle_breakpoint(Breakpoint::parse(&argument));
+            Ok(())
+        }
+        "action" | "expect" => session.edit_current(&command, &argument),
+        _ => Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "unknown step command")),
+    };
+
+    let mut output = String::new();
+    if let Err(err) = result {
+        output.push_str(&format!("FAILED: {}\n", err));
+    }
+    if command == "next" || command == "continue" {
+        output.push_str(&session.describe_last());
+    }
+    output.push_str(&session.describe_list());
+    output.push_str(&session.describe_current());
+    Ok(output)
+}
+
+#[tauri::command]
+async fn exportreport() -> Result<String, GUIError> {
+    let last_report = LASTREPORT.lock().unwrap();
+    let record = match last_report.as_ref() {
+        Some(record) => record,
+        None => {
+            eprintln!("No sequence was executed yet");
+            return Err(GUIError::Error);
+        }
+    };
+
+    let dialog_result = FileDialogBuilder::new()
+        .add_filter("HTML report", &["html"])
+        .add_filter("JUnit XML", &["xml"])
+        .add_filter("JSON", &["json"])
+        .save_file();
+    match dialog_result {
+        Some(path) => {
+            match record.export(&path.display().to_string()) {
+                Ok(()) => Ok(format!("Exported report {}", path.display())),
+                Err(err) => {
+                    eprintln!("Failed to export report: {}", err);
+                    Err(GUIError::Error)
+                }
+            }
+        }
+        None => Ok("User closed the save dialog.".to_string()),
+    }
+}
+
 #[tauri::command]
 async fn selectsequencefile() {
     lazy_static::lazy_static! {
@@ -386,7 +512,7 @@ async fn selectsequencefile() {
 }
 
 #[tauri::command]
-async fn sendsecurityaccess(level: String, key: String) -> Result<(), GUIError> {
+async fn sendsecurityaccess(level: String, authkey: String, proofkey: String) -> Result<(), GUIError> {
     lazy_static::lazy_static! {
         static ref LOCK: Arc<Mutex<()>> = Arc::new(Mutex::new(()));
     }
@@ -400,8 +526,7 @@ async fn sendsecurityaccess(level: String, key: String) -> Result<(), GUIError>
     let config = CONFIG.read().unwrap();
     let name: String;
     if !level.is_empty() && level.chars().all(|c| c.is_digit(16)) {//check level string should not empty and contain hex only
-        //name = format!("securityaccess_{:02X}", level.parse::<u16>().unwrap());
-        if let Ok(level_value) = u16::from_str_radix(&level, 16) {
+        if let Ok(level_value) = u8::from_str_radix(&level, 16) {
             name = format!("securityaccess_{:02X}", level_value);
         } else {
             return Err(GUIError::Error);
@@ -411,30 +536,41 @@ async fn sendsecurityaccess(level: String, key: String) -> Result<(), GUIError>
         eprintln!("Error key or level format, STOP");
         return Err(GUIError::Error);
     }
-    let action_str: String = format!(r#"["algorithm:AES128", "iv:random", "encryption_authentication_key:{}", "proof_of_ownership_key:{}"]"#, key, key);
-    let result: Result<Value, serde_json::Error> = serde_json::from_str(action_str.as_str());
-    match result {
-        Ok(action_value) => {
-            let item = SequenceItem {
-                name: String::from(name),
-                description: String::from(format!("Send security-access level {}", level)),
-                action: action_value,
-                expect: Value::Array(vec![
-                    Value::String(String::from("*")),
-                ]),
-                timeout: String::from("5s"),
-                fail: String::from(""),
-            };
-            match Executor::execute_cmd(EXECUTOR_OBJ.clone(), item, &config.ethernet.vendor) {
-                Ok(()) => debug!("Command executed successfully!"),
-                Err(err) => {
-                    eprintln!("Error executing command: {}, STOP", err);
-                    return Err(GUIError::Error);
-                }
-            }
+    // 32 bytes keys select AES-256, "@name" references are resolved first but stay references in the step
+    let resolved_authkey = match utils::keystore::resolve(&authkey) {
+        Ok(value) => value,
+        Err(err) => {
+            eprintln!("Error resolving authentication key: {}, STOP", err);
+            return Err(GUIError::Error);
         }
-        Err(e) => {
-            eprintln!("Error parsing security-access action: {}", e);
+    };
+    let resolved_authkey = resolved_authkey.trim();
+    let authkey_hex = resolved_authkey.strip_prefix("0x").or_else(|| resolved_authkey.strip_prefix("0X")).unwrap_or(resolved_authkey);
+    let algorithm = if authkey_hex.len() == 64 { "AES256" } else { "AES128" };
+    let raw_item = RawSequenceItem {
+        name,
+        description: String::from(format!("Send security-access level {}", level)),
+        fields: Map::from_iter([(String::from("action"), json!({
+            "algorithm": algorithm,
+            "iv": "random",
+            "encryption_authentication_key": authkey,
+            "proof_of_ownership_key": proofkey,
+        }))]),
+        expect: json!(["*"]),
+        timeout: String::from("5s"),
+        fail: String::from(""),
+    };
+    let item = match SequenceItem::from_raw(raw_item) {
+        Ok(item) => item,
+        Err(err) => {
+            eprintln!("Error parsing security-access action: {}", err);
+            return Err(GUIError::Error);
+        }
+    };
+    match Executor::execute_cmd(EXECUTOR_OBJ.clone(), item, &config.ethernet.vendor) {
+        Ok(()) => debug!("Command executed successfully!"),
+        Err(err) => {
+            eprintln!("Error executing command: {}, STOP", err);
             return Err(GUIError::Error);
         }
     }
@@ -442,6 +578,18 @@ async fn sendsecurityaccess(level: String, key: String) -> Result<(), GUIError>
     Ok(())
 }
 
+#[tauri::command]
+async fn unlockkeystore(path: String, password: String) -> Result<usize, GUIError> {
+    let password = zeroize::Zeroizing::new(password);
+    match utils::keystore::unlock(&PathBuf::from(path), &password) {
+        Ok(count) => Ok(count),
+        Err(err) => {
+            eprintln!("Failed to unlock keystore: {}", err);
+            Err(GUIError::Error)
+        }
+    }
+}
+
 #[tauri::command]
 async fn triggertesterpresent(enable: bool, interval: String) -> Result<(), GUIError> {
     lazy_static::lazy_static! {
@@ -456,7 +604,7 @@ async fn triggertesterpresent(enable: bool, interval: String) -> Result<(), GUIE
 
     debug!("Tester-Present event: {} {}", enable, interval);
 
-    if enable == true {
+    if enable {
         match Executor::start_tester_present(EXECUTOR_OBJ.clone(), interval.to_string())  {
             Ok(()) => debug!("start tester present successfully!"),
             Err(err) => {
@@ -502,6 +650,14 @@ pub fn run_gui() {
     /* Run GUI */
     tauri::Builder::default()
     // .manage(Database(Default::default()))
+    .setup(|app| {
+        // forward flashing progress of executor thread to frontend
+        let handle = app.handle();
+        FLASH_PROGRESS.subscribe(move |event| {
+            let _ = handle.emit_all("flash-progress", event.clone());
+        });
+        Ok(())
+    })
     .invoke_handler(tauri::generate_handler![
         updateconfig,
         connect,
@@ -512,7 +668,14 @@ pub fn run_gui() {
         selectsequencefile,
         flash,
         executesequence,
+        pausesequence,
+        resumesequence,
+        abortsequence,
+        steploadsequence,
+        stepcommand,
+        exportreport,
         sendsecurityaccess,
+        unlockkeystore,
         triggertesterpresent
     ])
     .build(tauri::generate_context!("src/gui/frontend/tauri.conf.json"))
@@ -1,6 +1,8 @@
 extern crate log;
 extern crate serde_json;
 extern crate serde;
+extern crate serde_yaml;
+extern crate toml;
 extern crate rand;
 extern crate cmac;
 extern crate aes;
@@ -11,6 +13,9 @@ extern crate hex;
 #[cfg(feature = "cli")]
 extern crate getopts;
 
+#[cfg(feature = "cli")]
+extern crate ctrlc;
+
 #[cfg(feature = "gui")]
 extern crate tauri;
 
@@ -18,13 +23,30 @@ mod utils {
     pub mod parse_config;
     pub mod common;
     pub mod excrypto;
+    pub mod keystore;
+    pub mod pkcs11;
 }
 
 mod executor {
     pub mod parse_sequence;
     pub mod parameters;
     pub mod securityaccess;
+    pub mod secured;
+    pub mod seedkey;
+    pub mod authentication;
     pub mod swdl;
+    pub mod upload;
+    pub mod filetransfer;
+    pub mod package;
+    pub mod image;
+    pub mod vbf;
+    pub mod progress;
+    pub mod link;
+    pub mod vendor;
+    pub mod compression;
+    pub mod report;
+    pub mod control;
+    pub mod stepper;
     pub mod executor;
 }
 
@@ -38,6 +60,7 @@ mod transport {
 #[cfg(feature = "cli")]
 mod cli {
     pub mod cli;
+    pub mod debugger;
 }
 
 #[cfg(feature = "gui")]
@@ -47,6 +70,8 @@ mod gui {
 
 
 fn main() {
+    executor::compression::register(std::sync::Arc::new(executor::compression::Lzss));
+
     #[cfg(feature = "gui")]
     {
         gui::gui::run_gui();
@@ -7,58 +7,25 @@ use std::net::TcpStream;
 use std::sync::{Arc, Mutex};
 use log::debug;
 
+/*****************************************************************************************************************
+ *  Define all gloval macro & variable here
+ ****************************************************************************************************************/
+const TRACE_MAX_BYTES: usize = 64; //bytes kept per recorded message
+const TRACE_MAX_ENTRIES: usize = 256; //exchanges kept per traced command
 
-// Define the Diag trait
-pub trait Transport {
-    fn init();
-    fn connect(&mut self) -> Result<(), io::Error>;
-    fn disconnect(&mut self) -> Result<(), io::Error>;
-    fn send_diag(&mut self, p_data: Vec<u8>) -> Result<(), io::Error>;
-    fn receive_diag(&mut self, timeout: u64) -> Result<Vec<u8>, io::Error>;
-
-    /**************** doip interface ****************/
-    fn send_doip_routing_activation(&mut self) -> Result<(), io::Error>;
-    fn send_doip_raw(&mut self, p_data: Vec<u8>) -> Result<(), io::Error>;
-    fn receive_doip(&mut self, timeout: u64) -> Result<Option<Vec<u8>>, io::Error>;
-}
 
 pub struct Diag {
     stream: Option<Arc<Mutex<TcpStream>>>,
+    trace: Option<Vec<DiagExchange>>,
 }
 
-// Implement the Diag trait for the Diag struct
-impl Transport for Diag {
-    fn init() {
-        Diag::init();
-    }
-
-    fn connect(&mut self) -> Result<(), io::Error> {
-        self.connect()
-    }
-
-    fn disconnect(&mut self) -> Result<(), io::Error> {
-        self.disconnect()
-    }
-
-    fn send_diag(&mut self, p_data: Vec<u8>) -> Result<(), io::Error> {
-        self.send_diag(p_data)
-    }
-
-    fn receive_diag(&mut self, timeout: u64) -> Result<Vec<u8>, io::Error> {
-        self.receive_diag(timeout)
-    }
-
-    fn send_doip_routing_activation(&mut self) -> Result<(), io::Error> {
-        self.send_doip_routing_activation()
-    }
-
-    fn send_doip_raw(&mut self, p_data: Vec<u8>) -> Result<(), io::Error> {
-        self.send_doip_raw(p_data)
-    }
-
-    fn receive_doip(&mut self, timeout: u64) -> Result<Option<Vec<u8>>, io::Error> {
-        self.receive_doip(timeout)
-    }
+/* One diag request and its final response, recorded while tracing is enabled */
+#[derive(Debug, Clone, Default)]
+pub struct DiagExchange {
+    pub request: Vec<u8>,
+    pub request_len: usize,
+    pub response: Vec<u8>,
+    pub response_len: usize,
 }
 
 impl Diag {
@@ -134,9 +101,7 @@ pub fn disconnect(&mut self) -> Result<(), io::Error> {
     match &mut self.stream {
         Some(stream) => {
             //drop tcp stream
-            if let Err(e) = doip::disconnect(stream) {
-                return Err(e);
-            }
+            doip::disconnect(stream)?;
             Ok(())
         }
         None => Err(io::Error::new(
@@ -158,12 +123,19 @@ pub fn disconnect(&mut self) -> Result<(), io::Error> {
  *  \return     Error code if any
  ****************************************************************************************************************/
 pub fn send_diag(&mut self, p_data: Vec<u8>) -> Result<(), io::Error> {
+    if let Some(trace) = &mut self.trace {
+        if trace.len() < TRACE_MAX_ENTRIES {
+            trace.push(DiagExchange {
+                request: p_data.iter().take(TRACE_MAX_BYTES).cloned().collect(),
+                request_len: p_data.len(),
+                ..Default::default()
+            });
+        }
+    }
     match &mut self.stream {
         Some(stream) => {
             //drop tcp stream
-            if let Err(e) = doip::send_doip_diag(stream, p_data) {
-                return Err(e);
-            }
+            doip::send_doip_diag(stream, p_data)?;
             Ok(())
         }
         None => Err(io::Error::new(
@@ -197,6 +169,26 @@ pub fn receive_diag(&mut self, timeout: u64) -> Result<Vec<u8>, io::Error> {
                         if data.len() == 3 && data[0] == 0x7f && data[2] == 0x78 { //pending diag
                             continue;
                         }
+                        if let Some(trace) = &mut self.trace {
+                            //ReadDataByIdentifier responses are kept whole, the report reads ECU identification from them
+                            let kept = if data.first() == Some(&0x62) { data.len() } else { TRACE_MAX_BYTES };
+                            let response: Vec<u8> = data.iter().take(kept).cloned().collect();
+                            match trace.last_mut() {
+                                Some(last) if last.response_len == 0 => {
+                                    last.response = response;
+                                    last.response_len = data.len();
+                                }
+                                _ => {
+                                    if trace.len() < TRACE_MAX_ENTRIES {
+                                        trace.push(DiagExchange {
+                                            response,
+                                            response_len: data.len(),
+                                            ..Default::default()
+                                        });
+                                    }
+                                }
+                            }
+                        }
                         return Ok(data);
                     },
                     Ok(None) => {
@@ -219,6 +211,37 @@ pub fn receive_diag(&mut self, timeout: u64) -> Result<Vec<u8>, io::Error> {
 
 
 
+/*****************************************************************************************************************
+ *  transport::diag::start_trace function
+ *  brief      Start recording diag requests and responses
+ *  details    Previous recorded exchanges are dropped. Recorded messages are truncated to keep memory low
+ *             during software download.
+ *  \param[in]  -
+ *  \param[out] -
+ *  \precondition: -
+ *  \reentrant:  FALSE
+ *  \return     -
+ ****************************************************************************************************************/
+pub fn start_trace(&mut self) {
+    self.trace = Some(Vec::new());
+}
+
+
+/*****************************************************************************************************************
+ *  transport::diag::stop_trace function
+ *  brief      Stop recording diag messages
+ *  details    -
+ *  \param[in]  -
+ *  \param[out] -
+ *  \precondition: -
+ *  \reentrant:  FALSE
+ *  \return     Exchanges recorded since start_trace
+ ****************************************************************************************************************/
+pub fn stop_trace(&mut self) -> Vec<DiagExchange> {
+    self.trace.take().unwrap_or_default()
+}
+
+
 /********************************************************************************************************************
  * Here to wrap doip functions to Diag object interface
  ********************************************************************************************************************/
@@ -243,13 +266,15 @@ pub fn send_doip_routing_activation(&mut self) -> Result<(), io::Error> {
     }
 }
 
+pub fn is_routing_activated(&self) -> bool {
+    self.stream.is_some() && doip::is_routing_activated()
+}
+
 pub fn send_doip_raw(&mut self, p_data: Vec<u8>) -> Result<(), io::Error> {
     match &mut self.stream {
         Some(stream) => {
             //drop tcp stream
-            if let Err(e) = soad::send_tcp(stream, p_data) {
-                return Err(e);
-            }
+            soad::send_tcp(stream, p_data)?;
             Ok(())
         }
         None => Err(io::Error::new(
@@ -267,14 +292,14 @@ pub fn receive_doip(&mut self, timeout: u64) -> Result<Option<Vec<u8>>, io::Erro
                 Ok(Some(data)) => {
                     // Process the received data
                     debug!("Received doip {} bytes: {:?}", data.len(), data);
-                    return Ok(None);
+                    Ok(None)
                 },
                 Ok(None) => {
-                    return Ok(None)
+                    Ok(None)
                 }
                 Err(e) => {
                     eprintln!("Error: {}", e);
-                    return Err(e)
+                    Err(e)
                 }
             }
         }
@@ -285,7 +310,7 @@ pub fn receive_doip(&mut self, timeout: u64) -> Result<Option<Vec<u8>>, io::Erro
     }
 }
 
-} //end imp Transport
+} //end impl Diag
 
 
 // Public function that returns a new Diag object
@@ -294,5 +319,6 @@ pub fn create_diag() -> Diag {
 
     Diag {
         stream: None, // Initialize the stream field to None
+        trace: None,
     }
 }
\ No newline at end of file
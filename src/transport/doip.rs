@@ -13,7 +13,10 @@ use crate::transport::soad;
  ****************************************************************************************************************/
 lazy_static::lazy_static! {
     static ref G_IS_ROUTING_SUCCESS: AtomicBool = AtomicBool::new(false); // Initial value
+    static ref RECEIVE_BUFFER: Mutex<Vec<u8>> = Mutex::new(Vec::new()); // tcp bytes not yet parsed as doip message
 }
+const DOIP_HEADER_LENGTH: usize = 8;
+const MAX_PAYLOAD_LENGTH: usize = 0x0100_0000; // larger length field is treated as broken framing
 
 
 /* define all global struct and variable here */
@@ -33,13 +36,13 @@ fn construct_doip_header(type_field: u16, length: u32) -> Result<Vec<u8>, io::Er
     let header = DoipHeader {
         version: config.doip.version,
         inverse_version: config.doip.inverse_version,
-        type_field: type_field as u16,
-        length: length as u32,
+        type_field,
+        length,
     };
 
     let mut header_bytes = Vec::new();
-    header_bytes.push(header.version as u8);
-    header_bytes.push(header.inverse_version as u8);
+    header_bytes.push(header.version);
+    header_bytes.push(header.inverse_version);
     header_bytes.extend_from_slice(&header.type_field.to_be_bytes());
     header_bytes.extend_from_slice(&header.length.to_be_bytes());
 
@@ -81,6 +84,7 @@ pub fn init() {
     match soad::connect(dest_addr) {
         Ok(stream) => {
             G_IS_ROUTING_SUCCESS.store(false, Ordering::Relaxed);
+            RECEIVE_BUFFER.lock().unwrap().clear();
             Ok(stream)
         }
         Err(e) => {
@@ -104,6 +108,7 @@ pub fn init() {
  ****************************************************************************************************************/
 pub fn disconnect(stream: &Arc<Mutex<TcpStream>>) -> Result<(), io::Error> {
     G_IS_ROUTING_SUCCESS.store(false, Ordering::Relaxed);
+    RECEIVE_BUFFER.lock().unwrap().clear();
     if let Err(err) = soad::disconnect(stream) {
         eprintln!("doip disconnect Error: {}", err);
         return Err(err);
@@ -223,7 +228,7 @@ pub fn send_doip_routing_activation(stream: &Arc<Mutex<TcpStream>>) -> Result<()
     p_data.push(config.doip.activation_code);
 
     // Add four bytes with value 0x00 reserved for ISO to the end of the vector in one line
-    p_data.extend(std::iter::repeat(0x00).take(4));
+    p_data.extend(std::iter::repeat_n(0x00, 4));
     if let Err(e) = send_doip(stream, p_data, 0x0005) {
         eprintln!("send_doip_diag Error: {}", e);
         return Err(e);
@@ -232,6 +237,45 @@ pub fn send_doip_routing_activation(stream: &Arc<Mutex<TcpStream>>) -> Result<()
     Ok(())
 }
 
+/* Routing activation of the current connection was confirmed by response code 0x10 */
+pub fn is_routing_activated() -> bool {
+    G_IS_ROUTING_SUCCESS.load(Ordering::Relaxed)
+}
+
+
+/*****************************************************************************************************************
+ *  transport::doip::receive_message function
+ *  brief      Function to read the next complete doip message from tcp stream
+ *  details    One tcp read may hold several doip messages or only a part of one. Bytes after the returned message
+ *             are kept for the next call, a partial message is completed by further reads.
+ *  \param[in]  stream: TcpStream that used with mutex to prevent race condition when sending/reading data
+ *              timeout: timeout(milliseconds) of every tcp read
+ *  \param[out] -
+ *  \precondition: Establish TCP connection successfully
+ *  \reentrant:  FALSE
+ *  \return     doip header and payload of one message
+ *              Error code if any
+ ****************************************************************************************************************/
+fn receive_message(stream: &Arc<Mutex<TcpStream>>, timeout: u64) -> Result<Vec<u8>, io::Error> {
+    let mut buffer = RECEIVE_BUFFER.lock().unwrap();
+    loop {
+        if buffer.len() >= DOIP_HEADER_LENGTH {
+            let length = u32::from_be_bytes([buffer[4], buffer[5], buffer[6], buffer[7]]) as usize;
+            if length > MAX_PAYLOAD_LENGTH {
+                let hex_string: String = buffer.iter().take(DOIP_HEADER_LENGTH).map(|b| format!("{:02X}", b)).collect();
+                debug!("Doip Received header: {:?}, payload.len: {}", hex_string, length);
+                buffer.clear();
+                return Err(Error::new(ErrorKind::InvalidData, "DoIp Length invalid"));
+            }
+            if buffer.len() >= DOIP_HEADER_LENGTH + length {
+                return Ok(buffer.drain(..DOIP_HEADER_LENGTH + length).collect());
+            }
+        }
+        let data = soad::receive_tcp(stream, timeout)?;
+        buffer.extend_from_slice(&data);
+    }
+}
+
 
 /*****************************************************************************************************************
  *  transport::doip::receive_doip function
@@ -249,15 +293,10 @@ pub fn receive_doip(stream: &Arc<Mutex<TcpStream>>, timeout: u64) -> Result<Opti
     let config = CONFIG.read().unwrap();
 
     loop {
-        match soad::receive_tcp(stream, timeout) {
+        match receive_message(stream, timeout) {
             Ok(data) => {
-                // parse doip header
-                // check length
-                if data.len() < 8 {
-                    return Err(Error::new(ErrorKind::InvalidData, "DoIp fragment invalid"));
-                }
                 // Separate the data into header and payload using split_at
-                let (header_bytes, payload_bytes) = data.split_at(8);
+                let (header_bytes, payload_bytes) = data.split_at(DOIP_HEADER_LENGTH);
                 // Convert header_bytes to Vec<u8>
                 let payload: Vec<u8> = payload_bytes.to_vec();
                 // Convert header_bytes to DoipHeader struct
@@ -273,11 +312,7 @@ pub fn receive_doip(stream: &Arc<Mutex<TcpStream>>, timeout: u64) -> Result<Opti
                     ]),
                 };
 
-                if header.length != payload.len() as u32 {
-                    let hex_string: String = payload_bytes.iter().map(|b| format!("{:02X}", b)).collect();
-                    debug!("Doip Received: {:?}\nheader.len: {}\npayload.len: {}", hex_string, header.length, payload.len());
-                    return Err(Error::new(ErrorKind::InvalidData, "DoIp Length invalid"));
-                }
+                debug!("Doip Received type 0x{:04X}, payload.len: {}", header.type_field, header.length);
 
                 // check version doip
                 if header.version != config.doip.version ||
@@ -323,6 +358,9 @@ pub fn receive_doip(stream: &Arc<Mutex<TcpStream>>, timeout: u64) -> Result<Opti
                         return Err(Error::new(ErrorKind::InvalidData, "Doip ACK received is not expected"));
                     },
                     0x0006 => { // Routing activation response
+                        if payload.len() < 5 { //4 bytes for tester-sga address, 1 byte response code
+                            return Err(Error::new(ErrorKind::InvalidData, "Doip activation response length invalid"));
+                        }
                         let (addresses_bytes, doip_payload_bytes) = payload.split_at(4);
                         // Check addresses matches with config
                         debug!("Receive doip activation {:02X?}", addresses_bytes);
@@ -339,7 +377,8 @@ pub fn receive_doip(stream: &Arc<Mutex<TcpStream>>, timeout: u64) -> Result<Opti
                             return Ok(None);
                         }
                         //TODO: send activation code reply to upper layer
-                        return Err(Error::new(ErrorKind::ConnectionRefused, "Doip activation fail"));
+                        return Err(Error::new(ErrorKind::ConnectionRefused,
+                            format!("Doip activation fail, response code 0x{:02X}", doip_payload_bytes[0])));
                     },
                     _ => {
                         continue;
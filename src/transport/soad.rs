@@ -60,11 +60,8 @@ pub fn connect(dest_addr: String) -> Result<Arc<Mutex<TcpStream>>, io::Error> {
 
     // Check if tester role is client, then call connect cmd to server. or else, start to listen socket
     if &config.ethernet.role == "client" {
-        if G_IS_INIT_SOCKET.load(Ordering::Relaxed) == false {
-            return Err(io::Error::new(
-                io::ErrorKind::Other,
-                "socket is not initilized yet!",
-            ));
+        if !G_IS_INIT_SOCKET.load(Ordering::Relaxed) {
+            return Err(io::Error::other("socket is not initilized yet!"));
         }
 
         debug!("Connecting to server! {}", dest_addr);
@@ -89,7 +86,7 @@ pub fn connect(dest_addr: String) -> Result<Arc<Mutex<TcpStream>>, io::Error> {
     }
     else if &config.ethernet.role == "server" {
         // Listen tcp stream in case tester role is server
-        if G_IS_INIT_SOCKET.load(Ordering::Relaxed) == false {
+        if !G_IS_INIT_SOCKET.load(Ordering::Relaxed) {
             // Extract the local IPv4 as a regular String or use an empty string if it's None.
             let local_ipv4 = if let Some(ipv4) = &config.ethernet.local_ipv4 {
                 ipv4.to_string()
@@ -127,16 +124,10 @@ pub fn connect(dest_addr: String) -> Result<Arc<Mutex<TcpStream>>, io::Error> {
             Ok(shared_stream)
         } else {
             debug!("Socket was already initialized. Skipping...");
-            Err(io::Error::new(
-                io::ErrorKind::Other,
-                "Socket was already initialized",
-            ))
+            Err(io::Error::other("Socket was already initialized"))
         }
     } else {
-        Err(io::Error::new(
-            io::ErrorKind::Other,
-            "Invalid tester role",
-        ))
+        Err(io::Error::other("Invalid tester role"))
     }
 }
 /* connect */
@@ -181,7 +172,7 @@ pub fn disconnect(stream: &Arc<Mutex<TcpStream>>) -> Result<(), io::Error> {
  ****************************************************************************************************************/
 pub fn send_tcp(stream: &Arc<Mutex<TcpStream>>, p_data: Vec<u8>) -> Result<(), io::Error> {
     // Check if the socket is connected before sending data
-    if G_IS_INIT_SOCKET.load(Ordering::Relaxed) == false {
+    if !G_IS_INIT_SOCKET.load(Ordering::Relaxed) {
         eprint!("Not initialized yet!");
         return Err(io::Error::new(io::ErrorKind::NotConnected, "Socket is not connected"));
     }
@@ -216,7 +207,7 @@ pub fn send_tcp(stream: &Arc<Mutex<TcpStream>>, p_data: Vec<u8>) -> Result<(), i
  ****************************************************************************************************************/
 pub fn receive_tcp(stream: &Arc<Mutex<TcpStream>>, timeout: u64) -> Result<Vec<u8>, io::Error> {
     // Check if the socket is connected before sending data
-    if G_IS_INIT_SOCKET.load(Ordering::Relaxed) == false {
+    if !G_IS_INIT_SOCKET.load(Ordering::Relaxed) {
         eprint!("Not initialized yet!");
         return Err(io::Error::new(io::ErrorKind::NotConnected, "Socket is not connected"));
     }
@@ -248,9 +239,9 @@ pub fn receive_tcp(stream: &Arc<Mutex<TcpStream>>, timeout: u64) -> Result<Vec<u
         }
         Err(ref e) if e.kind() == ErrorKind::WouldBlock => {
             // Timeout: No data received within the specified timeout
-            return Err(Error::new(ErrorKind::TimedOut, "Timeout: No data received within the specified timeout"));
+            Err(Error::new(ErrorKind::TimedOut, "Timeout: No data received within the specified timeout"))
         }
-        Err(e) => return Err(e),
+        Err(e) => Err(e),
     }
 }
 /* read_tcp */
\ No newline at end of file
@@ -1,5 +1,3 @@
-use hex;
-
 /*****************************************************************************************************************
  *  utils::common::parse_duration_to_milliseconds function
  *  brief      Function to convert time string to integer milliseconds
@@ -11,7 +9,7 @@ use hex;
  *  \return     Milliseconds if any
  ****************************************************************************************************************/
 pub fn parse_duration_to_milliseconds(duration_str: &str) -> Option<u64> {
-    let numeric_part: String = duration_str.chars().take_while(|c| c.is_digit(10)).collect();
+    let numeric_part: String = duration_str.chars().take_while(|c| c.is_ascii_digit()).collect();
     let unit_part: &str = &duration_str[numeric_part.len()..];
 
     match numeric_part.parse::<u64>() {
@@ -30,26 +28,6 @@ pub fn parse_duration_to_milliseconds(duration_str: &str) -> Option<u64> {
 }
 
 
-/*****************************************************************************************************************
- *  utils::common::vec_hex_strings_to_u8 function
- *  brief      Function to convert hex Vec<String> to Vec<u8> array
- *  details    Example: "11223344" -> 0x11 0x22 0x33 0x44
- *  \param[in]  hex_strings: refer to hex string
- *  \param[out] -
- *  \precondition -
- *  \reentrant:  TRUE
- *  \return     Vec<u8> if any
- ****************************************************************************************************************/
-pub fn vec_hex_strings_to_u8(hex_strings: &Vec<String>) -> Vec<u8> {
-    hex_strings
-        .iter()
-        .map(|hex_str| {
-            u8::from_str_radix(&hex_str[2..], 16).unwrap_or(0)
-        })
-        .collect()
-}
-
-
 /*****************************************************************************************************************
  *  utils::common::compare_expect_value function
  *  brief      Function to compare string and vec<u8> type in hex format
@@ -87,22 +65,6 @@ pub fn compare_expect_value(string_a: &str, vec_b: Vec<u8>) -> bool {
 }
 
 
-/*****************************************************************************************************************
- *  utils::common::hex_string_to_bytes function
- *  brief      Function to convert hex string to Vec<u8> array
- *  details    Example: "0x11223344" -> 0x11 0x22 0x33 0x44
- *  \param[in]  hex_strings: refer to hex string
- *  \param[out] -
- *  \precondition -
- *  \reentrant:  TRUE
- *  \return     Vec<u8> if any
- ****************************************************************************************************************/
-pub fn hex_string_to_bytes(hex_string: &str) -> Result<Vec<u8>, hex::FromHexError> {
-    let hex_string_without_prefix = hex_string.trim_start_matches("0x");
-    hex::decode(hex_string_without_prefix)
-}
-
-
 /*****************************************************************************************************************
  *  utils::common::hex_to_u16 function
  *  brief      Function to convert hex string to u16
@@ -113,6 +75,7 @@ pub fn hex_string_to_bytes(hex_string: &str) -> Result<Vec<u8>, hex::FromHexErro
  *  \reentrant:  TRUE
  *  \return     u16, 0 if error
  ****************************************************************************************************************/
+#[cfg(feature = "gui")]
 pub fn hex_to_u16(hex_string: &str) -> u16 {
     // Remove the leading "0x" if it exists.
     let hex_string_without_prefix = hex_string.trim_start_matches("0x");
@@ -131,4 +94,164 @@ pub fn hex_to_u16(hex_string: &str) -> u16 {
         return 0;
     }
     u16::from_be_bytes([hex_bytes[0], hex_bytes[1]])
-}
\ No newline at end of file
+}
+
+/*****************************************************************************************************************
+ *  utils::common::nrc_name function
+ *  brief      Function to get name of a negative response code
+ *  details    Names follow ISO14229-1 annex A.1
+ *  \param[in]  nrc: negative response code
+ *  \param[out] -
+ *  \precondition -
+ *  \reentrant:  TRUE
+ *  \return     NRC name
+ ****************************************************************************************************************/
+pub fn nrc_name(nrc: u8) -> &'static str {
+    match nrc {
+        0x10 => "generalReject",
+        0x11 => "serviceNotSupported",
+        0x12 => "subFunctionNotSupported",
+        0x13 => "incorrectMessageLengthOrInvalidFormat",
+        0x14 => "responseTooLong",
+        0x21 => "busyRepeatRequest",
+        0x22 => "conditionsNotCorrect",
+        0x24 => "requestSequenceError",
+        0x25 => "noResponseFromSubnetComponent",
+        0x26 => "failurePreventsExecutionOfRequestedAction",
+        0x31 => "requestOutOfRange",
+        0x33 => "securityAccessDenied",
+        0x34 => "authenticationRequired",
+        0x35 => "invalidKey",
+        0x36 => "exceededNumberOfAttempts",
+        0x37 => "requiredTimeDelayNotExpired",
+        0x50 => "certificateVerificationFailed-InvalidTimePeriod",
+        0x51 => "certificateVerificationFailed-InvalidSignature",
+        0x52 => "certificateVerificationFailed-InvalidChainOfTrust",
+        0x53 => "certificateVerificationFailed-InvalidType",
+        0x54 => "certificateVerificationFailed-InvalidFormat",
+        0x55 => "certificateVerificationFailed-InvalidContent",
+        0x56 => "certificateVerificationFailed-InvalidScope",
+        0x57 => "certificateVerificationFailed-InvalidCertificate",
+        0x58 => "ownershipVerificationFailed",
+        0x59 => "challengeCalculationFailed",
+        0x5A => "settingAccessRightsFailed",
+        0x5B => "sessionKeyCreationDerivationFailed",
+        0x5C => "configurationDataUsageFailed",
+        0x5D => "deAuthenticationFailed",
+        0x70 => "uploadDownloadNotAccepted",
+        0x71 => "transferDataSuspended",
+        0x72 => "generalProgrammingFailure",
+        0x73 => "wrongBlockSequenceCounter",
+        0x78 => "requestCorrectlyReceived-ResponsePending",
+        0x7E => "subFunctionNotSupportedInActiveSession",
+        0x7F => "serviceNotSupportedInActiveSession",
+        0x81 => "rpmTooHigh",
+        0x83 => "engineIsRunning",
+        0x88 => "vehicleSpeedTooHigh",
+        0x92 => "voltageTooHigh",
+        0x93 => "voltageTooLow",
+        _ => "unknownNrc",
+    }
+}
+
+
+/*****************************************************************************************************************
+ *  utils::common::describe_response function
+ *  brief      Function to decode a diag response in a human readable line
+ *  details    Example: 7F 27 35 -> "negative response to 0x27: invalidKey (0x35)"
+ *                      62 F1 86 01 -> "positive response to 0x22, data: F18601"
+ *  \param[in]  response: diag response
+ *  \param[out] -
+ *  \precondition -
+ *  \reentrant:  TRUE
+ *  \return     description string
+ ****************************************************************************************************************/
+pub fn describe_response(response: &[u8]) -> String {
+    match response {
+        [] => String::from("no response"),
+        [0x7F, sid, nrc, ..] => format!("negative response to 0x{:02X}: {} (0x{:02X})", sid, nrc_name(*nrc), nrc),
+        [sid, data @ ..] if *sid >= 0x40 => {
+            let data_string: String = data.iter().map(|byte| format!("{:02X}", byte)).collect();
+            format!("positive response to 0x{:02X}, data: {}", sid - 0x40, data_string)
+        }
+        _ => String::from("unknown response"),
+    }
+}
+
+
+/*****************************************************************************************************************
+ *  utils::common::crc16_ccitt function
+ *  brief      Function to calculate CRC16-CCITT checksum
+ *  details    Polynomial 0x1021, initial value 0xFFFF, no reflection, no final xor. Used by vbf data blocks
+ *  \param[in]  data: input bytes
+ *  \param[out] -
+ *  \precondition -
+ *  \reentrant:  TRUE
+ *  \return     checksum
+ ****************************************************************************************************************/
+pub fn crc16_ccitt(data: &[u8]) -> u16 {
+    let mut crc: u16 = 0xFFFF;
+    for byte in data.iter() {
+        crc ^= (*byte as u16) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 { (crc << 1) ^ 0x1021 } else { crc << 1 };
+        }
+    }
+    crc
+}
+
+
+/*****************************************************************************************************************
+ *  utils::common::crc32 function
+ *  brief      Function to calculate CRC32 checksum (ISO 3309)
+ *  details    Reflected polynomial 0xEDB88320, initial value and final xor 0xFFFFFFFF. Used by vbf file checksum
+ *  \param[in]  data: input bytes
+ *  \param[out] -
+ *  \precondition -
+ *  \reentrant:  TRUE
+ *  \return     checksum
+ ****************************************************************************************************************/
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc: u32 = 0xFFFF_FFFF;
+    for byte in data.iter() {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0xEDB8_8320 } else { crc >> 1 };
+        }
+    }
+    !crc
+}
+
+
+/*****************************************************************************************************************
+ *  Reader of consecutive fields of a diag response, None if the response is too short
+ ****************************************************************************************************************/
+pub struct ByteReader<'a> {
+    pub data: &'a [u8],
+}
+
+impl<'a> ByteReader<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        ByteReader { data }
+    }
+
+    pub fn take(&mut self, length: usize) -> Option<&'a [u8]> {
+        if self.data.len() < length {
+            return None;
+        }
+        let (field, rest) = self.data.split_at(length);
+        self.data = rest;
+        Some(field)
+    }
+
+    // Big endian number of length bytes
+    pub fn number(&mut self, length: usize) -> Option<u64> {
+        self.take(length).map(|field| field.iter().fold(0u64, |number, byte| (number << 8) | *byte as u64))
+    }
+
+    // Field preceded by its length in length_size bytes
+    pub fn length_prefixed(&mut self, length_size: usize) -> Option<&'a [u8]> {
+        let length = self.number(length_size)? as usize;
+        self.take(length)
+    }
+}
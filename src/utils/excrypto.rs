@@ -1,119 +1,690 @@
 use std::io::{self, Error, ErrorKind};
-use cmac::{Cmac, Mac};
-use aes::Aes128;
+use aes::{Aes128, Aes192, Aes256};
 use aes::cipher::generic_array::GenericArray;
-use aes::cipher::{KeyIvInit, StreamCipher};
+use aes::cipher::{BlockDecrypt, BlockEncrypt, KeyInit, KeyIvInit, StreamCipher};
+use aes_gcm::AesGcm;
+use aes_gcm::aead::{Aead, Payload};
+use aes_gcm::aead::consts::U12;
+use cmac::{Cmac, Mac};
+use hmac::Hmac;
+use serde::Deserialize;
+use sha2::{Digest, Sha256, Sha512};
+use subtle::ConstantTimeEq;
+use zeroize::Zeroizing;
 
-type Aes128Ctr64LE = ctr::Ctr64LE<aes::Aes128>;
+use crate::utils::pkcs11;
 
-pub fn encrypt_aes128_ctr(data_to_encrypt: &[u8], iv_bytes: &[u8], key: &str) -> Result<Vec<u8>, io::Error> {
-    let trimmed_key = if key.starts_with("0x") {
-        key.strip_prefix("0x").unwrap_or(key)
-    } else {
-        key
-    };
-    if iv_bytes.len() != 16 || trimmed_key.len() != 32 {
-        eprintln!("Error: key and iv_bytes should be 16 bytes in length");
-        return Err(io::Error::new(io::ErrorKind::InvalidInput, "Invalid input lengths"));
+/*****************************************************************************************************************
+ *  Define all gloval macro & variable here
+ ****************************************************************************************************************/
+pub const AES_BLOCK_LENGTH: usize = 16;
+pub const GCM_NONCE_LENGTH: usize = 12;
+pub const GCM_TAG_LENGTH: usize = 16;
+
+/*****************************************************************************************************************
+ *  AES key of 16, 24 or 32 bytes, or label of a secret key kept on the PKCS#11 token. Key bytes are wiped on drop
+ ****************************************************************************************************************/
+pub enum AesKey {
+    Plain(Zeroizing<Vec<u8>>),
+    Token(String),
+}
+
+/* HMAC key, any non empty length */
+pub struct MacKey(Zeroizing<Vec<u8>>);
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HashAlgorithm {
+    Sha256,
+    Sha512,
+}
+
+/* Counter block layout of AES-CTR */
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CtrCounter {
+    BigEndian128,   // NIST SP 800-38A, whole iv is the counter
+    LittleEndian64, // counter in the first 8 bytes of the iv, used by the volvo security access exchange
+}
+
+/* Public key verifying signatures of ECU certificates, proofs of ownership and software */
+pub enum PublicKey {
+    EcdsaP256(p256::ecdsa::VerifyingKey),
+    Rsa(Box<rsa::RsaPublicKey>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SignatureScheme {
+    EcdsaSha256,
+    EcdsaSha512,
+    RsaPkcs1Sha256,
+    RsaPkcs1Sha512,
+    RsaPssSha256,
+    RsaPssSha512,
+}
+
+fn invalid_input(message: String) -> io::Error {
+    Error::new(ErrorKind::InvalidInput, message)
+}
+
+/* Hex string with optional "0x" prefix, whitespace around it is ignored */
+fn parse_hex_key(kind: &str, key: &str) -> Result<Zeroizing<Vec<u8>>, io::Error> {
+    hex::decode(key.trim().trim_start_matches("0x")).map(Zeroizing::new)
+        .map_err(|err| invalid_input(format!("{} key is not a hex string: {}", kind, err)))
+}
+
+impl AesKey {
+
+/*****************************************************************************************************************
+ *  utils::excrypto::AesKey::parse function
+ *  brief      Read an AES key written in config or sequence
+ *  details    Hex key with optional "0x" or "pkcs11:<label>" of a key on the token. Keystore references "@name"
+ *             are resolved by the caller before
+ *  \param[in]  key: hex key or pkcs11 key label
+ *  \param[out] -
+ *  \precondition -
+ *  \reentrant:  TRUE
+ *  \return     key, InvalidInput error if not hex or not 16, 24 or 32 bytes
+ ****************************************************************************************************************/
+pub fn parse(key: &str) -> Result<AesKey, io::Error> {
+    if let Some(label) = pkcs11::key_label(key) {
+        if label.is_empty() {
+            return Err(invalid_input(String::from("pkcs11 key label is empty")));
+        }
+        return Ok(AesKey::Token(label.to_string()));
     }
-    // Parse the key and iv string into a byte array
-    let mut encrypted_data = data_to_encrypt.to_vec();
-    let key_bytes: Vec<u8> = (0..trimmed_key.len())
-        .step_by(2)
-        .map(|i| {
-            u8::from_str_radix(&trimmed_key[i..i + 2], 16)
-                .expect("Failed to parse hex string")
-        })
-        .collect();
+    AesKey::from_bytes(&parse_hex_key("AES", key)?)
+}
 
-    if key_bytes.len() != 16 || iv_bytes.len() != 16 {
-        return Err(Error::new(ErrorKind::InvalidInput, "wrong key length"));
+pub fn from_bytes(key: &[u8]) -> Result<AesKey, io::Error> {
+    match key.len() {
+        16 | 24 | 32 => Ok(AesKey::Plain(Zeroizing::new(key.to_vec()))),
+        length => Err(invalid_input(format!("AES key should be 16, 24 or 32 bytes, got {}", length))),
     }
+}
 
-    let mut cipher = Aes128Ctr64LE::new(GenericArray::from_slice(&key_bytes), GenericArray::from_slice(&iv_bytes));
-    cipher.apply_keystream(&mut encrypted_data);
+fn plain(&self, operation: &str) -> Result<&[u8], io::Error> {
+    match self {
+        AesKey::Plain(key) => Ok(key),
+        AesKey::Token(label) => Err(Error::new(ErrorKind::Unsupported,
+            format!("{} with key \"pkcs11:{}\" of the token is not supported", operation, label))),
+    }
+}
 
-    Ok(encrypted_data)
 }
 
-pub fn decrypt_aes128_ctr(encrypted_data: &[u8], iv_bytes: &[u8], key: &str) -> Result<Vec<u8>, io::Error> {
-    let trimmed_key = if key.starts_with("0x") {
-        key.strip_prefix("0x").unwrap_or(key)
-    } else {
-        key
-    };
-    if iv_bytes.len() != 16 || trimmed_key.len() != 32 {
-        eprintln!("Error: key and iv_bytes should be 16 bytes in length");
-        return Err(io::Error::new(io::ErrorKind::InvalidInput, "Invalid input lengths"));
+impl MacKey {
+
+/* Hex key with optional "0x", keys on the token are not supported for HMAC */
+pub fn parse(key: &str) -> Result<MacKey, io::Error> {
+    if pkcs11::key_label(key).is_some() {
+        return Err(Error::new(ErrorKind::Unsupported, "HMAC keys on a pkcs11 token are not supported"));
     }
-    // Parse the key and iv string into a byte array
-    let mut decrypted_data = encrypted_data.to_vec();
-    let key_bytes: Vec<u8> = (0..trimmed_key.len())
-        .step_by(2)
-        .map(|i| {
-            u8::from_str_radix(&trimmed_key[i..i + 2], 16)
-                .expect("Failed to parse hex string")
-        })
-        .collect();
+    MacKey::from_bytes(&parse_hex_key("HMAC", key)?)
+}
 
-    if key_bytes.len() != 16 || iv_bytes.len() != 16 {
-        return Err(Error::new(ErrorKind::InvalidInput, "wrong key length"));
+pub fn from_bytes(key: &[u8]) -> Result<MacKey, io::Error> {
+    if key.is_empty() {
+        return Err(invalid_input(String::from("HMAC key is empty")));
     }
+    Ok(MacKey(Zeroizing::new(key.to_vec())))
+}
 
-    let mut cipher = Aes128Ctr64LE::new(GenericArray::from_slice(&key_bytes), GenericArray::from_slice(&iv_bytes));
-    cipher.apply_keystream(&mut decrypted_data);
+}
 
-    Ok(decrypted_data)
+/* Same length and same content, in time independent of the content */
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && bool::from(a.ct_eq(b))
 }
 
-pub fn encrypt_aes128_cmac(data_to_encrypt: &[u8], key: &str) -> Result<Vec<u8>, io::Error> {
-    let trimmed_key = if key.starts_with("0x") {
-        key.strip_prefix("0x").unwrap_or(key)
-    } else {
-        key
-    };
-    if trimmed_key.len() != 32 {
-        eprintln!("Error: key should be 16 bytes in length");
-        return Err(io::Error::new(io::ErrorKind::InvalidInput, "Invalid input lengths"));
+fn check_blocks(operation: &str, data: &[u8]) -> Result<(), io::Error> {
+    if data.len() % AES_BLOCK_LENGTH != 0 {
+        return Err(invalid_input(format!("{} data should be whole 16 bytes blocks, got {} bytes", operation, data.len())));
     }
-    // Parse the key string into a byte array
-    let key_bytes: Vec<u8> = (0..trimmed_key.len())
-        .step_by(2)
-        .map(|i| {
-            u8::from_str_radix(&trimmed_key[i..i + 2], 16)
-                .expect("Failed to parse hex string")
-        })
-        .collect();
+    Ok(())
+}
 
-    if key_bytes.len() != 16 {
-        return Err(Error::new(ErrorKind::InvalidInput, "wrong key length"));
+fn check_iv(operation: &str, iv: &[u8], expected: usize) -> Result<(), io::Error> {
+    if iv.len() != expected {
+        return Err(invalid_input(format!("{} iv should be {} bytes, got {}", operation, expected, iv.len())));
     }
+    Ok(())
+}
+
+
+/*****************************************************************************************************************
+ *  utils::excrypto::aes_ecb_encrypt function
+ *  brief      Encrypt whole blocks with AES-ECB, no padding
+ *  details    AES-128/192/256 by key length. Keys of the token are supported for encryption only
+ *  \param[in]  key: AES key
+ *              data: multiple of 16 bytes
+ *  \param[out] -
+ *  \precondition -
+ *  \reentrant:  TRUE
+ *  \return     encrypted blocks
+ ****************************************************************************************************************/
+pub fn aes_ecb_encrypt(key: &AesKey, data: &[u8]) -> Result<Vec<u8>, io::Error> {
+    check_blocks("AES-ECB", data)?;
+    if let AesKey::Token(label) = key {
+        return pkcs11::aes_ecb_encrypt(label, data);
+    }
+    let key = key.plain("AES-ECB")?;
+    let mut output = data.to_vec();
+    match key.len() {
+        16 => ecb_with::<Aes128>(key, &mut output, true),
+        24 => ecb_with::<Aes192>(key, &mut output, true),
+        _ => ecb_with::<Aes256>(key, &mut output, true),
+    }?;
+    Ok(output)
+}
+
+pub fn aes_ecb_decrypt(key: &AesKey, data: &[u8]) -> Result<Vec<u8>, io::Error> {
+    check_blocks("AES-ECB", data)?;
+    let key = key.plain("AES-ECB decryption")?;
+    let mut output = data.to_vec();
+    match key.len() {
+        16 => ecb_with::<Aes128>(key, &mut output, false),
+        24 => ecb_with::<Aes192>(key, &mut output, false),
+        _ => ecb_with::<Aes256>(key, &mut output, false),
+    }?;
+    Ok(output)
+}
+
+fn ecb_with<C: BlockEncrypt + BlockDecrypt + KeyInit>(key: &[u8], data: &mut [u8], is_encrypt: bool) -> Result<(), io::Error> {
+    let cipher = C::new_from_slice(key).map_err(|_| invalid_input(String::from("AES key length is not supported")))?;
+    for block in data.chunks_exact_mut(C::block_size()) {
+        if is_encrypt {
+            cipher.encrypt_block(GenericArray::from_mut_slice(block));
+        } else {
+            cipher.decrypt_block(GenericArray::from_mut_slice(block));
+        }
+    }
+    Ok(())
+}
+
+
+/*****************************************************************************************************************
+ *  utils::excrypto::aes_cbc_encrypt function
+ *  brief      Encrypt whole blocks with AES-CBC, no padding
+ *  details    The caller pads the data if the protocol needs it. Keys of the token are supported for encryption
+ *  \param[in]  key: AES key
+ *              iv: 16 bytes
+ *              data: multiple of 16 bytes
+ *  \param[out] -
+ *  \precondition -
+ *  \reentrant:  TRUE
+ *  \return     encrypted blocks
+ ****************************************************************************************************************/
+pub fn aes_cbc_encrypt(key: &AesKey, iv: &[u8], data: &[u8]) -> Result<Vec<u8>, io::Error> {
+    check_iv("AES-CBC", iv, AES_BLOCK_LENGTH)?;
+    check_blocks("AES-CBC", data)?;
+    let mut output = Vec::with_capacity(data.len());
+    let mut previous = iv.to_vec();
+    for block in data.chunks_exact(AES_BLOCK_LENGTH) {
+        let chained: Vec<u8> = block.iter().zip(previous.iter()).map(|(byte, previous_byte)| byte ^ previous_byte).collect();
+        previous = aes_ecb_encrypt(key, &chained)?;
+        output.extend_from_slice(&previous);
+    }
+    Ok(output)
+}
+
+pub fn aes_cbc_decrypt(key: &AesKey, iv: &[u8], data: &[u8]) -> Result<Vec<u8>, io::Error> {
+    check_iv("AES-CBC", iv, AES_BLOCK_LENGTH)?;
+    let decrypted = aes_ecb_decrypt(key, data)?;
+    let previous_blocks = iv.iter().chain(data.iter());
+    Ok(decrypted.iter().zip(previous_blocks).map(|(byte, previous_byte)| byte ^ previous_byte).collect())
+}
 
-    // Create an AES-128-CMAC instance with the provided key
-    let mut cmac = Cmac::<Aes128>::new(GenericArray::from_slice(&key_bytes));
 
-    // Generate the CMAC tag by updating with the IV and data to encrypt
-    cmac.update(data_to_encrypt);
-    let result = cmac.finalize().into_bytes();
+/*****************************************************************************************************************
+ *  utils::excrypto::aes_ctr function
+ *  brief      Encrypt or decrypt data with AES-CTR
+ *  details    AES-128/192/256 by key length. With a key of the token the counter blocks are encrypted by the
+ *             token, the key never leaves it
+ *  \param[in]  key: AES key
+ *              iv: initial counter block, 16 bytes
+ *              data: plain or encrypted data, any length
+ *              counter: counter block layout
+ *  \param[out] -
+ *  \precondition -
+ *  \reentrant:  TRUE
+ *  \return     data xor key stream
+ ****************************************************************************************************************/
+pub fn aes_ctr(key: &AesKey, iv: &[u8], data: &[u8], counter: CtrCounter) -> Result<Vec<u8>, io::Error> {
+    check_iv("AES-CTR", iv, AES_BLOCK_LENGTH)?;
+    let key = match key {
+        AesKey::Token(label) => return token_aes_ctr(label, iv, data, counter),
+        AesKey::Plain(key) => key,
+    };
+    let mut output = data.to_vec();
+    match (key.len(), counter) {
+        (16, CtrCounter::BigEndian128) => apply_keystream::<ctr::Ctr128BE<Aes128>>(key, iv, &mut output),
+        (24, CtrCounter::BigEndian128) => apply_keystream::<ctr::Ctr128BE<Aes192>>(key, iv, &mut output),
+        (_, CtrCounter::BigEndian128) => apply_keystream::<ctr::Ctr128BE<Aes256>>(key, iv, &mut output),
+        (16, CtrCounter::LittleEndian64) => apply_keystream::<ctr::Ctr64LE<Aes128>>(key, iv, &mut output),
+        (24, CtrCounter::LittleEndian64) => apply_keystream::<ctr::Ctr64LE<Aes192>>(key, iv, &mut output),
+        (_, CtrCounter::LittleEndian64) => apply_keystream::<ctr::Ctr64LE<Aes256>>(key, iv, &mut output),
+    }?;
+    Ok(output)
+}
 
-    // Return the encrypted data (CMAC tag appended to the data)
-    let mut encrypted_data = Vec::new();
-    encrypted_data.extend_from_slice(&result);
-    Ok(encrypted_data)
+fn apply_keystream<S: KeyIvInit + StreamCipher>(key: &[u8], iv: &[u8], data: &mut [u8]) -> Result<(), io::Error> {
+    let mut cipher = S::new_from_slices(key, iv).map_err(|_| invalid_input(String::from("AES-CTR key or iv length is not supported")))?;
+    cipher.try_apply_keystream(data).map_err(|_| invalid_input(String::from("AES-CTR data is longer than the counter")))
 }
 
-pub fn verify_aes128_cmac(data_to_encrypt: &[u8], encrypted_data: Vec<u8>, key: &str) -> bool {
-    match encrypt_aes128_cmac(data_to_encrypt, key) {
-        Ok(authentication_data) => {
-            if encrypted_data == authentication_data {
-                return true;
+/* AES-CTR where the key stream blocks are encrypted by the token */
+fn token_aes_ctr(label: &str, iv: &[u8], data: &[u8], counter: CtrCounter) -> Result<Vec<u8>, io::Error> {
+    let block_count = data.len().div_ceil(AES_BLOCK_LENGTH);
+    let mut counter_blocks = Vec::with_capacity(block_count * AES_BLOCK_LENGTH);
+    for index in 0..block_count {
+        match counter {
+            CtrCounter::BigEndian128 => {
+                let mut initial = [0u8; AES_BLOCK_LENGTH];
+                initial.copy_from_slice(iv);
+                counter_blocks.extend_from_slice(&u128::from_be_bytes(initial).wrapping_add(index as u128).to_be_bytes());
+            }
+            CtrCounter::LittleEndian64 => {
+                let mut initial = [0u8; 8];
+                initial.copy_from_slice(&iv[..8]);
+                counter_blocks.extend_from_slice(&u64::from_le_bytes(initial).wrapping_add(index as u64).to_le_bytes());
+                counter_blocks.extend_from_slice(&iv[8..]);
             }
-            eprintln!("decrypt_aes128_cmac ok but verify error with {:?}", authentication_data);
-            return false;
         }
-        Err(err) => {
-            eprintln!("Encryption error: {}", err);
-            return false;
+    }
+    let key_stream = pkcs11::aes_ecb_encrypt(label, &counter_blocks)?;
+    Ok(data.iter().zip(key_stream).map(|(byte, key_byte)| byte ^ key_byte).collect())
+}
+
+
+/*****************************************************************************************************************
+ *  utils::excrypto::aes_gcm_encrypt function
+ *  brief      Encrypt and authenticate data with AES-GCM
+ *  details    AES-128/192/256 by key length, 12 bytes nonce, the 16 bytes tag is appended. A nonce must never be
+ *             used twice with the same key
+ *  \param[in]  key: AES key, not on the token
+ *              nonce: 12 bytes
+ *              aad: additional authenticated data, not encrypted
+ *              data: plain data
+ *  \param[out] -
+ *  \precondition -
+ *  \reentrant:  TRUE
+ *  \return     encrypted data followed by tag
+ ****************************************************************************************************************/
+pub fn aes_gcm_encrypt(key: &AesKey, nonce: &[u8], aad: &[u8], data: &[u8]) -> Result<Vec<u8>, io::Error> {
+    check_iv("AES-GCM", nonce, GCM_NONCE_LENGTH)?;
+    let key = key.plain("AES-GCM")?;
+    match key.len() {
+        16 => gcm_with::<AesGcm<Aes128, U12>>(key, nonce, aad, data, true),
+        24 => gcm_with::<AesGcm<Aes192, U12>>(key, nonce, aad, data, true),
+        _ => gcm_with::<AesGcm<Aes256, U12>>(key, nonce, aad, data, true),
+    }
+}
+
+/* Decrypt data followed by its tag, InvalidData error if the tag does not match */
+pub fn aes_gcm_decrypt(key: &AesKey, nonce: &[u8], aad: &[u8], data: &[u8]) -> Result<Vec<u8>, io::Error> {
+    check_iv("AES-GCM", nonce, GCM_NONCE_LENGTH)?;
+    if data.len() < GCM_TAG_LENGTH {
+        return Err(Error::new(ErrorKind::InvalidData, "AES-GCM data is shorter than its tag"));
+    }
+    let key = key.plain("AES-GCM")?;
+    match key.len() {
+        16 => gcm_with::<AesGcm<Aes128, U12>>(key, nonce, aad, data, false),
+        24 => gcm_with::<AesGcm<Aes192, U12>>(key, nonce, aad, data, false),
+        _ => gcm_with::<AesGcm<Aes256, U12>>(key, nonce, aad, data, false),
+    }
+}
+
+fn gcm_with<A: Aead + KeyInit>(key: &[u8], nonce: &[u8], aad: &[u8], data: &[u8], is_encrypt: bool) -> Result<Vec<u8>, io::Error> {
+    let cipher = A::new_from_slice(key).map_err(|_| invalid_input(String::from("AES key length is not supported")))?;
+    let payload = Payload { msg: data, aad };
+    if is_encrypt {
+        cipher.encrypt(GenericArray::from_slice(nonce), payload)
+            .map_err(|_| Error::new(ErrorKind::InvalidData, "AES-GCM encryption failed"))
+    } else {
+        cipher.decrypt(GenericArray::from_slice(nonce), payload)
+            .map_err(|_| Error::new(ErrorKind::InvalidData, "AES-GCM tag does not match"))
+    }
+}
+
+
+/* AES-CMAC (RFC 4493) tag of 16 bytes, AES-128/192/256 by key length or computed by the token */
+pub fn aes_cmac(key: &AesKey, data: &[u8]) -> Result<Vec<u8>, io::Error> {
+    match key {
+        AesKey::Token(label) => pkcs11::aes_cmac(label, data),
+        AesKey::Plain(key) => Ok(match key.len() {
+            16 => new_mac::<Cmac<Aes128>>(key, data)?,
+            24 => new_mac::<Cmac<Aes192>>(key, data)?,
+            _ => new_mac::<Cmac<Aes256>>(key, data)?,
+        }),
+    }
+}
+
+/* Check an AES-CMAC tag in constant time */
+pub fn verify_aes_cmac(key: &AesKey, data: &[u8], tag: &[u8]) -> Result<bool, io::Error> {
+    Ok(constant_time_eq(&aes_cmac(key, data)?, tag))
+}
+
+/* HMAC (RFC 2104) tag, 32 bytes with SHA-256 and 64 bytes with SHA-512 */
+pub fn hmac(algorithm: HashAlgorithm, key: &MacKey, data: &[u8]) -> Result<Vec<u8>, io::Error> {
+    match algorithm {
+        HashAlgorithm::Sha256 => new_mac::<Hmac<Sha256>>(&key.0, data),
+        HashAlgorithm::Sha512 => new_mac::<Hmac<Sha512>>(&key.0, data),
+    }
+}
+
+/* Check an HMAC tag in constant time */
+pub fn verify_hmac(algorithm: HashAlgorithm, key: &MacKey, data: &[u8], tag: &[u8]) -> Result<bool, io::Error> {
+    Ok(constant_time_eq(&hmac(algorithm, key, data)?, tag))
+}
+
+fn new_mac<M: Mac + KeyInit>(key: &[u8], data: &[u8]) -> Result<Vec<u8>, io::Error> {
+    let mut mac = <M as KeyInit>::new_from_slice(key).map_err(|_| invalid_input(String::from("MAC key length is not supported")))?;
+    mac.update(data);
+    Ok(mac.finalize().into_bytes().to_vec())
+}
+
+pub fn digest(algorithm: HashAlgorithm, data: &[u8]) -> Vec<u8> {
+    match algorithm {
+        HashAlgorithm::Sha256 => Sha256::digest(data).to_vec(),
+        HashAlgorithm::Sha512 => Sha512::digest(data).to_vec(),
+    }
+}
+
+
+impl SignatureScheme {
+
+pub fn hash(&self) -> HashAlgorithm {
+    match self {
+        SignatureScheme::EcdsaSha256 | SignatureScheme::RsaPkcs1Sha256 | SignatureScheme::RsaPssSha256 => HashAlgorithm::Sha256,
+        SignatureScheme::EcdsaSha512 | SignatureScheme::RsaPkcs1Sha512 | SignatureScheme::RsaPssSha512 => HashAlgorithm::Sha512,
+    }
+}
+
+}
+
+impl PublicKey {
+
+/* DER SubjectPublicKeyInfo of an ECDSA P-256 or RSA key. Ex: subject public key of a certificate */
+pub fn from_der(public_key_der: &[u8]) -> Result<PublicKey, io::Error> {
+    use p256::pkcs8::DecodePublicKey;
+
+    if let Ok(public_key) = p256::PublicKey::from_public_key_der(public_key_der) {
+        return Ok(PublicKey::EcdsaP256(public_key.into()));
+    }
+    if let Ok(public_key) = rsa::RsaPublicKey::from_public_key_der(public_key_der) {
+        return Ok(PublicKey::Rsa(Box::new(public_key)));
+    }
+    Err(Error::new(ErrorKind::InvalidData, "public key is not ECDSA P-256 or RSA"))
+}
+
+/* ECDSA for EC keys, PKCS#1 v1.5 for RSA keys */
+pub fn default_scheme(&self, hash: HashAlgorithm) -> SignatureScheme {
+    match (self, hash) {
+        (PublicKey::EcdsaP256(_), HashAlgorithm::Sha256) => SignatureScheme::EcdsaSha256,
+        (PublicKey::EcdsaP256(_), HashAlgorithm::Sha512) => SignatureScheme::EcdsaSha512,
+        (PublicKey::Rsa(_), HashAlgorithm::Sha256) => SignatureScheme::RsaPkcs1Sha256,
+        (PublicKey::Rsa(_), HashAlgorithm::Sha512) => SignatureScheme::RsaPkcs1Sha512,
+    }
+}
+
+/*****************************************************************************************************************
+ *  utils::excrypto::PublicKey::load function
+ *  brief      Read a public key file
+ *  details    PEM or DER SubjectPublicKeyInfo, PKCS#1 RSA public key, or the subject key of an X.509 certificate
+ *  \param[in]  path: public key or certificate file
+ *  \param[out] -
+ *  \precondition -
+ *  \reentrant:  TRUE
+ *  \return     public key
+ ****************************************************************************************************************/
+pub fn load(path: &str) -> Result<PublicKey, io::Error> {
+    use p256::pkcs8::DecodePublicKey;
+    use rsa::pkcs1::DecodeRsaPublicKey;
+    use x509_cert::der::{Decode, DecodePem, Encode};
+
+    let certificate_key = |certificate: x509_cert::Certificate| certificate.tbs_certificate.subject_public_key_info.to_der().ok()
+        .and_then(|der| PublicKey::from_der(&der).ok());
+    let contents = std::fs::read(path)?;
+    let key = match std::str::from_utf8(&contents) {
+        Ok(pem) if pem.contains("-----BEGIN") => p256::PublicKey::from_public_key_pem(pem).ok()
+            .map(|key| PublicKey::EcdsaP256(key.into()))
+            .or_else(|| rsa::RsaPublicKey::from_public_key_pem(pem).ok().map(|key| PublicKey::Rsa(Box::new(key))))
+            .or_else(|| rsa::RsaPublicKey::from_pkcs1_pem(pem).ok().map(|key| PublicKey::Rsa(Box::new(key))))
+            .or_else(|| x509_cert::Certificate::from_pem(pem).ok().and_then(certificate_key)),
+        _ => PublicKey::from_der(&contents).ok()
+            .or_else(|| rsa::RsaPublicKey::from_pkcs1_der(&contents).ok().map(|key| PublicKey::Rsa(Box::new(key))))
+            .or_else(|| x509_cert::Certificate::from_der(&contents).ok().and_then(certificate_key)),
+    };
+    key.ok_or_else(|| Error::new(ErrorKind::InvalidData, format!("{} is not an ECDSA P-256 or RSA public key or certificate", path)))
+}
+
+}
+
+/*****************************************************************************************************************
+ *  Private key of tester certificate, used to sign challenges of Authentication (0x29)
+ ****************************************************************************************************************/
+pub enum PrivateKey {
+    EcdsaP256(p256::ecdsa::SigningKey),
+    Rsa(Box<rsa::RsaPrivateKey>),
+    Token(String), // label of a private key on the PKCS#11 token
+}
+
+/*****************************************************************************************************************
+ *  utils::excrypto::load_private_key function
+ *  brief      Read an ECDSA P-256 or RSA private key file
+ *  details    PEM or DER, PKCS#8 for both key types, SEC1 for EC keys and PKCS#1 for RSA keys.
+ *             "pkcs11:<label>" selects a private key of the PKCS#11 token instead of a file.
+ *  \param[in]  path: private key file
+ *  \param[out] -
+ *  \precondition -
+ *  \reentrant:  TRUE
+ *  \return     private key
+ ****************************************************************************************************************/
+pub fn load_private_key(path: &str) -> Result<PrivateKey, io::Error> {
+    use p256::pkcs8::DecodePrivateKey;
+    use rsa::pkcs1::DecodeRsaPrivateKey;
+
+    if let Some(label) = pkcs11::key_label(path) {
+        return Ok(PrivateKey::Token(label.to_string()));
+    }
+    let contents = std::fs::read(path)?;
+    let key = match std::str::from_utf8(&contents) {
+        Ok(pem) if pem.contains("-----BEGIN") => p256::SecretKey::from_pkcs8_pem(pem).ok()
+            .or_else(|| p256::SecretKey::from_sec1_pem(pem).ok())
+            .map(|key| PrivateKey::EcdsaP256(key.into()))
+            .or_else(|| rsa::RsaPrivateKey::from_pkcs8_pem(pem).ok().map(|key| PrivateKey::Rsa(Box::new(key))))
+            .or_else(|| rsa::RsaPrivateKey::from_pkcs1_pem(pem).ok().map(|key| PrivateKey::Rsa(Box::new(key)))),
+        _ => p256::SecretKey::from_pkcs8_der(&contents).ok()
+            .or_else(|| p256::SecretKey::from_sec1_der(&contents).ok())
+            .map(|key| PrivateKey::EcdsaP256(key.into()))
+            .or_else(|| rsa::RsaPrivateKey::from_pkcs8_der(&contents).ok().map(|key| PrivateKey::Rsa(Box::new(key))))
+            .or_else(|| rsa::RsaPrivateKey::from_pkcs1_der(&contents).ok().map(|key| PrivateKey::Rsa(Box::new(key)))),
+    };
+    key.ok_or_else(|| Error::new(ErrorKind::InvalidData, format!("{} is not an ECDSA P-256 or RSA private key", path)))
+}
+
+/*****************************************************************************************************************
+ *  utils::excrypto::sign_sha256 function
+ *  brief      Sign data with SHA-256
+ *  details    ECDSA signatures are DER encoded, RSA signatures use PKCS#1 v1.5 padding
+ *  \param[in]  key: private key
+ *              data: data to sign
+ *  \param[out] -
+ *  \precondition -
+ *  \reentrant:  TRUE
+ *  \return     signature
+ ****************************************************************************************************************/
+pub fn sign_sha256(key: &PrivateKey, data: &[u8]) -> Result<Vec<u8>, io::Error> {
+    use rsa::signature::{SignatureEncoding, Signer};
+
+    match key {
+        PrivateKey::EcdsaP256(signing_key) => {
+            let signature: p256::ecdsa::Signature = signing_key.try_sign(data)
+                .map_err(|err| Error::new(ErrorKind::InvalidData, format!("ECDSA signing failed: {}", err)))?;
+            Ok(signature.to_der().as_bytes().to_vec())
+        }
+        PrivateKey::Rsa(private_key) => {
+            let signing_key = rsa::pkcs1v15::SigningKey::<sha2::Sha256>::new((**private_key).clone());
+            let signature = signing_key.try_sign(data)
+                .map_err(|err| Error::new(ErrorKind::InvalidData, format!("RSA signing failed: {}", err)))?;
+            Ok(signature.to_vec())
+        }
+        PrivateKey::Token(label) => pkcs11::sign_sha256(label, data),
+    }
+}
+
+/*****************************************************************************************************************
+ *  utils::excrypto::verify_signature function
+ *  brief      Verify a signature with a public key
+ *  details    ECDSA P-256 signatures may be DER encoded or raw r||s, SHA-512 digests are truncated to the curve
+ *             size. RSA-PSS uses MGF1 with the same hash and a salt of the hash length
+ *  \param[in]  public_key: key of the signer
+ *              scheme: signature algorithm and hash
+ *              data: signed data
+ *              signature: signature to check
+ *  \param[out] -
+ *  \precondition -
+ *  \reentrant:  TRUE
+ *  \return     error kind InvalidData if signature is wrong, InvalidInput if scheme does not fit the key
+ ****************************************************************************************************************/
+pub fn verify_signature(public_key: &PublicKey, scheme: SignatureScheme, data: &[u8], signature: &[u8]) -> Result<(), io::Error> {
+    use p256::ecdsa::signature::hazmat::PrehashVerifier;
+    use rsa::signature::Verifier;
+
+    let invalid = |message: &str| Error::new(ErrorKind::InvalidData, message.to_string());
+    match (public_key, scheme) {
+        (PublicKey::EcdsaP256(verifying_key), SignatureScheme::EcdsaSha256 | SignatureScheme::EcdsaSha512) => {
+            let signature = p256::ecdsa::Signature::from_der(signature)
+                .or_else(|_| p256::ecdsa::Signature::from_slice(signature))
+                .map_err(|_| invalid("ECDSA signature is malformed"))?;
+            verifying_key.verify_prehash(&digest(scheme.hash(), data), &signature)
+                .map_err(|_| invalid("ECDSA signature verification failed"))
+        }
+        (PublicKey::Rsa(rsa_key), SignatureScheme::RsaPkcs1Sha256 | SignatureScheme::RsaPkcs1Sha512) => {
+            let signature = rsa::pkcs1v15::Signature::try_from(signature).map_err(|_| invalid("RSA signature is malformed"))?;
+            let result = match scheme.hash() {
+                HashAlgorithm::Sha256 => rsa::pkcs1v15::VerifyingKey::<Sha256>::new((**rsa_key).clone()).verify(data, &signature),
+                HashAlgorithm::Sha512 => rsa::pkcs1v15::VerifyingKey::<Sha512>::new((**rsa_key).clone()).verify(data, &signature),
+            };
+            result.map_err(|_| invalid("RSA signature verification failed"))
         }
+        (PublicKey::Rsa(rsa_key), SignatureScheme::RsaPssSha256 | SignatureScheme::RsaPssSha512) => {
+            let signature = rsa::pss::Signature::try_from(signature).map_err(|_| invalid("RSA-PSS signature is malformed"))?;
+            let result = match scheme.hash() {
+                HashAlgorithm::Sha256 => rsa::pss::VerifyingKey::<Sha256>::new((**rsa_key).clone()).verify(data, &signature),
+                HashAlgorithm::Sha512 => rsa::pss::VerifyingKey::<Sha512>::new((**rsa_key).clone()).verify(data, &signature),
+            };
+            result.map_err(|_| invalid("RSA-PSS signature verification failed"))
+        }
+        (PublicKey::EcdsaP256(_), _) => Err(invalid_input(format!("{:?} signature needs an RSA key, got ECDSA P-256", scheme))),
+        (PublicKey::Rsa(_), _) => Err(invalid_input(format!("{:?} signature needs an ECDSA P-256 key, got RSA", scheme))),
     }
-}
\ No newline at end of file
+}
+
+
+/*****************************************************************************************************************
+ *  utils::excrypto::self_test function
+ *  brief      Check every primitive against known answers
+ *  details    AES-ECB of FIPS-197 C.1-C.3, AES-CBC and AES-CTR of SP 800-38A F.2.1/F.5.1, AES-GCM test case 2
+ *             of the GCM specification, AES-CMAC of RFC 4493, HMAC of RFC 4231 case 2, SHA of FIPS 180-2 "abc",
+ *             RSA signatures made by an independent implementation and an ECDSA sign/verify round trip. Altered
+ *             tags and signatures must be rejected
+ *  \param[in]  -
+ *  \param[out] -
+ *  \precondition -
+ *  \reentrant:  TRUE
+ *  \return     names of passed checks, InvalidData error naming the first failed check
+ ****************************************************************************************************************/
+pub fn self_test() -> Result<Vec<&'static str>, io::Error> {
+    let hex_bytes = |value: &str| hex::decode(value).unwrap_or_default();
+    let fips_plain = hex_bytes("00112233445566778899aabbccddeeff");
+    let sp800_key = AesKey::from_bytes(&hex_bytes("2b7e151628aed2a6abf7158809cf4f3c"))?;
+    let sp800_plain = hex_bytes("6bc1bee22e409f96e93d7e117393172aae2d8a571e03ac9c9eb76fac45af8e51");
+    let zero_key = AesKey::from_bytes(&[0u8; 16])?;
+    let jefe = MacKey::from_bytes(b"Jefe")?;
+    let jefe_data = b"what do ya want for nothing?";
+    let rsa_key = PublicKey::from_der(&hex_bytes(SELF_TEST_RSA_PUBLIC_KEY))?;
+
+    let known_answers: Vec<(&'static str, Vec<u8>, &str)> = vec![
+        ("AES-128-ECB", aes_ecb_encrypt(&AesKey::from_bytes(&(0..16).collect::<Vec<u8>>())?, &fips_plain)?,
+         "69c4e0d86a7b0430d8cdb78070b4c55a"),
+        ("AES-192-ECB", aes_ecb_encrypt(&AesKey::from_bytes(&(0..24).collect::<Vec<u8>>())?, &fips_plain)?,
+         "dda97ca4864cdfe06eaf70a0ec0d7191"),
+        ("AES-256-ECB", aes_ecb_encrypt(&AesKey::from_bytes(&(0..32).collect::<Vec<u8>>())?, &fips_plain)?,
+         "8ea2b7ca516745bfeafc49904b496089"),
+        ("AES-CBC", aes_cbc_encrypt(&sp800_key, &(0..16).collect::<Vec<u8>>(), &sp800_plain)?,
+         "7649abac8119b246cee98e9b12e9197d5086cb9b507219ee95db113a917678b2"),
+        ("AES-CTR", aes_ctr(&sp800_key, &hex_bytes("f0f1f2f3f4f5f6f7f8f9fafbfcfdfeff"), &sp800_plain, CtrCounter::BigEndian128)?,
+         "874d6191b620e3261bef6864990db6ce9806f66b7970fdff8617187bb9fffdff"),
+        ("AES-GCM", aes_gcm_encrypt(&zero_key, &[0u8; GCM_NONCE_LENGTH], &[], &[0u8; 16])?,
+         "0388dace60b6a392f328c2b971b2fe78ab6e47d42cec13bdf53a67b21257bddf"),
+        ("AES-CMAC", aes_cmac(&sp800_key, &sp800_plain[..16])?, "070a16b46b4d4144f79bdd9dd04a287c"),
+        ("HMAC-SHA256", hmac(HashAlgorithm::Sha256, &jefe, jefe_data)?,
+         "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"),
+        ("HMAC-SHA512", hmac(HashAlgorithm::Sha512, &jefe, jefe_data)?,
+         "164b7a7bfcf819e2e395fbe73b56e0a387bd64222e831fd610270cd7ea2505549758bf75c05a994a6d034f65f8f0e6fdcaeab1a34d4a6b4b636e070a38bce737"),
+        ("SHA-256", digest(HashAlgorithm::Sha256, b"abc"), "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"),
+        ("SHA-512", digest(HashAlgorithm::Sha512, b"abc"),
+         "ddaf35a193617abacc417349ae20413112e6fa4e89a97ea20a9eeee64b55d39a2192992a274fc1a836ba3c23a3feebbd454d4423643ce80e2a9ac94fa54ca49f"),
+    ];
+    let failed = |name: &str| Error::new(ErrorKind::InvalidData, format!("crypto self test {} failed", name));
+    let mut passed = Vec::new();
+    for (name, output, expected) in known_answers {
+        if hex::encode(output) != expected {
+            return Err(failed(name));
+        }
+        passed.push(name);
+    }
+
+    // decryption and rejection of altered data
+    let altered = |data: &[u8]| -> Vec<u8> { let mut data = data.to_vec(); data[0] ^= 0x01; data };
+    let cbc = aes_cbc_encrypt(&sp800_key, &[0u8; 16], &sp800_plain)?;
+    let gcm = aes_gcm_encrypt(&sp800_key, &[0u8; GCM_NONCE_LENGTH], b"aad", &sp800_plain)?;
+    let tag = aes_cmac(&sp800_key, &sp800_plain)?;
+    let mac = hmac(HashAlgorithm::Sha256, &jefe, jefe_data)?;
+    let checks: Vec<(&'static str, bool)> = vec![
+        ("AES-CBC decrypt", aes_cbc_decrypt(&sp800_key, &[0u8; 16], &cbc)? == sp800_plain),
+        ("AES-GCM decrypt", aes_gcm_decrypt(&sp800_key, &[0u8; GCM_NONCE_LENGTH], b"aad", &gcm)? == sp800_plain),
+        ("AES-GCM altered", aes_gcm_decrypt(&sp800_key, &[0u8; GCM_NONCE_LENGTH], b"aad", &altered(&gcm)).is_err()),
+        ("AES-CMAC verify", verify_aes_cmac(&sp800_key, &sp800_plain, &tag)? && !verify_aes_cmac(&sp800_key, &sp800_plain, &altered(&tag))?),
+        ("HMAC verify", verify_hmac(HashAlgorithm::Sha256, &jefe, jefe_data, &mac)?
+                        && !verify_hmac(HashAlgorithm::Sha256, &jefe, jefe_data, &mac[..16])?),
+        ("RSA-PSS-SHA256", verify_signature(&rsa_key, SignatureScheme::RsaPssSha256, SELF_TEST_MESSAGE,
+                                            &hex_bytes(SELF_TEST_RSA_PSS_SHA256)).is_ok()),
+        ("RSA-PKCS1-SHA512", verify_signature(&rsa_key, SignatureScheme::RsaPkcs1Sha512, SELF_TEST_MESSAGE,
+                                              &hex_bytes(SELF_TEST_RSA_PKCS1_SHA512)).is_ok()),
+        ("RSA altered", verify_signature(&rsa_key, SignatureScheme::RsaPssSha256, &altered(SELF_TEST_MESSAGE),
+                                         &hex_bytes(SELF_TEST_RSA_PSS_SHA256)).is_err()),
+        ("ECDSA-P256", ecdsa_round_trip()?),
+    ];
+    for (name, is_passed) in checks {
+        if !is_passed {
+            return Err(failed(name));
+        }
+        passed.push(name);
+    }
+    Ok(passed)
+}
+
+const SELF_TEST_MESSAGE: &[u8] = b"diag_tool self test";
+const SELF_TEST_RSA_PUBLIC_KEY: &str = "30819f300d06092a864886f70d010101050003818d0030818902818100b4f383a66a6f299d48ad52f46e67582a\
+    0f3d8fb5bc2c1bb0e5292be71c10a359dc4261ecbe2756fa35ea63b675724a9fe611f6e0a1598b0cfedd590c6a74848b3e2fcecee2db6ec1bcec495bdab0\
+    cf11eb35eda2d3e21e589759b550dca21e319628f4082b87c61a5c02d26b99ea6391947e6219fe673832b5e6afa6e019942b0203010001";
+const SELF_TEST_RSA_PSS_SHA256: &str = "74ba757665a66f15141e57ebe4fe98a2a2a17a30f092575f7d51d8da57e8d7e5748b70c792a3718a08ab754ce7\
+    7375ab537ec1b0e1eb6385cb27f872015daa06fc2a928f3b2644ee76d59335ffde56664ff23d5482ad92e094f356b978d22c2f6a0a580201500056ab3e6c\
+    39c1549b9c21c34395e3d572f9bde4d81a90b483cd";
+const SELF_TEST_RSA_PKCS1_SHA512: &str = "9f8c14bf8ea707f3eef065814d44ae74e3e9069226db86707da1a2c7121ad0e2bab23f9cf4c74437727c72b\
+    e39c4ede9ec0fb0534a527f06fd48fd68afd1a359e90829bc8f5358e829d02fa667a9c27e4387016724403a8ad93e1bb3f18fd3cd5fccc1eb0c7dfe191ce8b\
+    c748e7a0edfe63abd0f0e2aac2a3ef0c2bbc4721db2";
+
+/* Sign with a fixed P-256 key (RFC 6979 nonces), verify with both hashes and reject a signature over other data */
+fn ecdsa_round_trip() -> Result<bool, io::Error> {
+    let signing_key = p256::ecdsa::SigningKey::from_slice(&[0x11u8; 32])
+        .map_err(|_| Error::new(ErrorKind::InvalidData, "ECDSA self test key is not valid"))?;
+    let public_key = PublicKey::EcdsaP256(*signing_key.verifying_key());
+    let signature = sign_sha256(&PrivateKey::EcdsaP256(signing_key), SELF_TEST_MESSAGE)?;
+    Ok(verify_signature(&public_key, SignatureScheme::EcdsaSha256, SELF_TEST_MESSAGE, &signature).is_ok()
+        && verify_signature(&public_key, SignatureScheme::EcdsaSha512, SELF_TEST_MESSAGE, &signature).is_err()
+        && verify_signature(&public_key, SignatureScheme::EcdsaSha256, b"other data", &signature).is_err())
+}
@@ -0,0 +1,316 @@
+use log::debug;
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{self, BufRead, Error, ErrorKind, IsTerminal};
+use std::path::{Path, PathBuf};
+use std::sync::RwLock;
+use zeroize::{Zeroize, Zeroizing};
+
+use crate::utils::excrypto::{self, AesKey};
+
+/*****************************************************************************************************************
+ *  Define all gloval macro & variable here
+ ****************************************************************************************************************/
+lazy_static::lazy_static! {
+    static ref UNLOCKED_KEYSTORE: RwLock<Option<Keystore>> = RwLock::new(None);
+}
+
+pub const PASSWORD_ENV: &str = "DIAG_TOOL_KEYSTORE_PASSWORD";
+const FORMAT: &str = "diag_tool keystore";
+const VERSION: u32 = 1;
+const KDF_ALGORITHM: &str = "argon2id";
+const CIPHER: &str = "aes-256-gcm";
+const SALT_LENGTH: usize = 16;
+const NONCE_LENGTH: usize = 12;
+const KEY_LENGTH: usize = 32;
+const REFERENCE_PREFIX: char = '@';
+
+/* Key derivation parameters, stored in clear and authenticated as associated data of the ciphertext */
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+struct KdfParams {
+    algorithm: String,
+    memory_kib: u32,
+    iterations: u32,
+    parallelism: u32,
+    salt: String,
+}
+
+/* On-disk layout, data is the AES-256-GCM encrypted JSON object of name -> hex key */
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+struct KeystoreFile {
+    format: String,
+    version: u32,
+    kdf: KdfParams,
+    cipher: String,
+    nonce: String,
+    data: String,
+}
+
+/*****************************************************************************************************************
+ *  Password protected file of named keys. Sequence parameters reference a key as "@name", the hex value is
+ *  only kept in memory while the keystore is unlocked.
+ ****************************************************************************************************************/
+pub struct Keystore {
+    path: PathBuf,
+    kdf: KdfParams,
+    key: Zeroizing<[u8; KEY_LENGTH]>,
+    entries: BTreeMap<String, String>,
+}
+
+impl Drop for Keystore {
+    fn drop(&mut self) {
+        for value in self.entries.values_mut() {
+            value.zeroize();
+        }
+    }
+}
+
+impl Keystore {
+
+/*****************************************************************************************************************
+ *  utils::keystore::Keystore::create function
+ *  brief      Create an empty keystore file protected by password
+ *  details    An existing file is never overwritten
+ *  \param[in]  path: keystore file
+ *              password: keystore password, must not be empty
+ *  \param[out] -
+ *  \precondition -
+ *  \reentrant:  TRUE
+ *  \return     unlocked keystore
+ ****************************************************************************************************************/
+pub fn create(path: &Path, password: &str) -> Result<Keystore, io::Error> {
+    if path.exists() {
+        return Err(Error::new(ErrorKind::AlreadyExists, format!("keystore {} already exists", path.display())));
+    }
+    let kdf = new_kdf_params();
+    let key = derive_key(password, &kdf)?;
+    let keystore = Keystore { path: path.to_path_buf(), kdf, key, entries: BTreeMap::new() };
+    keystore.save()?;
+    Ok(keystore)
+}
+
+
+/*****************************************************************************************************************
+ *  utils::keystore::Keystore::open function
+ *  brief      Read and decrypt a keystore file
+ *  details    A wrong password and a modified file both fail the authentication of the ciphertext
+ *  \param[in]  path: keystore file
+ *              password: keystore password
+ *  \param[out] -
+ *  \precondition -
+ *  \reentrant:  TRUE
+ *  \return     unlocked keystore, PermissionDenied if password is wrong
+ ****************************************************************************************************************/
+pub fn open(path: &Path, password: &str) -> Result<Keystore, io::Error> {
+    let contents = fs::read_to_string(path)?;
+    let file: KeystoreFile = serde_json::from_str(&contents)
+        .map_err(|err| Error::new(ErrorKind::InvalidData, format!("{} is not a keystore: {}", path.display(), err)))?;
+    if file.format != FORMAT || file.version != VERSION || file.cipher != CIPHER || file.kdf.algorithm != KDF_ALGORITHM {
+        return Err(Error::new(ErrorKind::InvalidData,
+            format!("{}: keystore version {} with {}/{} is not supported", path.display(), file.version, file.kdf.algorithm, file.cipher)));
+    }
+    let nonce = decode_hex(&file.nonce, NONCE_LENGTH, "nonce")?;
+    let ciphertext = decode_hex(&file.data, 0, "data")?;
+    let key = derive_key(password, &file.kdf)?;
+    let aad = associated_data(&file.kdf)?;
+    let plaintext = Zeroizing::new(excrypto::aes_gcm_decrypt(&AesKey::from_bytes(key.as_ref())?, &nonce, &aad, &ciphertext)
+        .map_err(|_| Error::new(ErrorKind::PermissionDenied,
+            format!("cannot unlock keystore {}: wrong password or modified file", path.display())))?);
+    let entries = serde_json::from_slice(&plaintext)
+        .map_err(|err| Error::new(ErrorKind::InvalidData, format!("{}: keystore content is corrupted: {}", path.display(), err)))?;
+    debug!("Opened keystore {}", path.display());
+    Ok(Keystore { path: path.to_path_buf(), kdf: file.kdf, key, entries })
+}
+
+
+/*****************************************************************************************************************
+ *  utils::keystore::Keystore::save function
+ *  brief      Encrypt entries with a fresh nonce and write the keystore file
+ *  details    File is written next to the target and renamed, an interrupted save keeps the old file
+ *  \param[in]  -
+ *  \param[out] -
+ *  \precondition -
+ *  \reentrant:  FALSE
+ *  \return     error code if any
+ ****************************************************************************************************************/
+pub fn save(&self) -> Result<(), io::Error> {
+    let mut nonce = [0u8; NONCE_LENGTH];
+    rand::thread_rng().fill_bytes(&mut nonce);
+    let plaintext = Zeroizing::new(serde_json::to_vec(&self.entries)?);
+    let aad = associated_data(&self.kdf)?;
+    let ciphertext = excrypto::aes_gcm_encrypt(&AesKey::from_bytes(self.key.as_ref())?, &nonce, &aad, &plaintext)?;
+    let file = KeystoreFile {
+        format: String::from(FORMAT),
+        version: VERSION,
+        kdf: self.kdf.clone(),
+        cipher: String::from(CIPHER),
+        nonce: hex::encode(nonce),
+        data: hex::encode(ciphertext),
+    };
+    let mut temp_path = self.path.clone().into_os_string();
+    temp_path.push(".tmp");
+    fs::write(&temp_path, serde_json::to_string_pretty(&file)?)?;
+    fs::rename(&temp_path, &self.path)
+}
+
+/* Add or replace a key, value is a hex string with optional 0x prefix */
+pub fn insert(&mut self, name: &str, value: &str) -> Result<(), io::Error> {
+    validate_name(name)?;
+    let value = value.trim();
+    let value = value.strip_prefix("0x").or_else(|| value.strip_prefix("0X")).unwrap_or(value);
+    let bytes = Zeroizing::new(hex::decode(value)
+        .map_err(|err| Error::new(ErrorKind::InvalidInput, format!("value of key {} is not hex: {}", name, err)))?);
+    if bytes.is_empty() {
+        return Err(Error::new(ErrorKind::InvalidInput, format!("value of key {} is empty", name)));
+    }
+    if let Some(mut old_value) = self.entries.insert(name.to_string(), hex::encode_upper(bytes.as_slice())) {
+        old_value.zeroize();
+    }
+    Ok(())
+}
+
+pub fn remove(&mut self, name: &str) -> Result<(), io::Error> {
+    match self.entries.remove(name) {
+        Some(mut value) => {
+            value.zeroize();
+            Ok(())
+        }
+        None => Err(Error::new(ErrorKind::NotFound, format!("key {} is not in keystore {}", name, self.path.display()))),
+    }
+}
+
+pub fn names(&self) -> Vec<&str> {
+    self.entries.keys().map(|name| name.as_str()).collect()
+}
+
+pub fn get(&self, name: &str) -> Option<&str> {
+    self.entries.get(name).map(|value| value.as_str())
+}
+
+/* Derive a new key from new_password with a fresh salt, the file is rewritten */
+pub fn change_password(&mut self, new_password: &str) -> Result<(), io::Error> {
+    let kdf = new_kdf_params();
+    self.key = derive_key(new_password, &kdf)?;
+    self.kdf = kdf;
+    self.save()
+}
+
+}
+
+
+/*****************************************************************************************************************
+ *  utils::keystore::unlock function
+ *  brief      Open keystore whose keys are used to resolve "@name" references of sequence steps
+ *  details    A previously unlocked keystore is replaced
+ *  \param[in]  path: keystore file
+ *              password: keystore password
+ *  \param[out] -
+ *  \precondition -
+ *  \reentrant:  TRUE
+ *  \return     number of keys
+ ****************************************************************************************************************/
+pub fn unlock(path: &Path, password: &str) -> Result<usize, io::Error> {
+    let keystore = Keystore::open(path, password)?;
+    let count = keystore.entries.len();
+    *UNLOCKED_KEYSTORE.write().unwrap() = Some(keystore);
+    Ok(count)
+}
+
+
+/*****************************************************************************************************************
+ *  utils::keystore::resolve function
+ *  brief      Replace a key reference "@name" by the hex value stored in the unlocked keystore
+ *  details    Other values are returned unchanged
+ *  \param[in]  value: parameter value of a sequence step
+ *  \param[out] -
+ *  \precondition -
+ *  \reentrant:  TRUE
+ *  \return     hex key, NotFound if keystore is locked or has no such key
+ ****************************************************************************************************************/
+pub fn resolve(value: &str) -> Result<String, io::Error> {
+    let name = match value.trim().strip_prefix(REFERENCE_PREFIX) {
+        Some(name) => name,
+        None => return Ok(value.to_string()),
+    };
+    match UNLOCKED_KEYSTORE.read().unwrap().as_ref() {
+        Some(keystore) => keystore.get(name).map(|value| value.to_string())
+            .ok_or_else(|| Error::new(ErrorKind::NotFound, format!("key @{} is not in keystore {}", name, keystore.path.display()))),
+        None => Err(Error::new(ErrorKind::NotFound, format!("key @{} needs an unlocked keystore (--keystore)", name))),
+    }
+}
+
+/* Check a key reference "@name" without resolving it. None if value is not a reference */
+pub fn check_reference(value: &str) -> Option<Result<(), io::Error>> {
+    value.trim().strip_prefix(REFERENCE_PREFIX).map(validate_name)
+}
+
+
+/*****************************************************************************************************************
+ *  utils::keystore::read_secret function
+ *  brief      Read a password or key value without echo
+ *  details    Prompt and hidden input on a terminal, otherwise one line of stdin so scripts can pipe the value
+ *  \param[in]  prompt: text shown before the input
+ *  \param[out] -
+ *  \precondition -
+ *  \reentrant:  FALSE
+ *  \return     entered text
+ ****************************************************************************************************************/
+pub fn read_secret(prompt: &str) -> Result<Zeroizing<String>, io::Error> {
+    if io::stdin().is_terminal() {
+        return rpassword::prompt_password(prompt).map(Zeroizing::new);
+    }
+    let mut line = Zeroizing::new(String::new());
+    io::stdin().lock().read_line(&mut line)?;
+    Ok(Zeroizing::new(line.trim_end_matches(['\r', '\n']).to_string()))
+}
+
+fn validate_name(name: &str) -> Result<(), io::Error> {
+    if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.')) {
+        return Err(Error::new(ErrorKind::InvalidInput,
+            format!("key name \"{}\" should only contain letters, digits, '_', '-' and '.'", name)));
+    }
+    Ok(())
+}
+
+fn new_kdf_params() -> KdfParams {
+    let mut salt = [0u8; SALT_LENGTH];
+    rand::thread_rng().fill_bytes(&mut salt);
+    KdfParams {
+        algorithm: String::from(KDF_ALGORITHM),
+        memory_kib: argon2::Params::DEFAULT_M_COST,
+        iterations: argon2::Params::DEFAULT_T_COST,
+        parallelism: argon2::Params::DEFAULT_P_COST,
+        salt: hex::encode(salt),
+    }
+}
+
+fn derive_key(password: &str, kdf: &KdfParams) -> Result<Zeroizing<[u8; KEY_LENGTH]>, io::Error> {
+    if password.is_empty() {
+        return Err(Error::new(ErrorKind::InvalidInput, "keystore password should not be empty"));
+    }
+    let salt = decode_hex(&kdf.salt, SALT_LENGTH, "salt")?;
+    let params = argon2::Params::new(kdf.memory_kib, kdf.iterations, kdf.parallelism, Some(KEY_LENGTH))
+        .map_err(|err| Error::new(ErrorKind::InvalidData, format!("keystore kdf parameters: {}", err)))?;
+    let mut key = Zeroizing::new([0u8; KEY_LENGTH]);
+    argon2::Argon2::new(argon2::Algorithm::Argon2id, argon2::Version::V0x13, params)
+        .hash_password_into(password.as_bytes(), &salt, key.as_mut())
+        .map_err(|err| Error::new(ErrorKind::InvalidData, format!("keystore key derivation failed: {}", err)))?;
+    Ok(key)
+}
+
+fn associated_data(kdf: &KdfParams) -> Result<Vec<u8>, io::Error> {
+    let mut aad = format!("{} v{} {} ", FORMAT, VERSION, CIPHER).into_bytes();
+    aad.extend_from_slice(&serde_json::to_vec(kdf)?);
+    Ok(aad)
+}
+
+fn decode_hex(value: &str, length: usize, name: &str) -> Result<Vec<u8>, io::Error> {
+    match hex::decode(value) {
+        Ok(bytes) if length == 0 || bytes.len() == length => Ok(bytes),
+        _ => Err(Error::new(ErrorKind::InvalidData, format!("keystore {} is malformed", name))),
+    }
+}
@@ -41,6 +41,8 @@ pub fn parse_content(content: String) -> Result<(), io::Error> {
         let vendor = config_data["ethernet"]["vendor"]
             .as_str().expect("Invalid vendor field")
             .to_owned();
+        // vendor selects the profile of OEM routines, flash flow and security access
+        crate::executor::vendor::find(&vendor)?;
         Ethernet { interface, local_ipv4, local_ipv6, remote_ip, remote_port, role, vendor }
     };
 
@@ -139,6 +141,33 @@ pub fn parse_content(content: String) -> Result<(), io::Error> {
         }
     };
 
+    // Optional token with OEM keys, referenced as "pkcs11:<label>"
+    let pkcs11_settings = match config_data.get("pkcs11") {
+        Some(section) => Some(serde_json::from_value(section.clone())
+            .map_err(|err| Error::new(ErrorKind::InvalidInput, format!("pkcs11 section in json file not correct: {}", err)))?),
+        None => None,
+    };
+    crate::utils::pkcs11::configure(pkcs11_settings);
+
+    // Optional keys of SecuredDataTransmission (0x84), used by send_diag requests marked as secured
+    let secured_settings: Option<crate::executor::secured::SecuredSettings> = match config_data.get("secured_data_transmission") {
+        Some(section) => Some(serde_json::from_value(section.clone())
+            .map_err(|err| Error::new(ErrorKind::InvalidInput, format!("secured_data_transmission section in json file not correct: {}", err)))?),
+        None => None,
+    };
+    if let Some(settings) = &secured_settings {
+        settings.validate()?;
+    }
+    crate::executor::secured::configure(secured_settings);
+
+    // Optional public key checking sw_signature of vbf files before they are flashed
+    let vbf_settings = match config_data.get("vbf_verification") {
+        Some(section) => Some(serde_json::from_value(section.clone())
+            .map_err(|err| Error::new(ErrorKind::InvalidInput, format!("vbf_verification section in json file not correct: {}", err)))?),
+        None => None,
+    };
+    crate::executor::vbf::configure(vbf_settings)?;
+
     // Update the CONFIG global variable
     *CONFIG.write().expect("Failed to acquire write lock") = Config {
         ethernet,
@@ -166,5 +195,5 @@ pub fn parse(config_filename: String) -> Result<(), io::Error> {
     let mut file = File::open(&config_filename).expect("Failed to open config file");
     let mut contents = String::new();
     file.read_to_string(&mut contents).expect("Failed to read file");
-    return parse_content(contents);
+    parse_content(contents)
 }
\ No newline at end of file
@@ -0,0 +1,438 @@
+use serde::Deserialize;
+use std::io::{self, Error, ErrorKind};
+use std::sync::RwLock;
+
+/*****************************************************************************************************************
+ *  Define all gloval macro & variable here
+ ****************************************************************************************************************/
+lazy_static::lazy_static! {
+    static ref SETTINGS: RwLock<Option<Pkcs11Settings>> = RwLock::new(None);
+}
+
+pub const KEY_PREFIX: &str = "pkcs11:";
+
+/* "pkcs11" section of config file */
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Pkcs11Settings {
+    pub module: String,        // PKCS#11 library. Ex: /usr/lib/softhsm/libsofthsm2.so
+    #[serde(default)]
+    pub token: Option<String>, // token label, first token if not set
+    #[serde(default)]
+    pub pin: Option<String>,   // user PIN, DIAG_TOOL_PKCS11_PIN if not set, no login if neither is set
+}
+
+/*****************************************************************************************************************
+ *  utils::pkcs11::configure function
+ *  brief      Set token used for "pkcs11:<label>" keys
+ *  details    The token is opened on first use, an open session of previous settings is closed
+ *  \param[in]  settings: pkcs11 section of config, None if config has no such section
+ *  \param[out] -
+ *  \precondition -
+ *  \reentrant:  FALSE
+ *  \return -
+ ****************************************************************************************************************/
+pub fn configure(settings: Option<Pkcs11Settings>) {
+    let mut current = SETTINGS.write().unwrap();
+    if *current != settings {
+        #[cfg(feature = "pkcs11")]
+        token::close();
+        *current = settings;
+    }
+}
+
+/* Label of a key kept on the token, written as "pkcs11:<label>" in place of a hex key */
+pub fn key_label(key: &str) -> Option<&str> {
+    key.trim().strip_prefix(KEY_PREFIX)
+}
+
+fn settings() -> Result<Pkcs11Settings, io::Error> {
+    SETTINGS.read().unwrap().clone()
+        .ok_or_else(|| Error::new(ErrorKind::NotFound, "pkcs11 keys need a \"pkcs11\" section in config"))
+}
+
+
+/*****************************************************************************************************************
+ *  utils::pkcs11::aes_ecb_encrypt function
+ *  brief      Encrypt whole AES blocks with a secret key of the token
+ *  details    Used to build AES-CTR key streams, the key never leaves the token
+ *  \param[in]  label: CKA_LABEL of an AES secret key
+ *              blocks: data, multiple of 16 bytes
+ *  \param[out] -
+ *  \precondition -
+ *  \reentrant:  FALSE
+ *  \return     encrypted blocks
+ ****************************************************************************************************************/
+pub fn aes_ecb_encrypt(label: &str, blocks: &[u8]) -> Result<Vec<u8>, io::Error> {
+    #[cfg(feature = "pkcs11")]
+    { token::with_token(&settings()?, |token| token.aes_ecb_encrypt(label, blocks)) }
+    #[cfg(not(feature = "pkcs11"))]
+    { unsupported(label, blocks) }
+}
+
+/* AES-CMAC by the token, 16 bytes */
+pub fn aes_cmac(label: &str, data: &[u8]) -> Result<Vec<u8>, io::Error> {
+    #[cfg(feature = "pkcs11")]
+    { token::with_token(&settings()?, |token| token.aes_cmac(label, data)) }
+    #[cfg(not(feature = "pkcs11"))]
+    { unsupported(label, data) }
+}
+
+
+/*****************************************************************************************************************
+ *  utils::pkcs11::sign_sha256 function
+ *  brief      Sign data with SHA-256 by a private key of the token
+ *  details    Same encodings as software keys: ECDSA P-256 signatures DER encoded, RSA PKCS#1 v1.5
+ *  \param[in]  label: CKA_LABEL of an EC or RSA private key
+ *              data: data to sign
+ *  \param[out] -
+ *  \precondition -
+ *  \reentrant:  FALSE
+ *  \return     signature
+ ****************************************************************************************************************/
+pub fn sign_sha256(label: &str, data: &[u8]) -> Result<Vec<u8>, io::Error> {
+    #[cfg(feature = "pkcs11")]
+    { token::with_token(&settings()?, |token| token.sign_sha256(label, data)) }
+    #[cfg(not(feature = "pkcs11"))]
+    { unsupported(label, data) }
+}
+
+#[cfg(not(feature = "pkcs11"))]
+fn unsupported(label: &str, _data: &[u8]) -> Result<Vec<u8>, io::Error> {
+    settings()?;
+    Err(Error::new(ErrorKind::Unsupported, format!("key {}{} needs diag_tool built with feature pkcs11", KEY_PREFIX, label)))
+}
+
+
+/*****************************************************************************************************************
+ *  Cryptoki binding: only the functions used by diag_tool are declared, the module is loaded at runtime
+ ****************************************************************************************************************/
+#[cfg(feature = "pkcs11")]
+mod token {
+use log::debug;
+use sha2::{Digest, Sha256};
+use std::io::{self, Error, ErrorKind};
+use std::os::raw::{c_ulong, c_void};
+use std::ptr;
+use std::sync::Mutex;
+
+use super::Pkcs11Settings;
+
+lazy_static::lazy_static! {
+    static ref TOKEN: Mutex<Option<Token>> = Mutex::new(None);
+}
+
+const PIN_ENV: &str = "DIAG_TOOL_PKCS11_PIN"; // user PIN if config has none
+
+type CkUlong = c_ulong;
+type CkRv = CkUlong;
+type Fn0 = *const c_void; // function not used by diag_tool
+
+const CKR_OK: CkRv = 0x000;
+const CKR_USER_ALREADY_LOGGED_IN: CkRv = 0x100;
+const CKR_CRYPTOKI_ALREADY_INITIALIZED: CkRv = 0x191;
+const CKF_SERIAL_SESSION: CkUlong = 0x04;
+const CKU_USER: CkUlong = 1;
+const CKA_CLASS: CkUlong = 0x000;
+const CKA_LABEL: CkUlong = 0x003;
+const CKA_KEY_TYPE: CkUlong = 0x100;
+const CKO_PRIVATE_KEY: CkUlong = 3;
+const CKO_SECRET_KEY: CkUlong = 4;
+const CKK_RSA: CkUlong = 0x00;
+const CKK_EC: CkUlong = 0x03;
+const CKM_SHA256_RSA_PKCS: CkUlong = 0x0040;
+const CKM_ECDSA: CkUlong = 0x1041;
+const CKM_AES_ECB: CkUlong = 0x1081;
+const CKM_AES_CMAC: CkUlong = 0x108A;
+const AES_BLOCK_LENGTH: usize = 16;
+const MAX_SIGNATURE_LENGTH: usize = 1024;
+const TOKEN_LABEL_LENGTH: usize = 32;
+
+// Structures are packed on Windows (cryptoki pack(1)), naturally aligned elsewhere
+#[cfg_attr(windows, repr(C, packed))]
+#[cfg_attr(not(windows), repr(C))]
+struct Attribute {
+    attribute_type: CkUlong,
+    value: *mut c_void,
+    value_length: CkUlong,
+}
+
+#[cfg_attr(windows, repr(C, packed))]
+#[cfg_attr(not(windows), repr(C))]
+struct Mechanism {
+    mechanism: CkUlong,
+    parameter: *mut c_void,
+    parameter_length: CkUlong,
+}
+
+/* Room for CK_TOKEN_INFO (about 200 bytes), only the blank padded label at its start is read */
+#[repr(C, align(8))]
+struct TokenInfo([u8; 512]);
+
+/* Leading part of CK_FUNCTION_LIST up to C_Sign */
+#[cfg_attr(windows, repr(C, packed))]
+#[cfg_attr(not(windows), repr(C))]
+struct FunctionList {
+    _version: [u8; 2],
+    initialize: unsafe extern "C" fn(*mut c_void) -> CkRv,
+    finalize: unsafe extern "C" fn(*mut c_void) -> CkRv,
+    _get_info: Fn0,
+    _get_function_list: Fn0,
+    get_slot_list: unsafe extern "C" fn(u8, *mut CkUlong, *mut CkUlong) -> CkRv,
+    _get_slot_info: Fn0,
+    get_token_info: unsafe extern "C" fn(CkUlong, *mut TokenInfo) -> CkRv,
+    _get_mechanism_list: Fn0,
+    _get_mechanism_info: Fn0,
+    _init_token: Fn0,
+    _init_pin: Fn0,
+    _set_pin: Fn0,
+    open_session: unsafe extern "C" fn(CkUlong, CkUlong, *mut c_void, *const c_void, *mut CkUlong) -> CkRv,
+    close_session: unsafe extern "C" fn(CkUlong) -> CkRv,
+    _close_all_sessions: Fn0,
+    _get_session_info: Fn0,
+    _get_operation_state: Fn0,
+    _set_operation_state: Fn0,
+    login: unsafe extern "C" fn(CkUlong, CkUlong, *const u8, CkUlong) -> CkRv,
+    _logout: Fn0,
+    _create_object: Fn0,
+    _copy_object: Fn0,
+    _destroy_object: Fn0,
+    _get_object_size: Fn0,
+    get_attribute_value: unsafe extern "C" fn(CkUlong, CkUlong, *mut Attribute, CkUlong) -> CkRv,
+    _set_attribute_value: Fn0,
+    find_objects_init: unsafe extern "C" fn(CkUlong, *mut Attribute, CkUlong) -> CkRv,
+    find_objects: unsafe extern "C" fn(CkUlong, *mut CkUlong, CkUlong, *mut CkUlong) -> CkRv,
+    find_objects_final: unsafe extern "C" fn(CkUlong) -> CkRv,
+    encrypt_init: unsafe extern "C" fn(CkUlong, *mut Mechanism, CkUlong) -> CkRv,
+    encrypt: unsafe extern "C" fn(CkUlong, *const u8, CkUlong, *mut u8, *mut CkUlong) -> CkRv,
+    _encrypt_update: Fn0,
+    _encrypt_final: Fn0,
+    _decrypt_init: Fn0,
+    _decrypt: Fn0,
+    _decrypt_update: Fn0,
+    _decrypt_final: Fn0,
+    _digest_init: Fn0,
+    _digest: Fn0,
+    _digest_update: Fn0,
+    _digest_key: Fn0,
+    _digest_final: Fn0,
+    sign_init: unsafe extern "C" fn(CkUlong, *mut Mechanism, CkUlong) -> CkRv,
+    sign: unsafe extern "C" fn(CkUlong, *const u8, CkUlong, *mut u8, *mut CkUlong) -> CkRv,
+}
+
+type GetFunctionList = unsafe extern "C" fn(*mut *const FunctionList) -> CkRv;
+
+/* Logged-in session of the configured token */
+pub struct Token {
+    settings: Pkcs11Settings,
+    functions: &'static FunctionList, // owned by the module, valid while _library is loaded
+    session: CkUlong,
+    _library: libloading::Library,
+}
+
+// Safety: the session is only used while TOKEN is locked
+unsafe impl Send for Token {}
+
+impl Drop for Token {
+    fn drop(&mut self) {
+        // Safety: session was opened by this module, nothing uses it after drop
+        unsafe {
+            (self.functions.close_session)(self.session);
+            (self.functions.finalize)(ptr::null_mut());
+        }
+    }
+}
+
+pub fn close() {
+    *TOKEN.lock().unwrap() = None;
+}
+
+/* Run an operation with the token of settings, it is opened and logged in on first use */
+pub fn with_token<T>(settings: &Pkcs11Settings, operation: impl FnOnce(&Token) -> Result<T, io::Error>) -> Result<T, io::Error> {
+    let mut token = TOKEN.lock().unwrap();
+    if token.as_ref().is_none_or(|token| token.settings != *settings) {
+        *token = None;
+        *token = Some(Token::open(settings)?);
+    }
+    operation(token.as_ref().unwrap())
+}
+
+fn check(rv: CkRv, function: &str) -> Result<(), io::Error> {
+    if rv == CKR_OK {
+        return Ok(());
+    }
+    let name = match rv {
+        0x005 => "CKR_GENERAL_ERROR",
+        0x006 => "CKR_FUNCTION_FAILED",
+        0x007 => "CKR_ARGUMENTS_BAD",
+        0x060 => "CKR_KEY_HANDLE_INVALID",
+        0x063 => "CKR_KEY_TYPE_INCONSISTENT",
+        0x068 => "CKR_KEY_FUNCTION_NOT_PERMITTED",
+        0x070 => "CKR_MECHANISM_INVALID",
+        0x0A0 => "CKR_PIN_INCORRECT",
+        0x0A4 => "CKR_PIN_LOCKED",
+        0x0B3 => "CKR_SESSION_HANDLE_INVALID",
+        0x0E0 => "CKR_TOKEN_NOT_PRESENT",
+        0x101 => "CKR_USER_NOT_LOGGED_IN",
+        0x150 => "CKR_BUFFER_TOO_SMALL",
+        _ => "vendor defined or unexpected",
+    };
+    let kind = if rv == 0x0A0 || rv == 0x0A4 || rv == 0x101 { ErrorKind::PermissionDenied } else { ErrorKind::Other };
+    Err(Error::new(kind, format!("PKCS#11 {} failed: {} (0x{:X})", function, name, rv)))
+}
+
+impl Token {
+
+/*****************************************************************************************************************
+ *  utils::pkcs11::Token::open function
+ *  brief      Load module, find token by label, open a session and log in as user
+ *  details    PIN of settings wins over DIAG_TOOL_PKCS11_PIN, without PIN only public objects are usable
+ *  \param[in]  settings: pkcs11 section of config
+ *  \param[out] -
+ *  \precondition -
+ *  \reentrant:  FALSE
+ *  \return     open token
+ ****************************************************************************************************************/
+fn open(settings: &Pkcs11Settings) -> Result<Token, io::Error> {
+    // Safety: loading runs initialisers of the module, it is selected by the user in config
+    let library = unsafe { libloading::Library::new(&settings.module) }
+        .map_err(|err| Error::new(ErrorKind::NotFound, format!("cannot load PKCS#11 module {}: {}", settings.module, err)))?;
+    let functions = unsafe {
+        let get_function_list = library.get::<GetFunctionList>(b"C_GetFunctionList\0")
+            .map_err(|err| Error::new(ErrorKind::InvalidData, format!("{} is not a PKCS#11 module: {}", settings.module, err)))?;
+        let mut functions: *const FunctionList = ptr::null();
+        check(get_function_list(&mut functions), "C_GetFunctionList")?;
+        functions.as_ref().ok_or_else(|| Error::new(ErrorKind::InvalidData, "C_GetFunctionList returned no functions"))?
+    };
+    let rv = unsafe { (functions.initialize)(ptr::null_mut()) };
+    if rv != CKR_CRYPTOKI_ALREADY_INITIALIZED {
+        check(rv, "C_Initialize")?;
+    }
+    let mut token = Token { settings: settings.clone(), functions, session: 0, _library: library };
+    let slot = token.find_slot()?;
+    unsafe {
+        check((functions.open_session)(slot, CKF_SERIAL_SESSION, ptr::null_mut(), ptr::null(), &mut token.session), "C_OpenSession")?;
+    }
+    let pin = settings.pin.clone().or_else(|| std::env::var(PIN_ENV).ok());
+    if let Some(pin) = pin {
+        let rv = unsafe { (functions.login)(token.session, CKU_USER, pin.as_ptr(), pin.len() as CkUlong) };
+        if rv != CKR_USER_ALREADY_LOGGED_IN {
+            check(rv, "C_Login")?;
+        }
+    }
+    debug!("Opened PKCS#11 token {:?} of {}", settings.token, settings.module);
+    Ok(token)
+}
+
+fn find_slot(&self) -> Result<CkUlong, io::Error> {
+    let mut count: CkUlong = 0;
+    unsafe { check((self.functions.get_slot_list)(1, ptr::null_mut(), &mut count), "C_GetSlotList")? };
+    let mut slots: Vec<CkUlong> = vec![0; count as usize];
+    unsafe { check((self.functions.get_slot_list)(1, slots.as_mut_ptr(), &mut count), "C_GetSlotList")? };
+    slots.truncate(count as usize);
+    let wanted = match &self.settings.token {
+        Some(label) => label,
+        None => return slots.first().copied().ok_or_else(|| Error::new(ErrorKind::NotFound, "no PKCS#11 token present")),
+    };
+    for slot in slots {
+        let mut info = TokenInfo([0u8; 512]);
+        unsafe { check((self.functions.get_token_info)(slot, &mut info), "C_GetTokenInfo")? };
+        if String::from_utf8_lossy(&info.0[..TOKEN_LABEL_LENGTH]).trim_end() == wanted {
+            return Ok(slot);
+        }
+    }
+    Err(Error::new(ErrorKind::NotFound, format!("PKCS#11 token \"{}\" is not present", wanted)))
+}
+
+fn find_key(&self, class: CkUlong, label: &str) -> Result<CkUlong, io::Error> {
+    let mut class_value = class;
+    let mut template = [
+        Attribute { attribute_type: CKA_CLASS, value: &mut class_value as *mut CkUlong as *mut c_void,
+                    value_length: std::mem::size_of::<CkUlong>() as CkUlong },
+        Attribute { attribute_type: CKA_LABEL, value: label.as_ptr() as *mut c_void, value_length: label.len() as CkUlong },
+    ];
+    let mut handles: [CkUlong; 2] = [0; 2];
+    let mut count: CkUlong = 0;
+    unsafe {
+        check((self.functions.find_objects_init)(self.session, template.as_mut_ptr(), template.len() as CkUlong), "C_FindObjectsInit")?;
+        let rv = (self.functions.find_objects)(self.session, handles.as_mut_ptr(), handles.len() as CkUlong, &mut count);
+        check((self.functions.find_objects_final)(self.session), "C_FindObjectsFinal")?;
+        check(rv, "C_FindObjects")?;
+    }
+    match count {
+        1 => Ok(handles[0]),
+        0 => Err(Error::new(ErrorKind::NotFound, format!("PKCS#11 key \"{}\" is not on the token", label))),
+        _ => Err(Error::new(ErrorKind::InvalidInput, format!("PKCS#11 label \"{}\" matches several keys", label))),
+    }
+}
+
+fn key_type(&self, key: CkUlong) -> Result<CkUlong, io::Error> {
+    let mut key_type: CkUlong = 0;
+    let mut template = [Attribute { attribute_type: CKA_KEY_TYPE, value: &mut key_type as *mut CkUlong as *mut c_void,
+                                    value_length: std::mem::size_of::<CkUlong>() as CkUlong }];
+    unsafe { check((self.functions.get_attribute_value)(self.session, key, template.as_mut_ptr(), 1), "C_GetAttributeValue")? };
+    Ok(key_type)
+}
+
+fn encrypt(&self, mechanism: CkUlong, key: CkUlong, data: &[u8]) -> Result<Vec<u8>, io::Error> {
+    let mut mechanism = Mechanism { mechanism, parameter: ptr::null_mut(), parameter_length: 0 };
+    let mut output = vec![0u8; data.len() + AES_BLOCK_LENGTH];
+    let mut output_length = output.len() as CkUlong;
+    unsafe {
+        check((self.functions.encrypt_init)(self.session, &mut mechanism, key), "C_EncryptInit")?;
+        check((self.functions.encrypt)(self.session, data.as_ptr(), data.len() as CkUlong, output.as_mut_ptr(), &mut output_length), "C_Encrypt")?;
+    }
+    output.truncate(output_length as usize);
+    Ok(output)
+}
+
+fn sign(&self, mechanism: CkUlong, key: CkUlong, data: &[u8]) -> Result<Vec<u8>, io::Error> {
+    let mut mechanism = Mechanism { mechanism, parameter: ptr::null_mut(), parameter_length: 0 };
+    let mut signature = vec![0u8; MAX_SIGNATURE_LENGTH];
+    let mut signature_length = signature.len() as CkUlong;
+    unsafe {
+        check((self.functions.sign_init)(self.session, &mut mechanism, key), "C_SignInit")?;
+        check((self.functions.sign)(self.session, data.as_ptr(), data.len() as CkUlong, signature.as_mut_ptr(), &mut signature_length), "C_Sign")?;
+    }
+    signature.truncate(signature_length as usize);
+    Ok(signature)
+}
+
+pub fn aes_ecb_encrypt(&self, label: &str, blocks: &[u8]) -> Result<Vec<u8>, io::Error> {
+    if blocks.len() % AES_BLOCK_LENGTH != 0 {
+        return Err(Error::new(ErrorKind::InvalidInput, "AES-ECB data should be whole blocks"));
+    }
+    let key = self.find_key(CKO_SECRET_KEY, label)?;
+    let encrypted = self.encrypt(CKM_AES_ECB, key, blocks)?;
+    if encrypted.len() != blocks.len() {
+        return Err(Error::new(ErrorKind::InvalidData, format!("PKCS#11 AES-ECB returned {} bytes for {}", encrypted.len(), blocks.len())));
+    }
+    Ok(encrypted)
+}
+
+pub fn aes_cmac(&self, label: &str, data: &[u8]) -> Result<Vec<u8>, io::Error> {
+    let key = self.find_key(CKO_SECRET_KEY, label)?;
+    let mac = self.sign(CKM_AES_CMAC, key, data)?;
+    if mac.len() != AES_BLOCK_LENGTH {
+        return Err(Error::new(ErrorKind::InvalidData, format!("PKCS#11 AES-CMAC returned {} bytes", mac.len())));
+    }
+    Ok(mac)
+}
+
+pub fn sign_sha256(&self, label: &str, data: &[u8]) -> Result<Vec<u8>, io::Error> {
+    let key = self.find_key(CKO_PRIVATE_KEY, label)?;
+    match self.key_type(key)? {
+        CKK_EC => {
+            // CKM_ECDSA signs the digest and returns r || s
+            let signature = self.sign(CKM_ECDSA, key, &Sha256::digest(data))?;
+            let signature = p256::ecdsa::Signature::from_slice(&signature)
+                .map_err(|_| Error::new(ErrorKind::InvalidData, format!("PKCS#11 key \"{}\" is not a P-256 key", label)))?;
+            Ok(signature.to_der().as_bytes().to_vec())
+        }
+        CKK_RSA => self.sign(CKM_SHA256_RSA_PKCS, key, data),
+        key_type => Err(Error::new(ErrorKind::InvalidInput, format!("PKCS#11 key \"{}\" has unsupported type 0x{:X}", label, key_type))),
+    }
+}
+
+}
+}
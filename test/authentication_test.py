@@ -0,0 +1,175 @@
+"""
+Authentication (0x29) with PKI certificate exchange against a simulated DoIP ECU.
+
+A test CA issues the tester and ECU certificates, the ECU only accepts tester certificates of this CA:
+  - bidirectional:  ECDSA P-256 tester, ECU certificate and proof are verified against the CA, then deauthenticate
+  - unidirectional: RSA tester key, ECU verifies tester certificate and proof of ownership
+  - transmit:       transmitCertificate with a certificateEvaluationId
+  - untrusted_ecu:  ECU certificate of another CA, the step fails before the tester proof is sent
+  - wrong_key:      tester signs with a key not matching its certificate, ECU rejects the proof
+
+Usage: python3 test/authentication_test.py [path/to/diag_tool]
+"""
+import datetime
+import os
+import struct
+import sys
+import tempfile
+
+from cryptography import x509
+from cryptography.exceptions import InvalidSignature
+from cryptography.hazmat.primitives import hashes, serialization
+from cryptography.hazmat.primitives.asymmetric import ec, padding, rsa
+from cryptography.x509.oid import NameOID
+
+import sim
+
+
+def make_certificate(name, key, issuer_name, issuer_key, is_ca=False):
+    now = datetime.datetime.now(datetime.timezone.utc)
+    builder = (x509.CertificateBuilder()
+               .subject_name(x509.Name([x509.NameAttribute(NameOID.COMMON_NAME, name)]))
+               .issuer_name(x509.Name([x509.NameAttribute(NameOID.COMMON_NAME, issuer_name)]))
+               .public_key(key.public_key())
+               .serial_number(x509.random_serial_number())
+               .not_valid_before(now - datetime.timedelta(days=1))
+               .not_valid_after(now + datetime.timedelta(days=30))
+               .add_extension(x509.BasicConstraints(ca=is_ca, path_length=None), critical=True))
+    return builder.sign(issuer_key, hashes.SHA256())
+
+
+def verify(public_key, signature, data):
+    if isinstance(public_key, rsa.RSAPublicKey):
+        public_key.verify(signature, data, padding.PKCS1v15(), hashes.SHA256())
+    else:
+        public_key.verify(signature, data, ec.ECDSA(hashes.SHA256()))
+
+
+def length_prefixed(data):
+    return struct.pack('>H', len(data)) + data
+
+
+class Pki:
+    def __init__(self):
+        self.ca_key = ec.generate_private_key(ec.SECP256R1())
+        self.ca = make_certificate('Test CA', self.ca_key, 'Test CA', self.ca_key, is_ca=True)
+        self.ecu_key = ec.generate_private_key(ec.SECP256R1())
+        self.ecu = make_certificate('ECU 1D88', self.ecu_key, 'Test CA', self.ca_key)
+        other_ca_key = ec.generate_private_key(ec.SECP256R1())
+        self.untrusted_ecu = make_certificate('ECU 1D88', self.ecu_key, 'Test CA', other_ca_key)
+        self.tester_keys = {'ecdsa': ec.generate_private_key(ec.SECP256R1()),
+                            'rsa': rsa.generate_private_key(public_exponent=65537, key_size=2048)}
+        self.testers = {name: make_certificate('Tester ' + name, key, 'Test CA', self.ca_key)
+                        for name, key in self.tester_keys.items()}
+
+
+class AuthenticationEcuSimulator(sim.EcuSimulator):
+    def __init__(self, pki, ecu_certificate):
+        super().__init__(refuse_resume=False)
+        self.pki, self.ecu_certificate = pki, ecu_certificate
+        self.tester_key = None
+        self.challenge = None
+        self.authenticated = False
+        self.requests = []
+
+    def respond(self, request):
+        if request[0] != 0x29:
+            return super().respond(request)
+        sub_function = request[1]
+        self.requests.append(sub_function)
+        if sub_function == 0x00:
+            self.authenticated = False
+            return b'\x69\x00\x10'
+        if sub_function in (0x01, 0x02):
+            certificate_length = struct.unpack('>H', request[3:5])[0]
+            certificate = x509.load_der_x509_certificate(request[5:5 + certificate_length])
+            challenge_length = struct.unpack('>H', request[5 + certificate_length:7 + certificate_length])[0]
+            challenge_client = request[7 + certificate_length:7 + certificate_length + challenge_length]
+            try:
+                certificate.verify_directly_issued_by(self.pki.ca)
+            except (InvalidSignature, ValueError):
+                return b'\x7f\x29\x51'  # certificateVerificationFailed-InvalidSignature
+            self.tester_key = certificate.public_key()
+            self.challenge = os.urandom(16)
+            response = bytes([0x69, sub_function, 0x11]) + length_prefixed(self.challenge)
+            if sub_function == 0x02:
+                proof = self.pki.ecu_key.sign(challenge_client, ec.ECDSA(hashes.SHA256()))
+                response += length_prefixed(self.ecu_certificate.public_bytes(serialization.Encoding.DER))
+                response += length_prefixed(proof)
+            return response + length_prefixed(b'')
+        if sub_function == 0x03:
+            proof_length = struct.unpack('>H', request[2:4])[0]
+            try:
+                verify(self.tester_key, request[4:4 + proof_length], self.challenge)
+            except InvalidSignature:
+                return b'\x7f\x29\x58'  # ownershipVerificationFailed
+            self.authenticated = True
+            return b'\x69\x03\x12' + length_prefixed(b'')
+        if sub_function == 0x04:
+            certificate_length = struct.unpack('>H', request[4:6])[0]
+            x509.load_der_x509_certificate(request[6:6 + certificate_length]).verify_directly_issued_by(self.pki.ca)
+            self.evaluation_id = struct.unpack('>H', request[2:4])[0]
+            return b'\x69\x04\x13'
+        return b'\x7f\x29\x12'
+
+
+def write_pem(work_dir, name, data):
+    path = os.path.join(work_dir, name)
+    with open(path, 'wb') as pem_file:
+        pem_file.write(data)
+    return path
+
+
+def run(tool, pki, steps, ecu_certificate=None, tester='ecdsa', signing_key=None, extra_config=None, env=None):
+    ecu = AuthenticationEcuSimulator(pki, ecu_certificate or pki.ecu)
+    with tempfile.TemporaryDirectory() as work_dir:
+        files = {
+            'certificate': write_pem(work_dir, 'tester.pem', pki.testers[tester].public_bytes(serialization.Encoding.PEM)),
+            'private_key': write_pem(work_dir, 'tester.key', (signing_key or pki.tester_keys[tester]).private_bytes(
+                serialization.Encoding.PEM, serialization.PrivateFormat.PKCS8, serialization.NoEncryption())),
+            'trust_anchor': write_pem(work_dir, 'ca.pem', pki.ca.public_bytes(serialization.Encoding.PEM)),
+        }
+        sequence = [{"name": "authentication", "action": {key: files[key] if value is True else value
+                                                          for key, value in step.items()}} for step in steps]
+        report, _ = sim.run(tool, ecu, sequence, vendor='generic', extra_config=extra_config, env=env)
+    return ecu, report
+
+
+def main():
+    tool = sys.argv[1] if len(sys.argv) > 1 else os.path.join('target', 'debug', 'diag_tool')
+    pki = Pki()
+    bidirectional = {"mode": "bidirectional", "certificate": True, "private_key": True, "trust_anchor": True}
+
+    ecu, report = run(tool, pki, [bidirectional])
+    assert report['verdict'] == 'pass', 'bidirectional failed: %s' % sim.step_errors(report)
+    assert ecu.authenticated and ecu.requests == [0x02, 0x03], ecu.requests
+    ecu, report = run(tool, pki, [bidirectional, {"mode": "deauthenticate"}])
+    assert report['verdict'] == 'pass' and not ecu.authenticated, 'deauthenticate failed: %s' % sim.step_errors(report)
+    print('bidirectional: OK')
+
+    ecu, report = run(tool, pki, [{"mode": "unidirectional", "certificate": True, "private_key": True,
+                                   "communication_configuration": "0x00"}], tester='rsa')
+    assert report['verdict'] == 'pass', 'unidirectional failed: %s' % sim.step_errors(report)
+    assert ecu.authenticated and ecu.requests == [0x01, 0x03], ecu.requests
+    print('unidirectional: OK')
+
+    ecu, report = run(tool, pki, [{"mode": "transmit_certificate", "certificate": True,
+                                   "certificate_evaluation_id": "0x0102"}])
+    assert report['verdict'] == 'pass', 'transmit failed: %s' % sim.step_errors(report)
+    assert ecu.evaluation_id == 0x0102
+    print('transmit: OK')
+
+    ecu, report = run(tool, pki, [bidirectional], ecu_certificate=pki.untrusted_ecu)
+    assert report['verdict'] == 'fail' and not ecu.authenticated, 'untrusted ECU certificate was accepted'
+    assert ecu.requests == [0x02], 'proof was sent to an untrusted ECU: %s' % ecu.requests
+    assert 'trust anchor' in (report['steps'][2]['error'] or ''), report['steps'][2]['error']
+    print('untrusted_ecu: OK')
+
+    ecu, report = run(tool, pki, [bidirectional], signing_key=ec.generate_private_key(ec.SECP256R1()))
+    assert report['verdict'] == 'fail' and not ecu.authenticated, 'wrong tester key was accepted'
+    assert 'authentication 0x03 failed' in (report['steps'][2]['error'] or ''), report['steps'][2]['error']
+    print('wrong_key: OK')
+
+
+if __name__ == '__main__':
+    main()
@@ -0,0 +1,80 @@
+"""
+maxNumberOfBlockLength of RequestDownload responses against a simulated DoIP ECU.
+
+The ECU answers RequestDownload with a prepared 74 lengthFormatIdentifier maxNumberOfBlockLength response:
+  - valid:      1 to 4 length bytes, the smallest length 3 and the largest 1 MiB, TransferData fits the length
+  - identifier: lengthFormatIdentifier 0 or more than 4 length bytes, a response without identifier
+  - mismatch:   response longer or shorter than its lengthFormatIdentifier
+  - range:      maxNumberOfBlockLength 0, 2 and above 1 MiB
+Rejected responses fail the swdl step before any TransferData.
+
+Usage: python3 test/block_length_test.py [path/to/diag_tool]
+"""
+import os
+import sys
+
+import sim
+
+ADDRESS = 0x00080000
+IMAGE = bytes((index * 11 + 7) & 0xFF for index in range(300))
+ACTION = {"format": "bin", "address": "0x%08X" % ADDRESS, "max_reconnects": 0}
+
+
+class BlockLengthEcuSimulator(sim.EcuSimulator):
+    def __init__(self, download_response):
+        super().__init__()
+        self.download_response = download_response
+        self.transfer_lengths = []  # length of every TransferData request
+
+    def respond(self, request):
+        if request[0] == 0x36:
+            self.transfer_lengths.append(len(request))
+        response = super().respond(request)
+        return self.download_response if request[0] == 0x34 else response
+
+
+def main():
+    tool = sys.argv[1] if len(sys.argv) > 1 else os.path.join('target', 'debug', 'diag_tool')
+
+    for download_response, max_block_length in [
+        (b'\x74\x10\x03', 3), (b'\x74\x10\x42', 0x42), (b'\x74\x20\x00\x82', 0x82),
+        (b'\x74\x30\x00\x00\x22', 0x22), (b'\x74\x40\x00\x00\x00\x12', 0x12), (b'\x74\x40\x00\x10\x00\x00', 0x100000),
+    ]:
+        ecu = BlockLengthEcuSimulator(download_response)
+        report, errors = sim.flash(tool, ecu, ACTION, IMAGE)
+        assert report and report['verdict'] == 'pass', (download_response.hex(), errors)
+        assert ecu.read(ADDRESS, len(IMAGE)) == IMAGE, '%s: image differs' % download_response.hex()
+        expected = [min(max_block_length - 2, len(IMAGE) - offset) + 2
+                    for offset in range(0, len(IMAGE), max_block_length - 2)]
+        assert ecu.transfer_lengths == expected, (download_response.hex(), ecu.transfer_lengths)
+    print('valid: OK')
+
+    for download_response, message in [
+        (b'\x74', 'request-download response has no lengthFormatIdentifier'),
+        (b'\x74\x00', 'has invalid lengthFormatIdentifier 0x00'),
+        (b'\x74\x00\x42', 'has invalid lengthFormatIdentifier 0x00'),
+        (b'\x74\x50\x00\x00\x00\x00\x42', 'has invalid lengthFormatIdentifier 0x50'),
+        (b'\x74\xF0' + b'\x00' * 14 + b'\x42', 'has invalid lengthFormatIdentifier 0xF0'),
+    ]:
+        sim.expect_rejected(tool, BlockLengthEcuSimulator(download_response), ACTION, message, download_response.hex(),
+                            IMAGE)
+    print('identifier: OK')
+
+    for download_response in [b'\x74\x20\x42', b'\x74\x20\x00\x42\x00', b'\x74\x40\x00\x42', b'\x74\x10']:
+        sim.expect_rejected(tool, BlockLengthEcuSimulator(download_response), ACTION,
+                            'has invalid lengthFormatIdentifier 0x%02X' % download_response[1], download_response.hex(),
+                            IMAGE)
+    print('mismatch: OK')
+
+    for download_response, max_block_length in [
+        (b'\x74\x10\x00', 0), (b'\x74\x10\x02', 2), (b'\x74\x20\x00\x02', 2),
+        (b'\x74\x40\x00\x10\x00\x01', 0x100001), (b'\x74\x40\xFF\xFF\xFF\xFF', 0xFFFFFFFF),
+    ]:
+        sim.expect_rejected(tool, BlockLengthEcuSimulator(download_response), ACTION,
+                            'maxNumberOfBlockLength %d is out of range 3..%d' % (max_block_length, 0x100000),
+                            download_response.hex(), IMAGE)
+    print('range: OK')
+
+
+if __name__ == '__main__':
+    main()
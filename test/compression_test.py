@@ -0,0 +1,119 @@
+"""
+LZSS compression of raw images before TransferData against a simulated DoIP ECU.
+
+The ECU collects the compressed TransferData stream and expands it at RequestTransferExit with a reference
+implementation of the classic Okumura decoder, the expanded image has to match the file byte by byte:
+  - round_trip:  text, incompressible data, a run of one byte, leading spaces and a repeat at the far end of the window
+  - ratio:       repetitive data is sent compressed, dataFormatIdentifier 0x10 and memorySize of the image
+  - validation:  unknown compression and a dataFormatIdentifier of another compressionMethod are rejected at load
+
+Usage: python3 test/compression_test.py [path/to/diag_tool]
+"""
+import os
+import random
+import struct
+import sys
+
+import sim
+
+ADDRESS = 0x00080000
+RING_SIZE, MAX_MATCH, THRESHOLD = 4096, 18, 2
+
+
+def lzss_decode(data):
+    """Okumura LZSS: ring buffer of spaces written from 4096-18, flag bit set is a literal"""
+    ring = bytearray(b' ' * RING_SIZE)
+    output = bytearray()
+    index, flags = 0, 0
+
+    def put(byte):
+        ring[(RING_SIZE - MAX_MATCH + len(output)) % RING_SIZE] = byte
+        output.append(byte)
+
+    while index < len(data):
+        flags >>= 1
+        if not flags & 0x100:
+            flags = data[index] | 0xFF00
+            index += 1
+            if index == len(data):
+                break
+        if flags & 1:
+            put(data[index])
+            index += 1
+        else:
+            position = data[index] | ((data[index + 1] & 0xF0) << 4)
+            length = (data[index + 1] & 0x0F) + THRESHOLD + 1
+            index += 2
+            for offset in range(length):  # a match may copy bytes it has just written
+                put(ring[(position + offset) % RING_SIZE])
+    return bytes(output)
+
+
+class LzssEcuSimulator(sim.EcuSimulator):
+    def __init__(self):
+        super().__init__()
+        self.request = None  # dataFormatIdentifier, memoryAddress, memorySize of last RequestDownload
+        self.downloads = []  # (dataFormatIdentifier, memorySize, compressed length)
+
+    def respond(self, request):
+        if request[0] == 0x34:
+            address, length = struct.unpack('>II', request[3:11])
+            self.request = (request[1], address, length)
+        if request[0] == 0x37 and self.transfer is not None:
+            data_format_identifier, address, length = self.request
+            received = self.read(address, self.transfer['address'] - address)
+            if data_format_identifier >> 4 == 1:
+                image = lzss_decode(received)
+                if len(image) != length:
+                    return b'\x7f\x37\x72'
+                for index, byte in enumerate(image):
+                    self.memory[address + index] = byte
+            self.downloads.append((data_format_identifier, length, len(received)))
+        return super().respond(request)
+
+
+def flash(tool, image, compression="lzss", data_format_identifier=None):
+    ecu = LzssEcuSimulator()
+    action = {"format": "bin", "address": "0x%08X" % ADDRESS, "erase": "0x%08X,0x%X" % (ADDRESS, len(image)),
+              "compression": compression}
+    if data_format_identifier is not None:
+        action["data_format_identifier"] = data_format_identifier
+    report, errors = sim.flash(tool, ecu, action, image)
+    return ecu, report, errors
+
+
+def main():
+    tool = sys.argv[1] if len(sys.argv) > 1 else os.path.join('target', 'debug', 'diag_tool')
+    generator = random.Random(1)
+    noise = bytes(generator.randrange(256) for _ in range(4200))
+    images = {
+        'text': b''.join(b'sw_part_number %05d, block %d;\n' % (index, index % 7) for index in range(300)),
+        'incompressible': noise[:3000],
+        'run': b'\xAA' * 5000,
+        'spaces': b' ' * 40 + noise[:200],
+        'window_end': noise[:100] + noise[1000:1000 + RING_SIZE - MAX_MATCH - 100] + noise[:100],
+    }
+    for name, image in images.items():
+        ecu, report, errors = flash(tool, image)
+        assert report and report['verdict'] == 'pass', '%s failed: %s %s' % (name, report and sim.step_errors(report), errors)
+        assert ecu.read(ADDRESS, len(image)) == image, '%s: expanded image differs' % name
+    print('round_trip: OK')
+
+    data_format_identifier, memory_size, _ = ecu.downloads[0]
+    assert data_format_identifier == 0x10 and memory_size == len(images['window_end']), ecu.downloads
+    ecu, report, errors = flash(tool, images['run'])
+    assert ecu.downloads[0][2] < len(images['run']) // 4, 'run was not compressed: %s' % ecu.downloads
+    print('ratio: OK')
+
+    for compression, data_format_identifier, message in [
+        ("zip", None, 'swdl: compression "zip" is not supported'),
+        ("lzss", "0x20", 'swdl: dataFormatIdentifier compressionMethod does not match lzss method 1'),
+    ]:
+        ecu, report, errors = flash(tool, images['text'], compression, data_format_identifier)
+        assert message in errors, (message, errors)
+        assert not ecu.downloads and ecu.erase_count == 0, '%s: download was started' % compression
+    print('validation: OK')
+
+
+if __name__ == '__main__':
+    main()
@@ -0,0 +1,53 @@
+"""
+Shared crypto module utils::excrypto.
+
+  - selftest:        --crypto-selftest passes the FIPS/SP 800-38A/RFC known answers of every primitive
+  - aes192:          SecuredDataTransmission with AES-192 CMAC and AES-192 CTR keys against the simulated ECU
+  - malformed_keys:  keys with typos or wrong lengths are reported as errors, the tool does not panic
+
+Usage: python3 test/excrypto_test.py [path/to/diag_tool]
+"""
+import os
+import subprocess
+import sys
+
+import secured_transmission_test as secured
+import sim
+import volvo_sa_test
+
+PRIMITIVES = ['AES-128-ECB', 'AES-192-ECB', 'AES-256-ECB', 'AES-CBC', 'AES-CTR', 'AES-GCM', 'AES-CMAC', 'HMAC-SHA256',
+              'HMAC-SHA512', 'SHA-256', 'SHA-512', 'RSA-PSS-SHA256', 'RSA-PKCS1-SHA512', 'ECDSA-P256']
+
+
+def main():
+    tool = sys.argv[1] if len(sys.argv) > 1 else os.path.join('target', 'debug', 'diag_tool')
+
+    result = subprocess.run([tool, '--crypto-selftest'], capture_output=True, text=True, timeout=60)
+    assert result.returncode == 0, (result.stdout, result.stderr)
+    for name in PRIMITIVES:
+        assert '%s: OK' % name in result.stdout.splitlines(), (name, result.stdout)
+    print('selftest: OK')
+
+    secured.CMAC_KEY = bytes(range(0x80, 0x98))
+    secured.ENCRYPTION_KEY = bytes(range(0xA0, 0xB8))
+    settings = {"signature": "aes_cmac", "signature_key": secured.CMAC_KEY.hex(),
+                "encryption_key": secured.ENCRYPTION_KEY.hex(), "response_signature": True}
+    ecu, report, errors = secured.run(tool, settings, [secured.read_part_number()])
+    assert report and report['verdict'] == 'pass', 'aes192 failed: %s %s' % (report and sim.step_errors(report), errors)
+    assert ecu.internal == [b'\x22\xf1\x88'], ecu.internal
+    print('aes192: OK')
+
+    for key, message in [("0x" + "5G" * 16, "signature_key: AES key is not a hex string"),
+                         ("0x" + "55" * 20, "signature_key: AES key should be 16, 24 or 32 bytes, got 20")]:
+        ecu, report, errors = secured.run(tool, dict(settings, signature_key=key), [secured.read_part_number()])
+        assert message in errors and 'panicked' not in errors, (message, errors)
+        assert not ecu.requests, 'request was sent with a malformed key'
+    ecu, report, errors = volvo_sa_test.run(tool, volvo_sa_test.VolvoSaEcuSimulator(bytes(16), bytes(16)),
+                                            dict(volvo_sa_test.params(bytes(16), bytes(16)),
+                                                 encryption_authentication_key="0x" + "0Z" * 16))
+    assert 'encryption_authentication_key is not a hex key' in errors and 'panicked' not in errors, errors
+    print('malformed_keys: OK')
+
+
+if __name__ == '__main__':
+    main()
@@ -0,0 +1,122 @@
+"""
+RequestFileTransfer (0x38) push/pull CLI commands against a simulated DoIP ECU with a small file system.
+
+  - push:     push:<local> <remote> replaces the remote file in TransferData blocks of maxNumberOfBlockLength
+  - pull:     pull:<remote> <local> reads the remote file in blocks sent by the ECU
+  - negative: a missing remote file and a refused replace fail with the negative response, no local file is
+              written and the next command still works
+  - usage:    a command without both paths is rejected before anything is sent
+
+Usage: python3 test/file_transfer_test.py [path/to/diag_tool]
+"""
+import os
+import struct
+import sys
+import tempfile
+
+import sim
+
+BLOCK_LENGTH = 0x42  # maxNumberOfBlockLength of the ECU, 64 data bytes per TransferData
+READ_ONLY = '/rom/boot.cfg'
+
+
+class FileTransferEcuSimulator(sim.EcuSimulator):
+    def __init__(self, files):
+        super().__init__()
+        self.files = dict(files)
+        self.requests = []
+        self.file_transfer = None  # mode, remote path, data still to read or data received
+
+    def respond(self, request):
+        self.requests.append(bytes(request))
+        if request[0] == 0x38:
+            return self.request_file_transfer(request)
+        if request[0] == 0x36 and self.file_transfer is not None:
+            mode, path, data = self.file_transfer
+            if mode == 0x03:
+                data.extend(request[2:])
+                return bytes([0x76, request[1]])
+            block, self.file_transfer = data[:BLOCK_LENGTH - 2], (mode, path, data[BLOCK_LENGTH - 2:])
+            return bytes([0x76, request[1]]) + block
+        if request[0] == 0x37 and self.file_transfer is not None:
+            mode, path, data = self.file_transfer
+            if mode == 0x03:
+                self.files[path] = bytes(data)
+            self.file_transfer = None
+            return b'\x77'
+        return super().respond(request)
+
+    def request_file_transfer(self, request):
+        mode, path_length = request[1], struct.unpack('>H', request[2:4])[0]
+        path = request[4:4 + path_length].decode()
+        block_length = b'\x02' + struct.pack('>H', BLOCK_LENGTH)
+        if mode == 0x03:
+            if path == READ_ONLY:
+                return b'\x7f\x38\x70'  # uploadDownloadNotAccepted
+            self.file_transfer = (mode, path, bytearray())
+            return b'\x78\x03' + block_length + b'\x00'
+        if mode == 0x04:
+            if path not in self.files:
+                return b'\x7f\x38\x31'  # requestOutOfRange
+            data = self.files[path]
+            self.file_transfer = (mode, path, data)
+            return b'\x78\x04' + block_length + b'\x00' + b'\x00\x04' + struct.pack('>II', len(data), len(data))
+        return b'\x7f\x38\x12'
+
+    def transfer_data_lengths(self):
+        return [len(request) for request in self.requests if request[0] == 0x36]
+
+
+def run(tool, ecu, commands):
+    report, errors = sim.run(tool, ecu, [{"name": "send_diag", "action": ["1003"], "expect": ["5003*"]}],
+                             commands=['socket:connect', 'send_doip:activation'] + commands)
+    assert report and report['verdict'] == 'pass', errors
+    return errors
+
+
+def main():
+    tool = sys.argv[1] if len(sys.argv) > 1 else os.path.join('target', 'debug', 'diag_tool')
+    local_data = bytes((index * 17 + 9) & 0xFF for index in range(300))
+    remote_data = b''.join(b'log line %03d\n' % index for index in range(12))
+    with tempfile.TemporaryDirectory() as work_dir:
+        local_path = os.path.join(work_dir, 'app.cfg')
+        with open(local_path, 'wb') as local_file:
+            local_file.write(local_data)
+
+        ecu = FileTransferEcuSimulator({'/data/app.cfg': b'old'})
+        errors = run(tool, ecu, ['push:%s /data/app.cfg' % local_path])
+        assert ecu.files['/data/app.cfg'] == local_data, 'remote file differs: %s' % errors
+        request = next(request for request in ecu.requests if request[0] == 0x38)
+        assert request == b'\x38\x03\x00\x0d/data/app.cfg\x00\x04' + struct.pack('>II', 300, 300), request
+        assert ecu.transfer_data_lengths() == [BLOCK_LENGTH] * 4 + [300 - 4 * (BLOCK_LENGTH - 2) + 2], ecu.requests
+        print('push: OK')
+
+        pulled_path = os.path.join(work_dir, 'log.txt')
+        ecu = FileTransferEcuSimulator({'/data/log.txt': remote_data})
+        errors = run(tool, ecu, ['pull:/data/log.txt %s' % pulled_path])
+        assert os.path.exists(pulled_path), 'nothing pulled: %s' % errors
+        assert open(pulled_path, 'rb').read() == remote_data, 'pulled file differs'
+        assert len(ecu.transfer_data_lengths()) == -(-len(remote_data) // (BLOCK_LENGTH - 2)), ecu.requests
+        print('pull: OK')
+
+        missing_path = os.path.join(work_dir, 'missing.txt')
+        ecu = FileTransferEcuSimulator({'/data/log.txt': remote_data})
+        errors = run(tool, ecu, ['pull:/data/missing.txt %s' % missing_path, 'push:%s %s' % (local_path, READ_ONLY),
+                                 'push:%s /data/new.cfg' % local_path])
+        assert 'request-file-transfer failed: negative response to 0x38: requestOutOfRange (0x31)' in errors, errors
+        assert 'request-file-transfer failed: negative response to 0x38: uploadDownloadNotAccepted (0x70)' in errors, errors
+        assert not os.path.exists(missing_path), 'local file written for a missing remote file'
+        assert READ_ONLY not in ecu.files and ecu.transfer_data_lengths() == [BLOCK_LENGTH] * 4 + [46], ecu.requests
+        assert ecu.files['/data/new.cfg'] == local_data, 'command after a negative response failed'
+        print('negative: OK')
+
+        ecu = FileTransferEcuSimulator({})
+        errors = run(tool, ecu, ['push:%s' % local_path, 'pull:/data/log.txt'])
+        assert 'use format like this push:app.cfg /data/app.cfg' in errors, errors
+        assert 'use format like this pull:/data/app.cfg app.cfg' in errors, errors
+        assert not [request for request in ecu.requests if request[0] == 0x38], ecu.requests
+        print('usage: OK')
+
+
+if __name__ == '__main__':
+    main()
@@ -0,0 +1,105 @@
+"""
+Flash package execution against a simulated DoIP ECU.
+
+A zip package with a secondary bootloader, an application and a manifest is flashed by a flash_package step:
+  - pass:      preconditions, SBL download and activation, application download, part numbers match
+  - mismatch:  everything is flashed, but a part number read back differs, the step fails
+  - wrong_ecu: manifest targets another ECU, the package is rejected before anything is erased
+
+Usage: python3 test/flash_package_test.py [path/to/diag_tool]
+"""
+import json
+import os
+import struct
+import sys
+import tempfile
+import zipfile
+
+import sim
+
+SBL_ADDRESS, SBL_CALL = 0x20000000, 0x20000010
+APP_ADDRESS = 0x00080000
+SBL = bytes((index * 5 + 1) & 0xFF for index in range(300))
+APP = bytes((index * 11 + 7) & 0xFF for index in range(700))
+PART_NUMBERS = {0xF188: b'32233422 AA\x00\x00', 0xF12E: bytes.fromhex('01020304')}
+
+
+class PackageEcuSimulator(sim.EcuSimulator):
+    def __init__(self):
+        super().__init__()
+        self.activated_call = None
+
+    def respond(self, request):
+        sid = request[0]
+        if sid == 0x22:
+            did = struct.unpack('>H', request[1:3])[0]
+            if did not in PART_NUMBERS:
+                return b'\x7f\x22\x31'
+            return b'\x62' + request[1:3] + PART_NUMBERS[did]
+        if sid == 0x31 and request[2:4] == b'\x03\x01':
+            self.activated_call = struct.unpack('>I', request[4:8])[0]
+            return b'\x71\x01\x03\x01\x00'
+        return super().respond(request)
+
+
+def write_package(work_dir, ecu_address, expected_part_number):
+    package_path = os.path.join(work_dir, 'package.zip')
+    manifest = {
+        "name": "test package",
+        "ecu_address": ecu_address,
+        "preconditions": [
+            {"name": "send_diag", "action": ["1002"], "expect": ["5002*"]},
+        ],
+        "sbl": {
+            "file": {"path": "sbl.bin", "format": "bin", "address": "0x%08X" % SBL_ADDRESS},
+            "call_address": "0x%08X" % SBL_CALL,
+        },
+        "files": [
+            {"path": "app.bin", "format": "bin", "address": "0x%08X" % APP_ADDRESS,
+             "erase": "0x%08X,0x%X" % (APP_ADDRESS, len(APP)), "check_routine": "0x0202"},
+        ],
+        "part_numbers": [
+            {"did": "0xF188", "expect": expected_part_number},
+            {"did": "0xF12E", "expect": "0x01020304"},
+        ],
+    }
+    with zipfile.ZipFile(package_path, 'w', zipfile.ZIP_DEFLATED) as package:
+        package.writestr('package/manifest.json', json.dumps(manifest))
+        package.writestr('package/sbl.bin', SBL)
+        package.writestr('package/app.bin', APP)
+    return package_path
+
+
+def run(tool, ecu_address="0x1d88", expected_part_number="32233422 AA"):
+    ecu = PackageEcuSimulator()
+    with tempfile.TemporaryDirectory() as work_dir:
+        package_path = write_package(work_dir, ecu_address, expected_part_number)
+        report, _ = sim.run(tool, ecu, [{"name": "flash_package", "timeout": "1s", "action": {"path": package_path}}])
+    return ecu, report
+
+
+def main():
+    tool = sys.argv[1] if len(sys.argv) > 1 else os.path.join('target', 'debug', 'diag_tool')
+
+    ecu, report = run(tool)
+    assert report and len(report['steps']) == 4, 'sequence was not executed'
+    assert report['verdict'] == 'pass', 'sequence failed: %s' % sim.step_errors(report)
+    assert ecu.read(SBL_ADDRESS, len(SBL)) == SBL, 'SBL memory differs'
+    assert ecu.read(APP_ADDRESS, len(APP)) == APP, 'application memory differs'
+    assert ecu.activated_call == SBL_CALL, 'SBL was not activated at its call address'
+    print('pass: OK')
+
+    ecu, report = run(tool, expected_part_number="32233422 AB")
+    assert report['verdict'] == 'fail', 'part number mismatch was not detected'
+    assert ecu.read(APP_ADDRESS, len(APP)) == APP, 'application memory differs'
+    assert 'part numbers differ' in (report['steps'][2]['error'] or ''), report['steps'][2]['error']
+    print('mismatch: OK')
+
+    ecu, report = run(tool, ecu_address="0x1d89")
+    assert report['verdict'] == 'fail', 'package for another ECU was accepted'
+    assert ecu.erase_count == 0 and not ecu.memory, 'memory was changed by a rejected package'
+    print('wrong_ecu: OK')
+
+
+if __name__ == '__main__':
+    main()
@@ -0,0 +1,66 @@
+"""
+Hex strings of send_diag requests against a simulated DoIP ECU.
+
+  - sequence: "" and "0x" as request are rejected when the sequence is loaded, nothing reaches the ECU
+  - prefix:   "0X" is stripped like "0x", the request reaches the ECU
+  - expect:   an empty expect pattern is rejected when the sequence is loaded
+  - cli:      send_diag with an empty request is rejected, the next request still reaches the ECU
+
+Usage: python3 test/hex_request_test.py [path/to/diag_tool]
+"""
+import os
+import sys
+
+import sim
+
+
+class RecordingEcuSimulator(sim.EcuSimulator):
+    def __init__(self):
+        super().__init__()
+        self.requests = []
+
+    def respond(self, request):
+        self.requests.append(bytes(request))
+        return super().respond(request)
+
+
+def main():
+    tool = sys.argv[1] if len(sys.argv) > 1 else os.path.join('target', 'debug', 'diag_tool')
+
+    for request in ["", "0x"]:
+        ecu = RecordingEcuSimulator()
+        report, errors = sim.run(tool, ecu, [
+            {"name": "send_diag", "action": ["1003"], "expect": ["5003*"]},
+            {"name": "send_diag", "action": [request], "expect": ["*"]},
+        ], timeout=5)
+        assert 'send_diag: empty hex string "%s"' % request in errors, errors
+        assert not ecu.requests, '"%s": sequence was executed: %s' % (request, ecu.requests)
+    print('sequence: OK')
+
+    ecu = RecordingEcuSimulator()
+    report, errors = sim.run(tool, ecu, [{"name": "send_diag", "action": ["0X1003"], "expect": ["5003*"]}])
+    assert report and report['verdict'] == 'pass', errors
+    assert b'\x10\x03' in ecu.requests, ecu.requests
+    print('prefix: OK')
+
+    for expect in [[""], ["5003*", ""], ""]:
+        ecu = RecordingEcuSimulator()
+        report, errors = sim.run(tool, ecu, [
+            {"name": "send_diag", "action": ["1003"], "expect": ["5003*"]},
+            {"name": "send_diag", "action": ["1003", "1001"], "expect": expect},
+        ], timeout=5)
+        assert 'send_diag: empty expect pattern, use "*" to accept any response' in errors, (expect, errors)
+        assert not ecu.requests, '%s: sequence was executed: %s' % (expect, ecu.requests)
+    print('expect: OK')
+
+    ecu = RecordingEcuSimulator()
+    report, errors = sim.run(tool, ecu, [{"name": "send_diag", "action": ["1003"], "expect": ["5003*"]}],
+                             commands=['socket:connect', 'send_doip:activation', 'send_diag:', 'send_diag:1001'])
+    assert report and report['verdict'] == 'pass', errors
+    assert 'empty hex string' in errors, errors
+    assert ecu.requests[-1] == b'\x10\x01' and b'' not in ecu.requests, ecu.requests
+    print('cli: OK')
+
+
+if __name__ == '__main__':
+    main()
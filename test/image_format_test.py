@@ -0,0 +1,126 @@
+"""
+Intel HEX and Motorola S-record images against a simulated DoIP ECU.
+
+Images are built here record by record and flashed with a swdl step, the ECU memory has to match:
+  - ihex:        data with extended segment (02) and extended linear (04) addresses, start address records (03, 05)
+  - srec:        S1/S2/S3 data records with S0 header, S5 count and S7/S8/S9 termination
+  - ihex_errors: wrong checksum, byte count mismatch, wrong length of 02/03/04/05 records, unknown type, missing end
+  - srec_errors: wrong checksum, byte count mismatch, unknown type, record shorter than its address, no data
+  - long_header: memory uploaded to an .s37 file with a path longer than an S0 record holds, the dump flashes again
+
+Usage: python3 test/image_format_test.py [path/to/diag_tool]
+"""
+import os
+import sys
+import tempfile
+
+import sim
+
+
+def ihex_record(record_type, offset, data, checksum=None):
+    record = bytes([len(data), offset >> 8, offset & 0xFF, record_type]) + data
+    checksum = (-sum(record)) & 0xFF if checksum is None else checksum
+    return ':%s%02X' % (record.hex().upper(), checksum)
+
+
+def srec_record(record_type, address, data, count=None):
+    record = bytes([len(address) + len(data) + 1 if count is None else count]) + address + data
+    return 'S%s%s%02X' % (record_type, record.hex().upper(), ~sum(record) & 0xFF)
+
+
+def action(path, erase):
+    return {"path": path, "format": "hex" if path.endswith('.hex') else "srec",
+            "erase": ["0x%08X,0x%X" % range_ for range_ in erase]}
+
+
+def image(lines):
+    return ('\n'.join(lines) + '\n').encode()
+
+
+def main():
+    tool = sys.argv[1] if len(sys.argv) > 1 else os.path.join('target', 'debug', 'diag_tool')
+    low, segment, linear = bytes(range(40)), b'segment addressed', bytes(range(200, 256))
+    erase = [(0x1000, 0x100), (0x20010, 0x100), (0x8FFF0, 0x100)]
+    with tempfile.TemporaryDirectory() as work_dir:
+        ecu = sim.EcuSimulator()
+        report, errors = sim.flash(tool, ecu, action('app.hex', erase), image([
+            ihex_record(0x00, 0x1000, low[:16]), ihex_record(0x00, 0x1010, low[16:]),
+            ihex_record(0x02, 0, b'\x20\x00'), ihex_record(0x00, 0x0010, segment),
+            ihex_record(0x03, 0, b'\x00\x00\x10\x00'),
+            ihex_record(0x04, 0, b'\x00\x08'), ihex_record(0x00, 0xFFF0, linear[:16]),
+            ihex_record(0x04, 0, b'\x00\x09'), ihex_record(0x00, 0x0000, linear[16:]),
+            ihex_record(0x05, 0, b'\x00\x08\xFF\xF0'), ihex_record(0x01, 0, b''),
+        ]))
+        assert report and report['verdict'] == 'pass', errors
+        assert ecu.read(0x1000, len(low)) == low, 'data records differ'
+        assert ecu.read(0x20010, len(segment)) == segment, 'extended segment address is not applied'
+        assert ecu.read(0x8FFF0, len(linear)) == linear, 'extended linear address is not applied'
+        print('ihex: OK')
+
+        ecu = sim.EcuSimulator()
+        report, errors = sim.flash(tool, ecu, action('app.s37', erase), image([
+            srec_record('0', b'\x00\x00', b'app'), srec_record('1', b'\x10\x00', low),
+            srec_record('2', b'\x02\x00\x10', segment), srec_record('3', b'\x00\x08\xFF\xF0', linear),
+            srec_record('5', b'\x00\x03', b''), srec_record('7', b'\x00\x00\x00\x00', b''),
+            srec_record('8', b'\x00\x00\x00', b''), srec_record('9', b'\x00\x00', b''),
+        ]))
+        assert report and report['verdict'] == 'pass', errors
+        assert ecu.read(0x1000, len(low)) == low and ecu.read(0x20010, len(segment)) == segment
+        assert ecu.read(0x8FFF0, len(linear)) == linear, 'S3 record differs'
+        print('srec: OK')
+
+        data, eof = ihex_record(0x00, 0x1000, low[:16]), ihex_record(0x01, 0, b'')
+        for lines, message in [
+            ([ihex_record(0x00, 0x1000, low[:16], checksum=0), eof], 'line 1: wrong checksum'),
+            ([data, ':10100000' + low[:15].hex().upper() + '00', eof], 'line 2: record length does not match byte count'),
+            ([data, ':0000', eof], 'line 2: record length does not match byte count'),
+            ([ihex_record(0x04, 0, b'\x00\x08\x00'), data, eof], 'line 1: record type 04 has 3 data bytes, expected 2'),
+            ([ihex_record(0x02, 0, b'\x20'), data, eof], 'line 1: record type 02 has 1 data bytes, expected 2'),
+            ([data, ihex_record(0x05, 0, b'\x00\x08'), eof], 'line 2: record type 05 has 2 data bytes, expected 4'),
+            ([data, ihex_record(0x03, 0, b''), eof], 'line 2: record type 03 has 0 data bytes, expected 4'),
+            ([data, ihex_record(0x06, 0, b''), eof], 'line 2: unsupported record type 06'),
+            ([data, 'S1130000', eof], "line 2: record does not start with ':'"),
+            ([data, ':1G', eof], 'line 2: invalid hex record'),
+            ([data], 'missing end of file record'),
+            ([data, eof, data], 'line 3: record after end of file record'),
+        ]:
+            sim.expect_rejected(tool, sim.EcuSimulator(), action('app.hex', erase[:1]), message, lines, image(lines))
+        print('ihex_errors: OK')
+
+        data = srec_record('1', b'\x10\x00', low[:16])
+        for lines, message in [
+            ([data[:-2] + '00'], 'line 1: wrong checksum'),
+            ([srec_record('1', b'\x10\x00', low[:16], count=20)], 'line 1: record length does not match byte count'),
+            ([data, srec_record('4', b'\x00\x00', b'')], 'line 2: unsupported record type S4'),
+            ([srec_record('3', b'\x00\x10', b'')], 'line 1: record too short for its address'),
+            ([data, ':100000'], "line 2: record does not start with 'S'"),
+            ([srec_record('0', b'\x00\x00', b'app'), srec_record('9', b'\x00\x00', b'')], 'no data record in S-record file'),
+        ]:
+            sim.expect_rejected(tool, sim.EcuSimulator(), action('app.s37', erase[:1]), message, lines, image(lines))
+        print('srec_errors: OK')
+
+        # S0 record holds 252 header bytes, the dump path is longer
+        dump_dir = os.path.join(work_dir, *(['memory_dump_directory_%02d' % index for index in range(12)]))
+        os.makedirs(dump_dir)
+        dump_path = os.path.join(dump_dir, 'dump.s37')
+        assert len(dump_path) > 252
+        ecu = sim.EcuSimulator()
+        memory = bytes((index * 5 + 1) & 0xFF for index in range(100))
+        for index, byte in enumerate(memory):
+            ecu.memory[0x2000 + index] = byte
+        report, errors = sim.run(tool, ecu, [{"name": "upload", "timeout": "1s", "action": {
+            "path": dump_path, "address": "0x2000", "length": "0x64"}}])
+        assert report and report['verdict'] == 'pass', (errors, report and sim.step_errors(report))
+        with open(dump_path) as dump_file:
+            header = bytes.fromhex(dump_file.readline().strip()[2:])
+        assert header[0] == len(header) - 1 == 255 and ~sum(header[:-1]) & 0xFF == header[-1], 'S0 record is invalid'
+        assert header[3:-1] == dump_path.encode()[:252]
+        ecu = sim.EcuSimulator()
+        report, errors = sim.flash(tool, ecu, action(dump_path, [(0x2000, 0x100)]))
+        assert report and report['verdict'] == 'pass', errors
+        assert ecu.read(0x2000, len(memory)) == memory, 'dump does not flash back'
+        print('long_header: OK')
+
+
+if __name__ == '__main__':
+    main()
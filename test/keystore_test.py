@@ -0,0 +1,63 @@
+"""
+Encrypted keystore: management commands and "@name" key references in a securityaccess step.
+
+  - manage:         create, add, list, remove and change password with passwords piped on stdin
+  - wrong_password: keystore cannot be opened, the file does not contain the key in clear text
+  - reference:      securityaccess secret "@sa_secret" is resolved from the unlocked keystore, the ECU unlocks
+  - locked:         without --keystore the reference cannot be resolved and the step fails
+
+Usage: python3 test/keystore_test.py [path/to/diag_tool]
+"""
+import os
+import subprocess
+import sys
+import tempfile
+
+import seed_key_test
+
+SECRET = 'A5C3'
+PASSWORD, NEW_PASSWORD = 'correct horse', 'battery staple'
+
+
+def keystore(tool, keystore_path, args, lines):
+    result = subprocess.run([tool, '--keystore', keystore_path] + args, input='\n'.join(lines) + '\n',
+                            capture_output=True, text=True, timeout=60,
+                            env={key: value for key, value in os.environ.items() if key != 'DIAG_TOOL_KEYSTORE_PASSWORD'})
+    return result.stdout, result.stderr
+
+
+def main():
+    tool = sys.argv[1] if len(sys.argv) > 1 else os.path.join('target', 'debug', 'diag_tool')
+    with tempfile.TemporaryDirectory() as work_dir:
+        keystore_path = os.path.join(work_dir, 'keys.store')
+        keystore(tool, keystore_path, ['--keystore-create', '--keystore-add', 'sa_secret'], [PASSWORD, PASSWORD, '0x' + SECRET])
+        keystore(tool, keystore_path, ['--keystore-add', 'old_key'], [PASSWORD, '00112233'])
+        keystore(tool, keystore_path, ['--keystore-remove', 'old_key'], [PASSWORD])
+        keystore(tool, keystore_path, ['--keystore-passwd'], [PASSWORD, NEW_PASSWORD, NEW_PASSWORD])
+        output, errors = keystore(tool, keystore_path, ['--keystore-list'], [NEW_PASSWORD])
+        assert output.split() == ['@sa_secret'], (output, errors)
+        print('manage: OK')
+
+        output, errors = keystore(tool, keystore_path, ['--keystore-list'], [PASSWORD])
+        assert 'wrong password' in errors and not output, (output, errors)
+        with open(keystore_path) as keystore_file:
+            contents = keystore_file.read()
+        assert SECRET not in contents.upper() and 'sa_secret' not in contents, 'keystore is not encrypted'
+        print('wrong_password: OK')
+
+        params = {"algorithm": "xor", "secret": "@sa_secret"}
+        env = dict(os.environ, DIAG_TOOL_KEYSTORE_PASSWORD=NEW_PASSWORD)
+        ecu, report = seed_key_test.run(tool, 'xor', params, bytes.fromhex(SECRET),
+                                        extra_args=['--keystore', keystore_path], env=env)
+        assert report['verdict'] == 'pass', 'reference failed: %s' % [step['error'] for step in report['steps']]
+        assert ecu.unlocked == [0x01]
+        print('reference: OK')
+
+        ecu, report = seed_key_test.run(tool, 'xor', params, bytes.fromhex(SECRET))
+        assert report['verdict'] == 'fail' and not ecu.unlocked, 'reference was resolved without keystore'
+        assert 'keystore' in (report['steps'][2]['error'] or ''), report['steps'][2]['error']
+        print('locked: OK')
+
+
+if __name__ == '__main__':
+    main()
@@ -0,0 +1,297 @@
+"""
+PKCS#11 keys ("pkcs11:<label>") against a mock token module compiled from C with OpenSSL.
+
+The mock module keeps its keys in a directory, secret keys as <label>.key and private keys as <label>.pem.
+diag_tool only gets labels, the keys never appear in config or sequence:
+  - volvo_sa:       securityaccess AES128 exchange, CTR and CMAC of both keys are computed by the token
+  - authentication: 0x29 bidirectional, the challenge is signed by an EC private key of the token
+  - secured:        0x84 request signed and response verified with an AES-CMAC key of the token
+  - wrong_pin:      login fails, the step reports CKR_PIN_INCORRECT
+  - missing_key:    label not on the token, the step fails before sendKey
+
+Needs diag_tool built with "cargo build --features pkcs11", a C compiler and OpenSSL headers.
+Usage: python3 test/pkcs11_test.py [path/to/diag_tool]
+"""
+import os
+import shutil
+import subprocess
+import sys
+import tempfile
+
+from cryptography.hazmat.primitives import serialization
+
+import authentication_test
+import secured_transmission_test
+import sim
+
+PIN = '1234'
+
+MODULE_SOURCE = r'''
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+#include <openssl/core_names.h>
+#include <openssl/ecdsa.h>
+#include <openssl/evp.h>
+#include <openssl/pem.h>
+
+typedef unsigned long CK_ULONG;
+typedef struct { CK_ULONG type; void *value; CK_ULONG length; } CK_ATTRIBUTE;
+typedef struct { CK_ULONG mechanism; void *parameter; CK_ULONG length; } CK_MECHANISM;
+
+#define MAX_OBJECTS 16
+static struct { CK_ULONG class; char path[512]; } objects[MAX_OBJECTS];
+static CK_ULONG object_count, found[MAX_OBJECTS], found_count, mechanism, active_key;
+static int logged_in;
+
+static CK_ULONG C_Initialize(void *args) { object_count = 0; logged_in = 0; return 0; }
+static CK_ULONG C_Finalize(void *reserved) { return 0; }
+static CK_ULONG C_GetSlotList(unsigned char present, CK_ULONG *slots, CK_ULONG *count)
+{
+    if (slots) slots[0] = 7;
+    *count = 1;
+    return 0;
+}
+static CK_ULONG C_GetTokenInfo(CK_ULONG slot, unsigned char *info)
+{
+    memset(info, ' ', 32);
+    memcpy(info, "diag-test", 9);
+    return 0;
+}
+static CK_ULONG C_OpenSession(CK_ULONG slot, CK_ULONG flags, void *app, void *notify, CK_ULONG *session)
+{
+    *session = 1;
+    return 0;
+}
+static CK_ULONG C_CloseSession(CK_ULONG session) { return 0; }
+static CK_ULONG C_Login(CK_ULONG session, CK_ULONG user, const unsigned char *pin, CK_ULONG length)
+{
+    const char *expected = getenv("MOCK_PKCS11_PIN");
+    if (!expected || strlen(expected) != length || memcmp(pin, expected, length)) return 0xA0;
+    logged_in = 1;
+    return 0;
+}
+
+/* Objects are looked up by class and label, private and secret keys only after login */
+static CK_ULONG C_FindObjectsInit(CK_ULONG session, CK_ATTRIBUTE *template, CK_ULONG count)
+{
+    CK_ULONG index, class = 0;
+    char label[128] = "", path[512];
+    FILE *file;
+    for (index = 0; index < count; index++) {
+        if (template[index].type == 0x000) class = *(CK_ULONG *)template[index].value;
+        if (template[index].type == 0x003 && template[index].length < sizeof(label)) {
+            memcpy(label, template[index].value, template[index].length);
+            label[template[index].length] = 0;
+        }
+    }
+    found_count = 0;
+    if (!logged_in || object_count == MAX_OBJECTS) return 0;
+    snprintf(path, sizeof(path), "%s/%s.%s", getenv("MOCK_PKCS11_DIR"), label, class == 4 ? "key" : "pem");
+    if (!(file = fopen(path, "rb"))) return 0;
+    fclose(file);
+    objects[object_count].class = class;
+    strcpy(objects[object_count].path, path);
+    found[found_count++] = ++object_count;
+    return 0;
+}
+static CK_ULONG C_FindObjects(CK_ULONG session, CK_ULONG *handles, CK_ULONG max, CK_ULONG *count)
+{
+    for (*count = 0; *count < found_count && *count < max; (*count)++) handles[*count] = found[*count];
+    return 0;
+}
+static CK_ULONG C_FindObjectsFinal(CK_ULONG session) { return 0; }
+
+static EVP_PKEY *load_private_key(CK_ULONG key)
+{
+    EVP_PKEY *pkey;
+    FILE *file = fopen(objects[key - 1].path, "r");
+    if (!file) return NULL;
+    pkey = PEM_read_PrivateKey(file, NULL, NULL, NULL);
+    fclose(file);
+    return pkey;
+}
+static size_t load_secret_key(CK_ULONG key, unsigned char *secret)
+{
+    size_t length;
+    FILE *file = fopen(objects[key - 1].path, "rb");
+    if (!file) return 0;
+    length = fread(secret, 1, 32, file);
+    fclose(file);
+    return length;
+}
+
+static CK_ULONG C_GetAttributeValue(CK_ULONG session, CK_ULONG key, CK_ATTRIBUTE *template, CK_ULONG count)
+{
+    EVP_PKEY *pkey;
+    if (key == 0 || key > object_count) return 0x60;
+    if (template[0].type != 0x100 || !(pkey = load_private_key(key))) return 0x12;
+    *(CK_ULONG *)template[0].value = EVP_PKEY_get_base_id(pkey) == EVP_PKEY_EC ? 0x03 : 0x00;
+    EVP_PKEY_free(pkey);
+    return 0;
+}
+
+static CK_ULONG operation_init(CK_ULONG session, CK_MECHANISM *operation, CK_ULONG key)
+{
+    if (key == 0 || key > object_count) return 0x60;
+    mechanism = operation->mechanism;
+    active_key = key;
+    return 0;
+}
+
+static CK_ULONG C_Encrypt(CK_ULONG session, const unsigned char *data, CK_ULONG length, unsigned char *output,
+                          CK_ULONG *output_length)
+{
+    unsigned char secret[32];
+    size_t secret_length = load_secret_key(active_key, secret);
+    int written = 0;
+    EVP_CIPHER_CTX *ctx;
+    if (mechanism != 0x1081) return 0x70;
+    if (length % 16) return 0x21;
+    ctx = EVP_CIPHER_CTX_new();
+    EVP_EncryptInit_ex(ctx, secret_length == 32 ? EVP_aes_256_ecb() : EVP_aes_128_ecb(), NULL, secret, NULL);
+    EVP_CIPHER_CTX_set_padding(ctx, 0);
+    EVP_EncryptUpdate(ctx, output, &written, data, (int)length);
+    EVP_CIPHER_CTX_free(ctx);
+    *output_length = (CK_ULONG)written;
+    return 0;
+}
+
+static CK_ULONG C_Sign(CK_ULONG session, const unsigned char *data, CK_ULONG length, unsigned char *signature,
+                       CK_ULONG *signature_length)
+{
+    unsigned char secret[32], der[128];
+    size_t secret_length, size;
+    EVP_PKEY *pkey;
+    EVP_PKEY_CTX *pctx;
+    if (mechanism == 0x108A) {
+        EVP_MAC *mac = EVP_MAC_fetch(NULL, "CMAC", NULL);
+        EVP_MAC_CTX *ctx = EVP_MAC_CTX_new(mac);
+        OSSL_PARAM params[2] = {OSSL_PARAM_END, OSSL_PARAM_END};
+        secret_length = load_secret_key(active_key, secret);
+        params[0] = OSSL_PARAM_construct_utf8_string(OSSL_MAC_PARAM_CIPHER,
+                                                     secret_length == 32 ? "AES-256-CBC" : "AES-128-CBC", 0);
+        EVP_MAC_init(ctx, secret, secret_length, params);
+        EVP_MAC_update(ctx, data, length);
+        EVP_MAC_final(ctx, signature, &size, *signature_length);
+        EVP_MAC_CTX_free(ctx);
+        EVP_MAC_free(mac);
+        *signature_length = size;
+        return 0;
+    }
+    if (!(pkey = load_private_key(active_key))) return 0x60;
+    if (mechanism == 0x1041) {
+        const unsigned char *cursor = der;
+        const BIGNUM *r, *s;
+        ECDSA_SIG *sig;
+        pctx = EVP_PKEY_CTX_new(pkey, NULL);
+        size = sizeof(der);
+        EVP_PKEY_sign_init(pctx);
+        EVP_PKEY_sign(pctx, der, &size, data, length);
+        EVP_PKEY_CTX_free(pctx);
+        sig = d2i_ECDSA_SIG(NULL, &cursor, (long)size);
+        ECDSA_SIG_get0(sig, &r, &s);
+        BN_bn2binpad(r, signature, 32);
+        BN_bn2binpad(s, signature + 32, 32);
+        ECDSA_SIG_free(sig);
+        *signature_length = 64;
+    } else if (mechanism == 0x0040) {
+        EVP_MD_CTX *ctx = EVP_MD_CTX_new();
+        size = *signature_length;
+        EVP_DigestSignInit(ctx, NULL, EVP_sha256(), NULL, pkey);
+        EVP_DigestSign(ctx, signature, &size, data, length);
+        EVP_MD_CTX_free(ctx);
+        *signature_length = size;
+    } else {
+        EVP_PKEY_free(pkey);
+        return 0x70;
+    }
+    EVP_PKEY_free(pkey);
+    return 0;
+}
+
+static struct { unsigned char version[2]; void *functions[44]; } function_list = {{2, 40}, {
+    C_Initialize, C_Finalize, NULL, NULL, C_GetSlotList, NULL, C_GetTokenInfo, NULL, NULL, NULL, NULL, NULL,
+    C_OpenSession, C_CloseSession, NULL, NULL, NULL, NULL, C_Login, NULL, NULL, NULL, NULL, NULL,
+    C_GetAttributeValue, NULL, C_FindObjectsInit, C_FindObjects, C_FindObjectsFinal, operation_init, C_Encrypt,
+    NULL, NULL, NULL, NULL, NULL, NULL, NULL, NULL, NULL, NULL, NULL, operation_init, C_Sign}};
+
+CK_ULONG C_GetFunctionList(void **list)
+{
+    *list = &function_list;
+    return 0;
+}
+'''
+
+
+def volvo_sa(tool, module, key_dir, params, pin=PIN):
+    encryption_key, proof_key = (open(os.path.join(key_dir, name + '.key'), 'rb').read() for name in ('sa_enc', 'sa_pop'))
+    ecu = sim.VolvoSaEcuSimulator(encryption_key, proof_key)
+    env = dict(os.environ, MOCK_PKCS11_DIR=key_dir, MOCK_PKCS11_PIN=PIN, DIAG_TOOL_PKCS11_PIN=pin)
+    report, _ = sim.run(tool, ecu, [{"name": "securityaccess_05", "action": params, "expect": ["67*"]}],
+                        extra_config={"pkcs11": {"module": module, "token": "diag-test"}}, env=env)
+    return ecu, report
+
+
+def main():
+    tool = sys.argv[1] if len(sys.argv) > 1 else os.path.join('target', 'debug', 'diag_tool')
+    compiler = shutil.which('cc')
+    if compiler is None:
+        print('pkcs11: SKIPPED, no C compiler')
+        return
+    with tempfile.TemporaryDirectory() as work_dir:
+        source_path = os.path.join(work_dir, 'mock_pkcs11.c')
+        module = os.path.join(work_dir, 'libmockpkcs11.so')
+        with open(source_path, 'w') as source_file:
+            source_file.write(MODULE_SOURCE)
+        subprocess.check_call([compiler, '-shared', '-fPIC', '-o', module, source_path, '-lcrypto'])
+        key_dir = os.path.join(work_dir, 'token')
+        os.mkdir(key_dir)
+        for name in ('sa_enc', 'sa_pop'):
+            with open(os.path.join(key_dir, name + '.key'), 'wb') as key_file:
+                key_file.write(os.urandom(16))
+
+        params = {"algorithm": "AES128", "iv": "random",
+                  "encryption_authentication_key": "pkcs11:sa_enc", "proof_of_ownership_key": "pkcs11:sa_pop"}
+        ecu, report = volvo_sa(tool, module, key_dir, params)
+        assert report['verdict'] == 'pass', 'volvo_sa failed: %s' % sim.step_errors(report)
+        assert ecu.unlocked == [0x05], ecu.unlocked
+        print('volvo_sa: OK')
+
+        pki = authentication_test.Pki()
+        with open(os.path.join(key_dir, 'tester.pem'), 'wb') as key_file:
+            key_file.write(pki.tester_keys['ecdsa'].private_bytes(
+                serialization.Encoding.PEM, serialization.PrivateFormat.PKCS8, serialization.NoEncryption()))
+        step = {"mode": "bidirectional", "certificate": True, "private_key": "pkcs11:tester", "trust_anchor": True}
+        ecu, report = authentication_test.run(tool, pki, [step], extra_config={
+            "pkcs11": {"module": module, "token": "diag-test", "pin": PIN}}, env=dict(os.environ, MOCK_PKCS11_DIR=key_dir,
+                                                                                  MOCK_PKCS11_PIN=PIN))
+        assert report['verdict'] == 'pass', 'authentication failed: %s' % sim.step_errors(report)
+        assert ecu.authenticated and ecu.requests == [0x02, 0x03], ecu.requests
+        print('authentication: OK')
+
+        with open(os.path.join(key_dir, 'sdt_mac.key'), 'wb') as key_file:
+            key_file.write(secured_transmission_test.CMAC_KEY)
+        ecu = secured_transmission_test.SecuredEcuSimulator('aes_cmac')
+        report, errors = sim.run(tool, ecu, [secured_transmission_test.read_part_number()], extra_config={
+            "pkcs11": {"module": module, "token": "diag-test", "pin": PIN},
+            "secured_data_transmission": {"signature": "aes_cmac", "signature_key": "pkcs11:sdt_mac",
+                                          "response_signature": True}}, env=dict(os.environ, MOCK_PKCS11_DIR=key_dir,
+                                                                                 MOCK_PKCS11_PIN=PIN))
+        assert report and report['verdict'] == 'pass', 'secured failed: %s %s' % (report and sim.step_errors(report), errors)
+        assert ecu.internal == [b'\x22\xf1\x88'], ecu.internal
+        print('secured: OK')
+
+        ecu, report = volvo_sa(tool, module, key_dir, params, pin='0000')
+        assert report['verdict'] == 'fail' and not ecu.unlocked, 'wrong PIN was accepted'
+        assert 'CKR_PIN_INCORRECT' in (report['steps'][2]['error'] or ''), report['steps'][2]['error']
+        print('wrong_pin: OK')
+
+        ecu, report = volvo_sa(tool, module, key_dir, dict(params, proof_of_ownership_key="pkcs11:other"))
+        assert report['verdict'] == 'fail' and not ecu.unlocked, 'missing key was used'
+        assert 'not on the token' in (report['steps'][2]['error'] or ''), report['steps'][2]['error']
+        print('missing_key: OK')
+
+
+if __name__ == '__main__':
+    main()
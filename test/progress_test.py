@@ -0,0 +1,85 @@
+"""
+Progress bar of the CLI against a simulated DoIP ECU.
+
+The progress events of a step are drawn on one terminal line, every update starts with a carriage return:
+  - download: a bin image flashed with a swdl step draws erase, download blocks and done, percent and bytes sent
+              never go back and end at 100% of the image
+  - upload:   an ECU sending more data than requested fails the upload step, bar and percent stay in range
+
+Usage: python3 test/progress_test.py [path/to/diag_tool]
+"""
+import os
+import re
+import sys
+import tempfile
+
+import sim
+
+ADDRESS, LENGTH = 0x00080000, 300
+IMAGE = bytes((index * 13 + 5) & 0xFF for index in range(LENGTH))
+BAR = re.compile(r'\[([#-]*)\] +(\d+)% (\S+) (.+?) 0x([0-9A-F]+)/0x([0-9A-F]+) [\d.]+ kB/s')
+
+
+class OverlongUploadEcuSimulator(sim.EcuSimulator):
+    def respond(self, request):
+        response = super().respond(request)
+        if request[0] == 0x36 and self.transfer and self.transfer.get('upload'):
+            return response + bytes(8)  # more than the block the tool asked for
+        return response
+
+
+def run(tool, ecu, work_dir, sequence):
+    output_path = os.path.join(work_dir, 'output.txt')
+    with open(output_path, 'wb') as output:
+        report, errors = sim.run(tool, ecu, sequence, stdout=output)
+    assert report, errors
+    bars = []
+    for line in re.split(r'[\r\n]', open(output_path, errors='replace').read()):
+        match = BAR.search(line)
+        if match:
+            bar, percent, _, phase, sent, total = match.groups()
+            bars.append((bar, int(percent), phase, int(sent, 16), int(total, 16)))
+    assert 'panicked' not in errors, errors
+    return report, errors + ' '.join(sim.step_errors(report)), bars
+
+
+def check_range(bars):
+    for bar, percent, phase, sent, total in bars:
+        assert len(bar) == 20 and bar.count('#') == percent * 20 // 100, bar
+        assert 0 <= percent <= 100 and sent <= total, (percent, sent, total)
+
+
+def main():
+    tool = sys.argv[1] if len(sys.argv) > 1 else os.path.join('target', 'debug', 'diag_tool')
+    with tempfile.TemporaryDirectory() as work_dir:
+        image_path = os.path.join(work_dir, 'app.bin')
+        with open(image_path, 'wb') as image_file:
+            image_file.write(IMAGE)
+        ecu = sim.EcuSimulator()
+        report, errors, bars = run(tool, ecu, work_dir, [
+            {"name": "send_diag", "action": ["1002"], "expect": ["5002*"]},
+            {"name": "swdl", "timeout": "1s", "action": {"path": image_path, "format": "bin",
+                                                         "address": "0x%08X" % ADDRESS}},
+        ])
+        assert report['verdict'] == 'pass', errors
+        check_range(bars)
+        phases = [phase for _, _, phase, _, _ in bars]
+        assert phases[0].startswith('erase') and phases[-1] == 'done', phases
+        downloads = [(percent, sent) for _, percent, phase, sent, _ in bars if phase.startswith('download')]
+        assert downloads and downloads == sorted(downloads), downloads
+        assert bars[-1][1:] == (100, 'done', LENGTH, LENGTH), bars[-1]
+        print('download: OK')
+
+        ecu = OverlongUploadEcuSimulator()
+        for index in range(0x50):
+            ecu.memory[ADDRESS + index] = index
+        report, errors, bars = run(tool, ecu, work_dir, [{"name": "upload", "timeout": "1s", "action": {
+            "path": os.path.join(work_dir, 'dump.bin'), "address": "0x%08X" % ADDRESS, "length": "0x50"}}])
+        assert report['verdict'] == 'fail', 'overlong upload passed'
+        assert 'ECU sent 0x60 bytes, 0x50 were requested' in errors, errors
+        check_range(bars)
+        print('upload: OK')
+
+
+if __name__ == '__main__':
+    main()
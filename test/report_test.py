@@ -0,0 +1,98 @@
+"""
+Sequence report export (--report) in JUnit XML, JSON and HTML against a simulated DoIP ECU.
+
+The ECU answers a part number longer than the trace limit of a message, a long write request is cut in the trace:
+  - json:  ECU identification holds the whole part number and VIN, the cut request is marked with its length
+  - junit: testcase, failure and skipped counts, identification properties and the failure message
+  - html:  identification, verdicts, the cut marker and escaped descriptions
+
+Usage: python3 test/report_test.py [path/to/diag_tool]
+"""
+import os
+import sys
+import tempfile
+import time
+import xml.etree.ElementTree as ElementTree
+
+import sim
+
+PART_NUMBER = ''.join('PN%03d-' % index for index in range(20))  # 120 characters
+VIN = 'YV1LFA2D1H1234567'
+WRITE_DATA = bytes(range(100))
+WRITE_REQUEST = (b'\x2e\xf1\x98' + WRITE_DATA).hex().upper()
+
+
+class IdentificationEcuSimulator(sim.EcuSimulator):
+    def respond(self, request):
+        if request[:3] == b'\x22\xf1\x88':
+            return b'\x62\xf1\x88' + PART_NUMBER.encode()
+        if request[:3] == b'\x22\xf1\x90':
+            return b'\x62\xf1\x90' + VIN.encode()
+        if request[:3] == b'\x2e\xf1\x98':
+            return b'\x6e\xf1\x98'
+        if request[:2] == b'\x10\x02':
+            return b'\x7f\x10\x22'
+        return super().respond(request)
+
+
+def wait_for(path):
+    start = time.time()
+    while not os.path.exists(path) and time.time() < start + 5:
+        time.sleep(0.1)
+    with open(path, encoding='utf-8') as report_file:
+        return report_file.read()
+
+
+def main():
+    tool = sys.argv[1] if len(sys.argv) > 1 else os.path.join('target', 'debug', 'diag_tool')
+    with tempfile.TemporaryDirectory() as work_dir:
+        junit_path = os.path.join(work_dir, 'report.xml')
+        html_path = os.path.join(work_dir, 'report.html')
+        report, errors = sim.run(tool, IdentificationEcuSimulator(), [
+            {"name": "send_diag", "description": "read part number", "action": ["22F188"], "expect": ["62F188*"]},
+            {"name": "send_diag", "description": "read VIN", "action": ["22F190"], "expect": ["62F190*"]},
+            {"name": "send_diag", "description": "write <fingerprint> & date", "action": [WRITE_REQUEST],
+             "expect": ["6EF198"]},
+            {"name": "send_diag", "description": "programming session", "action": ["1002"], "expect": ["5002*"]},
+            {"name": "send_diag", "description": "not reached", "action": ["1001"], "expect": ["5001*"]},
+        ], extra_args=['--report', junit_path, '--report', html_path])
+        assert report, 'no report written: %s' % errors
+        junit, html = wait_for(junit_path), wait_for(html_path)
+
+    identification = {entry['did']: entry['value'] for entry in report['ecu_identification']}
+    assert identification.get('F188') == PART_NUMBER, 'part number cut: %s' % identification
+    assert identification.get('F190') == VIN, identification
+    steps = report['steps']
+    assert [step['verdict'] for step in steps[2:]] == ['pass', 'pass', 'pass', 'fail', 'skipped', 'skipped'], steps
+    write = steps[4]['exchanges'][0]
+    cut = '… (%d bytes)' % (len(WRITE_DATA) + 3)
+    assert write['request'] == WRITE_REQUEST[:128] + cut, write
+    assert write['response'] == '6EF198', write
+    assert steps[2]['exchanges'][0]['response'] == '62F188' + PART_NUMBER.encode().hex().upper(), steps[2]
+    print('json: OK')
+
+    root = ElementTree.fromstring(junit.encode())
+    suite = root.find('testsuite')
+    assert (suite.get('tests'), suite.get('failures'), suite.get('skipped')) == (str(len(steps)), '1', '2'), suite.attrib
+    properties = {prop.get('name'): prop.get('value') for prop in suite.iter('property')}
+    assert properties.get('ecu.ecuSoftwareNumber') == PART_NUMBER, properties
+    assert properties.get('ecu.VIN') == VIN, properties
+    testcases = suite.findall('testcase')
+    assert len(testcases) == len(steps), len(testcases)
+    failure = testcases[5].find('failure')
+    assert failure is not None and failure.get('message') == steps[5]['error'], steps[5]
+    assert testcases[6].find('skipped') is not None
+    assert 'write <fingerprint> & date' in testcases[4].get('name'), testcases[4].get('name')
+    assert cut in testcases[4].find('system-out').text
+    print('junit: OK')
+
+    assert PART_NUMBER in html and VIN in html, 'identification missing in html'
+    assert 'write &lt;fingerprint&gt; &amp; date' in html and '<fingerprint>' not in html
+    assert cut in html
+    assert html.count('<td class="PASS">PASS') == 5 and html.count('<td class="FAIL">FAIL') == 1, html
+    assert html.count('<td class="SKIPPED">SKIPPED') == 2
+    print('html: OK')
+
+
+if __name__ == '__main__':
+    main()
@@ -0,0 +1,168 @@
+"""
+SecuredDataTransmission (0x84) of send_diag requests marked as secured against a simulated DoIP ECU.
+
+The ECU verifies the signature and anti-replay counter of every 0x84 request, decrypts the internal message, answers
+it and wraps the internal response the same way (encrypted if the request was, signed if a signature was requested):
+  - cmac_encrypted:   AES-CMAC and AES-CTR, the DID of the internal request is never sent in clear
+  - hmac_signed:      HMAC-SHA256 with signed responses, counter increases per request
+  - per_request:      secured [false, true], only the second request is wrapped
+  - keystore_key:     signature key referenced as @name of an encrypted keystore
+  - forged_response:  ECU signs the response with another key, the step fails
+  - replayed_counter: ECU answers with an old anti-replay counter, the step fails
+  - refused:          negative response 7F 84 is reported
+  - validation:       secured list length, secured on another step and a config without keys are rejected at load
+
+Usage: python3 test/secured_transmission_test.py [path/to/diag_tool]
+"""
+import hashlib
+import hmac
+import json
+import os
+import struct
+import subprocess
+import sys
+import tempfile
+
+import sim
+from sim import aes_cmac, aes_ctr
+
+CMAC_KEY = bytes(range(16))
+HMAC_KEY = bytes(range(0x40, 0x60))
+ENCRYPTION_KEY = bytes.fromhex('2b7e151628aed2a6abf7158809cf4f3c')
+PART_NUMBER = b'32233422 AA'
+KEYSTORE_PASSWORD = 'secured-test'
+
+
+class SecuredEcuSimulator(sim.EcuSimulator):
+    def __init__(self, method, sign_key=None, counter_offset=0, refuse=False):
+        super().__init__(False)
+        self.method, self.counter_offset, self.refuse = method, counter_offset, refuse
+        self.sign_key = sign_key or (CMAC_KEY if method == 'aes_cmac' else HMAC_KEY)
+        self.verify_key = CMAC_KEY if method == 'aes_cmac' else HMAC_KEY
+        self.requests = []
+        self.internal = []
+        self.counters = []
+
+    def mac(self, key, data):
+        return aes_cmac(key, data) if self.method == 'aes_cmac' else hmac.new(key, data, hashlib.sha256).digest()
+
+    def respond(self, request):
+        self.requests.append(bytes(request))
+        if request[0] == 0x22 and request[1:3] == b'\xf1\x88':
+            return b'\x62\xf1\x88' + PART_NUMBER
+        if request[0] != 0x84:
+            return super().respond(request)
+        if self.refuse:
+            return b'\x7f\x84\x33'
+        admin, calculation, signature_length, counter = struct.unpack('>HBHH', request[1:8])
+        signed, signature = request[:len(request) - signature_length], request[len(request) - signature_length:]
+        if not admin & 0x0001 or not admin & 0x0020 or self.mac(self.verify_key, signed) != signature:
+            return b'\x7f\x84\x35'
+        if self.counters and counter <= self.counters[-1]:
+            return b'\x7f\x84\x24'
+        self.counters.append(counter)
+        internal = signed[8:]
+        if admin & 0x0010:
+            internal = aes_ctr(ENCRYPTION_KEY, internal[:16], internal[16:])
+        self.internal.append(internal)
+        inner_response = self.respond(internal)
+
+        response_admin = admin & 0x0010
+        if admin & 0x0010:
+            iv = os.urandom(16)
+            inner_response = iv + aes_ctr(ENCRYPTION_KEY, iv, inner_response)
+        response_signature_length = 0
+        if admin & 0x0040:
+            response_admin |= 0x0020
+            response_signature_length = 16 if self.method == 'aes_cmac' else 32
+        response = struct.pack('>BHBHH', 0xC4, response_admin, calculation, response_signature_length,
+                               (counter + self.counter_offset) & 0xFFFF) + inner_response
+        if admin & 0x0040:
+            response += self.mac(self.sign_key, response)
+        return response
+
+
+def run(tool, secured_settings, sequence, ecu=None, keystore=None):
+    ecu = ecu or SecuredEcuSimulator((secured_settings or {}).get('signature', 'aes_cmac'))
+    with tempfile.TemporaryDirectory() as work_dir:
+        environment, extra_args = dict(os.environ), []
+        if keystore is not None:
+            keystore_path = os.path.join(work_dir, 'keys.store')
+            for index, (name, key) in enumerate(keystore.items()):
+                create = ['--keystore-create'] if index == 0 else []
+                subprocess.run([tool, '--keystore', keystore_path] + create + ['--keystore-add', name],
+                               input='\n'.join([KEYSTORE_PASSWORD] * (len(create) + 1) + [key]) + '\n',
+                               capture_output=True, text=True, timeout=60, check=True)
+            extra_args = ['--keystore', keystore_path]
+            environment["DIAG_TOOL_KEYSTORE_PASSWORD"] = KEYSTORE_PASSWORD
+        report, errors = sim.run(tool, ecu, sequence, extra_args=extra_args, env=environment, extra_config=(
+            None if secured_settings is None else {"secured_data_transmission": secured_settings}))
+    return ecu, report, errors
+
+
+def read_part_number(secured=True, count=1):
+    return {"name": "send_diag", "action": ["22F188"] * count, "expect": ["62F188" + PART_NUMBER.hex().upper()] * count,
+            "secured": secured}
+
+
+def main():
+    tool = sys.argv[1] if len(sys.argv) > 1 else os.path.join('target', 'debug', 'diag_tool')
+    cmac_settings = {"signature": "aes_cmac", "signature_key": "0x" + CMAC_KEY.hex(),
+                     "encryption_key": "0x" + ENCRYPTION_KEY.hex(), "response_signature": True}
+    hmac_settings = {"signature": "hmac_sha256", "signature_key": HMAC_KEY.hex(), "response_signature": True,
+                     "signature_encryption_calculation": "0x01"}
+
+    ecu, report, errors = run(tool, cmac_settings, [read_part_number()])
+    assert report and report['verdict'] == 'pass', 'cmac_encrypted failed: %s %s' % (report and sim.step_errors(report), errors)
+    assert ecu.internal == [b'\x22\xf1\x88'], ecu.internal
+    assert not [request for request in ecu.requests if request[0] == 0x84 and b'\x22\xf1\x88' in request], \
+        'internal request was sent in clear'
+    print('cmac_encrypted: OK')
+
+    ecu, report, errors = run(tool, hmac_settings, [read_part_number(count=2)])
+    assert report and report['verdict'] == 'pass', 'hmac_signed failed: %s %s' % (report and sim.step_errors(report), errors)
+    assert ecu.counters == [1, 2], ecu.counters
+    assert all(request[3] == 0x01 for request in ecu.requests if request[0] == 0x84), 'signatureEncryptionCalculation'
+    print('hmac_signed: OK')
+
+    ecu, report, errors = run(tool, hmac_settings, [read_part_number(secured=[False, True], count=2)])
+    assert report and report['verdict'] == 'pass', 'per_request failed: %s %s' % (report and sim.step_errors(report), errors)
+    assert [request[0] for request in ecu.requests] == [0x22, 0x84, 0x22], ecu.requests
+    print('per_request: OK')
+
+    ecu, report, errors = run(tool, dict(cmac_settings, signature_key="@secured_mac"), [read_part_number()],
+                              keystore={"secured_mac": CMAC_KEY.hex()})
+    assert report and report['verdict'] == 'pass', 'keystore_key failed: %s %s' % (report and sim.step_errors(report), errors)
+    print('keystore_key: OK')
+
+    ecu, report, errors = run(tool, cmac_settings, [read_part_number()],
+                              SecuredEcuSimulator('aes_cmac', sign_key=bytes(16)))
+    assert report and report['verdict'] == 'fail', 'forged response was accepted'
+    assert 'SecuredDataTransmission response signature is wrong' in (sim.step_errors(report)[2] or ''), sim.step_errors(report)
+    print('forged_response: OK')
+
+    ecu, report, errors = run(tool, hmac_settings, [read_part_number()],
+                              SecuredEcuSimulator('hmac_sha256', counter_offset=-1))
+    assert report and report['verdict'] == 'fail', 'replayed counter was accepted'
+    assert 'anti-replay counter 0x0000 does not match request 0x0001' in (sim.step_errors(report)[2] or ''), sim.step_errors(report)
+    print('replayed_counter: OK')
+
+    ecu, report, errors = run(tool, hmac_settings, [read_part_number()], SecuredEcuSimulator('hmac_sha256', refuse=True))
+    assert report and report['verdict'] == 'fail', 'negative response was accepted'
+    assert 'SecuredDataTransmission refused' in (sim.step_errors(report)[2] or ''), sim.step_errors(report)
+    print('refused: OK')
+
+    for settings, sequence, message in [
+        (hmac_settings, [read_part_number(secured=[True, False])], 'send_diag: secured has 2 entries for 1 requests'),
+        (hmac_settings, [{"name": "delay", "action": "wait", "secured": True}], 'delay: unknown field `secured`'),
+        ({"signature": "aes_cmac"}, [read_part_number()], 'secured_data_transmission: signature AesCmac needs signature_key'),
+        (None, [read_part_number()], 'secured requests need a "secured_data_transmission" section in config'),
+    ]:
+        ecu, report, errors = run(tool, settings, sequence)
+        assert not [request for request in ecu.requests if request[0] == 0x84], 'secured request was sent'
+        assert message in errors + json.dumps(report and sim.step_errors(report)), (message, errors)
+    print('validation: OK')
+
+
+if __name__ == '__main__':
+    main()
@@ -0,0 +1,101 @@
+"""
+SecurityAccess attempt counter and delay timer against a simulated DoIP ECU.
+
+The ECU allows 3 wrong keys: invalidKey (0x35) with the remaining attempts appended, then exceededNumberOfAttempts
+(0x36) and requiredTimeDelayNotExpired (0x37) to requestSeed until its delay timer expires:
+  - remaining:     wrong key, the step error reports the attempts remaining on the ECU
+  - attempt_limit: max_attempts 2, wrong key in the sequence then twice as CLI command, only 2 keys reach the ECU
+  - no_wait:       delay timer running at start, the step fails with requiredTimeDelayNotExpired
+  - wait:          wait_delay covers the delay timer, requestSeed is repeated until the ECU unlocks
+  - wait_bounded:  delay timer longer than wait_delay, the step gives up after wait_delay
+  - validation:    wait_delay above the upper bound and max_attempts 0 are rejected at load
+
+Usage: python3 test/security_attempts_test.py [path/to/diag_tool]
+"""
+import json
+import os
+import sys
+import time
+
+import seed_key_test
+
+SECRET = bytes.fromhex('A5C3')
+
+
+class AttemptCounterEcuSimulator(seed_key_test.SeedKeyEcuSimulator):
+    def __init__(self, delay=0.0, attempts=3):
+        super().__init__('xor', SECRET)
+        self.delay, self.attempts = delay, attempts
+        self.delay_until = time.time() + delay  # ISO 14229 delay timer may run after power up
+        self.failed = 0
+        self.seed_requests = 0
+        self.keys = 0
+
+    def respond(self, request):
+        if request[0] != 0x27:
+            return super().respond(request)
+        if request[1] % 2:
+            self.seed_requests += 1
+            if time.time() < self.delay_until:
+                return b'\x7f\x27\x37'
+            return super().respond(request)
+        self.keys += 1
+        response = super().respond(request)
+        if response[0] == 0x67:
+            self.failed = 0
+            return response
+        self.failed += 1
+        if self.failed >= self.attempts:
+            self.failed, self.delay_until = 0, time.time() + self.delay
+            return b'\x7f\x27\x36'
+        return b'\x7f\x27\x35' + bytes([self.attempts - self.failed])
+
+
+def run(tool, params, ecu, commands=()):
+    return seed_key_test.run(tool, 'xor', dict({"algorithm": "xor"}, **params), SECRET, ecu=ecu, commands=commands)
+
+
+def errors(report):
+    return [step['error'] or '' for step in report['steps']]
+
+
+def main():
+    tool = sys.argv[1] if len(sys.argv) > 1 else os.path.join('target', 'debug', 'diag_tool')
+    wrong = {"secret": "0x0000"}
+
+    ecu, report = run(tool, wrong, AttemptCounterEcuSimulator())
+    assert report['verdict'] == 'fail' and ecu.keys == 1, errors(report)
+    assert 'invalidKey (0x35), ECU reports 2 attempts remaining' in errors(report)[2], errors(report)
+    print('remaining: OK')
+
+    command = 'securityaccess_01:' + json.dumps({"algorithm": "xor", "secret": "0x0000", "max_attempts": 2})
+    ecu, report = run(tool, dict(wrong, max_attempts=2), AttemptCounterEcuSimulator(), commands=[command, command])
+    assert ecu.keys == 2 and not ecu.unlocked, 'ECU got %d keys' % ecu.keys
+    assert 'attempt limit 2 reached' in ecu.errors, ecu.errors
+    print('attempt_limit: OK')
+
+    ecu, report = run(tool, {"secret": "0xA5C3"}, AttemptCounterEcuSimulator(delay=2.0))
+    assert report['verdict'] == 'fail' and ecu.seed_requests == 1, errors(report)
+    assert 'requiredTimeDelayNotExpired (0x37), delay timer of the ECU is running' in errors(report)[2], errors(report)
+    print('no_wait: OK')
+
+    ecu, report = run(tool, {"secret": "0xA5C3", "wait_delay": "5s"}, AttemptCounterEcuSimulator(delay=2.0))
+    assert report['verdict'] == 'pass', errors(report)
+    assert ecu.unlocked == [0x01] and ecu.seed_requests > 1, ecu.seed_requests
+    print('wait: OK')
+
+    start = time.time()
+    ecu, report = run(tool, {"secret": "0xA5C3", "wait_delay": "2s"}, AttemptCounterEcuSimulator(delay=20.0))
+    assert report['verdict'] == 'fail' and not ecu.unlocked, errors(report)
+    assert time.time() - start < 15 and 'requiredTimeDelayNotExpired' in errors(report)[2], errors(report)
+    print('wait_bounded: OK')
+
+    for invalid, message in [({"wait_delay": "11m"}, 'wait_delay "11m" is longer than 10 minutes'),
+                             ({"max_attempts": "0"}, 'max_attempts should be at least 1')]:
+        ecu, report = run(tool, dict({"secret": "0xA5C3"}, **invalid), AttemptCounterEcuSimulator())
+        assert not ecu.seed_requests and message in ecu.errors, ecu.errors
+    print('validation: OK')
+
+
+if __name__ == '__main__':
+    main()
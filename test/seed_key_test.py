@@ -0,0 +1,123 @@
+"""
+Classic SecurityAccess (0x27 requestSeed/sendKey) with seed/key algorithms against a simulated DoIP ECU.
+
+The ECU sends a fresh seed per level and unlocks only if the key matches its own calculation:
+  - xor, mask, crc16, crc32: built-in algorithms with a hex secret
+  - library:                 GenerateKeyEx of a shared library compiled from C (skipped without a C compiler)
+  - wrong_key:               wrong secret, the ECU answers invalidKey and the step fails
+
+Usage: python3 test/seed_key_test.py [path/to/diag_tool]
+"""
+import os
+import shutil
+import struct
+import subprocess
+import sys
+import tempfile
+import zlib
+
+import sim
+
+LIBRARY_SOURCE = r'''
+int GenerateKeyEx(const unsigned char *seed, unsigned int seed_size, unsigned int level, const char *variant,
+                  unsigned char *key, unsigned int max_key_size, unsigned int *key_size)
+{
+    unsigned int index;
+    unsigned char salt = 0;
+    while (*variant) salt += (unsigned char)*variant++;
+    if (seed_size > max_key_size) return 1;
+    for (index = 0; index < seed_size; index++) key[index] = (unsigned char)(seed[index] + level + salt);
+    *key_size = seed_size;
+    return 0;
+}
+'''
+
+
+def crc16(data):
+    crc = 0xFFFF
+    for byte in data:
+        crc ^= byte << 8
+        for _ in range(8):
+            crc = ((crc << 1) ^ 0x1021 if crc & 0x8000 else crc << 1) & 0xFFFF
+    return struct.pack('>H', crc)
+
+
+def mask(seed, secret):
+    key, mask_value = struct.unpack('>I', seed)[0], struct.unpack('>I', secret)[0]
+    for _ in range(35):
+        key = ((key << 1) ^ mask_value if key & 0x80000000 else key << 1) & 0xFFFFFFFF
+    return struct.pack('>I', key)
+
+
+ALGORITHMS = {
+    'xor': lambda seed, secret, level: bytes(byte ^ secret[index % len(secret)] for index, byte in enumerate(seed)),
+    'mask': lambda seed, secret, level: mask(seed, secret),
+    'crc16': lambda seed, secret, level: crc16(seed + secret),
+    'crc32': lambda seed, secret, level: struct.pack('>I', zlib.crc32(seed + secret)),
+    'library': lambda seed, secret, level: bytes((byte + level + sum(b'v2')) & 0xFF for byte in seed),
+}
+
+
+class SeedKeyEcuSimulator(sim.EcuSimulator):
+    def __init__(self, algorithm, secret):
+        super().__init__(refuse_resume=False)
+        self.algorithm, self.secret = algorithm, secret
+        self.seed = None
+        self.unlocked = []
+
+    def respond(self, request):
+        if request[0] != 0x27:
+            return super().respond(request)
+        level = request[1]
+        if level % 2:
+            self.seed = os.urandom(4)
+            return bytes([0x67, level]) + self.seed
+        expected = ALGORITHMS[self.algorithm](self.seed, self.secret, level - 1)
+        if self.seed is None or request[2:] != expected:
+            return b'\x7f\x27\x35'
+        self.unlocked.append(level - 1)
+        return bytes([0x67, level])
+
+
+def run(tool, algorithm, params, secret, level=0x01, extra_args=(), env=None, ecu=None, commands=()):
+    """commands are typed on the CLI after the sequence, stderr of the tool is kept in ecu.errors"""
+    ecu = ecu or SeedKeyEcuSimulator(algorithm, secret)
+    report, ecu.errors = sim.run(tool, ecu, [{"name": "securityaccess_%02X" % level, "action": params}],
+                                 vendor='generic', extra_args=extra_args, env=env, commands=commands)
+    return ecu, report
+
+
+def check(tool, algorithm, params, secret, level=0x01):
+    ecu, report = run(tool, algorithm, params, secret, level)
+    assert report['verdict'] == 'pass', '%s failed: %s' % (algorithm, [step['error'] for step in report['steps']])
+    assert ecu.unlocked == [level], '%s did not unlock level %02X' % (algorithm, level)
+    print('%s: OK' % algorithm)
+
+
+def main():
+    tool = sys.argv[1] if len(sys.argv) > 1 else os.path.join('target', 'debug', 'diag_tool')
+    check(tool, 'xor', {"algorithm": "xor", "secret": "0xA5C3"}, bytes.fromhex('A5C3'))
+    check(tool, 'mask', {"algorithm": "mask", "secret": "0x12345678"}, bytes.fromhex('12345678'), level=0x11)
+    check(tool, 'crc16', {"algorithm": "crc16", "secret": "0xCAFE"}, bytes.fromhex('CAFE'))
+    check(tool, 'crc32', {"algorithm": "crc32"}, b'')
+
+    compiler = shutil.which('cc')
+    if compiler is None:
+        print('library: SKIPPED, no C compiler')
+    else:
+        with tempfile.TemporaryDirectory() as build_dir:
+            source_path = os.path.join(build_dir, 'seedkey.c')
+            library_path = os.path.join(build_dir, 'libseedkey.so')
+            with open(source_path, 'w') as source_file:
+                source_file.write(LIBRARY_SOURCE)
+            subprocess.check_call([compiler, '-shared', '-fPIC', '-o', library_path, source_path])
+            check(tool, 'library', {"algorithm": "library", "library": library_path, "variant": "v2"}, b'', level=0x03)
+
+    ecu, report = run(tool, 'xor', {"algorithm": "xor", "secret": "0x0000"}, bytes.fromhex('A5C3'))
+    assert report['verdict'] == 'fail' and not ecu.unlocked, 'wrong key was accepted'
+    assert 'sendKey' in (report['steps'][2]['error'] or ''), report['steps'][2]['error']
+    print('wrong_key: OK')
+
+
+if __name__ == '__main__':
+    main()
@@ -0,0 +1,79 @@
+"""
+Abort of a running sequence with Ctrl-C against a simulated DoIP ECU.
+
+The sequence waits in a long delay when Ctrl-C is pressed, its cleanup block waits for the ECU and resets it:
+  - cleanup:  remaining items are skipped, the cleanup delay is waited for and ECUReset 11 01 reaches the ECU
+  - repeated: Ctrl-C pressed again during the cleanup delay is ignored with a warning, the cleanup still resets the ECU
+  - no_abort: without Ctrl-C the cleanup block is not executed
+
+Usage: python3 test/sequence_abort_test.py [path/to/diag_tool]
+"""
+import os
+import sys
+import time
+
+import sim
+
+CLEANUP_DELAY = 1.0
+CLEANUP = [
+    {"name": "delay", "description": "wait before reset", "timeout": "%dms" % (CLEANUP_DELAY * 1000)},
+    {"name": "send_diag", "description": "reset ECU", "action": ["1101"], "expect": ["5101"]},
+]
+
+
+class ResetEcuSimulator(sim.EcuSimulator):
+    def __init__(self):
+        super().__init__()
+        self.requests = []  # (time, request)
+
+    def respond(self, request):
+        self.requests.append((time.time(), bytes(request)))
+        if request[:2] == b'\x11\x01':
+            return b'\x51\x01'
+        return super().respond(request)
+
+    def resets(self):
+        return [at for at, request in self.requests if request[:2] == b'\x11\x01']
+
+
+def run(tool, abort_after):
+    ecu = ResetEcuSimulator()
+    start = time.time()
+    report, errors = sim.run(tool, ecu, [
+        {"name": "send_diag", "action": ["1003"], "expect": ["5003*"]},
+        {"name": "delay", "description": "long wait", "timeout": "3s"},
+        {"name": "send_diag", "action": ["1002"], "expect": ["5002*"]},
+    ], cleanup=CLEANUP, abort_after=abort_after)
+    return ecu, report, errors, start
+
+
+def main():
+    tool = sys.argv[1] if len(sys.argv) > 1 else os.path.join('target', 'debug', 'diag_tool')
+
+    ecu, report, errors, start = run(tool, abort_after=1.0)
+    assert report and report['verdict'] != 'pass', 'aborted sequence passed: %s' % errors
+    steps = [(step['name'], step['verdict']) for step in report['steps']]
+    assert ('send_diag', 'skipped') in steps, steps
+    assert steps[-2:] == [('delay', 'pass'), ('send_diag', 'pass')], 'cleanup did not pass: %s %s' % (
+        steps, sim.step_errors(report))
+    assert b'\x10\x02' not in [request[:2] for _, request in ecu.requests], 'item after abort was executed'
+    resets = ecu.resets()
+    assert len(resets) == 1, 'ECUReset did not reach the ECU: %s' % ecu.requests
+    assert resets[0] - start >= 1.0 + CLEANUP_DELAY - 0.2, 'cleanup delay was not waited for'
+    assert resets[0] - start < 3.0, 'abort waited for the whole sequence delay'
+    print('cleanup: OK')
+
+    ecu, report, errors, start = run(tool, abort_after=[1.0, 1.0 + CLEANUP_DELAY / 2])
+    assert report, 'second Ctrl-C stopped the tool during cleanup: %s' % errors
+    assert 'Ctrl-C is ignored until its cleanup is done' in errors, errors
+    assert report['steps'][-1]['verdict'] == 'pass' and len(ecu.resets()) == 1, 'cleanup did not reset the ECU'
+    print('repeated: OK')
+
+    ecu, report, errors, _ = run(tool, abort_after=None)
+    assert report and report['verdict'] == 'pass', 'sequence failed: %s %s' % (sim.step_errors(report), errors)
+    assert not ecu.resets(), 'cleanup was executed without abort'
+    print('no_abort: OK')
+
+
+if __name__ == '__main__':
+    main()
@@ -0,0 +1,85 @@
+"""
+JSON, YAML and TOML sequence files and --convert.
+
+  - round_trip:  json/sequence.json converted JSON -> YAML -> TOML -> JSON equals its JSON conversion, comments of the
+                 YAML and TOML files are ignored
+  - run:         the same sequence converted to .yaml, .yml and .toml sends the same requests to a simulated ECU
+  - unknown_ext: --convert writes JSON to a file with an unknown extension and such a file is loaded as JSON
+
+Usage: python3 test/sequence_format_test.py [path/to/diag_tool]
+"""
+import json
+import os
+import subprocess
+import sys
+import tempfile
+
+import sim
+
+SEQUENCE_JSON = os.path.join(os.path.dirname(os.path.abspath(__file__)), '..', 'json', 'sequence.json')
+
+
+class RecordingEcuSimulator(sim.EcuSimulator):
+    def __init__(self):
+        super().__init__()
+        self.requests = []
+
+    def respond(self, request):
+        self.requests.append(bytes(request))
+        return super().respond(request)
+
+
+def convert(tool, input_path, output_path, comment=None):
+    result = subprocess.run([tool, '--convert', input_path, '--output', output_path],
+                            capture_output=True, text=True, timeout=30)
+    assert result.returncode == 0 and 'Converted' in result.stdout, (input_path, output_path, result.stderr)
+    if comment is not None:
+        with open(output_path) as output_file:
+            contents = output_file.read()
+        with open(output_path, 'w') as output_file:
+            output_file.write(comment + '\n' + contents)
+    return output_path
+
+
+def main():
+    tool = sys.argv[1] if len(sys.argv) > 1 else os.path.join('target', 'debug', 'diag_tool')
+    with tempfile.TemporaryDirectory() as work_dir:
+        path = lambda name: os.path.join(work_dir, name)
+        # first conversion writes the canonical form: defaults filled in, parameter lists as objects
+        original = json.load(open(convert(tool, SEQUENCE_JSON, path('original.json'))))
+        convert(tool, path('original.json'), path('sequence.yaml'), comment='# converted from json/sequence.json')
+        convert(tool, path('sequence.yaml'), path('sequence.toml'), comment='# converted from sequence.yaml')
+        convert(tool, path('sequence.toml'), path('round_trip.json'))
+        assert json.load(open(path('round_trip.json'))) == original, 'JSON -> YAML -> TOML -> JSON differs'
+        assert len(original['sequence']) == len(json.load(open(SEQUENCE_JSON))['sequence'])
+        print('round_trip: OK')
+
+        sequence = [
+            {"name": "send_diag", "description": "programming session", "action": ["1002"], "expect": ["5002*"]},
+            {"name": "delay", "action": "settle", "timeout": "10ms"},
+            {"name": "send_diag", "action": ["1003", "3E00"], "expect": ["5003*", "7E00"]},
+        ]
+        ecu = RecordingEcuSimulator()
+        report, errors = sim.run(tool, ecu, sequence)
+        assert report and report['verdict'] == 'pass', errors
+        for converted_file in ['sequence.yaml', 'sequence.yml', 'sequence.toml', 'sequence.seq']:
+            converted_ecu = RecordingEcuSimulator()
+            report, errors = sim.run(tool, converted_ecu, sequence, converted_file=converted_file)
+            assert report and report['verdict'] == 'pass' and report['sequence'].endswith(converted_file), \
+                (converted_file, errors)
+            assert [step['name'] for step in report['steps']] == \
+                ['socket', 'send_doip', 'send_diag', 'delay', 'send_diag', 'socket'], (converted_file, report['steps'])
+            assert converted_ecu.requests == ecu.requests, (converted_file, converted_ecu.requests, ecu.requests)
+        print('run: OK')
+
+        convert(tool, path('sequence.toml'), path('sequence.txt'))
+        assert json.load(open(path('sequence.txt'))) == original, 'unknown extension is not written as JSON'
+        with open(path('sequence.txt')) as text_file, open(path('sequence.conf'), 'w') as conf_file:
+            conf_file.write(text_file.read())
+        convert(tool, path('sequence.conf'), path('from_conf.json'))
+        assert json.load(open(path('from_conf.json'))) == original, 'unknown extension is not read as JSON'
+        print('unknown_ext: OK')
+
+
+if __name__ == '__main__':
+    main()
@@ -0,0 +1,317 @@
+"""
+DoIP ECU simulator and diag_tool runner shared by the test-cases.
+
+EcuSimulator answers routing activation, DiagnosticSessionControl, TesterPresent, eraseMemory, RequestDownload,
+TransferData and RequestTransferExit with memory kept in a dict. Test-cases subclass it and override respond(request)
+for the services they check, requests they do not handle go to super().respond(request).
+VolvoSaEcuSimulator adds the volvo SecurityAccess AES exchange with its AES-CTR/CMAC reference.
+
+run() writes config and sequence to a temporary directory, runs the sequence between connect/activation and disconnect
+and returns the report (None if the tool stopped before writing it) with the stderr output of the tool.
+flash() runs a swdl step after the programming session, expect_rejected() checks that it fails before the ECU is
+erased or written. verify_vbf()/expect_vbf_rejected() check vbf files offline with --verify-vbf.
+"""
+import json
+import os
+import signal
+import socket
+import struct
+import subprocess
+import tempfile
+import threading
+import time
+
+from cryptography.hazmat.primitives import cmac
+from cryptography.hazmat.primitives.ciphers import Cipher, algorithms, modes
+
+TESTER, ECU, SGA = 0x0e80, 0x1d88, 0x1d24
+MAX_BLOCK_LENGTH = 0x42  # 64 data bytes per TransferData
+FAIL_HANDLER = {"send_diag": {"name": "send_diag", "action": ["1001"], "expect": ["5001*"]}}
+
+
+def doip(payload_type, payload):
+    return struct.pack('>BBHI', 2, 0xfd, payload_type, len(payload)) + payload
+
+
+def address_and_length(request):
+    """memoryAddress and memorySize after an addressAndLengthFormatIdentifier"""
+    address_len, length_len = request[0] & 0x0F, request[0] >> 4
+    address = int.from_bytes(request[1:1 + address_len], 'big')
+    return address, int.from_bytes(request[1 + address_len:1 + address_len + length_len], 'big')
+
+
+class EcuSimulator:
+    def __init__(self, refuse_resume=False, drop_after_blocks=None):
+        self.refuse_resume = refuse_resume
+        self.drop_after_blocks = drop_after_blocks  # TCP connection is closed once before this TransferData
+        self.memory = {}
+        self.erase_count = 0
+        self.connection_count = 0
+        self.transfer = None
+        self.blocks = 0
+        self.is_dropped = False
+        self.server = socket.socket()
+        self.server.setsockopt(socket.SOL_SOCKET, socket.SO_REUSEADDR, 1)
+        self.server.bind(('127.0.0.1', 0))
+        self.server.listen()
+        self.port = self.server.getsockname()[1]
+        threading.Thread(target=self.serve, daemon=True).start()
+
+    def serve(self):
+        while True:
+            conn, _ = self.server.accept()
+            self.connection_count += 1
+            self.handle(conn)
+            conn.close()
+
+    def handle(self, conn):
+        buf = b''
+        while True:
+            try:
+                data = conn.recv(65536)
+            except OSError:
+                return
+            if not data:
+                return
+            buf += data
+            while len(buf) >= 8:
+                _, _, payload_type, length = struct.unpack('>BBHI', buf[:8])
+                if len(buf) < 8 + length:
+                    break
+                payload, buf = buf[8:8 + length], buf[8 + length:]
+                if payload_type == 0x0005:
+                    code = self.activation_response()
+                    conn.sendall(doip(0x0006, struct.pack('>HH', TESTER, SGA) + bytes([code]) + b'\x00' * 4))
+                elif payload_type == 0x8001:
+                    request = payload[4:]
+                    if request[0] == 0x36 and not self.is_dropped and self.blocks == self.drop_after_blocks:
+                        self.is_dropped = True
+                        return  # connection lost before TransferData is acknowledged
+                    # ACK and response in one TCP write, the tool has to split DoIP messages itself
+                    reply = doip(0x8002, struct.pack('>HH', ECU, TESTER) + b'\x00')
+                    response = self.respond(request)
+                    if response is not None:
+                        reply += doip(0x8001, struct.pack('>HH', ECU, TESTER) + response)
+                    conn.sendall(reply)
+
+    def activation_response(self):
+        """routing activation response code, 0x10 is successful"""
+        return 0x10
+
+    def respond(self, request):
+        sid = request[0]
+        if sid == 0x10:
+            return bytes([0x50, request[1], 0x00, 0x32, 0x01, 0xF4])
+        if sid == 0x3E:
+            return None if request[1] & 0x80 else b'\x7e\x00'
+        if sid == 0x31 and request[2:4] == b'\xff\x00':
+            self.erase_count += 1
+            start, length = struct.unpack('>II', request[4:12])
+            for address in range(start, start + length):
+                self.memory.pop(address, None)
+            return b'\x71\x01\xff\x00\x00'
+        if sid == 0x31:
+            return b'\x71' + request[1:4] + b'\x10\x00'
+        if sid == 0x34:
+            address, length = struct.unpack('>II', request[3:11])
+            if self.refuse_resume and self.is_dropped and self.erase_count == 1:
+                return b'\x7f\x34\x70'
+            self.transfer = {'address': address, 'end': address + length, 'counter': 1}
+            return bytes([0x74, 0x20]) + struct.pack('>H', MAX_BLOCK_LENGTH)
+        if sid == 0x35:
+            address, length = address_and_length(request[2:])
+            self.transfer = {'address': address, 'end': address + length, 'counter': 1, 'upload': True}
+            return bytes([0x75, 0x20]) + struct.pack('>H', MAX_BLOCK_LENGTH)
+        if sid == 0x36:
+            if self.transfer is None:
+                return b'\x7f\x36\x24'
+            if request[1] != self.transfer['counter']:
+                return b'\x7f\x36\x73'
+            if self.transfer.get('upload'):
+                length = min(MAX_BLOCK_LENGTH - 2, self.transfer['end'] - self.transfer['address'])
+                data = self.read(self.transfer['address'], length)
+                self.transfer['address'] += length
+                self.transfer['counter'] = (self.transfer['counter'] + 1) & 0xFF
+                self.blocks += 1
+                return bytes([0x76, request[1]]) + data
+            data = request[2:]
+            for index, byte in enumerate(data):
+                self.memory[self.transfer['address'] + index] = byte
+            self.transfer['address'] += len(data)
+            self.transfer['counter'] = (self.transfer['counter'] + 1) & 0xFF
+            self.blocks += 1
+            return bytes([0x76, request[1]])
+        if sid == 0x37:
+            if self.transfer is None:
+                return b'\x7f\x37\x24'
+            self.transfer = None
+            return b'\x77'
+        if sid == 0x23:
+            address, length = address_and_length(request[1:])
+            return b'\x63' + self.read(address, length)
+        return b'\x7f' + bytes([sid]) + b'\x11'
+
+    def read(self, address, length):
+        return bytes(self.memory.get(address + index, 0xFF) for index in range(length))
+
+
+def aes_cmac(key, data):
+    mac = cmac.CMAC(algorithms.AES(key))
+    mac.update(data)
+    return mac.finalize()
+
+
+def aes_ctr(key, iv, data):
+    """AES-CTR with a 64-bit little endian counter in the first 8 bytes of the iv"""
+    counter = int.from_bytes(iv[:8], 'little')
+    blocks = b''.join(((counter + index) % 2 ** 64).to_bytes(8, 'little') + iv[8:]
+                      for index in range((len(data) + 15) // 16))
+    encryptor = Cipher(algorithms.AES(key), modes.ECB()).encryptor()
+    key_stream = encryptor.update(blocks) + encryptor.finalize()
+    return bytes(byte ^ key_byte for byte, key_byte in zip(data, key_stream))
+
+
+class VolvoSaEcuSimulator(EcuSimulator):
+    """SecurityAccess AES exchange: requestSeed message 0x0001, seed 0x0002, sendKey 0x0003"""
+
+    def __init__(self, encryption_key, proof_key, seed_fault=None):
+        super().__init__(refuse_resume=False)
+        self.encryption_key, self.proof_key = encryption_key, proof_key
+        self.seed_fault = seed_fault
+        self.randoms = None
+        self.requests = []
+        self.unlocked = []
+
+    def respond(self, request):
+        if request[0] != 0x27:
+            return super().respond(request)
+        self.requests.append(request)
+        level = request[1]
+        if aes_cmac(self.encryption_key, request[:-16]) != request[-16:]:
+            return b'\x7f\x27\x35'
+        header_length = 6 if level % 2 else 4  # requestSeed also carries the authentication method
+        iv, encrypted = request[header_length:header_length + 16], request[header_length + 16:-16]
+        if level % 2:
+            client_random, server_random = aes_ctr(self.encryption_key, iv, encrypted), os.urandom(16)
+            self.randoms = client_random + server_random
+            server_iv = os.urandom(16)
+            proof = aes_cmac(self.proof_key, server_random + client_random)
+            payload = bytes([0x67, level, 0x00, 0x02]) + server_iv + aes_ctr(self.encryption_key, server_iv,
+                                                                             server_random + proof)
+            authentication_code = aes_cmac(self.encryption_key, payload)
+            if self.seed_fault == 'short':
+                return payload[:30]
+            if self.seed_fault == 'mac':
+                authentication_code = bytes(16)
+            return payload + authentication_code
+        if self.randoms is None or aes_ctr(self.encryption_key, iv, encrypted) != aes_cmac(self.proof_key, self.randoms):
+            return b'\x7f\x27\x35'
+        self.unlocked.append(level - 1)
+        return bytes([0x67, level])
+
+
+def write_config(work_dir, port, vendor='volvo', extra_config=None):
+    config_path = os.path.join(work_dir, 'config.json')
+    with open(config_path, 'w') as config_file:
+        json.dump(dict({
+            "ethernet": {"interface": "lo", "local_ipv4": "127.0.0.1", "remote_ip": "127.0.0.1",
+                         "remote_port": str(port), "role": "client", "vendor": vendor},
+            "doip": {"version": "0x2", "inverse_version": "0xfd", "tester_addr": "0x0e80",
+                     "ecu_addr": "0x1d88", "sga_addr": "0x1d24", "activation_code": "0"},
+            "parameter": {"vin": "", "tester_present": False, "tester_present_interval": "1000ms"},
+        }, **(extra_config or {})), config_file)
+    return config_path
+
+
+def run(tool, ecu, sequence, vendor='volvo', extra_config=None, extra_args=(), env=None, commands=(), timeout=30,
+        cleanup=None, abort_after=None, stdout=subprocess.DEVNULL, converted_file=None):
+    """commands are typed on the CLI after the report is written, abort_after presses Ctrl-C after so many seconds
+    (a list of seconds presses it several times),
+    stdout takes the output of the tool (progress bar, command results),
+    converted_file runs the sequence converted to this file name with --convert (sequence.yaml, sequence.toml...)"""
+    with tempfile.TemporaryDirectory() as work_dir:
+        config_path = write_config(work_dir, ecu.port, vendor, extra_config)
+        sequence_path = os.path.join(work_dir, 'sequence.json')
+        report_path = os.path.join(work_dir, 'report.json')
+        with open(sequence_path, 'w') as sequence_file:
+            json.dump({"sequence": [
+                {"name": "socket", "action": "connect"},
+                {"name": "send_doip", "action": "activation"},
+            ] + sequence + [
+                {"name": "socket", "action": "disconnect"},
+            ], "fail_handler": FAIL_HANDLER, "cleanup": cleanup or []}, sequence_file)
+        if converted_file:
+            converted_path = os.path.join(work_dir, converted_file)
+            subprocess.run([tool, '--convert', sequence_path, '--output', converted_path], capture_output=True, timeout=30)
+            sequence_path = converted_path
+
+        process = subprocess.Popen([tool, '--config', config_path, '--sequence', sequence_path,
+                                    '--report', report_path] + list(extra_args), stdin=subprocess.PIPE,
+                                   stdout=stdout, stderr=subprocess.PIPE, env=env)
+        presses = sorted(abort_after if isinstance(abort_after, (list, tuple)) else
+                         [] if abort_after is None else [abort_after])
+        start = time.time()
+        while not os.path.exists(report_path) and process.poll() is None and time.time() < start + timeout:
+            if presses and time.time() >= start + presses[0]:
+                process.send_signal(signal.SIGINT)
+                presses.pop(0)
+            time.sleep(0.2)
+        time.sleep(0.2)
+        for command in commands:
+            process.stdin.write((command + '\n').encode())
+            process.stdin.flush()
+            time.sleep(1)
+        process.kill()
+        errors = process.communicate()[1].decode(errors='replace')
+        if not os.path.exists(report_path):
+            return None, errors
+        with open(report_path) as report_file:
+            report = json.load(report_file)
+    return report, errors
+
+
+def step_errors(report):
+    return [step['error'] or '' for step in report['steps']]
+
+
+def flash(tool, ecu, action, image=None, extra_config=None):
+    """programming session and a swdl step with action. image (bytes) is written to a temporary file named like
+    action["path"] (app.bin if not set), errors also hold the step errors of the report"""
+    with tempfile.TemporaryDirectory() as work_dir:
+        if image is not None:
+            action = dict(action, path=os.path.join(work_dir, os.path.basename(action.get('path', 'app.bin'))))
+            with open(action['path'], 'wb') as image_file:
+                image_file.write(image)
+        report, errors = run(tool, ecu, [
+            {"name": "send_diag", "action": ["1002"], "expect": ["5002*"]},
+            {"name": "swdl", "timeout": "1s", "action": action},
+        ], extra_config=extra_config)
+    return report, errors + ' '.join(report and step_errors(report) or [])
+
+
+def expect_rejected(tool, ecu, action, message, name, image=None, extra_config=None):
+    """flash fails with message before anything is erased or downloaded"""
+    report, errors = flash(tool, ecu, action, image, extra_config)
+    assert report and report['verdict'] == 'fail', '%s was flashed' % name
+    assert message in errors, (name, message, errors)
+    assert ecu.erase_count == 0 and ecu.blocks == 0, '%s: ECU was erased or written before the step failed' % name
+
+
+def verify_vbf(tool, work_dir, vbf, extra_config=None):
+    """--verify-vbf without ECU, vbf is a path or the file contents (bytes, written to app.vbf)"""
+    if isinstance(vbf, bytes):
+        vbf_path = os.path.join(work_dir, 'app.vbf')
+        with open(vbf_path, 'wb') as vbf_file:
+            vbf_file.write(vbf)
+    else:
+        vbf_path = vbf
+    config_path = write_config(work_dir, 13400, extra_config=extra_config)
+    return subprocess.run([tool, '--config', config_path, '--verify-vbf', vbf_path],
+                          capture_output=True, text=True, timeout=60)
+
+
+def expect_vbf_rejected(tool, work_dir, vbf, message, name, extra_config=None):
+    """--verify-vbf fails with message"""
+    result = verify_vbf(tool, work_dir, vbf, extra_config)
+    assert result.returncode != 0, '%s was accepted: %s' % (name, result.stdout)
+    assert message in result.stderr, (name, message, result.stderr)
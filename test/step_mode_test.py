@@ -0,0 +1,63 @@
+"""
+Tester-present in step mode (--step) against a simulated DoIP ECU.
+
+The user steps into extendedDiagnosticSession and waits at the prompt:
+  - waiting: TesterPresent 3E 80 keeps the session alive while no step is executed
+  - quit:    leaving step mode stops tester-present, a later CLI request is not followed by 3E 80
+  - disabled: without tester_present in config nothing is sent while waiting
+
+Usage: python3 test/step_mode_test.py [path/to/diag_tool]
+"""
+import os
+import sys
+import time
+
+import sim
+
+INTERVAL = 0.3
+
+
+class TesterPresentEcuSimulator(sim.EcuSimulator):
+    def __init__(self):
+        super().__init__()
+        self.requests = []  # (time, request)
+
+    def respond(self, request):
+        self.requests.append((time.time(), bytes(request)))
+        return super().respond(request)
+
+    def times(self, prefix):
+        return [at for at, request in self.requests if request.startswith(prefix)]
+
+
+def run(tool, tester_present):
+    ecu = TesterPresentEcuSimulator()
+    parameter = {"vin": "", "tester_present": tester_present, "tester_present_interval": "%dms" % (INTERVAL * 1000)}
+    # connect, activation, session, wait at the prompt, quit, then a CLI request as marker
+    commands = ['n', 'n', 'n', 'p', 'p', 'p', 'q', 'send_diag:1001', 'p']
+    sim.run(tool, ecu, [{"name": "send_diag", "action": ["1003"], "expect": ["5003*"]}],
+            extra_config={"parameter": parameter}, extra_args=['--step'], commands=commands, timeout=1)
+    return ecu
+
+
+def main():
+    tool = sys.argv[1] if len(sys.argv) > 1 else os.path.join('target', 'debug', 'diag_tool')
+
+    ecu = run(tool, True)
+    session, quit_marker = ecu.times(b'\x10\x03'), ecu.times(b'\x10\x01')
+    assert len(session) == 1 and len(quit_marker) == 1, 'steps were not executed: %s' % ecu.requests
+    waiting = [at for at in ecu.times(b'\x3e\x80') if session[0] < at < quit_marker[0]]
+    assert len(waiting) >= 5, 'tester-present was not sent while waiting at the prompt: %s' % ecu.requests
+    print('waiting: OK')
+    late = [at for at in ecu.times(b'\x3e\x80') if at > quit_marker[0] + INTERVAL]
+    assert not late, 'tester-present was not stopped after quit: %d requests' % len(late)
+    print('quit: OK')
+
+    ecu = run(tool, False)
+    assert len(ecu.times(b'\x10\x03')) == 1, 'steps were not executed: %s' % ecu.requests
+    assert not ecu.times(b'\x3e'), 'tester-present was sent although it is disabled'
+    print('disabled: OK')
+
+
+if __name__ == '__main__':
+    main()
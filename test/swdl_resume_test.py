@@ -0,0 +1,88 @@
+"""
+Simulated communication loss during software download.
+
+A DoIP ECU simulator drops the TCP connection in the middle of TransferData. The diag tool has to reconnect,
+activate routing, enter programming session again and continue the download:
+  - resume:  ECU keeps its transfer state, download continues from the last acknowledged block without a new erase
+  - restart: ECU refuses RequestDownload of the remaining data, flashing restarts from erase
+  - refused: gateway refuses routing activation after reconnect, session is not replayed and the step fails
+  - timeout: ECU keeps the connection but stops answering TransferData, the step fails without reconnecting
+
+Usage: python3 test/swdl_resume_test.py [path/to/diag_tool]
+"""
+import os
+import sys
+
+import sim
+
+ADDRESS = 0x00080000
+IMAGE = bytes((index * 7 + 3) & 0xFF for index in range(1000))
+DROP_AFTER_BLOCKS = 5
+ACTION = {"format": "bin", "address": "0x%08X" % ADDRESS, "erase": "0x%08X,0x%X" % (ADDRESS, len(IMAGE)),
+          "check_routine": "0x0202"}
+
+
+class RefusingGatewaySimulator(sim.EcuSimulator):
+    def __init__(self):
+        super().__init__(drop_after_blocks=DROP_AFTER_BLOCKS)
+        self.sessions = []
+
+    def activation_response(self):
+        return 0x10 if self.connection_count == 1 else 0x06  # source address already registered
+
+    def respond(self, request):
+        if request[0] == 0x10:
+            self.sessions.append((self.connection_count, request[1]))
+        return super().respond(request)
+
+
+class SilentEcuSimulator(sim.EcuSimulator):
+    def respond(self, request):
+        if request[0] == 0x36 and self.blocks == DROP_AFTER_BLOCKS:
+            return None  # TransferData is acknowledged on DoIP, the response never comes
+        return super().respond(request)
+
+
+def run(tool, refuse_resume):
+    ecu = sim.EcuSimulator(refuse_resume, drop_after_blocks=DROP_AFTER_BLOCKS)
+    report, _ = sim.flash(tool, ecu, ACTION, IMAGE)
+    assert report and len(report['steps']) == 5, 'sequence was not executed'
+    assert report['verdict'] == 'pass', 'sequence failed: %s' % sim.step_errors(report)
+    assert ecu.is_dropped, 'connection was not dropped'
+    assert ecu.connection_count == 2, 'expected one reconnection, got %d connections' % ecu.connection_count
+    assert ecu.read(ADDRESS, len(IMAGE)) == IMAGE, 'flashed memory differs from image'
+    expected_erase_count = 2 if refuse_resume else 1
+    assert ecu.erase_count == expected_erase_count, \
+        'expected %d erase, got %d' % (expected_erase_count, ecu.erase_count)
+
+
+def refused(tool):
+    ecu = RefusingGatewaySimulator()
+    report, _ = sim.flash(tool, ecu, ACTION, IMAGE)
+    assert report and report['verdict'] == 'fail', 'download went on without routing activation'
+    assert 'routing activation refused after reconnect' in sim.step_errors(report)[3], sim.step_errors(report)
+    assert ecu.connection_count == 2, 'refused activation was retried, got %d connections' % ecu.connection_count
+    assert ecu.sessions == [(1, 0x02)], 'session was replayed after refused activation: %s' % ecu.sessions
+
+
+def timeout(tool):
+    ecu = SilentEcuSimulator()
+    report, _ = sim.flash(tool, ecu, ACTION, IMAGE)
+    assert report and report['verdict'] == 'fail', 'download passed without TransferData response'
+    assert ecu.connection_count == 1, 'missing response was handled as link loss, got %d connections' % ecu.connection_count
+    assert ecu.erase_count == 1 and ecu.blocks == DROP_AFTER_BLOCKS, 'download went on after the timeout'
+
+
+def main():
+    tool = sys.argv[1] if len(sys.argv) > 1 else os.path.join('target', 'debug', 'diag_tool')
+    for name, refuse_resume in (('resume', False), ('restart', True)):
+        run(tool, refuse_resume)
+        print('%s: OK' % name)
+    refused(tool)
+    print('refused: OK')
+    timeout(tool)
+    print('timeout: OK')
+
+
+if __name__ == '__main__':
+    main()
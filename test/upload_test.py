@@ -0,0 +1,127 @@
+"""
+Memory upload (upload step) against a simulated DoIP ECU.
+
+The ECU memory is filled with a pattern, the dump file has to hold the same bytes:
+  - request_upload: RequestUpload/TransferData/RequestTransferExit, dump written as bin and as Intel HEX
+  - fallback:       negative response to RequestUpload falls back to ReadMemoryByAddress in block_length requests,
+                    method request_upload fails instead and method read_memory does not try RequestUpload
+  - reference:      dump matching a reference passes, a differing byte fails and the dump is kept,
+                    reference data outside the dumped memory fails
+
+Usage: python3 test/upload_test.py [path/to/diag_tool]
+"""
+import os
+import sys
+import tempfile
+
+import sim
+from image_format_test import ihex_record
+
+ADDRESS, LENGTH = 0x00021000, 0x150
+MEMORY = bytes((index * 29 + 3) & 0xFF for index in range(LENGTH))
+
+
+class UploadEcuSimulator(sim.EcuSimulator):
+    def __init__(self, refuse_upload=False):
+        super().__init__()
+        self.refuse_upload = refuse_upload
+        self.requests = []
+        for index, byte in enumerate(MEMORY):
+            self.memory[ADDRESS + index] = byte
+
+    def respond(self, request):
+        self.requests.append(bytes(request))
+        if request[0] == 0x35 and self.refuse_upload:
+            return b'\x7f\x35\x31'
+        return super().respond(request)
+
+    def services(self):
+        return [request[0] for request in self.requests]
+
+
+def upload(tool, work_dir, ecu, name='dump.bin', **action):
+    path = os.path.join(work_dir, name)
+    report, errors = sim.run(tool, ecu, [{"name": "upload", "timeout": "1s", "action": dict({
+        "path": path, "address": "0x%08X" % ADDRESS, "length": "0x%X" % LENGTH}, **action)}])
+    assert report, errors
+    return path, report, errors + ' '.join(sim.step_errors(report))
+
+
+def read_ihex(path):
+    memory, base = {}, 0
+    for line in open(path):
+        record = bytes.fromhex(line.strip()[1:])
+        assert sum(record) & 0xFF == 0, 'wrong checksum in %s' % line
+        offset, record_type, data = int.from_bytes(record[1:3], 'big'), record[3], record[4:-1]
+        if record_type == 0x04:
+            base = int.from_bytes(data, 'big') << 16
+        elif record_type == 0x00:
+            for index, byte in enumerate(data):
+                memory[base + offset + index] = byte
+    return bytes(memory[address] for address in sorted(memory)), min(memory)
+
+
+def main():
+    tool = sys.argv[1] if len(sys.argv) > 1 else os.path.join('target', 'debug', 'diag_tool')
+    with tempfile.TemporaryDirectory() as work_dir:
+        ecu = UploadEcuSimulator()
+        path, report, errors = upload(tool, work_dir, ecu)
+        assert report['verdict'] == 'pass', errors
+        assert open(path, 'rb').read() == MEMORY, 'bin dump differs'
+        assert 0x35 in ecu.services() and 0x23 not in ecu.services(), ecu.services()
+        assert ecu.services().count(0x36) == -(-LENGTH // (sim.MAX_BLOCK_LENGTH - 2)), ecu.services()
+        path, report, errors = upload(tool, work_dir, UploadEcuSimulator(), name='dump.hex')
+        assert report['verdict'] == 'pass', errors
+        assert read_ihex(path) == (MEMORY, ADDRESS), 'hex dump differs'
+        print('request_upload: OK')
+
+        ecu = UploadEcuSimulator(refuse_upload=True)
+        path, report, errors = upload(tool, work_dir, ecu, name='fallback.bin', block_length="0x40")
+        assert report['verdict'] == 'pass', errors
+        assert 'fall back to ReadMemoryByAddress' in errors, errors
+        assert open(path, 'rb').read() == MEMORY, 'dump read by ReadMemoryByAddress differs'
+        reads = [request for request in ecu.requests if request[0] == 0x23]
+        assert [request[1] for request in reads] == [0x44] * 6, reads
+        assert [int.from_bytes(request[6:10], 'big') for request in reads] == [0x40] * 5 + [0x10], reads
+
+        ecu = UploadEcuSimulator(refuse_upload=True)
+        path, report, errors = upload(tool, work_dir, ecu, name='refused.bin', method="request_upload")
+        assert report['verdict'] == 'fail' and 'request-upload rejected' in errors, errors
+        assert 0x23 not in ecu.services() and not os.path.exists(path), ecu.services()
+
+        ecu = UploadEcuSimulator()
+        path, report, errors = upload(tool, work_dir, ecu, name='read.bin', method="read_memory")
+        assert report['verdict'] == 'pass', errors
+        assert 0x35 not in ecu.services() and open(path, 'rb').read() == MEMORY, ecu.services()
+        print('fallback: OK')
+
+        reference_path = os.path.join(work_dir, 'reference.bin')
+        with open(reference_path, 'wb') as reference_file:
+            reference_file.write(MEMORY)
+        path, report, errors = upload(tool, work_dir, UploadEcuSimulator(), reference=reference_path)
+        assert report['verdict'] == 'pass', errors
+
+        changed = bytearray(MEMORY)
+        changed[0x42] ^= 0xFF
+        with open(reference_path, 'wb') as reference_file:
+            reference_file.write(changed)
+        path, report, errors = upload(tool, work_dir, UploadEcuSimulator(), name='differs.bin', reference=reference_path)
+        assert report['verdict'] == 'fail', 'differing memory passed'
+        assert 'memory differs from %s in 1 ranges, 0x1 bytes' % reference_path in errors, errors
+        assert open(path, 'rb').read() == MEMORY, 'dump was not kept'
+
+        # reference 0x10 bytes before and 0x20 bytes after the dumped memory
+        reference_path = os.path.join(work_dir, 'reference.hex')
+        with open(reference_path, 'w') as reference_file:
+            reference_file.write('\n'.join([ihex_record(0x04, 0, (ADDRESS >> 16).to_bytes(2, 'big'))] + [
+                ihex_record(0x00, (ADDRESS + offset) & 0xFFFF, data)
+                for offset, data in [(-0x10, bytes(0x10)), (0, MEMORY[:0x10]), (LENGTH, bytes(0x20))]
+            ] + [ihex_record(0x01, 0, b'')]) + '\n')
+        path, report, errors = upload(tool, work_dir, UploadEcuSimulator(), name='uncovered.bin', reference=reference_path)
+        assert report['verdict'] == 'fail', 'reference outside the dump passed'
+        assert 'in 0 ranges, 0x0 bytes, 0x30 bytes of reference are not in the dump' in errors, errors
+        print('reference: OK')
+
+
+if __name__ == '__main__':
+    main()
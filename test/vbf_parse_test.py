@@ -0,0 +1,94 @@
+"""
+Parsing and checksums of vbf files with --verify-vbf, no ECU needed.
+
+vbf files are built here from a header text and data blocks:
+  - good:      comments, a description list and two blocks are accepted
+  - bad_crc16: a block CRC16-CCITT that does not match its data is rejected
+  - bad_crc32: a file_checksum that does not match the data part is rejected
+  - truncated: a data part ending inside a block is rejected
+  - malformed: header syntax errors, missing and invalid fields are rejected with their reason
+
+Usage: python3 test/vbf_parse_test.py [path/to/diag_tool]
+"""
+import os
+import struct
+import sys
+import tempfile
+import zlib
+
+import sim
+from vbf_verification_test import crc16_ccitt
+
+APP_ADDRESS = 0x00080000
+BLOCKS = [(APP_ADDRESS, bytes((index * 7 + 3) & 0xFF for index in range(300))),
+          (APP_ADDRESS + 0x1000, b'calibration data' * 8)]
+FIELDS = [
+    'description = { "application", "built for // test /* not a comment */" };',
+    'sw_part_number = "32233422"; // part number',
+    '/* multi line\n       comment */ sw_version = "AA";',
+    'sw_part_type = EXE;', 'data_format_identifier = 0x00;', 'ecu_address = 0x1D88;',
+    'erase = { { 0x%08X, 0x00002000 } };' % APP_ADDRESS,
+]
+
+
+def data_part(blocks):
+    return b''.join(struct.pack('>II', address, len(data)) + data + struct.pack('>H', crc16_ccitt(data))
+                    for address, data in blocks)
+
+
+def vbf(fields, data, version='2.6', checksum=None):
+    fields = fields + ['file_checksum = 0x%08X;' % (zlib.crc32(data) if checksum is None else checksum)]
+    return ('vbf_version = %s;\nheader {\n    %s\n}' % (version, '\n    '.join(fields))).encode() + data
+
+
+def main():
+    tool = sys.argv[1] if len(sys.argv) > 1 else os.path.join('target', 'debug', 'diag_tool')
+    data = data_part(BLOCKS)
+    with tempfile.TemporaryDirectory() as work_dir:
+        result = sim.verify_vbf(tool, work_dir, vbf(FIELDS, data))
+        assert result.returncode == 0 and 'OK, signature not checked' in result.stdout, result.stdout + result.stderr
+        print('good: OK')
+
+        corrupted = bytearray(data)
+        corrupted[-1] ^= 0xFF
+        sim.expect_vbf_rejected(tool, work_dir, vbf(FIELDS, bytes(corrupted)),
+                                'vbf block 1 at 0x%08X checksum mismatch' % (APP_ADDRESS + 0x1000), 'bad_crc16')
+        print('bad_crc16: OK')
+
+        sim.expect_vbf_rejected(tool, work_dir, vbf(FIELDS, data, checksum=zlib.crc32(data) ^ 1),
+                                'vbf file_checksum mismatch', 'bad_crc32')
+        print('bad_crc32: OK')
+
+        sim.expect_vbf_rejected(tool, work_dir, vbf(FIELDS, data[:-10]),
+                                'vbf block 1 at 0x%08X is truncated' % (APP_ADDRESS + 0x1000), 'truncated')
+        sim.expect_vbf_rejected(tool, work_dir, vbf(FIELDS, data + b'\x00\x08'), 'vbf block 2 header is truncated',
+                                'short header')
+        print('truncated: OK')
+
+        without = lambda prefix: [field for field in FIELDS if not field.startswith(prefix)]
+        for name, contents, message in [
+            ('version', vbf(FIELDS, data, version='1.0'), 'vbf version 1.0 is not supported'),
+            ('no_version', vbf(FIELDS, data).replace(b'vbf_version', b'version', 1), 'expected vbf_version'),
+            ('no_header', vbf(FIELDS, data).replace(b'header {', b'{', 1), 'expected header'),
+            ('no_semicolon', vbf(['sw_version = "AA"'] + without('/*'), data), "expected ';'"),
+            ('duplicated', vbf(FIELDS + ['sw_part_type = SBL;'], data), 'duplicated field sw_part_type'),
+            ('string', vbf(['sw_version = "AA;'] + without('/*'), data), 'vbf header line 3: unterminated string'),
+            ('comment', vbf(FIELDS + ['/* open comment'], data[:8]), 'unterminated comment'),
+            ('character', vbf(FIELDS + ['call = #1;'], data), "unexpected character '#'"),
+            ('no_ecu_address', vbf([field for field in FIELDS if 'ecu_address' not in field], data),
+             'vbf header has no ecu_address'),
+            ('erase_pair', vbf(without('erase') + ['erase = { { 0x%08X } };' % APP_ADDRESS], data),
+             'vbf erase range is not { start, length }'),
+            ('erase_list', vbf(without('erase') + ['erase = 0x%08X;' % APP_ADDRESS], data),
+             'vbf field erase is not a list'),
+            ('data_format_identifier', vbf(without('data_format') + ['data_format_identifier = 0x100;'], data),
+             'vbf data_format_identifier is longer than 1 byte'),
+        ]:
+            sim.expect_vbf_rejected(tool, work_dir, contents, message, name)
+        result = sim.verify_vbf(tool, work_dir, vbf(without('data_format'), data))
+        assert result.returncode == 0, 'data_format_identifier is optional: %s' % result.stderr
+        print('malformed: OK')
+
+
+if __name__ == '__main__':
+    main()
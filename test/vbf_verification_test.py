@@ -0,0 +1,162 @@
+"""
+Offline verification of vbf files: verification block table hashes, verification_block_root_hash and sw_signature.
+
+vbf files are built here with a verification block table (format, entry count, start/length/SHA-256 per data block),
+its SHA-256 as root hash and a signature of the root hash:
+  - offline:         --verify-vbf checks an ECDSA signed file without ECU, without vbf_verification only hashes
+  - flash_signed:    swdl step verifies the file, then erases and downloads it
+  - tampered_block:  a data block with valid CRCs differs from its hash, the step fails before erasing
+  - wrong_root_hash: verification_block_root_hash does not match the table, the step fails before erasing
+  - wrong_key:       file is signed by another key or not signed, the step fails before erasing
+  - rsa_pss_dev:     RSA-PSS signature in sw_signature_dev with an X.509 certificate as public key
+  - config:          a scheme not fitting the key and a missing key file are rejected when config is loaded
+
+Usage: python3 test/vbf_verification_test.py [path/to/diag_tool]
+"""
+import hashlib
+import os
+import struct
+import sys
+import tempfile
+import zlib
+
+from cryptography.hazmat.primitives import hashes, serialization
+from cryptography.hazmat.primitives.asymmetric import ec, padding, rsa
+
+import sim
+from authentication_test import make_certificate
+
+APP_ADDRESS, TABLE_ADDRESS = 0x00080000, 0x00090000
+BLOCKS = [(APP_ADDRESS, bytes((index * 13 + 5) & 0xFF for index in range(600))),
+          (APP_ADDRESS + 0x1000, bytes((index * 3 + 1) & 0xFF for index in range(200)))]
+
+
+def crc16_ccitt(data):
+    crc = 0xFFFF
+    for byte in data:
+        crc ^= byte << 8
+        for _ in range(8):
+            crc = ((crc << 1) ^ 0x1021 if crc & 0x8000 else crc << 1) & 0xFFFF
+    return crc
+
+
+def hash_table(blocks):
+    return struct.pack('>HH', 0, len(blocks)) + b''.join(
+        struct.pack('>II', address, len(data)) + hashlib.sha256(data).digest() for address, data in blocks)
+
+
+def sign_ecdsa(key, root_hash):
+    return key.sign(root_hash, ec.ECDSA(hashes.SHA256()))
+
+
+def write_vbf(path, blocks, sign=None, signature_field='sw_signature', root_hash=None, table_blocks=None):
+    table = hash_table(table_blocks or blocks)
+    root_hash = root_hash or hashlib.sha256(table).digest()
+    data_part = b''.join(struct.pack('>II', address, len(data)) + data + struct.pack('>H', crc16_ccitt(data))
+                         for address, data in blocks + [(TABLE_ADDRESS, table)])
+    fields = [
+        'sw_part_number = "32233422";', 'sw_version = "AA";', 'sw_part_type = EXE;',
+        'data_format_identifier = 0x00;', 'ecu_address = 0x1D88;',
+        'erase = { { 0x%08X, 0x00002000 }, { 0x%08X, 0x00001000 } };' % (APP_ADDRESS, TABLE_ADDRESS),
+        'verification_block_start = 0x%08X;' % TABLE_ADDRESS,
+        'verification_block_length = 0x%08X;' % len(table),
+        'verification_block_root_hash = 0x%s;' % root_hash.hex().upper(),
+    ]
+    if sign is not None:
+        fields.append('%s = 0x%s;' % (signature_field, sign(root_hash).hex().upper()))
+    fields.append('file_checksum = 0x%08X;' % zlib.crc32(data_part))
+    with open(path, 'wb') as vbf_file:
+        vbf_file.write(('vbf_version = 2.6;\nheader {\n    %s\n}' % '\n    '.join(fields)).encode() + data_part)
+
+
+def write_key(work_dir, name, key):
+    path = os.path.join(work_dir, name)
+    with open(path, 'wb') as key_file:
+        key_file.write(key.public_key().public_bytes(serialization.Encoding.PEM,
+                                                     serialization.PublicFormat.SubjectPublicKeyInfo))
+    return path
+
+
+def config(verification):
+    return verification and {"vbf_verification": verification}
+
+
+def main():
+    tool = sys.argv[1] if len(sys.argv) > 1 else os.path.join('target', 'debug', 'diag_tool')
+    signer = ec.generate_private_key(ec.SECP256R1())
+    other = ec.generate_private_key(ec.SECP256R1())
+
+    with tempfile.TemporaryDirectory() as work_dir:
+        verification = {"public_key": write_key(work_dir, 'signer.pem', signer)}
+        signed_path = os.path.join(work_dir, 'app.vbf')
+        write_vbf(signed_path, BLOCKS, sign=lambda root_hash: sign_ecdsa(signer, root_hash))
+
+        result = sim.verify_vbf(tool, work_dir, signed_path, config(verification))
+        assert result.returncode == 0 and '%s: OK, signature verified' % signed_path in result.stdout, result
+        result = sim.verify_vbf(tool, work_dir, signed_path, config(None))
+        assert result.returncode == 0 and 'signature not checked' in result.stdout, result
+        print('offline: OK')
+
+        ecu = sim.EcuSimulator()
+        report, errors = sim.flash(tool, ecu, {"path": signed_path, "format": "vbf"}, extra_config=config(verification))
+        assert report and report['verdict'] == 'pass', 'flash_signed failed: %s %s' % (
+            report and sim.step_errors(report), errors)
+        for address, data in BLOCKS:
+            assert ecu.read(address, len(data)) == data, 'flashed memory differs at 0x%08X' % address
+        print('flash_signed: OK')
+
+        tampered_path = os.path.join(work_dir, 'tampered.vbf')
+        write_vbf(tampered_path, [BLOCKS[0], (BLOCKS[1][0], b'\x00' + BLOCKS[1][1][1:])], table_blocks=BLOCKS,
+                  sign=lambda root_hash: sign_ecdsa(signer, root_hash))
+        sim.expect_rejected(tool, sim.EcuSimulator(), {"path": tampered_path, "format": "vbf"},
+                            'vbf block 1 at 0x00081000 does not match its hash in verification block table',
+                            'tampered_block', extra_config=config(verification))
+        result = sim.verify_vbf(tool, work_dir, tampered_path, config(None))
+        assert result.returncode == 1 and 'does not match its hash' in result.stderr, result
+        print('tampered_block: OK')
+
+        root_hash_path = os.path.join(work_dir, 'root_hash.vbf')
+        write_vbf(root_hash_path, BLOCKS, root_hash=bytes(32), sign=lambda root_hash: sign_ecdsa(signer, root_hash))
+        sim.expect_rejected(tool, sim.EcuSimulator(), {"path": root_hash_path, "format": "vbf"},
+                            'vbf verification block does not match verification_block_root_hash', 'wrong_root_hash',
+                            extra_config=config(verification))
+        print('wrong_root_hash: OK')
+
+        other_path = os.path.join(work_dir, 'other.vbf')
+        write_vbf(other_path, BLOCKS, sign=lambda root_hash: sign_ecdsa(other, root_hash))
+        sim.expect_rejected(tool, sim.EcuSimulator(), {"path": other_path, "format": "vbf"},
+                            'vbf sw_signature: ECDSA signature verification failed', 'wrong_key',
+                            extra_config=config(verification))
+        unsigned_path = os.path.join(work_dir, 'unsigned.vbf')
+        write_vbf(unsigned_path, BLOCKS)
+        sim.expect_rejected(tool, sim.EcuSimulator(), {"path": unsigned_path, "format": "vbf"},
+                            'vbf header has no sw_signature', 'unsigned', extra_config=config(verification))
+        print('wrong_key: OK')
+
+        rsa_signer = rsa.generate_private_key(public_exponent=65537, key_size=2048)
+        certificate_path = os.path.join(work_dir, 'signer.crt')
+        with open(certificate_path, 'wb') as certificate_file:
+            certificate_file.write(make_certificate('Software signer', rsa_signer, 'Software signer', rsa_signer)
+                                   .public_bytes(serialization.Encoding.PEM))
+        pss = padding.PSS(mgf=padding.MGF1(hashes.SHA256()), salt_length=padding.PSS.DIGEST_LENGTH)
+        dev_path = os.path.join(work_dir, 'dev.vbf')
+        write_vbf(dev_path, BLOCKS, signature_field='sw_signature_dev',
+                  sign=lambda root_hash: rsa_signer.sign(root_hash, pss, hashes.SHA256()))
+        rsa_verification = {"public_key": certificate_path, "scheme": "rsa_pss_sha256"}
+        result = sim.verify_vbf(tool, work_dir, dev_path, config(dict(rsa_verification, signature="sw_signature_dev")))
+        assert result.returncode == 0 and 'signature verified' in result.stdout, result
+        result = sim.verify_vbf(tool, work_dir, dev_path, config(rsa_verification))
+        assert result.returncode == 1 and 'vbf header has no sw_signature' in result.stderr, result
+        print('rsa_pss_dev: OK')
+
+        for settings, message in [
+            ({"public_key": certificate_path, "scheme": "ecdsa_sha256"}, 'vbf_verification: scheme EcdsaSha256 does not fit'),
+            ({"public_key": os.path.join(work_dir, 'missing.pem')}, 'vbf_verification: public_key'),
+        ]:
+            result = sim.verify_vbf(tool, work_dir, signed_path, config(settings))
+            assert message in result.stderr and 'OK' not in result.stdout, (message, result)
+        print('config: OK')
+
+
+if __name__ == '__main__':
+    main()
@@ -0,0 +1,91 @@
+"""
+Vendor profiles selected by vendor of config against a simulated DoIP ECU.
+
+The same flash package is flashed with both built-in profiles, the ECU records every RoutineControl request:
+  - iso14229:       checkProgrammingPreconditions 0x0203 first, eraseMemory record encoded with
+                    addressAndLengthFormatIdentifier 0x44, checkProgrammingDependencies 0xFF01 after the last file
+  - volvo:          routine 0x0206 first, eraseMemory record with 4 bytes start and length, no dependency check
+  - state_warning:  package preconditions reach programming session without security access, the missing
+                    programming level of the profile is reported
+  - seed_key_only:  generic profile refuses the AES authentication exchange, nothing is sent to the ECU
+  - unknown_vendor: vendor without profile is rejected when config is loaded
+
+Usage: python3 test/vendor_profile_test.py [path/to/diag_tool]
+"""
+import os
+import struct
+import sys
+import tempfile
+
+import flash_package_test
+import sim
+
+
+class RoutineEcuSimulator(flash_package_test.PackageEcuSimulator):
+    """Records requests, eraseMemory accepts both memory range records"""
+
+    def __init__(self):
+        super().__init__()
+        self.requests = []
+        self.routines = []
+
+    def respond(self, request):
+        self.requests.append(bytes(request))
+        if request[0] == 0x31:
+            self.routines.append((struct.unpack('>H', request[2:4])[0], bytes(request[4:])))
+            if request[2:4] == b'\xff\x00' and len(request) == 13 and request[4] == 0x44:
+                request = request[:4] + request[5:]
+        return super().respond(request)
+
+
+def run(tool, vendor, sequence, ecu):
+    with tempfile.TemporaryDirectory() as work_dir:
+        package_path = flash_package_test.write_package(work_dir, "0x1d88", "32233422 AA")
+        report, errors = sim.run(tool, ecu, [dict(item, action={"path": package_path}) if item['name'] == 'flash_package'
+                                             else item for item in sequence], vendor=vendor)
+    return ecu, report, errors
+
+
+def flash(tool, vendor):
+    sequence = [{"name": "flash_package", "timeout": "1s", "action": {}}]  # path of the written package
+    return run(tool, vendor, sequence, RoutineEcuSimulator())
+
+
+def main():
+    tool = sys.argv[1] if len(sys.argv) > 1 else os.path.join('target', 'debug', 'diag_tool')
+    app_range = struct.pack('>II', flash_package_test.APP_ADDRESS, len(flash_package_test.APP))
+
+    ecu, report, errors = flash(tool, 'iso14229')
+    assert report and report['verdict'] == 'pass', 'iso14229 failed: %s %s' % (report and sim.step_errors(report), errors)
+    assert [routine for routine, _ in ecu.routines] == [0x0203, 0x0301, 0xFF00, 0x0202, 0xFF01], ecu.routines
+    assert (0xFF00, b'\x44' + app_range) in ecu.routines, 'eraseMemory record is not encoded with 0x44'
+    assert ecu.read(flash_package_test.APP_ADDRESS, len(flash_package_test.APP)) == flash_package_test.APP
+    print('iso14229: OK')
+
+    ecu, report, errors = flash(tool, 'volvo')
+    assert report and report['verdict'] == 'pass', 'volvo failed: %s %s' % (report and sim.step_errors(report), errors)
+    assert [routine for routine, _ in ecu.routines] == [0x0206, 0x0301, 0xFF00, 0x0202], ecu.routines
+    assert (0xFF00, app_range) in ecu.routines, 'eraseMemory record is not start and length'
+    print('volvo: OK')
+
+    assert 'volvo programming needs security access level 0x01, unlocked level is none' in errors, errors
+    assert 'programming needs session' not in errors, errors
+    print('state_warning: OK')
+
+    sequence = [{"name": "securityaccess_01", "action": {"algorithm": "AES128", "iv": "random",
+                                                          "encryption_authentication_key": "0x" + "55" * 16,
+                                                          "proof_of_ownership_key": "0x" + "55" * 16}}]
+    ecu, report, errors = run(tool, 'generic', sequence, RoutineEcuSimulator())
+    assert report and report['verdict'] == 'fail', 'AES exchange was accepted by the generic profile'
+    assert 'vendor profile "iso14229" supports only seed/key algorithms, not AES128' in (sim.step_errors(report)[2] or ''), \
+        sim.step_errors(report)
+    assert not [request for request in ecu.requests if request[0] == 0x27], 'security access was sent'
+    print('seed_key_only: OK')
+
+    ecu, report, errors = flash(tool, 'acme')
+    assert not ecu.requests and 'vendor "acme" has no profile, known vendors: volvo, iso14229' in errors, errors
+    print('unknown_vendor: OK')
+
+
+if __name__ == '__main__':
+    main()
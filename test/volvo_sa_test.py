@@ -0,0 +1,138 @@
+"""
+Volvo SecurityAccess (0x27 AES authentication exchange) against a simulated DoIP ECU.
+
+The ECU checks the CMAC of every request, decrypts the client random number and accepts the sendKey only if the
+proof of ownership matches:
+  - known_answer: AES-CTR/CMAC reference of the simulator checked against RFC 4493 / SP 800-38B vectors, then the
+                  exchange with fixed keys, iv and random numbers: the reference and --crypto-selftest of the tool
+                  (executor::securityaccess::self_test) must produce the same messages
+  - fixed_iv:     AES128 with an explicit iv, both requests carry it and ClientSendKey matches the expected bytes
+  - aes256:       AES256 with 32 bytes keys and random iv
+  - short_reply:  truncated ServerResponseSeed, the step fails with a length error instead of crashing
+  - bad_mac:      wrong authentication code of ServerResponseSeed, no sendKey is sent
+  - validation:   unknown algorithm, bad iv and key length not matching the algorithm are rejected at load
+
+Usage: python3 test/volvo_sa_test.py [path/to/diag_tool]
+"""
+import os
+import subprocess
+import sys
+
+import sim
+from sim import VolvoSaEcuSimulator, aes_cmac, aes_ctr
+
+FIXED_IV = bytes(range(16))
+
+# (key, message, tag) of RFC 4493 example 2 and SP 800-38B D.3 example 2
+CMAC_VECTORS = [
+    ('2b7e151628aed2a6abf7158809cf4f3c', '6bc1bee22e409f96e93d7e117393172a', '070a16b46b4d4144f79bdd9dd04a287c'),
+    ('603deb1015ca71be2b73aef0857d77811f352c073b6108d72d9810a30914dff4', '6bc1bee22e409f96e93d7e117393172a',
+     '28a7023f452e8f82bd4bf28d8c37c35c'),
+]
+
+# (self test name, key length, ClientRequestSeed, ServerResponseSeed, ClientSendKey) of level 05 with
+# encryption_authentication_key 0x00.., proof_of_ownership_key following it, iv 0xF0..0xFF, client random number
+# 0xA0..0xAF, server iv 0xB0..0xBF and server random number 0xC0..0xCF. Computed with pyca/cryptography, no supplier
+# vectors are available, same values as executor::securityaccess::self_test
+VOLVO_SA_VECTORS = [
+    ('VOLVO-SA-AES128', 16,
+     '270500010001f0f1f2f3f4f5f6f7f8f9fafbfcfdfeffc606654b90f797ef3ff874ac9fbb0302192312edbce360a57601da2024c25996',
+     '67050002b0b1b2b3b4b5b6b7b8b9babbbcbdbebf23d4e25d14220f88bc6f96523a2712d1a277f6ceeb4f385399c1f1717dd3743c'
+     '7d0642ff6913fb7372e015b29ec20b6b',
+     '27060003f0f1f2f3f4f5f6f7f8f9fafbfcfdfeffdb6492e12ce3247baf4b550e073b8ccc54bef5aae88e69d19ac2d3fa8f42c608'),
+    ('VOLVO-SA-AES256', 32,
+     '270500010001f0f1f2f3f4f5f6f7f8f9fafbfcfdfeff32a16f2e8733266cf2c04cffec9fcdbb6351cbbedf6fec5d82e5a462ee7fc268',
+     '67050002b0b1b2b3b4b5b6b7b8b9babbbcbdbebfa0ccc5607d770223c0e8991d1dbdb4694a5aae096dbd96cb4430d96678136cbb'
+     'f44b2dcff30c89085d54258f9213f358',
+     '27060003f0f1f2f3f4f5f6f7f8f9fafbfcfdfeff94a49403dec320fdd4a5590132ff765c71818b84334ebcd8eedfc36c6e8e3fec'),
+]
+
+
+def client_request_seed(encryption_key, level, iv, client_random):
+    request = bytes([0x27, level, 0x00, 0x01, 0x00, 0x01]) + iv + aes_ctr(encryption_key, iv, client_random)
+    return request + aes_cmac(encryption_key, request)
+
+
+def server_response_seed(encryption_key, proof_key, level, server_iv, randoms):
+    client_random, server_random = randoms[:16], randoms[16:]
+    payload = bytes([0x67, level, 0x00, 0x02]) + server_iv + aes_ctr(encryption_key, server_iv,
+                                                                     server_random + aes_cmac(proof_key, server_random + client_random))
+    return payload + aes_cmac(encryption_key, payload)
+
+
+def send_key(encryption_key, proof_key, level, iv, randoms):
+    request = bytes([0x27, level, 0x00, 0x03]) + iv + aes_ctr(encryption_key, iv, aes_cmac(proof_key, randoms))
+    return request + aes_cmac(encryption_key, request)
+
+
+def run(tool, ecu, params, extra_config=None, env=None):
+    report, errors = sim.run(tool, ecu, [{"name": "securityaccess_05", "action": params, "expect": ["67*"]}],
+                             extra_config=extra_config, env=env)
+    return ecu, report, errors
+
+
+def step_error(report):
+    return report['steps'][2]['error'] or ''
+
+
+def params(encryption_key, proof_key, algorithm='AES128', iv='random'):
+    return {"algorithm": algorithm, "iv": iv, "encryption_authentication_key": '0x' + encryption_key.hex(),
+            "proof_of_ownership_key": '0x' + proof_key.hex()}
+
+
+def main():
+    tool = sys.argv[1] if len(sys.argv) > 1 else os.path.join('target', 'debug', 'diag_tool')
+    for key, message, tag in CMAC_VECTORS:
+        assert aes_cmac(bytes.fromhex(key), bytes.fromhex(message)).hex() == tag, 'CMAC reference is wrong'
+    iv, randoms, server_iv = bytes(range(0xF0, 0x100)), bytes(range(0xA0, 0xB0)) + bytes(range(0xC0, 0xD0)), bytes(range(0xB0, 0xC0))
+    for name, key_length, seed_request, seed_response, key_request in VOLVO_SA_VECTORS:
+        encryption_key, proof_key = bytes(range(key_length)), bytes(range(key_length, 2 * key_length))
+        assert client_request_seed(encryption_key, 0x05, iv, randoms[:16]).hex() == seed_request, name
+        assert server_response_seed(encryption_key, proof_key, 0x05, server_iv, randoms).hex() == seed_response, name
+        assert send_key(encryption_key, proof_key, 0x06, iv, randoms).hex() == key_request, name
+    result = subprocess.run([tool, '--crypto-selftest'], capture_output=True, text=True, timeout=60)
+    assert result.returncode == 0, (result.stdout, result.stderr)
+    for name, *_ in VOLVO_SA_VECTORS:
+        assert '%s: OK' % name in result.stdout.splitlines(), (name, result.stdout)
+    print('known_answer: OK')
+
+    encryption_key, proof_key = os.urandom(16), os.urandom(16)
+    ecu = VolvoSaEcuSimulator(encryption_key, proof_key)
+    ecu, report, errors = run(tool, ecu, params(encryption_key, proof_key, iv='0x' + FIXED_IV.hex()))
+    assert report and report['verdict'] == 'pass', 'fixed_iv failed: %s %s' % (report and step_error(report), errors)
+    assert ecu.unlocked == [0x05], ecu.unlocked
+    request_seed, client_send_key = ecu.requests
+    assert request_seed[6:22] == FIXED_IV and client_send_key[4:20] == FIXED_IV, 'explicit iv was not used'
+    assert client_send_key == send_key(encryption_key, proof_key, 0x06, FIXED_IV, ecu.randoms), 'ClientSendKey differs'
+    print('fixed_iv: OK')
+
+    encryption_key, proof_key = os.urandom(32), os.urandom(32)
+    ecu = VolvoSaEcuSimulator(encryption_key, proof_key)
+    ecu, report, errors = run(tool, ecu, params(encryption_key, proof_key, algorithm='AES256'))
+    assert report and report['verdict'] == 'pass', 'aes256 failed: %s %s' % (report and step_error(report), errors)
+    assert ecu.unlocked == [0x05] and ecu.requests[0][6:22] != ecu.requests[1][4:20], ecu.unlocked
+    print('aes256: OK')
+
+    ecu = VolvoSaEcuSimulator(encryption_key, proof_key, seed_fault='short')
+    ecu, report, errors = run(tool, ecu, params(encryption_key, proof_key, algorithm='AES256'))
+    assert report and report['verdict'] == 'fail' and not ecu.unlocked, 'short reply: %s' % errors
+    assert 'ServerResponseSeed should be 68 bytes, got 30' in step_error(report), step_error(report)
+    print('short_reply: OK')
+
+    ecu = VolvoSaEcuSimulator(encryption_key, proof_key, seed_fault='mac')
+    ecu, report, errors = run(tool, ecu, params(encryption_key, proof_key, algorithm='AES256'))
+    assert report and report['verdict'] == 'fail' and len(ecu.requests) == 1, 'bad_mac: %s' % errors
+    assert 'authentication code' in step_error(report), step_error(report)
+    print('bad_mac: OK')
+
+    for invalid, message in [({"algorithm": "AES512"}, 'unknown algorithm "AES512"'),
+                             ({"iv": "0x0102"}, 'iv "0x0102" should be "random" or 16 hex bytes'),
+                             ({"algorithm": "AES128"}, 'encryption_authentication_key should be 16 bytes for the algorithm, got 32')]:
+        ecu = VolvoSaEcuSimulator(encryption_key, proof_key)
+        ecu, report, errors = run(tool, ecu, dict(params(encryption_key, proof_key, algorithm='AES256'), **invalid))
+        assert not ecu.requests and message in errors, 'validation of %s: %s' % (invalid, errors)
+    print('validation: OK')
+
+
+if __name__ == '__main__':
+    main()